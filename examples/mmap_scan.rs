@@ -0,0 +1,80 @@
+//! Times a full scan of one table read three ways: with a system call per
+//! page into owned values, through a memory map into owned values, and
+//! through a memory map with the values borrowed from it. Each way runs
+//! RUNS times, 5 by default, and the fastest run counts.
+//!
+//! ```text
+//! cargo run --release --example mmap_scan -- DATABASE TABLE [RUNS]
+//! ```
+
+#[cfg(unix)]
+fn main() -> anyhow::Result<()> {
+    use sqlite_starter_rust::{
+        record::{Record, ValueRef},
+        Database,
+    };
+    use std::{ops::ControlFlow, time::Instant};
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (path, table) = match &args[..] {
+        [path, table, ..] => (path.clone(), table.as_str()),
+        _ => anyhow::bail!("usage: mmap_scan DATABASE TABLE [RUNS]"),
+    };
+    let runs = args.get(2).map(|runs| runs.parse()).transpose()?.unwrap_or(5);
+
+    // Rows and bytes of text and blobs seen, so that every value is read.
+    let time = |name: &str, scan: &dyn Fn() -> anyhow::Result<(usize, usize)>| -> anyhow::Result<()> {
+        let mut best = None;
+        let mut seen = (0, 0);
+        for _ in 0..runs {
+            let start = Instant::now();
+            seen = scan()?;
+            let took = start.elapsed();
+            best = Some(best.map_or(took, |best: std::time::Duration| best.min(took)));
+        }
+        let best = best.unwrap_or_default();
+        println!("{:<16} {:>10.2} ms  {} rows, {} bytes", name, best.as_secs_f64() * 1000.0, seen.0, seen.1);
+        Ok(())
+    };
+    let owned = |db: &Database| {
+        let mut seen = (0, 0);
+        db.scan_table(table, None, &mut |_, row| {
+            seen.0 += 1;
+            for value in row {
+                seen.1 += match value {
+                    Record::Text(text) => text.len(),
+                    Record::Blob(bytes) => bytes.len(),
+                    other => other.to_string().len(),
+                };
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+        Ok(seen)
+    };
+
+    let pread = Database::load_db(path.clone())?;
+    let mapped = Database::load_db_mapped(path)?;
+    time("pread, owned", &|| owned(&pread))?;
+    time("mmap, owned", &|| owned(&mapped))?;
+    time("mmap, borrowed", &|| {
+        let mut seen = (0, 0);
+        mapped.scan_borrowed(table, &mut |_, row| {
+            seen.0 += 1;
+            for value in row {
+                seen.1 += match value {
+                    ValueRef::Text(text) => text.len(),
+                    ValueRef::Blob(bytes) => bytes.len(),
+                    other => other.to_record().to_string().len(),
+                };
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+        Ok(seen)
+    })?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("mmap_scan needs a Unix system to map the file");
+}
@@ -0,0 +1,132 @@
+#!/bin/sh
+#
+# Builds a corpus of databases with the sqlite3 command-line shell, one for
+# each part of the file format the reader has to get right, and records what
+# sqlite3 answers to a few queries against each. tests/fixtures.rs then asks
+# the same of this program and compares.
+#
+# Usage: ./gen_fixtures.sh [DIR]   (default: fixtures)
+
+set -e
+
+dir=${1:-fixtures}
+rm -rf "$dir"
+mkdir -p "$dir"
+
+# db NAME SQL: creates DIR/NAME.db from SQL.
+db() {
+    sqlite3 "$dir/$1.db" "$2"
+}
+
+# query NAME SQL: records SQL, and sqlite3's output for it, as the next
+# query against DIR/NAME.db. NULL is written as this program's list mode
+# writes it.
+n=0
+query() {
+    n=$((n + 1))
+    printf '%s\n' "$2" > "$dir/$1.$n.sql"
+    sqlite3 -nullvalue NULL "$dir/$1.db" "$2" > "$dir/$1.$n.out"
+}
+
+# Rows too big for a page, as text and blobs, spilling to chains of
+# overflow pages.
+db overflow "
+PRAGMA page_size = 1024;
+CREATE TABLE docs(id INTEGER PRIMARY KEY, body TEXT, data BLOB);
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 50)
+INSERT INTO docs SELECT i, printf('%.*c', i * 200, 'x'), randomblob(i * 150) FROM n;
+"
+query overflow "SELECT id, length(body), length(data) FROM docs WHERE id % 7 = 0"
+query overflow "SELECT count(*) FROM docs"
+
+# Rows in an index b-tree keyed by the primary key.
+db without_rowid "
+CREATE TABLE kv(k TEXT PRIMARY KEY, v INTEGER) WITHOUT ROWID;
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+INSERT INTO kv SELECT printf('key%05d', i), i * 3 FROM n;
+"
+query without_rowid "SELECT k, v FROM kv WHERE k = 'key01234'"
+query without_rowid "SELECT count(*), sum(v) FROM kv"
+
+# Small pages and enough rows for interior pages above interior pages, in
+# the table and in its index.
+db deep "
+PRAGMA page_size = 512;
+CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT, n INTEGER);
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 20000)
+INSERT INTO t SELECT i, printf('name %d', i), i % 97 FROM n;
+CREATE INDEX t_name ON t(name);
+"
+query deep "SELECT id, n FROM t WHERE name = 'name 12345'"
+query deep "SELECT count(*), max(id) FROM t"
+query deep "SELECT id FROM t WHERE id > 19990"
+query deep "PRAGMA integrity_check"
+
+# Every serial type: NULL, each integer width, the constants 0 and 1, a
+# float, text and blobs, empty and not.
+db serial_types "
+CREATE TABLE v(id INTEGER PRIMARY KEY, x);
+INSERT INTO v(x) VALUES (NULL), (0), (1), (-1), (127), (-128), (32767), (-32768),
+  (8388607), (-8388608), (2147483647), (-2147483648), (140737488355327),
+  (-140737488355328), (9223372036854775807), (-9223372036854775808),
+  (1.5), (-0.25), (1e300), (''), ('text'), (x''), (x'00ff10');
+"
+query serial_types "SELECT id, typeof(x), x FROM v"
+
+# Commits still in the -wal file: the copy is taken while the shell that
+# wrote them has the database open, before it checkpoints on close.
+sqlite3 "$dir/wal-writer.db" > /dev/null <<END
+PRAGMA journal_mode = WAL;
+CREATE TABLE t(id INTEGER PRIMARY KEY, v TEXT);
+INSERT INTO t(v) VALUES ('checkpointed');
+PRAGMA wal_checkpoint;
+INSERT INTO t(v) VALUES ('only in the wal'), ('also in the wal');
+.system cp $dir/wal-writer.db $dir/wal.db && cp $dir/wal-writer.db-wal $dir/wal.db-wal
+END
+rm -f "$dir/wal-writer.db"*
+query wal "SELECT id, v FROM t"
+
+# Text in both byte orders of UTF-16.
+for encoding in le be; do
+    db utf16$encoding "
+PRAGMA encoding = 'UTF-16$encoding';
+CREATE TABLE words(id INTEGER PRIMARY KEY, word TEXT);
+INSERT INTO words(word) VALUES ('plain'), ('naïve'), ('Straße'), ('日本語'), ('𝄞 clef');
+CREATE INDEX words_word ON words(word);
+"
+    query utf16$encoding "SELECT id, word, length(word) FROM words"
+    query utf16$encoding "SELECT id FROM words WHERE word = 'Straße'"
+done
+
+# Pointer-map pages between the b-tree pages, and a file shrunk by
+# deletes.
+db autovacuum "
+PRAGMA page_size = 1024;
+PRAGMA auto_vacuum = FULL;
+CREATE TABLE a(id INTEGER PRIMARY KEY, v TEXT);
+CREATE TABLE b(id INTEGER PRIMARY KEY, v TEXT);
+WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 3000)
+INSERT INTO a SELECT i, printf('a%d', i) FROM n;
+INSERT INTO b SELECT id, v FROM a;
+DELETE FROM a WHERE id % 3 = 0;
+"
+query autovacuum "SELECT count(*), min(v), max(id) FROM a"
+query autovacuum "SELECT v FROM b WHERE id = 2999"
+
+# The indexes sqlite makes for PRIMARY KEY and UNIQUE constraints, next to
+# one made by CREATE INDEX, as the schema pragmas list them.
+db constraints "
+CREATE TABLE u(id INT PRIMARY KEY, a TEXT UNIQUE, b, c DEFAULT (1 + 2), UNIQUE(b, c), UNIQUE(id));
+CREATE INDEX u_c ON u(c);
+CREATE TABLE w(k TEXT PRIMARY KEY, v UNIQUE) WITHOUT ROWID;
+"
+query constraints "PRAGMA table_info(u)"
+query constraints "PRAGMA index_list(u)"
+query constraints "PRAGMA index_list(w)"
+query constraints "PRAGMA index_info(sqlite_autoindex_u_3)"
+query constraints "PRAGMA index_info(sqlite_autoindex_w_1)"
+query constraints "PRAGMA index_info(sqlite_autoindex_w_2)"
+query constraints "PRAGMA table_info(missing)"
+query constraints "PRAGMA integrity_check"
+
+echo "Fixtures written to $dir."
@@ -0,0 +1,244 @@
+use crate::{
+    messages::msg,
+    record::{compare_records, Record},
+};
+use anyhow::{anyhow, Result};
+use std::{cmp::Ordering, collections::HashSet};
+
+pub fn is_aggregate(name: &str, arg_count: usize) -> bool {
+    match name.to_lowercase().as_str() {
+        "count" | "sum" | "total" | "avg" => true,
+        // with two or more arguments these are the scalar functions
+        "min" | "max" => arg_count == 1,
+        _ => false,
+    }
+}
+
+/// Running state of one aggregate function call.
+pub struct Accumulator {
+    kind: Kind,
+    distinct: Option<HashSet<DistinctKey>>,
+}
+
+enum Kind {
+    CountRows(i64),
+    Count(i64),
+    Sum(Sum),
+    Total(Sum),
+    Avg(Sum),
+    Min(Option<Record>),
+    Max(Option<Record>),
+}
+
+/// Mirrors sqlite's SumCtx: an exact integer sum until the first REAL input
+/// or integer overflow, then Kahan-Babuska-Neumaier compensated floating point.
+#[derive(Default)]
+struct Sum {
+    integer: i64,
+    real: f64,
+    error: f64,
+    approximate: bool,
+    overflow: bool,
+    count: i64,
+}
+
+/// Integers beyond 2^52 are split so no precision is lost converting to f64.
+const PRECISE_LIMIT: i64 = 4_503_599_627_370_496;
+
+impl Sum {
+    fn step(&mut self, value: &Record) {
+        self.count += 1;
+        match (numeric(value), self.approximate) {
+            (Numeric::Integer(v), false) => match self.integer.checked_add(v) {
+                Some(total) => self.integer = total,
+                None => {
+                    self.overflow = true;
+                    self.init_approximate();
+                    self.step_integer(v);
+                }
+            },
+            (Numeric::Real(v), false) => {
+                self.init_approximate();
+                self.step_real(v);
+            }
+            (Numeric::Integer(v), true) => self.step_integer(v),
+            (Numeric::Real(v), true) => {
+                self.overflow = false;
+                self.step_real(v);
+            }
+        }
+    }
+
+    fn init_approximate(&mut self) {
+        self.approximate = true;
+        if self.integer <= -PRECISE_LIMIT || self.integer >= PRECISE_LIMIT {
+            let small = self.integer % 16384;
+            self.real = (self.integer - small) as f64;
+            self.error = small as f64;
+        } else {
+            self.real = self.integer as f64;
+            self.error = 0.0;
+        }
+    }
+
+    fn step_integer(&mut self, value: i64) {
+        if value <= -PRECISE_LIMIT || value >= PRECISE_LIMIT {
+            let small = value % 16384;
+            self.step_real((value - small) as f64);
+            self.step_real(small as f64);
+        } else {
+            self.step_real(value as f64);
+        }
+    }
+
+    fn step_real(&mut self, value: f64) {
+        let sum = self.real;
+        let total = sum + value;
+        if sum.abs() > value.abs() {
+            self.error += (sum - total) + value;
+        } else {
+            self.error += (value - total) + sum;
+        }
+        self.real = total;
+    }
+
+    fn real_total(&self) -> f64 {
+        if self.error.is_finite() {
+            self.real + self.error
+        } else {
+            self.real
+        }
+    }
+}
+
+/// Hashable identity of a GROUP BY key: values that compare equal share a
+/// group, and all NULLs form one group.
+pub type GroupKey = Vec<Option<DistinctKey>>;
+
+pub fn group_key(values: &[Record]) -> GroupKey {
+    values.iter().map(DistinctKey::new).collect()
+}
+
+#[derive(PartialEq, Eq, Hash)]
+pub enum DistinctKey {
+    Integer(i64),
+    Real(u64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl DistinctKey {
+    fn new(value: &Record) -> Option<Self> {
+        match value {
+            Record::Null => None,
+            Record::Float(v) if v.fract() == 0.0 && v.abs() < 9.2e18 => {
+                Some(DistinctKey::Integer(*v as i64))
+            }
+            Record::Float(v) => Some(DistinctKey::Real(v.to_bits())),
+            Record::Text(s) => Some(DistinctKey::Text(s.clone())),
+            Record::Blob(b) => Some(DistinctKey::Blob(b.clone())),
+            other => other.integer().map(DistinctKey::Integer),
+        }
+    }
+}
+
+impl Accumulator {
+    pub fn new(name: &str, arg_count: usize, distinct: bool) -> Result<Self> {
+        let kind = match (name.to_lowercase().as_str(), arg_count) {
+            ("count", 0) if !distinct => Kind::CountRows(0),
+            ("count", 1) => Kind::Count(0),
+            ("sum", 1) => Kind::Sum(Sum::default()),
+            ("total", 1) => Kind::Total(Sum::default()),
+            ("avg", 1) => Kind::Avg(Sum::default()),
+            ("min", 1) => Kind::Min(None),
+            ("max", 1) => Kind::Max(None),
+            _ => Err(anyhow!(msg!("wrong-argument-count", function = name)))?,
+        };
+
+        Ok(Self {
+            kind,
+            distinct: distinct.then(HashSet::new),
+        })
+    }
+
+    /// Feeds one row; `value` is the evaluated argument, if the function takes one.
+    pub fn step(&mut self, value: Option<&Record>) {
+        let value = match value {
+            Some(Record::Null) => return,
+            Some(value) => value,
+            None => {
+                if let Kind::CountRows(n) = &mut self.kind {
+                    *n += 1;
+                }
+                return;
+            }
+        };
+
+        if let Some(seen) = &mut self.distinct {
+            if !DistinctKey::new(value).is_some_and(|key| seen.insert(key)) {
+                return;
+            }
+        }
+
+        match &mut self.kind {
+            Kind::CountRows(_) => {}
+            Kind::Count(n) => *n += 1,
+            Kind::Sum(sum) | Kind::Total(sum) | Kind::Avg(sum) => sum.step(value),
+            Kind::Min(min) => keep_if(min, value, Ordering::Less),
+            Kind::Max(max) => keep_if(max, value, Ordering::Greater),
+        }
+    }
+
+    pub fn finish(self) -> Result<Record> {
+        match self.kind {
+            Kind::CountRows(n) | Kind::Count(n) => Ok(Record::Int64(n)),
+            Kind::Sum(sum) if sum.count == 0 => Ok(Record::Null),
+            Kind::Sum(sum) if sum.approximate && sum.overflow => Err(anyhow!(msg!("integer-overflow"))),
+            Kind::Sum(sum) if sum.approximate => Ok(Record::Float(sum.real_total())),
+            Kind::Sum(sum) => Ok(Record::Int64(sum.integer)),
+            Kind::Total(sum) if sum.approximate => Ok(Record::Float(sum.real_total())),
+            Kind::Total(sum) => Ok(Record::Float(sum.integer as f64)),
+            Kind::Avg(sum) if sum.count == 0 => Ok(Record::Null),
+            Kind::Avg(sum) if sum.approximate => Ok(Record::Float(sum.real_total() / sum.count as f64)),
+            Kind::Avg(sum) => Ok(Record::Float(sum.integer as f64 / sum.count as f64)),
+            Kind::Min(value) | Kind::Max(value) => Ok(value.unwrap_or(Record::Null)),
+        }
+    }
+}
+
+/// Replaces `best` with `value` if it is the first value or compares `wanted`.
+fn keep_if(best: &mut Option<Record>, value: &Record, wanted: Ordering) {
+    if best.as_ref().is_none_or(|best| compare_records(value, best) == wanted) {
+        *best = Some(value.clone());
+    }
+}
+
+enum Numeric {
+    Integer(i64),
+    Real(f64),
+}
+
+/// Numeric affinity as applied by sum()/total(): integer-looking text counts
+/// as an integer, any other value contributes its leading numeric prefix.
+fn numeric(value: &Record) -> Numeric {
+    match value {
+        Record::Float(v) => Numeric::Real(*v),
+        Record::Text(s) => match s.trim().parse::<i64>() {
+            Ok(v) => Numeric::Integer(v),
+            Err(_) => Numeric::Real(leading_real(s)),
+        },
+        Record::Blob(b) => Numeric::Real(leading_real(&String::from_utf8_lossy(b))),
+        other => Numeric::Integer(other.integer().unwrap_or(0)),
+    }
+}
+
+fn leading_real(text: &str) -> f64 {
+    let text = text.trim_start();
+    let end = text
+        .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+        .unwrap_or(text.len());
+    (1..=end)
+        .rev()
+        .find_map(|end| text[..end].parse().ok())
+        .unwrap_or(0.0)
+}
@@ -0,0 +1,91 @@
+//! Audit log of the writes made to a database, for tracing afterwards what
+//! changed and when. Every line is one JSON object. A statement's line goes
+//! to the log, synced, before any of its pages are written; the transaction
+//! it belongs to then gets a line of its own, `COMMIT` or `ROLLBACK`, so a
+//! statement without one belongs to a transaction that a crash cut short.
+
+use crate::{
+    output::{civil_from_days, json_string, json_value},
+    record::Record,
+};
+use anyhow::Result;
+use itertools::Itertools;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// An audit log open for appending.
+pub struct AuditLog {
+    file: File,
+    /// Whether statements were logged since the last transaction ended.
+    pending: AtomicBool,
+}
+
+impl AuditLog {
+    /// Opens the log at `path`, creating it when there is none yet.
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            file,
+            pending: AtomicBool::new(false),
+        })
+    }
+
+    /// Logs `statement`, run on the database at `database`, with `fields`
+    /// as the JSON members that describe what it does.
+    pub(crate) fn statement(&self, database: Option<&str>, statement: &str, fields: &[(&str, String)]) -> Result<()> {
+        self.append(database, statement, fields)?;
+        self.pending.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Logs the end of the transaction, if it logged any statement.
+    pub(crate) fn end(&self, database: Option<&str>, committed: bool) -> Result<()> {
+        if !self.pending.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.append(database, if committed { "COMMIT" } else { "ROLLBACK" }, &[])
+    }
+
+    fn append(&self, database: Option<&str>, statement: &str, fields: &[(&str, String)]) -> Result<()> {
+        let database = database.map_or("null".to_string(), json_string);
+        let members = [("time", json_string(&timestamp())), ("database", database), ("statement", json_string(statement))];
+        let members = members.iter().chain(fields).map(|(name, value)| format!("{}:{}", json_string(name), value));
+        writeln!(&self.file, "{{{}}}", members.format(","))?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// The JSON of a change to the row `rowid`: its values by column name before
+/// and after, leaving out `before` for a row that is new.
+pub(crate) fn row_change(rowid: i64, names: &[&str], before: Option<&[Record]>, after: &[Record]) -> String {
+    let image = |values: &[Record]| {
+        let members = names.iter().zip(values).map(|(name, value)| format!("{}:{}", json_string(name), json_value(value)));
+        format!("{{{}}}", members.format(","))
+    };
+    match before {
+        Some(before) => format!("{{\"rowid\":{},\"before\":{},\"after\":{}}}", rowid, image(before), image(after)),
+        None => format!("{{\"rowid\":{},\"after\":{}}}", rowid, image(after)),
+    }
+}
+
+/// The current time in UTC, as RFC 3339 with milliseconds.
+fn timestamp() -> String {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (days, seconds) = (since_epoch.as_secs() / 86400, since_epoch.as_secs() % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -1,4 +1,4 @@
-use super::record::Record;
+use super::record::{RawRecord, Record};
 use std::fmt::Display;
 
 #[derive(Debug)]
@@ -21,7 +21,7 @@ pub struct LeafIndexCell {
 #[derive(Debug)]
 pub struct LeafTableCell {
     pub row_id: u64,
-    pub values: Vec<Record>,
+    pub record: RawRecord,
 }
 
 impl Display for InteriorIndexCell {
@@ -65,8 +65,9 @@ impl Display for LeafIndexCell {
 impl Display for LeafTableCell {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "row_id: {}, values: [", self.row_id)?;
-        for (i, value) in self.values.iter().enumerate() {
-            if i == self.values.len() - 1 {
+        let values = self.record.values().map_err(|_| std::fmt::Error)?;
+        for (i, value) in values.iter().enumerate() {
+            if i == values.len() - 1 {
                 write!(f, "{}", value)?;
             } else {
                 write!(f, "{}, ", value)?;
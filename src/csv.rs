@@ -0,0 +1,60 @@
+//! Reading CSV as RFC 4180 describes it, for `.import`.
+
+use crate::messages::msg;
+use anyhow::{anyhow, Result};
+
+/// One record of a CSV file.
+#[derive(Debug)]
+pub struct CsvRecord {
+    /// The line the record starts on, counting from 1.
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+/// Splits `text` into records, which end at a newline, whether `\n` or
+/// `\r\n`, outside quotes. Fields are separated by commas and may be quoted
+/// with `"`, doubling any quote inside; a quoted field may span lines.
+pub fn parse(text: &str) -> Result<Vec<CsvRecord>> {
+    let mut records = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while chars.peek().is_some() {
+        let start = line;
+        let mut fields = Vec::new();
+        loop {
+            let mut field = String::new();
+            if chars.next_if_eq(&'"').is_some() {
+                loop {
+                    match chars.next() {
+                        None => Err(anyhow!(msg!("unterminated-field", line = start)))?,
+                        Some('"') if chars.next_if_eq(&'"').is_some() => field.push('"'),
+                        Some('"') => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                    }
+                }
+            }
+            // Whatever follows a closing quote belongs to the field too, as
+            // sqlite reads it.
+            while let Some(c) = chars.next_if(|&c| !matches!(c, ',' | '\n' | '\r')) {
+                field.push(c);
+            }
+            fields.push(field);
+            match chars.next() {
+                Some(',') => continue,
+                Some('\r') => {
+                    chars.next_if_eq(&'\n');
+                }
+                _ => (),
+            }
+            line += 1;
+            break;
+        }
+        records.push(CsvRecord { line: start, fields });
+    }
+    Ok(records)
+}
@@ -1,381 +1,2521 @@
 use crate::{
+    audit::AuditLog,
+    error::Error,
     cell::{InteriorIndexCell, InteriorTableCell, LeafIndexCell, LeafTableCell},
+    aggregate::{self, Accumulator, GroupKey},
+    functions::{self, CaseFolding, Prng},
+    interrupt,
+    journal::{self, Journal},
+    lock::{SharedLock, WriteLock},
+    messages::msg,
+    output::{Rendering, RowWriter},
+    source::{PageSource, ReadOnly},
+    profile::Profile,
+    trace::ReadTrace,
+    wal::WalSource,
     page::{
         schema::{self, Schema},
         Kind,
     },
-    record::{ColumnType, Record},
-    sql::{parse_sql, Condition, Statement},
+    record::{
+        compare_records, parse_record, parse_varint, Collation, FromRow, RawRecord, Record, Row, TextDecoding,
+        TextEncoding,
+    },
+    sql::{
+        parse_literals, parse_sql, Affinity, ColumnDef, ColumnRef, CompareOp, Condition, Expr, IndexColumn, Statement,
+        TableConstraint, TableRef,
+    },
     Page, DB_HEADER_SIZE,
 };
 use anyhow::{anyhow, Result};
+#[cfg(unix)]
+use crate::mmap::Mapped;
 use itertools::Itertools;
-use nom::number::complete::{be_f64, be_i16, be_i24, be_i32, be_i64, be_i8, be_u32};
-use std::{fs::File, os::unix::fs::FileExt};
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::Entry, HashMap},
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io,
+    ops::{Bound, ControlFlow, RangeBounds},
+    rc::Rc,
+    str::FromStr,
+    sync::{
+        atomic::{self, AtomicBool, AtomicU64},
+        Arc, Mutex, RwLock,
+    },
+};
+
+mod attach;
+mod backup;
+mod borrowed;
+pub mod btree;
+mod bulk;
+mod carve;
+mod checksum;
+mod compound;
+mod dedup;
+mod extract;
+mod freelist;
+mod header;
+mod integrity;
+mod lint;
+mod pages;
+mod parallel;
+mod plan;
+mod prepared;
+mod repair;
+mod snapshot;
+mod view;
+mod write;
+
+pub use backup::{apply_delta, Delta};
+pub use carve::{Carved, Region, Slack};
+pub use checksum::Checksum;
+pub use dedup::{dedup_pages, Dedup, DedupFile};
+pub use freelist::{Freelist, PageRole, PageUse};
+pub use lint::{Lint, LintKind};
+pub use prepared::Prepared;
+pub use repair::{Repair, Repaired};
+use btree::Walk;
+use plan::{Access, KeyRange, SelectPlan};
+
+/// A leaf page of a table b-tree, as handed out by [`Database::table_pages`].
+pub struct PageRef {
+    /// Page number, counting from 1.
+    pub number: usize,
+    /// The rows stored on the page, in rowid order.
+    pub cells: Vec<LeafTableCell>,
+}
+
+/// A table column as reported by [`Database::columns`] and `PRAGMA table_info`.
+#[derive(Debug)]
+pub struct ColumnInfo {
+    pub cid: usize,
+    pub name: String,
+    /// Declared type as written, or empty.
+    pub declared_type: String,
+    pub affinity: Affinity,
+    pub not_null: bool,
+    /// DEFAULT clause as written.
+    pub default: Option<String>,
+    /// Position in the primary key counting from 1, or 0 outside it.
+    pub pk: usize,
+}
+
+/// Which way [`Database::index_entries`] walks an index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// In the order the index stores its keys.
+    Forward,
+    Backward,
+}
+
+/// One entry of an index: the indexed values and the row they belong to.
+#[derive(Debug)]
+pub struct IndexEntry {
+    pub key: Vec<Record>,
+    pub rowid: i64,
+}
+
+/// What is left to do in a walk: a page to read, or an entry already read.
+enum Step<T> {
+    Page(usize),
+    Entry(T),
+}
+
+/// Lazy in-order walk over the entries of an index b-tree that lie between
+/// two bounds. Subtrees that cannot hold such entries are never read.
+struct IndexWalk<'a> {
+    db: &'a Database,
+    /// Collation and order of the key columns; missing ones are ascending BINARY.
+    columns: &'a [IndexColumn],
+    low: Bound<Vec<Record>>,
+    high: Bound<Vec<Record>>,
+    direction: Direction,
+    stack: Vec<Step<Vec<Record>>>,
+    walk: Walk,
+}
+
+impl IndexWalk<'_> {
+    fn below_low(&self, key: &[Record]) -> bool {
+        match &self.low {
+            Bound::Included(low) => compare_key(key, low, self.columns).is_lt(),
+            Bound::Excluded(low) => compare_key(key, low, self.columns).is_le(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    fn above_high(&self, key: &[Record]) -> bool {
+        match &self.high {
+            Bound::Included(high) => compare_key(key, high, self.columns).is_gt(),
+            Bound::Excluded(high) => compare_key(key, high, self.columns).is_ge(),
+            Bound::Unbounded => false,
+        }
+    }
+
+    /// Steps for one page in key order, leaving out what the bounds rule out.
+    /// A child only holds keys between the cells on either side of it.
+    fn expand(&self, page_num: usize) -> Result<Vec<Step<Vec<Record>>>> {
+        let mut steps = Vec::new();
+        match self.db.read_page(page_num)? {
+            Page::InteriorIndex { rmptr, cells } => {
+                let mut previous: Option<&[Record]> = None;
+                for cell in &cells {
+                    if !self.below_low(&cell.keys) && !previous.is_some_and(|p| self.above_high(p)) {
+                        steps.push(Step::Page(cell.left_child as usize));
+                    }
+                    if !self.below_low(&cell.keys) && !self.above_high(&cell.keys) {
+                        steps.push(Step::Entry(cell.keys.clone()));
+                    }
+                    previous = Some(&cell.keys);
+                }
+                if !previous.is_some_and(|p| self.above_high(p)) {
+                    steps.push(Step::Page(rmptr as usize));
+                }
+            }
+            Page::LeafIndex { cells } => {
+                let keys = cells.into_iter().map(|cell| cell.keys);
+                steps.extend(
+                    keys.filter(|key| !self.below_low(key) && !self.above_high(key))
+                        .map(Step::Entry),
+                );
+            }
+            _ => Err(malformed(Some(page_num), msg!("not-an-index-page", page = page_num)))?,
+        }
+        Ok(steps)
+    }
+}
+
+impl Iterator for IndexWalk<'_> {
+    type Item = Result<Vec<Record>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(step) = self.stack.pop() {
+            match step {
+                Step::Entry(key) => return Some(Ok(key)),
+                Step::Page(page_num) => match check_children(self.expand(page_num), page_num, &mut self.walk) {
+                    Ok(steps) if self.direction == Direction::Forward => {
+                        self.stack.extend(steps.into_iter().rev())
+                    }
+                    Ok(steps) => self.stack.extend(steps),
+                    Err(e) => {
+                        self.stack.clear();
+                        return Some(Err(e));
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+/// Lazy walk in rowid order over the cells of a table b-tree whose rowids
+/// lie in `low..=high` and, when there are `keys`, are among them. Subtrees
+/// that cannot hold such cells are never read. The walk keeps its place on a
+/// stack rather than in recursion, so it can stop between any two rows, and
+/// no tree is too deep for it.
+struct TableWalk<'a> {
+    db: &'a Database,
+    low: i64,
+    high: i64,
+    /// Sorted.
+    keys: Option<Vec<i64>>,
+    stack: Vec<Step<LeafTableCell>>,
+    walk: Walk,
+}
+
+impl TableWalk<'_> {
+    fn wanted(&self, rowid: i64) -> bool {
+        (self.low..=self.high).contains(&rowid)
+            && self.keys.as_ref().is_none_or(|keys| keys.binary_search(&rowid).is_ok())
+    }
+
+    /// Whether a child holding the rowids `after + 1..=through` can hold one
+    /// the walk wants.
+    fn wants_between(&self, after: Option<i64>, through: Option<i64>) -> bool {
+        let (after, through) = (after.unwrap_or(i64::MIN), through.unwrap_or(i64::MAX));
+        if after >= self.high || through < self.low {
+            return false;
+        }
+        match &self.keys {
+            Some(keys) => keys.get(keys.partition_point(|&key| key <= after)).is_some_and(|&key| key <= through),
+            None => true,
+        }
+    }
+
+    /// Steps for one page in rowid order, leaving out what the walk rules out.
+    /// A cell's left child holds the rowids after the previous cell's, up to
+    /// and including its own; the right-most child the rest.
+    fn expand(&self, page_num: usize) -> Result<Vec<Step<LeafTableCell>>> {
+        let mut steps = Vec::new();
+        match self.db.read_page(page_num)? {
+            Page::InteriorTable { rmptr, cells } => {
+                let mut previous = None;
+                for cell in &cells {
+                    let rowid = cell.row_id as i64;
+                    if self.wants_between(previous, Some(rowid)) {
+                        steps.push(Step::Page(cell.left_child as usize));
+                    }
+                    previous = Some(rowid);
+                }
+                if self.wants_between(previous, None) {
+                    steps.push(Step::Page(rmptr as usize));
+                }
+            }
+            Page::LeafTable { cells } => {
+                steps.extend(cells.into_iter().filter(|cell| self.wanted(cell.row_id as i64)).map(Step::Entry));
+            }
+            _ => Err(malformed(Some(page_num), msg!("not-a-table-page", page = page_num)))?,
+        }
+        Ok(steps)
+    }
+}
+
+impl Iterator for TableWalk<'_> {
+    type Item = Result<LeafTableCell>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(step) = self.stack.pop() {
+            match step {
+                Step::Entry(cell) => return Some(Ok(cell)),
+                Step::Page(page_num) => match check_children(self.expand(page_num), page_num, &mut self.walk) {
+                    Ok(steps) => self.stack.extend(steps.into_iter().rev()),
+                    Err(e) => {
+                        self.stack.clear();
+                        return Some(Err(e));
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+/// The steps of `page_num` once every child page among them has passed
+/// [`Walk::check_child`].
+fn check_children<T>(steps: Result<Vec<Step<T>>>, page_num: usize, walk: &mut Walk) -> Result<Vec<Step<T>>> {
+    let steps = steps?;
+    for step in &steps {
+        if let Step::Page(child) = step {
+            walk.check_child(page_num, *child)?;
+        }
+    }
+    Ok(steps)
+}
+
+/// A row's place in its table's key order: the rowid, or the primary key of a
+/// WITHOUT ROWID table. Written as `rowid 42` or `key 'a', 7`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowKey {
+    Rowid(i64),
+    PrimaryKey(Vec<Record>),
+}
+
+impl Display for RowKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RowKey::Rowid(rowid) => write!(f, "rowid {}", rowid),
+            RowKey::PrimaryKey(key) => write!(f, "key {}", key.iter().map(functions::quote).join(", ")),
+        }
+    }
+}
+
+impl FromStr for RowKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(rowid) = s.strip_prefix("rowid ") {
+            return Ok(RowKey::Rowid(rowid.trim().parse()?));
+        }
+        match s.strip_prefix("key ").map(parse_literals) {
+            Some(Ok(key)) if !key.is_empty() => Ok(RowKey::PrimaryKey(key)),
+            _ => Err(anyhow!(msg!("invalid-row-key", key = s))),
+        }
+    }
+}
+
+/// Receives the rows of a table scan with their keys; `Break` stops the scan.
+type KeyedRowCallback<'a> = dyn FnMut(RowKey, Vec<Record>) -> Result<ControlFlow<()>> + 'a;
+
+/// A table as declared by its CREATE TABLE statement.
+struct Table {
+    columns: Vec<ColumnDef>,
+    /// For WITHOUT ROWID tables, the column each record field holds.
+    key_order: Option<Vec<usize>>,
+    /// For WITHOUT ROWID tables, how many leading record fields are the primary key.
+    key_len: usize,
+    constraints: Vec<TableConstraint>,
+    strict: bool,
+}
+
+/// One table of a SELECT's FROM clause. Joined rows hold the columns of every
+/// table in FROM order.
+struct Source {
+    name: String,
+    /// The alias, or the table name when there is none.
+    qualifier: String,
+    rootpage: usize,
+    table: Table,
+    /// Where the table's columns start in a joined row.
+    offset: usize,
+    lookup: Option<Lookup>,
+    /// The columns the statement uses, when it does not use them all; the
+    /// others are not decoded.
+    wanted: Option<Vec<bool>>,
+    /// The attached database the table is in; `None` for the main one.
+    db: Option<Arc<Database>>,
+    /// For a view, its rows, read when the statement was planned.
+    view: Option<Arc<[Vec<Record>]>>,
+}
+
+/// Finds an inner table's rows matching the value at `outer` in the joined row
+/// built so far, instead of scanning the whole table for each outer row.
+struct Lookup {
+    probe: Probe,
+    outer: usize,
+    /// The affinity the outer value takes before it is looked up.
+    affinity: Option<Affinity>,
+}
+
+enum Probe {
+    /// The join column is the rowid.
+    Rowid,
+    /// Root page of an index on the join column.
+    Index(usize),
+}
+
+/// Value of an expression for the row or group a condition is tested against.
+type Evaluator<'a> = dyn FnMut(&Expr) -> Result<Record> + 'a;
+
+/// Aggregate state of one GROUP BY group.
+struct Group {
+    key: Vec<Record>,
+    accumulators: Vec<Accumulator>,
+    last_row: Vec<Record>,
+}
+
+impl Group {
+    fn new(key: Vec<Record>, calls: &[&Expr]) -> Result<Self> {
+        let accumulators = calls
+            .iter()
+            .map(|call| match call {
+                Expr::Function {
+                    name,
+                    args,
+                    distinct,
+                } => Accumulator::new(name, args.len(), *distinct),
+                _ => unreachable!(),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            key,
+            accumulators,
+            last_row: Vec::new(),
+        })
+    }
+}
+
+/// Receives the rows of a scan one at a time; `Break` stops the scan.
+type RowCallback<'a> = dyn FnMut(Vec<Record>) -> Result<ControlFlow<()>> + 'a;
+
+/// An index as declared by its CREATE INDEX statement. Its records hold the
+/// key columns in order, followed by the rowid.
+struct Index {
+    name: String,
+    table: String,
+    columns: Vec<IndexColumn>,
+    rootpage: usize,
+    unique: bool,
+    partial: bool,
+}
+
+/// Keeps the rows of a result set, for [`Database::query`].
+#[derive(Default)]
+struct RowCollector {
+    names: Rc<[String]>,
+    rows: Vec<Row>,
+}
+
+impl RowWriter for RowCollector {
+    fn write_header(&mut self, names: &[String]) -> Result<()> {
+        self.names = names.into();
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: &[Record]) -> Result<()> {
+        self.rows.push(Row::new(self.names.clone(), row.to_vec()));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
 
 pub struct Database {
-    db: File,
+    db: Box<dyn PageSource>,
     page_size: usize,
+    /// Bytes of each page that b-tree cells may use: the page size less the
+    /// bytes reserved at the end of every page.
+    usable_size: usize,
     schema: Vec<Schema>,
+    indexes: Vec<Index>,
+    /// Header field at offset 44; formats below 4 predate DESC indexes and
+    /// the 0/1 serial types.
+    schema_format: u32,
+    rng: Prng,
+    text_encoding: TextEncoding,
+    text_decoding: TextDecoding,
+    max_rows: Option<usize>,
+    case_folding: CaseFolding,
+    /// The file the database was opened from. Transactions keep their
+    /// journal next to it, or in memory when there is none.
+    path: Option<String>,
+    /// The journal of the open transaction, from its first write on.
+    journal: Mutex<Option<Journal>>,
+    /// The file locks of the open transaction, taken with its journal.
+    write_lock: Mutex<Option<WriteLock>>,
+    /// Set between BEGIN and COMMIT or ROLLBACK, when statements no longer
+    /// commit on their own.
+    in_transaction: AtomicBool,
+    /// Bumped by every write to the file, a rollback's included, so that
+    /// cursors can tell the pages they hold may be stale.
+    writes: AtomicU64,
+    /// Where every write is logged before it is made, when set.
+    audit_log: Option<Arc<AuditLog>>,
+    /// Where the pages each statement reads are traced, when set.
+    read_trace: Option<ReadTrace>,
+    /// Whether result columns are rendered by their declared types.
+    smart_rendering: bool,
+    /// Threads that share a full scan of a table, when more than one.
+    scan_threads: Option<usize>,
+    /// The app the database is known to come from.
+    profile: Option<&'static Profile>,
+    /// Databases opened with ATTACH, by the name queries qualify their
+    /// tables with.
+    attached: Vec<(String, Arc<Database>)>,
+    /// Views whose rows are being read, innermost last, to catch one that
+    /// reads itself.
+    expanding: Mutex<Vec<String>>,
 }
 
+// Handles are shared between threads, by parallel scans among others.
+const _: fn() = || {
+    fn shareable<T: Send + Sync>() {}
+    shareable::<Database>();
+};
+
 impl Database {
+    /// Opens the database file at `path` for reading and writing, or only for
+    /// reading when that is all its permissions allow.
+    /// A journal left behind by a transaction that never finished is played
+    /// back first.
     pub fn load_db(path: String) -> Result<Database> {
-        let file = File::open(&path)?;
+        Self::load(path, Ok, false)
+    }
+
+    /// Opens the database at `path` like [`Database::load_db`], even when
+    /// the file is shorter than its header says, for reading what is left:
+    /// tables whose pages are all there read as usual, and reading a page
+    /// past the end fails with [`Error::CorruptPage`].
+    pub fn load_db_partial(path: String) -> Result<Database> {
+        Self::load(path, Ok, true)
+    }
+
+    /// Opens the database at `path` like [`Database::load_db`], reading the
+    /// file through a memory map instead of a system call per page.
+    #[cfg(unix)]
+    pub fn load_db_mapped(path: String) -> Result<Database> {
+        Self::load(path, Mapped::new, false)
+    }
+
+    fn load<S: PageSource + 'static>(path: String, source: fn(File) -> Result<S>, partial: bool) -> Result<Database> {
+        let mut source: Box<dyn PageSource> = match OpenOptions::new().read(true).write(true).open(&path) {
+            Ok(file) => Box::new(source(file)?),
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                Box::new(ReadOnly(source(File::open(&path).map_err(Error::Io)?)?))
+            }
+            Err(e) => Err(Error::Io(e))?,
+        };
+        // A journal no writer holds on to is what a crashed one left, and
+        // playing it back needs the file to itself.
+        if let Ok(journal) = OpenOptions::new().read(true).write(true).open(format!("{}-journal", path)) {
+            let lock = SharedLock::acquire(OpenOptions::new().read(true).write(true).open(&path)?)?;
+            if !lock.writer_active()? && journal::is_hot(&format!("{}-journal", path))? {
+                let mut lock = WriteLock::new(lock);
+                lock.exclusive()?;
+                if journal::play_back(&journal, source.as_ref())? {
+                    journal.truncate(0)?;
+                    journal.sync()?;
+                }
+            }
+        }
+        // In WAL mode the latest commits may not have reached the file yet.
+        let mut header = [0; DB_HEADER_SIZE];
+        if source.read_exact_at(&mut header, 0).is_ok() && (header[18] == 2 || header[19] == 2) {
+            source = Box::new(WalSource::open(source, &path, header_page_size(&header))?);
+        }
+        let mut db = Self::open_with(source, partial)?;
+        db.path = Some(path);
+        Ok(db)
+    }
+
+    /// Opens a database held entirely in memory, with no filesystem involved.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Database> {
+        Self::open(Box::new(RwLock::new(bytes)))
+    }
+
+    /// Opens the database whose bytes `source` reads. An empty source is an
+    /// empty database, as it is to sqlite, and one shorter than its header
+    /// says fails with [`Error::TruncatedDatabase`].
+    pub fn open(source: Box<dyn PageSource>) -> Result<Database> {
+        Self::open_with(source, false)
+    }
+
+    fn open_with(source: Box<dyn PageSource>, partial: bool) -> Result<Database> {
+        let size = source.size()?;
+        // The first CREATE TABLE writes the header of an empty database.
+        let (page_size, usable_size, schema_format, text_encoding) = match size {
+            0 => (EMPTY_PAGE_SIZE, EMPTY_PAGE_SIZE, 4, TextEncoding::Utf8),
+            _ => {
+                let mut db_header = [0; DB_HEADER_SIZE];
+                source
+                    .read_exact_at(&mut db_header, 0)
+                    .map_err(|_| anyhow!(msg!("not-a-database")))?;
+                if !db_header.starts_with(b"SQLite format 3\0") {
+                    Err(anyhow!(msg!("not-a-database")))?
+                }
+                let page_size = header_page_size(&db_header);
+                let expected = expected_size(&db_header, page_size, size);
+                if expected > size && !partial {
+                    Err(Error::TruncatedDatabase { expected, actual: size })?
+                }
+                let schema_format =
+                    u32::from_be_bytes([db_header[44], db_header[45], db_header[46], db_header[47]]);
+                // A database without any schema yet may still have format 0.
+                if schema_format > 4 {
+                    Err(Error::UnsupportedFeature(msg!("unsupported-schema-format", format = schema_format)))?
+                }
+                let text_encoding = TextEncoding::from_header(u32::from_be_bytes([
+                    db_header[56],
+                    db_header[57],
+                    db_header[58],
+                    db_header[59],
+                ]))?;
+                (page_size, page_size - db_header[20] as usize, schema_format, text_encoding)
+            }
+        };
+
+        let mut db = Database {
+            db: source,
+            page_size,
+            usable_size,
+            schema: Vec::new(),
+            indexes: Vec::new(),
+            schema_format,
+            rng: Prng::new(),
+            text_encoding,
+            text_decoding: TextDecoding::Strict,
+            max_rows: None,
+            case_folding: CaseFolding::Ascii,
+            path: None,
+            journal: Mutex::new(None),
+            write_lock: Mutex::new(None),
+            in_transaction: AtomicBool::new(false),
+            writes: AtomicU64::new(0),
+            audit_log: None,
+            read_trace: None,
+            smart_rendering: false,
+            scan_threads: None,
+            profile: None,
+            attached: Vec::new(),
+            expanding: Mutex::new(Vec::new()),
+        };
+        if size > 0 {
+            (db.schema, db.indexes) = db.load_schema()?;
+        }
+        Ok(db)
+    }
+
+    /// Reads the schema again, after a statement changed it.
+    fn reload_schema(&mut self) -> Result<()> {
+        self.usable_size = self.page_size - (self.header_field(20)? >> 24) as usize;
+        self.schema_format = self.header_field(44)?;
+        self.text_encoding = TextEncoding::from_header(self.header_field(56)?)?;
+        (self.schema, self.indexes) = self.load_schema()?;
+        Ok(())
+    }
+
+    /// The entries of `sqlite_schema`, read like the rows of any other table,
+    /// and the indexes among them that parse.
+    fn load_schema(&self) -> Result<(Vec<Schema>, Vec<Index>)> {
+        let schema = self
+            .table_walk(1, (i64::MIN, i64::MAX), None)
+            .map(|cell| {
+                let cell = cell?;
+                schema_entry(cell.row_id, cell.record.values()?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let indexes = schema
+            .iter()
+            .filter(|s| s.kind == schema::Kind::Index)
+            .filter_map(|s| match parse_sql(&s.sql) {
+                Ok(Statement::CreateIndex {
+                    table,
+                    columns,
+                    unique,
+                    partial,
+                    ..
+                }) => Some(Index {
+                    name: s.name.clone(),
+                    table,
+                    // Before format 4 every index is stored ascending, DESC or not.
+                    columns: columns
+                        .into_iter()
+                        .map(|column| IndexColumn {
+                            descending: column.descending && self.schema_format >= 4,
+                            ..column
+                        })
+                        .collect(),
+                    rootpage: s.rootpage,
+                    unique,
+                    partial,
+                }),
+                _ => None,
+            })
+            .collect();
+        Ok((schema, indexes))
+    }
+
+    /// Chooses how text values that are not valid UTF-8 are read.
+    pub fn set_text_decoding(&mut self, decoding: TextDecoding) {
+        self.text_decoding = decoding;
+    }
+
+    pub fn text_decoding(&self) -> TextDecoding {
+        self.text_decoding
+    }
+
+    pub fn case_folding(&self) -> CaseFolding {
+        self.case_folding
+    }
+
+    /// Shows the selected columns declared BOOLEAN, DATE, DATETIME or
+    /// TIMESTAMP in a friendlier form in table and json output; see
+    /// [`Rendering`].
+    pub fn set_smart_rendering(&mut self, smart: bool) {
+        self.smart_rendering = smart;
+    }
 
+    /// Chooses how LIKE and NOCASE fold letters; see [`CaseFolding`].
+    pub fn set_case_folding(&mut self, folding: CaseFolding) {
+        self.case_folding = folding;
+    }
+
+    /// The file the database was opened from; `None` for one in memory.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Whether writes are refused because the file could only be opened for reading.
+    pub fn is_read_only(&self) -> bool {
+        self.db.is_read_only()
+    }
+
+    /// Takes over `other`'s text decoding, row cap, case folding and audit
+    /// log, so that a database opened later in a session reads like the
+    /// first one and has its writes logged to the same place.
+    pub fn copy_settings(&mut self, other: &Database) {
+        self.text_decoding = other.text_decoding;
+        self.max_rows = other.max_rows;
+        self.case_folding = other.case_folding;
+        self.audit_log = other.audit_log.clone();
+        self.smart_rendering = other.smart_rendering;
+        self.scan_threads = other.scan_threads;
+        self.profile = other.profile;
+    }
+
+    /// Logs every write from now on to `log`; see [`AuditLog`].
+    pub fn set_audit_log(&mut self, log: Option<AuditLog>) {
+        self.audit_log = log.map(Arc::new);
+    }
+
+    /// Traces the pages of this database that each statement reads from now
+    /// on to `trace`; see [`ReadTrace`]. With parallel scans the reads of
+    /// the threads come interleaved.
+    pub fn set_read_trace(&mut self, trace: Option<ReadTrace>) {
+        self.read_trace = trace;
+    }
+
+    /// Splits full scans of a table across `threads` threads, for aggregates
+    /// over large tables; rows still come in rowid order. Only on Unix.
+    pub fn set_scan_parallel(&mut self, threads: Option<usize>) {
+        self.scan_threads = threads.filter(|&threads| threads > 1);
+    }
+
+    /// Reads the database as one written by the app `profile` describes,
+    /// rendering the columns it knows along with those smart rendering does.
+    pub fn set_profile(&mut self, profile: Option<&'static Profile>) {
+        self.profile = profile;
+    }
+
+    pub fn profile(&self) -> Option<&'static Profile> {
+        self.profile
+    }
+
+    /// Stops every query after it has produced `max` rows.
+    pub fn set_max_rows(&mut self, max: Option<usize>) {
+        self.max_rows = max;
+    }
+
+    /// Fixes the seed used by `random()` and `randomblob()`.
+    pub fn set_seed(&self, seed: u64) {
+        self.rng.reseed(seed);
+    }
+
+    /// Number of pages in the database. The header's count is only trusted
+    /// when the same writer also stamped its version-valid-for field;
+    /// otherwise it is worked out from the size of the file.
+    pub fn page_count(&self) -> Result<usize> {
         let mut db_header = [0; DB_HEADER_SIZE];
-        file.read_at(&mut db_header, 0)?;
-        let page_size = u16::from_be_bytes([db_header[16], db_header[17]]);
+        if let Err(e) = self.db.read_exact_at(&mut db_header, 0) {
+            // An empty file is a database of no pages.
+            return match self.db.size()? {
+                0 => Ok(0),
+                _ => Err(e),
+            };
+        }
+        let field = |at: usize| {
+            u32::from_be_bytes([db_header[at], db_header[at + 1], db_header[at + 2], db_header[at + 3]])
+        };
+        let (change_counter, in_header, valid_for) = (field(24), field(28), field(92));
+        if in_header != 0 && change_counter == valid_for {
+            return Ok(in_header as usize);
+        }
+        Ok((self.db.size()? / self.page_size as u64) as usize)
+    }
+
+    /// The bytes of page `page_num` exactly as stored, counting from 1.
+    /// Page 1 starts with the 100-byte database header.
+    pub fn raw_page(&self, page_num: usize) -> Result<Vec<u8>> {
+        let page_count = self.page_count()?;
+        if page_num == 0 || page_num > page_count {
+            Err(anyhow!(
+                "page {} is out of range (the database has {} pages)",
+                page_num,
+                page_count
+            ))?
+        }
+        self.read_raw_page(page_num)
+    }
+
+    /// The file change counter, bumped by every transaction that writes.
+    pub fn change_counter(&self) -> Result<u32> {
+        self.header_field(24)
+    }
+
+    /// The schema cookie, bumped by every change to the schema.
+    pub fn schema_cookie(&self) -> Result<u32> {
+        self.header_field(40)
+    }
+
+    /// How many times the file has been written to since it was opened.
+    pub(super) fn writes(&self) -> u64 {
+        self.writes.load(atomic::Ordering::SeqCst)
+    }
+
+    fn header_field(&self, offset: usize) -> Result<u32> {
+        let mut field = [0; 4];
+        self.db.read_exact_at(&mut field, offset as u64)?;
+        Ok(u32::from_be_bytes(field))
+    }
+
+    /// The leaf pages of `table` in rowid order. Pages are only read as the
+    /// iterator reaches them; the first error ends it, and a write to the
+    /// database before the last page fails it with [`Error::CursorInvalidated`].
+    pub fn table_pages<'a>(&'a self, table: &str) -> impl Iterator<Item = Result<PageRef>> + 'a {
+        let start = match self.get_table(table) {
+            Ok(Table { key_order: Some(_), .. }) => Err(anyhow!(msg!("is-without-rowid", table = table))),
+            Ok(_) => self.get_table_rootpage(table),
+            Err(e) => Err(e),
+        };
+        let (mut pending, mut stack) = match start {
+            Ok(rootpage) => (None, vec![rootpage]),
+            Err(e) => (Some(e), Vec::new()),
+        };
+        let writes = self.writes();
+
+        std::iter::from_fn(move || {
+            if let Some(e) = pending.take() {
+                return Some(Err(e));
+            }
+            if !stack.is_empty() && self.writes() != writes {
+                stack.clear();
+                return Some(Err(Error::CursorInvalidated.into()));
+            }
+            while let Some(number) = stack.pop() {
+                match self.read_page(number) {
+                    Ok(Page::LeafTable { cells }) => return Some(Ok(PageRef { number, cells })),
+                    Ok(Page::InteriorTable { rmptr, cells }) => {
+                        stack.push(rmptr as usize);
+                        stack.extend(cells.iter().rev().map(|cell| cell.left_child as usize));
+                    }
+                    Ok(_) => {
+                        stack.clear();
+                        return Some(Err(malformed(Some(number), msg!("not-a-table-page", page = number))));
+                    }
+                    Err(e) => {
+                        stack.clear();
+                        return Some(Err(e));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Splits the rowids of `table` into at most `parts` inclusive ranges that
+    /// together cover every rowid, cutting at the keys of its interior pages
+    /// so that each range spans about as many leaves. The tree is read from
+    /// the root down only until a level has enough keys to cut at.
+    pub fn rowid_ranges(&self, table: &str, parts: usize) -> Result<Vec<(i64, i64)>> {
+        if let Table { key_order: Some(_), .. } = self.get_table(table)? {
+            Err(anyhow!(msg!("is-without-rowid", table = table)))?
+        }
+        self.tree_rowid_ranges(self.get_table_rootpage(table)?, parts)
+    }
+
+    /// [`Database::rowid_ranges`] of the table b-tree rooted at `rootpage`.
+    fn tree_rowid_ranges(&self, rootpage: usize, parts: usize) -> Result<Vec<(i64, i64)>> {
+        let mut level = vec![rootpage];
+        // Keys of the levels above as well: they separate this level's pages.
+        let mut keys = Vec::new();
+        for _ in 0..pages::MAX_DEPTH {
+            if keys.len() + 1 >= parts {
+                break;
+            }
+            let mut children = Vec::new();
+            for &page_num in &level {
+                match self.read_page(page_num)? {
+                    Page::InteriorTable { rmptr, cells } => {
+                        keys.extend(cells.iter().map(|cell| cell.row_id as i64));
+                        children.extend(cells.iter().map(|cell| cell.left_child as usize).chain([rmptr as usize]));
+                    }
+                    Page::LeafTable { .. } => {}
+                    _ => Err(malformed(Some(page_num), msg!("not-a-table-page", page = page_num)))?,
+                }
+            }
+            // Leaves have no keys to cut at; the level above had all there are.
+            if children.is_empty() {
+                break;
+            }
+            level = children;
+        }
+        keys.sort_unstable();
+        keys.dedup();
+        // A range has to start after its cut.
+        keys.retain(|&key| key < i64::MAX);
+
+        let parts = parts.clamp(1, keys.len() + 1);
+        let cuts = (1..parts).map(|part| keys[part * (keys.len() + 1) / parts - 1]);
+        let mut ranges = Vec::new();
+        let mut low = i64::MIN;
+        for cut in cuts {
+            ranges.push((low, cut));
+            low = cut + 1;
+        }
+        ranges.push((low, i64::MAX));
+        Ok(ranges)
+    }
+
+    /// Visits every row of `table` in key order together with its key, starting
+    /// after `after` so that a scan that was cut short can pick up where it
+    /// stopped.
+    pub fn scan_table(&self, table: &str, after: Option<&RowKey>, on_row: &mut KeyedRowCallback) -> Result<()> {
+        let rootpage = self.get_table_rootpage(table)?;
+        let Table {
+            mut columns,
+            key_order,
+            key_len,
+            ..
+        } = self.get_table(table)?;
+
+        let Some(order) = key_order else {
+            let low = match after {
+                None => i64::MIN,
+                Some(RowKey::Rowid(rowid)) => match rowid.checked_add(1) {
+                    Some(low) => low,
+                    None => return Ok(()),
+                },
+                Some(key) => Err(anyhow!(msg!("resume-rowid-table", table = table, key = key)))?,
+            };
+            // Without an INTEGER PRIMARY KEY the rowid rides along in an extra column.
+            let hidden = !columns.iter().any(|c| c.rowid_alias);
+            if hidden {
+                columns.push(ColumnDef::rowid());
+            }
+            let rowid = columns.iter().position(|c| c.rowid_alias).unwrap_or_default();
+            let mut on_row = |mut row: Vec<Record>| {
+                let key = row[rowid].integer().unwrap_or_default();
+                if hidden {
+                    row.pop();
+                }
+                on_row(RowKey::Rowid(key), row)
+            };
+            let _ = self.execute_select_range(rootpage, &columns, None, (low, i64::MAX), &mut on_row)?;
+            return Ok(());
+        };
+
+        let low = match after {
+            None => Bound::Unbounded,
+            Some(RowKey::PrimaryKey(key)) => Bound::Excluded(key.clone()),
+            Some(key) => Err(anyhow!(msg!("resume-without-rowid", table = table, key = key)))?,
+        };
+        // The table b-tree is ordered like an index on its primary key.
+        let key_columns = order[..key_len]
+            .iter()
+            .map(|&idx| IndexColumn {
+                name: columns[idx].name.clone(),
+                collation: columns[idx].collation.clone(),
+                descending: columns[idx].descending,
+            })
+            .collect_vec();
+        for record in self.index_walk(rootpage, &key_columns, (low, Bound::Unbounded), Direction::Forward) {
+            let record = record?;
+            let key = record.iter().take(key_len).cloned().collect();
+            if on_row(RowKey::PrimaryKey(key), without_rowid_values(record, &columns, &order))?.is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Entries of the index `index_name` whose keys lie in `range`, read lazily
+    /// in `direction`. Bounds may name fewer columns than the index has; they
+    /// then match every key starting with those values. Keys compare in the
+    /// index's own order, so DESC and NOCASE columns are honoured.
+    pub fn index_entries<'a>(
+        &'a self,
+        index_name: &str,
+        range: impl RangeBounds<Vec<Record>>,
+        direction: Direction,
+    ) -> impl Iterator<Item = Result<IndexEntry>> + 'a {
+        let index = match self.indexes.iter().find(|index| index.name.eq_ignore_ascii_case(index_name)) {
+            Some(index) => match self.get_table(&index.table) {
+                Ok(Table { key_order: Some(_), .. }) => {
+                    Err(anyhow!(msg!("index-on-without-rowid", index = index_name)))
+                }
+                Ok(_) => Ok(index),
+                Err(e) => Err(e),
+            },
+            None => Err(anyhow!(msg!("no-such-index", name = index_name))),
+        };
+        let (pending, walk) = match index {
+            Ok(index) => (
+                None,
+                Some(self.index_walk(index.rootpage, &index.columns, range, direction)),
+            ),
+            Err(e) => (Some(e), None),
+        };
+
+        pending
+            .map(Err)
+            .into_iter()
+            .chain(walk.into_iter().flatten().map(|key| {
+                let mut key = key?;
+                match key.pop().as_ref().and_then(Record::integer) {
+                    Some(rowid) => Ok(IndexEntry { key, rowid }),
+                    None => Err(anyhow!(msg!("index-entry-without-rowid"))),
+                }
+            }))
+    }
+
+    fn index_walk<'a>(
+        &'a self,
+        rootpage: usize,
+        columns: &'a [IndexColumn],
+        range: impl RangeBounds<Vec<Record>>,
+        direction: Direction,
+    ) -> IndexWalk<'a> {
+        IndexWalk {
+            db: self,
+            columns,
+            low: range.start_bound().cloned(),
+            high: range.end_bound().cloned(),
+            direction,
+            stack: vec![Step::Page(rootpage)],
+            walk: Walk::default(),
+        }
+    }
+
+    /// A walk over the cells of the table b-tree rooted at `rootpage` whose
+    /// rowids are in `low..=high` and, given `keys`, among them.
+    fn table_walk(&self, rootpage: usize, (low, high): (i64, i64), keys: Option<&[usize]>) -> TableWalk<'_> {
+        TableWalk {
+            db: self,
+            low,
+            high,
+            keys: keys.map(|keys| keys.iter().map(|&key| key as i64).sorted_unstable().collect()),
+            stack: vec![Step::Page(rootpage)],
+            walk: Walk::default(),
+        }
+    }
+
+    /// Visits the rows of the cells `cells` yields.
+    fn visit_cells(
+        &self,
+        cells: TableWalk,
+        columns: &[ColumnDef],
+        wanted: Option<&[bool]>,
+        on_row: &mut RowCallback,
+    ) -> Result<ControlFlow<()>> {
+        for cell in cells {
+            if on_row(row_values(cell?, columns, wanted)?)?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// The columns of `table` in declaration order.
+    pub fn columns(&self, table: &str) -> Result<Vec<ColumnInfo>> {
+        let schema = self.get_schema(table)?;
+        let Statement::CreateTable {
+            columns,
+            constraints,
+            without_rowid,
+            ..
+        } = parse_sql(&schema.sql)?
+        else {
+            Err(anyhow!(msg!("invalid-table-schema")))?
+        };
+        let table_pk = constraints.iter().find_map(|c| match c {
+            TableConstraint::PrimaryKey(pk) => Some(pk),
+            _ => None,
+        });
+
+        let mut column_pk = 0;
+        Ok(columns
+            .iter()
+            .enumerate()
+            .map(|(cid, column)| {
+                let pk = match table_pk {
+                    Some(pk) => pk
+                        .iter()
+                        .position(|name| name.eq_ignore_ascii_case(&column.name))
+                        .map_or(0, |idx| idx + 1),
+                    None if column.primary_key => {
+                        column_pk += 1;
+                        column_pk
+                    }
+                    None => 0,
+                };
+                ColumnInfo {
+                    cid,
+                    name: column.name.clone(),
+                    declared_type: column.declared_type(),
+                    affinity: column.affinity(),
+                    // WITHOUT ROWID tables refuse NULL keys, declared or not.
+                    not_null: column.not_null || (without_rowid && pk > 0),
+                    default: column.default.clone(),
+                    pk,
+                }
+            })
+            .collect())
+    }
+
+    /// The database header and schema summary, laid out like sqlite3's `.dbinfo`.
+    pub fn info(&self) -> Result<()> {
+        let mut db_header = [0; DB_HEADER_SIZE];
+        self.db
+            .read_exact_at(&mut db_header, 0)
+            .map_err(|_| anyhow!(msg!("unreadable-header")))?;
+        let field = |at: usize| {
+            u32::from_be_bytes([db_header[at], db_header[at + 1], db_header[at + 2], db_header[at + 3]])
+        };
+        let encoding = match self.text_encoding {
+            TextEncoding::Utf8 => "utf8",
+            TextEncoding::Utf16le => "utf16le",
+            TextEncoding::Utf16be => "utf16be",
+        };
+        let schema_size: usize = self.schema.iter().map(|s| s.sql.len()).sum();
+
+        println!("database page size:  {}", self.page_size);
+        println!("write format:        {}", db_header[18]);
+        println!("read format:         {}", db_header[19]);
+        println!("reserved bytes:      {}", db_header[20]);
+        println!("file change counter: {}", field(24));
+        println!("database page count: {}", self.page_count()?);
+        println!("freelist page count: {}", field(36));
+        println!("schema cookie:       {}", field(40));
+        println!("schema format:       {}", field(44));
+        println!("default cache size:  {}", field(48));
+        println!("autovacuum top root: {}", field(52));
+        println!("incremental vacuum:  {}", field(64));
+        println!("text encoding:       {} ({})", field(56), encoding);
+        println!("user version:        {}", field(60));
+        println!("application id:      {}", field(68));
+        println!("software version:    {}", field(96));
+        println!("number of tables:    {}", self.schema_count(schema::Kind::Table));
+        println!("number of indexes:   {}", self.schema_count(schema::Kind::Index));
+        println!("number of triggers:  {}", self.schema_count(schema::Kind::Trigger));
+        println!("number of views:     {}", self.schema_count(schema::Kind::View));
+        println!("schema size:         {}", schema_size);
+        // Only ever changes while the database is open; a fresh reader sees 1.
+        println!("data version         1");
+        Ok(())
+    }
+
+    /// The rows of `sqlite_schema`, in the order the file has them.
+    pub fn schema(&self) -> &[Schema] {
+        &self.schema
+    }
+
+    pub fn tables(&self) -> Result<()> {
+        for schema in &self.schema {
+            if matches!(schema.kind, schema::Kind::Table | schema::Kind::View) {
+                println!("{}", schema.name);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn schema_sql(&self, filter: Option<&str>) -> Result<()> {
+        for schema in &self.schema {
+            let matches = filter.is_none_or(|name| {
+                schema.name.eq_ignore_ascii_case(name) || schema.tbl_name.eq_ignore_ascii_case(name)
+            });
+            if matches && !schema.sql.is_empty() {
+                println!("{};", schema.sql);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the statement `sql` and collects the rows it produces.
+    pub fn query(&mut self, sql: &str) -> Result<Vec<Row>> {
+        let mut rows = RowCollector::default();
+        self.execute_statement(&parse_sql(sql)?, &mut rows)?;
+        Ok(rows.rows)
+    }
+
+    /// Runs the statement `sql` and converts each row it produces into a `T`.
+    pub fn query_as<T: FromRow>(&mut self, sql: &str) -> Result<Vec<T>> {
+        self.query(sql)?.iter().map(T::from_row).collect()
+    }
+
+    /// Fixes what the main and every attached database read until the next
+    /// statement.
+    fn pin_snapshots(&self) -> Result<()> {
+        self.db.pin_snapshot()?;
+        for (_, db) in &self.attached {
+            db.db.pin_snapshot()?;
+        }
+        Ok(())
+    }
+
+    /// Runs `statement`, writing the rows it produces to `out`. The pages it
+    /// read go to the read trace, if there is one, even when it fails.
+    pub fn execute_statement(&mut self, statement: &Statement, out: &mut dyn RowWriter) -> Result<()> {
+        let result = self.run_statement(statement, out);
+        match &self.read_trace {
+            Some(trace) => result.and(trace.end_statement()),
+            None => result,
+        }
+    }
+
+    fn run_statement(&mut self, statement: &Statement, out: &mut dyn RowWriter) -> Result<()> {
+        self.pin_snapshots()?;
+        match statement {
+            Statement::Select { .. } => {
+                let plan = self.plan_select(statement)?;
+                self.execute_select_plan(&plan, self.max_rows, out)?;
+            }
+            Statement::Compound { .. } => self.execute_compound(statement, out)?,
+            Statement::Explain(statement) => self.explain(statement, out)?,
+            Statement::Pragma { name, arg } => self.execute_pragma(name, arg.as_deref(), out)?,
+            Statement::Update {
+                table,
+                assignments,
+                condition,
+            } => {
+                self.autocommit(|| self.execute_update(table, assignments, condition.as_ref()))?;
+            }
+            Statement::CreateTable { .. } => {
+                self.autocommit(|| self.execute_create_table(statement))?;
+                self.reload_schema()?;
+            }
+            Statement::CreateIndex { .. } => {
+                self.autocommit(|| self.execute_create_index(statement))?;
+                self.reload_schema()?;
+            }
+            Statement::RenameTable { table, new_name } => {
+                self.autocommit(|| self.execute_rename_table(table, new_name))?;
+                self.reload_schema()?;
+            }
+            Statement::Vacuum => {
+                if self.in_transaction.load(atomic::Ordering::Relaxed) {
+                    Err(anyhow!(msg!("vacuum-in-transaction")))?
+                }
+                self.autocommit(|| self.execute_vacuum())?;
+                self.reload_schema()?;
+            }
+            Statement::CreateView { view, .. } => {
+                Err(Error::UnsupportedFeature(msg!("create-view-unsupported", name = view)))?
+            }
+            Statement::Attach { path, name } => self.execute_attach(path, name)?,
+            Statement::Detach { name } => self.execute_detach(name)?,
+            Statement::Begin => {
+                if self.in_transaction.swap(true, atomic::Ordering::Relaxed) {
+                    Err(anyhow!(msg!("nested-transaction")))?
+                }
+            }
+            Statement::Commit => {
+                if !self.in_transaction.load(atomic::Ordering::Relaxed) {
+                    Err(anyhow!(msg!("commit-without-transaction")))?
+                }
+                self.commit()?;
+                self.in_transaction.store(false, atomic::Ordering::Relaxed);
+            }
+            Statement::Rollback => {
+                if !self.in_transaction.load(atomic::Ordering::Relaxed) {
+                    Err(anyhow!(msg!("rollback-without-transaction")))?
+                }
+                self.roll_back()?;
+                self.in_transaction.store(false, atomic::Ordering::Relaxed);
+                self.reload_schema()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How smart rendering shows each result column of `plan`: as the
+    /// profile has it or else by declared type, for the ones that are plain
+    /// columns.
+    fn renderings(&self, plan: &SelectPlan) -> Vec<Option<Rendering>> {
+        plan.selected
+            .iter()
+            .map(|e| {
+                let Expr::Column(column) = e else {
+                    return None;
+                };
+                let idx = plan.columns.iter().position(|c| c.name.eq_ignore_ascii_case(&column.name))?;
+                let profiled = self.profile.and_then(|profile| {
+                    let source = plan.sources.iter().rfind(|s| s.offset <= idx)?;
+                    profile.rendering(&source.name, &source.table.columns[idx - source.offset].name)
+                });
+                profiled.or_else(|| Rendering::for_declared_type(&plan.columns[idx].declared_type()))
+            })
+            .collect()
+    }
+
+    /// Runs a planned SELECT, writing at most `max_rows` of its rows to `out`.
+    fn execute_select_plan(&self, plan: &SelectPlan, max_rows: Option<usize>, out: &mut dyn RowWriter) -> Result<()> {
+        let SelectPlan {
+            sources,
+            columns,
+            names,
+            selected: selected_columns,
+            condition,
+            group_by,
+            having,
+            access,
+            aggregate,
+        } = plan;
+        let (condition, having, aggregate) = (condition.as_ref(), having.as_ref(), *aggregate);
+        out.write_header(names)?;
+        if self.smart_rendering {
+            out.set_renderings(&self.renderings(plan));
+        }
+
+        let capped = |written: usize| max_rows.is_some_and(|max| written >= max);
+        if let Access::CountRows(range) = access {
+            if !capped(0) {
+                let (on, rootpage) = (self.on(&sources[0]), sources[0].rootpage);
+                let count = match range {
+                    None => on.count_rows(rootpage, &mut Walk::default())?,
+                    Some((low, high)) => on.count_rows_between(rootpage, *low, *high, &mut Walk::default())?,
+                };
+                out.write_row(&vec![Record::Int64(count as i64); selected_columns.len()])?;
+            }
+            return Ok(());
+        }
+
+        let mut calls = Vec::new();
+        selected_columns.iter().for_each(|col| col.aggregate_calls(&mut calls));
+        if let Some(having) = having {
+            having.aggregate_calls(&mut calls);
+        }
+
+        let scan_threads = self.scan_threads.filter(|_| {
+            cfg!(unix)
+                && sources.len() == 1
+                && *access == Access::Scan
+                && sources[0].table.key_order.is_none()
+                && sources[0].view.is_none()
+        });
+        let mut groups: HashMap<GroupKey, Group> = HashMap::new();
+        let mut written = 0;
+        let mut on_row = |row: Vec<Record>| -> Result<ControlFlow<()>> {
+            interrupt::check()?;
+            // The threads of a parallel scan only hand on the rows that match.
+            if let Some(condition) = condition.filter(|_| scan_threads.is_none()) {
+                if !self.matches(condition, columns, &mut |e| self.evaluate(e, columns, &row))? {
+                    return Ok(ControlFlow::Continue(()));
+                }
+            }
+            if aggregate {
+                let key = group_by
+                    .iter()
+                    .map(|e| self.evaluate(e, columns, &row))
+                    .collect::<Result<Vec<_>>>()?;
+                let group = match groups.entry(aggregate::group_key(&key)) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(Group::new(key, &calls)?),
+                };
+                self.step_group(group, &calls, columns, row)?;
+                return Ok(ControlFlow::Continue(()));
+            }
+            if capped(written) {
+                return Ok(ControlFlow::Break(()));
+            }
+
+            let values = selected_columns
+                .iter()
+                .map(|col| self.evaluate(col, columns, &row))
+                .collect::<Result<Vec<_>>>()?;
+            out.write_row(&values)?;
+            written += 1;
+
+            Ok(match capped(written) {
+                true => ControlFlow::Break(()),
+                false => ControlFlow::Continue(()),
+            })
+        };
+
+        // Stopping early is not an error: whatever was written stands.
+        let _ = match (sources.len(), scan_threads) {
+            (1, Some(threads)) => self.scan_parallel(&sources[0], columns, condition, threads, &mut on_row)?,
+            (1, None) => self.visit_source(&sources[0], columns, access, &mut on_row)?,
+            _ => self.execute_join(sources, Vec::new(), &mut on_row)?,
+        };
+
+        if !aggregate {
+            return Ok(());
+        }
+        // Without GROUP BY an empty input still yields one row.
+        if groups.is_empty() && group_by.is_empty() {
+            let mut group = Group::new(Vec::new(), &calls)?;
+            group.last_row = vec![Record::Null; columns.len()];
+            groups.insert(GroupKey::new(), group);
+        }
+
+        let mut groups = groups.into_values().collect_vec();
+        groups.sort_by(|a, b| {
+            a.key
+                .iter()
+                .zip(&b.key)
+                .map(|(a, b)| compare_records(a, b))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+        for group in groups {
+            if capped(written) {
+                break;
+            }
+            let results = group
+                .accumulators
+                .into_iter()
+                .map(Accumulator::finish)
+                .collect::<Result<Vec<_>>>()?;
+            let mut evaluate = |e: &Expr| self.evaluate_grouped(e, &calls, &results, columns, &group.last_row);
+            if let Some(having) = having {
+                if !self.matches(having, columns, &mut evaluate)? {
+                    continue;
+                }
+            }
+
+            let values = selected_columns
+                .iter()
+                .map(&mut evaluate)
+                .collect::<Result<Vec<_>>>()?;
+            out.write_row(&values)?;
+            written += 1;
+        }
+        Ok(())
+    }
+
+    fn execute_pragma(&self, name: &str, arg: Option<&str>, out: &mut dyn RowWriter) -> Result<()> {
+        match (name, arg) {
+            // Like sqlite, no rows for a table that does not exist.
+            ("table_info" | "index_list", Some(table)) if self.get_schema(table).is_err() => Ok(()),
+            ("table_info", Some(table)) => {
+                let header = ["cid", "name", "type", "notnull", "dflt_value", "pk"];
+                out.write_header(&header.map(String::from))?;
+                for column in self.columns(table)? {
+                    let default = match column.default {
+                        Some(default) => Record::Text(pragma_default(&default).to_string()),
+                        None => Record::Null,
+                    };
+                    out.write_row(&[
+                        Record::Int64(column.cid as i64),
+                        Record::Text(column.name),
+                        Record::Text(pragma_type(&column.declared_type)),
+                        Record::Int64(column.not_null as i64),
+                        default,
+                        Record::Int64(column.pk as i64),
+                    ])?;
+                }
+                Ok(())
+            }
+            ("index_list", Some(table)) => self.index_list(table, out),
+            ("index_info", Some(index)) => self.index_info(index, out),
+            ("database_list", None) => self.database_list(out),
+            // One row per problem `.integrity-check` finds, or `ok`.
+            ("integrity_check" | "quick_check", None) => {
+                out.write_header(&["integrity_check".to_string()])?;
+                let problems = self.integrity_check()?;
+                if problems.is_empty() {
+                    out.write_row(&[Record::Text("ok".to_string())])?;
+                }
+                for problem in problems {
+                    out.write_row(&[Record::Text(problem)])?;
+                }
+                Ok(())
+            }
+            // Foreign keys are never enforced, which `.dump` output turns off anyway.
+            ("foreign_keys", None) => {
+                out.write_header(&["foreign_keys".to_string()])?;
+                out.write_row(&[Record::Int64(0)])
+            }
+            ("foreign_keys", Some(value)) if ["off", "false", "no", "0"].iter().any(|off| value.eq_ignore_ascii_case(off)) => Ok(()),
+            ("table_info" | "index_list" | "index_info", None) => Err(anyhow!(msg!("pragma-needs-argument", pragma = name))),
+            // sqlite ignores pragmas it does not know; a typo here should not
+            // look like an answer.
+            _ => Err(anyhow!(msg!("unsupported-pragma", pragma = name))),
+        }
+    }
+
+    /// `PRAGMA index_list`: the indexes of `table`, the most recently
+    /// created first, as sqlite lists them.
+    fn index_list(&self, table: &str, out: &mut dyn RowWriter) -> Result<()> {
+        let table_name = self.get_schema(table)?.name.clone();
+        let autoindexes = self.autoindexes(table)?;
+        let header = ["seq", "name", "unique", "origin", "partial"];
+        out.write_header(&header.map(String::from))?;
+        let mut listed = Vec::new();
+        for entry in self.schema.iter().rev().filter(|s| s.kind == schema::Kind::Index && s.tbl_name.eq_ignore_ascii_case(table)) {
+            match self.indexes.iter().find(|index| index.name.eq_ignore_ascii_case(&entry.name)) {
+                Some(index) => listed.push((entry.name.clone(), index.unique, "c", index.partial)),
+                // The indexes sqlite makes for constraints, which have no SQL.
+                None if entry.sql.is_empty() => {
+                    let origin = autoindex_number(&entry.name).and_then(|n| autoindexes.get(n - 1)).map_or("u", |(origin, _)| origin);
+                    listed.push((entry.name.clone(), true, origin, false));
+                }
+                None => {}
+            }
+        }
+        // The key of a WITHOUT ROWID table is its b-tree, with no row of its own.
+        if self.get_table(table)?.key_order.is_some() {
+            listed.push((format!("sqlite_autoindex_{}_1", table_name), true, "pk", false));
+        }
+        for (seq, (name, unique, origin, partial)) in listed.into_iter().enumerate() {
+            out.write_row(&[
+                Record::Int64(seq as i64),
+                Record::Text(name),
+                Record::Int64(unique as i64),
+                Record::Text(origin.to_string()),
+                Record::Int64(partial as i64),
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// The indexes sqlite makes for the PRIMARY KEY (`pk`) and UNIQUE (`u`)
+    /// constraints of `table`, with their key columns, in the order of their
+    /// numbers: the order the CREATE TABLE declares them, column constraints
+    /// first. A constraint on the same columns as an earlier one shares its
+    /// index, and the rowid needs none.
+    fn autoindexes(&self, table: &str) -> Result<Vec<(&'static str, Vec<String>)>> {
+        let Statement::CreateTable { columns, constraints, .. } = parse_sql(&self.get_schema(table)?.sql)? else {
+            return Ok(Vec::new());
+        };
+        let rowid_key = columns.iter().any(|c| c.rowid_alias);
+        let mut keys = Vec::new();
+        for column in &columns {
+            if column.primary_key && !rowid_key {
+                keys.push(("pk", vec![column.name.clone()]));
+            }
+            if column.unique {
+                keys.push(("u", vec![column.name.clone()]));
+            }
+        }
+        for constraint in &constraints {
+            match constraint {
+                TableConstraint::PrimaryKey(key) if !rowid_key => keys.push(("pk", key.clone())),
+                TableConstraint::Unique(key) => keys.push(("u", key.clone())),
+                _ => {}
+            }
+        }
+        let same = |a: &[String], b: &[String]| a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.eq_ignore_ascii_case(b));
+        let mut autoindexes: Vec<(&'static str, Vec<String>)> = Vec::new();
+        for (origin, key) in keys {
+            if !autoindexes.iter().any(|(_, earlier)| same(earlier, &key)) {
+                autoindexes.push((origin, key));
+            }
+        }
+        Ok(autoindexes)
+    }
+
+    /// `PRAGMA index_info`: the key columns of the index `name`, with their
+    /// positions in its table, `-1` standing for the rowid. Nothing for an
+    /// index that does not exist, as in sqlite.
+    fn index_info(&self, name: &str, out: &mut dyn RowWriter) -> Result<()> {
+        let (table, keys) = match self.indexes.iter().find(|index| index.name.eq_ignore_ascii_case(name)) {
+            Some(index) => (index.table.clone(), index.columns.iter().map(|key| key.name.clone()).collect::<Vec<_>>()),
+            None => {
+                // `sqlite_autoindex_TABLE_N`, whose columns are those of its constraint.
+                let autoindex = name
+                    .get(..17)
+                    .filter(|prefix| prefix.eq_ignore_ascii_case("sqlite_autoindex_"))
+                    .and_then(|_| Some((name[17..].rsplit_once('_')?.0, autoindex_number(name)?)))
+                    .filter(|(table, _)| self.get_schema(table).is_ok());
+                let Some((table, number)) = autoindex else {
+                    return Ok(());
+                };
+                match self.autoindexes(table)?.into_iter().nth(number - 1) {
+                    Some((_, key)) => (table.to_string(), key),
+                    None => return Ok(()),
+                }
+            }
+        };
+        let columns = self.columns(&table)?;
+        let header = ["seqno", "cid", "name"];
+        out.write_header(&header.map(String::from))?;
+        for (seqno, key) in keys.iter().enumerate() {
+            let (cid, name) = match columns.iter().find(|c| c.name.eq_ignore_ascii_case(key)) {
+                Some(column) => (column.cid as i64, Record::Text(column.name.clone())),
+                None => (-1, Record::Null),
+            };
+            out.write_row(&[Record::Int64(seqno as i64), Record::Int64(cid), name])?;
+        }
+        Ok(())
+    }
+
+    /// An index search for the candidate rows for `condition`, when some
+    /// conjunct can be answered from an index. The caller still filters rows
+    /// by the full condition.
+    fn index_access(&self, table: &str, columns: &[ColumnDef], condition: &Condition) -> Option<Access> {
+        let search = |column: &str, ranges: Vec<KeyRange>, equality| {
+            // The index is kept in BINARY order, which NOCASE comparisons cannot use.
+            if is_nocase(column, columns) {
+                return None;
+            }
+            let index = self.get_index(table, column)?;
+            // The keys are stored with the column's affinity, so `num = '5'`
+            // looks for the integer 5.
+            let affinity = columns.iter().find(|c| c.name.eq_ignore_ascii_case(column)).map(ColumnDef::affinity);
+            let key = |bound: Bound<Record>| bound.map(|key| coerce(affinity, key));
+            let ranges = ranges.into_iter().map(|(low, high)| (key(low), key(high))).collect();
+            Some(Access::IndexSearch {
+                index: index.name.clone(),
+                column: column.to_string(),
+                rootpage: index.rootpage,
+                ranges,
+                equality,
+            })
+        };
+        let indexed = |e: &Expr| match e {
+            Expr::Column(column) => Some(column.name.clone()),
+            _ => None,
+        };
+        match condition {
+            Condition::Equals { column, value } => {
+                let key = Record::Text(value.clone());
+                search(column, vec![(Bound::Included(key.clone()), Bound::Included(key))], true)
+            }
+            // Unicode folding lets non-ASCII text match ASCII prefixes.
+            Condition::Like { .. } if self.case_folding == CaseFolding::Unicode => None,
+            Condition::Like { column, pattern } => {
+                let (low, high) = functions::like_prefix_range(pattern)?;
+                let range = (Bound::Included(Record::Text(low)), Bound::Excluded(Record::Text(high)));
+                search(column, vec![range], false)
+            }
+            Condition::Between { expr, low, high } => {
+                let range = (Bound::Included(self.constant(low)?), Bound::Included(self.constant(high)?));
+                search(&indexed(expr)?, vec![range], false)
+            }
+            // One lookup for each value in the list.
+            Condition::In { expr, list } => {
+                let ranges = list
+                    .iter()
+                    .map(|e| self.constant(e).map(|key| (Bound::Included(key.clone()), Bound::Included(key))))
+                    .collect::<Option<Vec<_>>>()?;
+                search(&indexed(expr)?, ranges, true)
+            }
+            Condition::And(a, b) => self
+                .index_access(table, columns, a)
+                .or_else(|| self.index_access(table, columns, b)),
+            Condition::Compare {
+                left,
+                op: CompareOp::Eq,
+                right,
+            } => {
+                let (column, key) = match (indexed(left), indexed(right)) {
+                    (Some(column), None) => (column, self.constant(right)?),
+                    (None, Some(column)) => (column, self.constant(left)?),
+                    _ => return None,
+                };
+                search(&column, vec![(Bound::Included(key.clone()), Bound::Included(key))], true)
+            }
+            Condition::Or(..) | Condition::Not(_) | Condition::Compare { .. } => None,
+        }
+    }
+
+    /// The table `table` names, for a scan starting at column `offset` of the
+    /// joined row.
+    fn source(&self, table: &TableRef, offset: usize) -> Result<Source> {
+        let db = self.catalog(table.schema.as_deref(), &table.name)?;
+        let on = db.as_deref().unwrap_or(self);
+        if let Some(source) = on.view_source(table, offset)? {
+            return Ok(source);
+        }
+        let mut source = Source {
+            name: table.name.clone(),
+            qualifier: table.qualifier().to_string(),
+            rootpage: on.get_table_rootpage(&table.name)?,
+            table: on.get_table(&table.name)?,
+            offset,
+            lookup: None,
+            wanted: None,
+            db,
+            view: None,
+        };
+        // Rowid tables without an INTEGER PRIMARY KEY still answer to `rowid`.
+        let columns = &mut source.table.columns;
+        if source.table.key_order.is_none()
+            && !columns.iter().any(|c| c.rowid_alias || c.name.eq_ignore_ascii_case("rowid"))
+        {
+            columns.push(ColumnDef::rowid());
+        }
+        Ok(source)
+    }
+
+    /// Visits the rows of one table that may match `condition`: those an index
+    /// or a rowid range picks out when the condition allows it, otherwise all
+    /// of them. The caller still filters every row by the condition.
+    fn scan_source(
+        &self,
+        source: &Source,
+        columns: &[ColumnDef],
+        condition: Option<&Condition>,
+        on_row: &mut RowCallback,
+    ) -> Result<ControlFlow<()>> {
+        let access = self.access(source, columns, condition);
+        self.visit_source(source, columns, &access, on_row)
+    }
+
+    /// Visits the rows of one table that `access` picks out.
+    fn visit_source(
+        &self,
+        source: &Source,
+        columns: &[ColumnDef],
+        access: &Access,
+        on_row: &mut RowCallback,
+    ) -> Result<ControlFlow<()>> {
+        if let Some(rows) = &source.view {
+            return visit_rows(rows, on_row);
+        }
+        let (on, rootpage, wanted) = (self.on(source), source.rootpage, source.wanted.as_deref());
+        // Indexes on WITHOUT ROWID tables point at primary keys, not rowids.
+        if let Some(order) = &source.table.key_order {
+            return on.execute_select_without_rowid(rootpage, columns, order, on_row);
+        }
+        match access {
+            Access::Scan => on.execute_select(rootpage, columns, wanted, on_row),
+            Access::RowidRange(low, high) => {
+                on.execute_select_range(rootpage, columns, wanted, (*low, *high), on_row)
+            }
+            Access::Rowids(keys) => on.execute_select_with_index(rootpage, columns, wanted, keys, on_row),
+            Access::IndexSearch {
+                rootpage: index,
+                ranges,
+                ..
+            } => {
+                let keys = on.index_rowids(*index, ranges)?;
+                on.execute_select_with_index(rootpage, columns, wanted, &keys, on_row)
+            }
+            Access::CountRows(_) => unreachable!("counted rows are not visited"),
+        }
+    }
+
+    /// A way to look up `source`'s rows from an equality in `conjuncts` between
+    /// one of its columns and a column of a table before it, when that column
+    /// is the rowid or leads an index.
+    fn join_lookup(&self, source: &Source, columns: &[ColumnDef], conjuncts: &[&Condition]) -> Option<Lookup> {
+        // Indexes on WITHOUT ROWID tables point at primary keys, not rowids.
+        if source.table.key_order.is_some() || source.view.is_some() {
+            return None;
+        }
+        let own = source.offset..source.offset + source.table.columns.len();
+        let position = |e: &Expr| match e {
+            Expr::Column(column) => columns.iter().position(|c| c.name.eq_ignore_ascii_case(&column.name)),
+            _ => None,
+        };
+        conjuncts.iter().find_map(|condition| {
+            let Condition::Compare {
+                left,
+                op: CompareOp::Eq,
+                right,
+            } = condition
+            else {
+                return None;
+            };
+            let (left, right) = (position(left)?, position(right)?);
+            // The lookups compare with BINARY, like the indexes they use.
+            if [left, right].iter().any(|&idx| is_nocase(&columns[idx].name, columns)) {
+                return None;
+            }
+            let (inner, outer) = match (own.contains(&left), own.contains(&right)) {
+                (true, false) => (left, right),
+                (false, true) => (right, left),
+                _ => return None,
+            };
+            if outer >= source.offset {
+                return None;
+            }
+            // Two columns compare as numbers when either is numeric, which
+            // an index of text cannot look up.
+            let numeric = |idx: usize| matches!(columns[idx].affinity(), Affinity::Integer | Affinity::Real | Affinity::Numeric);
+            let affinity = match (numeric(inner), numeric(outer)) {
+                (true, _) => Some(Affinity::Numeric),
+                (false, true) => return None,
+                (false, false) => None,
+            };
+            let column = &source.table.columns[inner - source.offset];
+            let probe = match column.rowid_alias {
+                true => Probe::Rowid,
+                false => Probe::Index(self.on(source).get_index_rootpage(&source.name, &column.name)?),
+            };
+            Some(Lookup { probe, outer, affinity })
+        })
+    }
 
-        let loader = DbLoader::new(file, page_size);
-        let schema = loader.read_schema()?;
+    /// Extends `prefix`, which holds a row of each table before `sources`, with
+    /// every combination of rows of the rest and hands the joined rows to `on_row`.
+    fn execute_join(
+        &self,
+        sources: &[Source],
+        prefix: Vec<Record>,
+        on_row: &mut RowCallback,
+    ) -> Result<ControlFlow<()>> {
+        let Some((source, rest)) = sources.split_first() else {
+            return on_row(prefix);
+        };
+        let mut inner = |row: Vec<Record>| {
+            let mut joined = prefix.clone();
+            joined.extend(row);
+            self.execute_join(rest, joined, on_row)
+        };
 
-        Ok(Database {
-            db: loader.db,
-            page_size: loader.page_size,
-            schema,
-        })
+        if let Some(rows) = &source.view {
+            return visit_rows(rows, &mut inner);
+        }
+        let on = self.on(source);
+        let keys = match &source.lookup {
+            Some(lookup) => on.lookup_rowids(lookup, &prefix[lookup.outer])?,
+            None => None,
+        };
+        let (columns, wanted) = (&source.table.columns, source.wanted.as_deref());
+        match (keys, &source.table.key_order) {
+            (Some(keys), _) => on.execute_select_with_index(source.rootpage, columns, wanted, &keys, &mut inner),
+            (None, Some(order)) => {
+                on.execute_select_without_rowid(source.rootpage, columns, order, &mut inner)
+            }
+            (None, None) => on.execute_select(source.rootpage, columns, wanted, &mut inner),
+        }
     }
 
-    pub fn info(&self) -> Result<()> {
-        println!("database page size: {}", self.page_size);
-        let table_count = self.table_count()?;
-        println!("number of tables: {}", table_count);
-        Ok(())
+    /// Sorted rowids of the rows whose join column equals `value`, or `None`
+    /// when the lookup cannot answer it and the table has to be scanned.
+    fn lookup_rowids(&self, lookup: &Lookup, value: &Record) -> Result<Option<Vec<usize>>> {
+        if let Record::Null = value {
+            return Ok(Some(Vec::new()));
+        }
+        let value = &coerce(lookup.affinity, value.clone());
+        match lookup.probe {
+            Probe::Rowid => Ok(match value {
+                Record::Text(_) | Record::Blob(_) => Some(Vec::new()),
+                // The rowid descent compares unsigned, so negative rowids are scanned for.
+                value => value.integer().filter(|&rowid| rowid >= 0).map(|rowid| vec![rowid as usize]),
+            }),
+            Probe::Index(rootpage) => {
+                let key = vec![value.clone()];
+                let mut keys = Vec::new();
+                for entry in self.index_walk(rootpage, &[], key.clone()..=key, Direction::Forward) {
+                    keys.extend(entry?.last().and_then(Record::integer).map(|r| r as usize));
+                }
+                keys.sort_unstable();
+                keys.dedup();
+                Ok(Some(keys))
+            }
+        }
     }
 
-    pub fn tables(&self) -> Result<()> {
-        for schema in &self.schema {
-            if schema.kind == schema::Kind::Table {
-                println!("{}", schema.name);
+    /// Sorted rowids of the index entries whose leading key lies in one of
+    /// `ranges`. Cells are key-ordered, so only the pages that can hold such
+    /// keys are visited, once for each range.
+    fn index_rowids(&self, page_num: usize, ranges: &[KeyRange]) -> Result<Vec<usize>> {
+        let mut keys = Vec::new();
+        for (low, high) in ranges {
+            let range = (low.clone().map(|key| vec![key]), high.clone().map(|key| vec![key]));
+            for key in self.index_walk(page_num, &[], range, Direction::Forward) {
+                keys.extend(key?.last().and_then(Record::integer).map(|r| r as usize));
             }
         }
-        Ok(())
+        keys.sort_unstable();
+        keys.dedup();
+        Ok(keys)
     }
 
-    pub fn execute_statement(&self, statement: &Statement) -> Result<()> {
-        match statement {
-            Statement::Select {
-                table,
-                columns: selected_columns,
-                condition,
-            } => {
-                let mut results = Vec::new();
-                let count;
-                match condition {
-                    None => {
-                        let rootpage = self.get_table_rootpage(&table)?;
-                        count = self.execute_select(statement, rootpage, &mut results)?;
-                    }
-                    Some(Condition::Equals { column, value }) => {
-                        let index_rootpage = self.get_index_rootpage(&table, column);
-
-                        match index_rootpage {
-                            Some(rootpage) => {
-                                let mut keys = Vec::new();
-                                self.execute_index(rootpage, value, &mut keys)?;
-                                count = keys.len();
-                                let rootpage = self.get_table_rootpage(&table)?;
-                                self.execute_select_with_index(
-                                    statement,
-                                    rootpage,
-                                    &mut results,
-                                    &keys,
-                                )?;
-                            }
-                            None => {
-                                let rootpage = self.get_table_rootpage(&table)?;
-                                count = self.execute_select(statement, rootpage, &mut results)?;
-                            }
-                        }
-                    }
-                }
+    /// Visits the rows whose rowids are in `keys`, in rowid order.
+    fn execute_select_with_index(
+        &self,
+        page_num: usize,
+        columns: &[ColumnDef],
+        wanted: Option<&[bool]>,
+        keys: &[usize],
+        on_row: &mut RowCallback,
+    ) -> Result<ControlFlow<()>> {
+        self.visit_cells(self.table_walk(page_num, (i64::MIN, i64::MAX), Some(keys)), columns, wanted, on_row)
+    }
 
-                let col_count = selected_columns
-                    .iter()
-                    .filter(|c| c.as_str().to_lowercase() != "count(*)")
-                    .count();
-
-                if selected_columns[0].to_lowercase() == "count(*)" {
-                    println!("{}", count);
-                } else {
-                    for (idx, res) in results.into_iter().enumerate() {
-                        if (idx + 1) % col_count == 0 {
-                            println!("{}", res);
-                        } else {
-                            print!("{}|", res);
-                        }
-                    }
+    /// Walks the table b-tree rooted at `page_num` in rowid order.
+    fn execute_select(
+        &self,
+        page_num: usize,
+        columns: &[ColumnDef],
+        wanted: Option<&[bool]>,
+        on_row: &mut RowCallback,
+    ) -> Result<ControlFlow<()>> {
+        self.visit_cells(self.table_walk(page_num, (i64::MIN, i64::MAX), None), columns, wanted, on_row)
+    }
+
+    /// Inclusive bounds on the rowid implied by comparing it with constants in
+    /// the conjuncts of `condition`. They may admit more rows than the
+    /// condition does; the caller still filters every row by it.
+    fn rowid_range(&self, columns: &[ColumnDef], condition: &Condition) -> Option<(i64, i64)> {
+        let is_rowid = |e: &Expr| {
+            matches!(e, Expr::Column(column) if columns.iter().any(|c| c.rowid_alias && c.name.eq_ignore_ascii_case(&column.name)))
+        };
+        let constant = |e: &Expr| self.rowid_constant(e);
+        let flipped = |op: CompareOp| match op {
+            CompareOp::Lt => CompareOp::Gt,
+            CompareOp::Le => CompareOp::Ge,
+            CompareOp::Gt => CompareOp::Lt,
+            CompareOp::Ge => CompareOp::Le,
+            op => op,
+        };
+
+        let mut bounds = Vec::new();
+        for conjunct in condition.conjuncts() {
+            match conjunct {
+                Condition::Equals { column, value } if is_rowid(&Expr::Column(ColumnRef::bare(column))) => {
+                    let value = functions::apply_affinity(Affinity::Integer, Record::Text(value.clone()));
+                    bounds.push((CompareOp::Eq, value));
+                }
+                Condition::Compare { left, op, right } if is_rowid(left) => {
+                    bounds.extend(constant(right).map(|v| (*op, v)));
                 }
+                Condition::Compare { left, op, right } if is_rowid(right) => {
+                    bounds.extend(constant(left).map(|v| (flipped(*op), v)));
+                }
+                Condition::Between { expr, low, high } if is_rowid(expr) => {
+                    bounds.extend(constant(low).map(|v| (CompareOp::Ge, v)));
+                    bounds.extend(constant(high).map(|v| (CompareOp::Le, v)));
+                }
+                _ => {}
             }
-            _ => unimplemented!(),
         }
 
-        Ok(())
+        let mut range = None;
+        for (op, value) in bounds {
+            // Text and blobs sort after every rowid and NULL matches nothing;
+            // leaving those to the filter only admits more rows.
+            let (floor, ceil) = match value {
+                Record::Float(v) => (v.floor() as i64, v.ceil() as i64),
+                value => match value.integer() {
+                    Some(v) => (v, v),
+                    None => continue,
+                },
+            };
+            let (low, high) = range.get_or_insert((i64::MIN, i64::MAX));
+            match op {
+                CompareOp::Eq => (*low, *high) = ((*low).max(floor), (*high).min(ceil)),
+                CompareOp::Gt | CompareOp::Ge => *low = (*low).max(floor),
+                CompareOp::Lt | CompareOp::Le => *high = (*high).min(ceil),
+                CompareOp::Ne => {}
+            }
+        }
+        range
     }
 
-    fn execute_index(&self, page_num: usize, value: &String, keys: &mut Vec<usize>) -> Result<()> {
-        let page = self.read_page(page_num)?;
+    /// The rowids a conjunct of `condition` lists with `rowid IN (...)`, sorted.
+    /// Values that are not integers match no rowid and are left out.
+    fn rowid_list(&self, columns: &[ColumnDef], condition: &Condition) -> Option<Vec<usize>> {
+        let list = condition.conjuncts().into_iter().find_map(|conjunct| match conjunct {
+            Condition::In { expr, list } => match expr.as_ref() {
+                Expr::Column(column) if columns.iter().any(|c| c.rowid_alias && c.name.eq_ignore_ascii_case(&column.name)) => Some(list),
+                _ => None,
+            },
+            _ => None,
+        })?;
+        let mut keys = Vec::new();
+        for e in list {
+            match self.rowid_constant(e)? {
+                Record::Float(v) if v.fract() != 0.0 => {}
+                value => match value.integer() {
+                    // The rowid descent compares unsigned, so negative rowids are scanned for.
+                    Some(rowid) if rowid < 0 => return None,
+                    Some(rowid) => keys.push(rowid as usize),
+                    None => {}
+                },
+            }
+        }
+        keys.sort_unstable();
+        keys.dedup();
+        Some(keys)
+    }
 
-        match page {
-            Page::InteriorIndex { rmptr, cells } => {
-                for cell in cells {
-                    for key in cell.keys.chunks(2) {
-                        if let Record::Text(val) = &key[0] {
-                            if value < val {
-                                self.execute_index(cell.left_child as usize, value, keys)?;
-                            } else if value == val {
-                                match key[1] {
-                                    Record::Int24(rowid) => keys.push(rowid as usize),
-                                    _ => Err(anyhow!("Invalid record type"))?,
-                                }
-                                self.execute_index(cell.left_child as usize, value, keys)?;
-                            }
-                        }
-                    }
+    /// The value of `e` when it reads no column, to search by.
+    fn constant(&self, e: &Expr) -> Option<Record> {
+        let mut refs = Vec::new();
+        e.column_refs(&mut refs);
+        match refs.is_empty() && !e.is_aggregate() {
+            true => self.evaluate(e, &[], &[]).ok(),
+            false => None,
+        }
+    }
+
+    /// The value of `e` when it reads no column, as a rowid compares with
+    /// it: text that spells out a number is the number.
+    fn rowid_constant(&self, e: &Expr) -> Option<Record> {
+        self.constant(e).map(|value| functions::apply_affinity(Affinity::Integer, value))
+    }
+
+    /// The rowids `low..=high` of exactly the rows that match `condition`,
+    /// when all it does is compare the rowid with integer constants.
+    fn exact_rowid_range(&self, columns: &[ColumnDef], condition: &Condition) -> Option<(i64, i64)> {
+        let is_rowid = |e: &Expr| {
+            matches!(e, Expr::Column(column) if columns.iter().any(|c| c.rowid_alias && c.name.eq_ignore_ascii_case(&column.name)))
+        };
+        // Other constants compare by affinity and type, which a range cannot express.
+        let integer = |e: &Expr| self.rowid_constant(e)?.integer();
+        let (mut low, mut high) = (i64::MIN, i64::MAX);
+        for conjunct in condition.conjuncts() {
+            let (op, value) = match conjunct {
+                Condition::Compare { left, op, right } if is_rowid(left) => (*op, integer(right)?),
+                Condition::Compare { left, op, right } if is_rowid(right) => {
+                    let op = match op {
+                        CompareOp::Lt => CompareOp::Gt,
+                        CompareOp::Le => CompareOp::Ge,
+                        CompareOp::Gt => CompareOp::Lt,
+                        CompareOp::Ge => CompareOp::Le,
+                        op => *op,
+                    };
+                    (op, integer(left)?)
                 }
-                self.execute_index(rmptr as usize, value, keys)?;
-            }
-            Page::LeafIndex { cells } => cells.iter().for_each(|c| {
-                for key in c.keys.chunks(2) {
-                    if let Record::Text(val) = &key[0] {
-                        if value == val {
-                            match key[1] {
-                                Record::Int24(rowid) => keys.push(rowid as usize),
-                                _ => {}
-                            }
-                        }
-                    }
+                Condition::Between { expr, low: a, high: b } if is_rowid(expr) => {
+                    (low, high) = (low.max(integer(a)?), high.min(integer(b)?));
+                    continue;
                 }
-            }),
-
-            _ => Err(anyhow!("Invalid page type"))?,
+                _ => return None,
+            };
+            match op {
+                CompareOp::Eq => (low, high) = (low.max(value), high.min(value)),
+                CompareOp::Ge => low = low.max(value),
+                CompareOp::Gt => low = low.max(value.checked_add(1)?),
+                CompareOp::Le => high = high.min(value),
+                CompareOp::Lt => high = high.min(value.checked_sub(1)?),
+                CompareOp::Ne => return None,
+            }
         }
+        Some((low, high))
+    }
 
-        Ok(())
+    /// Walks the rows of the table b-tree rooted at `page_num` whose rowids lie
+    /// in `low..=high`, descending only into the subtrees that can hold them.
+    fn execute_select_range(
+        &self,
+        page_num: usize,
+        columns: &[ColumnDef],
+        wanted: Option<&[bool]>,
+        range: (i64, i64),
+        on_row: &mut RowCallback,
+    ) -> Result<ControlFlow<()>> {
+        self.visit_cells(self.table_walk(page_num, range, None), columns, wanted, on_row)
     }
 
-    fn execute_select_with_index(
+    /// Walks the index b-tree of a WITHOUT ROWID table in key order. Interior
+    /// cells hold rows too, between those of their left child and the next.
+    fn execute_select_without_rowid(
         &self,
-        statement: &Statement,
         page_num: usize,
-        results: &mut Vec<Record>,
-        keys: &Vec<usize>,
-    ) -> Result<()> {
-        if let Statement::Select {
-            table,
-            columns: selected_cols,
-            ..
-        } = statement
-        {
-            let page = self.read_page(page_num)?;
-            match page {
-                Page::LeafTable { cells } => {
-                    let schema = self.get_schema(&table)?;
-                    let create_statement = parse_sql(&schema.sql)?;
-                    if let Statement::CreateTable { columns, .. } = create_statement {
-                        let cells = cells
-                            .iter()
-                            .filter(|cell| {
-                                if let Record::Int64(key) = cell.values[0] {
-                                    keys.contains(&(key as usize))
-                                } else {
-                                    false
-                                }
-                            })
-                            .collect_vec();
-
-                        for cell in cells {
-                            for col in selected_cols {
-                                match col {
-                                    col if col.to_lowercase().as_str() == "count(*)" => {}
-                                    col => {
-                                        let col_idx = columns
-                                            .iter()
-                                            .position(|c| c.name == *col)
-                                            .ok_or(anyhow!("nonexistent column"))?;
-                                        results.push(cell.values[col_idx].clone());
-                                    }
-                                }
-                            }
-                        }
+        columns: &[ColumnDef],
+        order: &[usize],
+        on_row: &mut RowCallback,
+    ) -> Result<ControlFlow<()>> {
+        for keys in self.index_walk(page_num, &[], .., Direction::Forward) {
+            if on_row(without_rowid_values(keys?, columns, order))?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn matches(
+        &self,
+        condition: &Condition,
+        columns: &[ColumnDef],
+        evaluate: &mut Evaluator,
+    ) -> Result<bool> {
+        Ok(self.truth(condition, columns, evaluate)? == Some(true))
+    }
+
+    /// The collation values of `expr` compare with: a column's own, BINARY
+    /// for anything else.
+    fn expr_collation(&self, expr: &Expr, columns: &[ColumnDef]) -> Collation {
+        match expr {
+            Expr::Column(column) => column_collation(&column.name, columns, self.case_folding),
+            _ => Collation::Binary,
+        }
+    }
+
+    /// Three-valued result of a condition: `None` is SQL NULL. `evaluate`
+    /// supplies the value of each expression for the row or group at hand.
+    fn truth(
+        &self,
+        condition: &Condition,
+        columns: &[ColumnDef],
+        evaluate: &mut Evaluator,
+    ) -> Result<Option<bool>> {
+        let folding = self.case_folding;
+
+        Ok(match condition {
+            Condition::Equals { column, value } => {
+                let column = Expr::Column(ColumnRef::bare(column));
+                let value = Expr::Literal(Record::Text(value.clone()));
+                let affinity = comparison_affinity(&column, &value, columns);
+                match (evaluate(&column)?, evaluate(&value)?) {
+                    (Record::Null, _) => None,
+                    (left, right) => {
+                        let (left, right) = (coerce(affinity, left), coerce(affinity, right));
+                        Some(self.expr_collation(&column, columns).compare(&left, &right).is_eq())
                     }
                 }
-                Page::InteriorTable { rmptr, cells } => {
-                    if keys.iter().any(|key| *key < cells[0].row_id as usize) {
-                        self.execute_select_with_index(
-                            statement,
-                            cells[0].left_child as usize,
-                            results,
-                            keys,
-                        )?;
+            }
+            Condition::Like { column, pattern } => match evaluate(&Expr::Column(ColumnRef::bare(column)))? {
+                Record::Null => None,
+                Record::Text(s) => Some(functions::like(pattern, &s, folding)),
+                other => Some(functions::like(pattern, &other.to_string(), folding)),
+            },
+            Condition::And(a, b) => match (self.truth(a, columns, evaluate)?, self.truth(b, columns, evaluate)?) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Condition::Or(a, b) => match (self.truth(a, columns, evaluate)?, self.truth(b, columns, evaluate)?) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+            Condition::Not(c) => self.truth(c, columns, evaluate)?.map(|t| !t),
+            Condition::Compare { left, op, right } => {
+                // As in sqlite, a column on the left decides the collation first.
+                let collation = [left, right]
+                    .iter()
+                    .find(|e| matches!(e.as_ref(), Expr::Column(_)))
+                    .map_or(Collation::Binary, |e| self.expr_collation(e, columns));
+                let affinity = comparison_affinity(left, right, columns);
+                match (evaluate(left)?, evaluate(right)?) {
+                    (Record::Null, _) | (_, Record::Null) => None,
+                    (left, right) => {
+                        let (left, right) = (coerce(affinity, left), coerce(affinity, right));
+                        Some(op.holds(collation.compare(&left, &right)))
                     }
-
-                    for two_cell in cells.windows(2) {
-                        if keys.iter().any(|key| {
-                            *key < two_cell[1].row_id as usize
-                                && *key >= two_cell[0].row_id as usize
-                        }) {
-                            self.execute_select_with_index(
-                                statement,
-                                two_cell[1].left_child as usize,
-                                results,
-                                keys,
-                            )?;
+                }
+            }
+            // Each bound is compared on its own terms, as `expr >= low AND expr <= high`.
+            Condition::Between { expr, low, high } => {
+                let collation = self.expr_collation(expr, columns);
+                let value = evaluate(expr)?;
+                let mut holds = |bound: &Expr, op: CompareOp| -> Result<Option<bool>> {
+                    let affinity = comparison_affinity(expr, bound, columns);
+                    Ok(match (&value, evaluate(bound)?) {
+                        (Record::Null, _) | (_, Record::Null) => None,
+                        (value, bound) => {
+                            let (value, bound) = (coerce(affinity, value.clone()), coerce(affinity, bound));
+                            Some(op.holds(collation.compare(&value, &bound)))
+                        }
+                    })
+                };
+                match (holds(low, CompareOp::Ge)?, holds(high, CompareOp::Le)?) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }
+            }
+            // Not found is NULL rather than false when a NULL was compared with.
+            Condition::In { expr, list } => {
+                let collation = self.expr_collation(expr, columns);
+                let value = evaluate(expr)?;
+                let mut found = Some(false);
+                for item in list {
+                    let affinity = comparison_affinity(expr, item, columns);
+                    let equal = match (&value, evaluate(item)?) {
+                        (Record::Null, _) | (_, Record::Null) => None,
+                        (value, item) => {
+                            let (value, item) = (coerce(affinity, value.clone()), coerce(affinity, item));
+                            Some(collation.compare(&value, &item).is_eq())
+                        }
+                    };
+                    match equal {
+                        Some(true) => {
+                            found = Some(true);
+                            break;
                         }
+                        None => found = None,
+                        Some(false) => {}
                     }
+                }
+                found
+            }
+        })
+    }
 
-                    if keys
-                        .iter()
-                        .any(|key| *key > cells[cells.len() - 1].row_id as usize)
-                    {
-                        self.execute_select_with_index(statement, rmptr as usize, results, keys)?;
-                    }
+    fn evaluate(&self, expr: &Expr, columns: &[ColumnDef], values: &[Record]) -> Result<Record> {
+        match expr {
+            // Qualifiers were checked against the table before the scan.
+            Expr::Column(col) => {
+                let col_idx = columns.iter().position(|c| c.name.eq_ignore_ascii_case(&col.name));
+                if col_idx.is_none() && col.quoted {
+                    return Ok(Record::Text(col.name.clone()));
                 }
-                _ => unreachable!(),
+                let col_idx = col_idx.ok_or_else(|| {
+                    Error::no_such_column(&col.to_string(), col.table.as_deref(), columns.iter().map(|c| c.name.as_str()))
+                })?;
+                Ok(values[col_idx].clone())
+            }
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Parameter { .. } => Ok(Record::Null),
+            Expr::Binary { op, left, right } => Ok(functions::arithmetic(
+                *op,
+                &self.evaluate(left, columns, values)?,
+                &self.evaluate(right, columns, values)?,
+            )),
+            Expr::Negate(e) => Ok(functions::negate(&self.evaluate(e, columns, values)?)),
+            Expr::Function { name, args, .. } if aggregate::is_aggregate(name, args.len()) => {
+                Err(anyhow!(msg!("aggregate-misuse", function = name)))
             }
+            Expr::Function { name, args, .. } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.evaluate(arg, columns, values))
+                    .collect::<Result<Vec<_>>>()?;
+                functions::call_scalar(name, &args, &self.rng, self.case_folding)
+            }
+        }
+    }
 
-            Ok(())
-        } else {
-            unreachable!()
+    /// Feeds one input row to the aggregate calls of its group.
+    fn step_group(
+        &self,
+        group: &mut Group,
+        calls: &[&Expr],
+        columns: &[ColumnDef],
+        row: Vec<Record>,
+    ) -> Result<()> {
+        for (call, accumulator) in calls.iter().zip(&mut group.accumulators) {
+            match call {
+                Expr::Function { args, .. } => match args.first() {
+                    Some(arg) => accumulator.step(Some(&self.evaluate(arg, columns, &row)?)),
+                    None => accumulator.step(None),
+                },
+                _ => unreachable!(),
+            }
         }
+        group.last_row = row;
+        Ok(())
     }
 
-    fn execute_select(
+    /// Evaluates an output expression of a group: aggregate calls take their
+    /// finished values, bare columns the values of the group's last row.
+    fn evaluate_grouped(
         &self,
-        statement: &Statement,
-        page_num: usize,
-        results: &mut Vec<Record>,
-    ) -> Result<usize> {
-        if let Statement::Select {
-            table,
-            columns: selected_cols,
-            condition,
-        } = statement
-        {
-            let mut count = 0;
-            let page = self.read_page(page_num)?;
-            let schema = self.get_schema(&table)?;
-            let create_statement = parse_sql(&schema.sql)?;
-            if let Statement::CreateTable {
-                table: _table,
+        expr: &Expr,
+        calls: &[&Expr],
+        results: &[Record],
+        columns: &[ColumnDef],
+        last_row: &[Record],
+    ) -> Result<Record> {
+        if let Some(idx) = calls.iter().position(|call| *call == expr) {
+            return Ok(results[idx].clone());
+        }
+        match expr {
+            Expr::Function { name, args, .. } if expr.is_aggregate() => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.evaluate_grouped(arg, calls, results, columns, last_row))
+                    .collect::<Result<Vec<_>>>()?;
+                functions::call_scalar(name, &args, &self.rng, self.case_folding)
+            }
+            Expr::Binary { op, left, right } => Ok(functions::arithmetic(
+                *op,
+                &self.evaluate_grouped(left, calls, results, columns, last_row)?,
+                &self.evaluate_grouped(right, calls, results, columns, last_row)?,
+            )),
+            Expr::Negate(e) => Ok(functions::negate(
+                &self.evaluate_grouped(e, calls, results, columns, last_row)?,
+            )),
+            expr => self.evaluate(expr, columns, last_row),
+        }
+    }
+
+    fn get_table(&self, table_name: &str) -> Result<Table> {
+        let schema = self.get_schema(table_name)?;
+        match parse_sql(&schema.sql)? {
+            Statement::CreateTable {
                 columns,
-            } = create_statement
-            {
-                match page {
-                    Page::LeafTable { cells } => {
-                        let cells = cells
-                            .iter()
-                            .filter(|cell| {
-                                if let Some(condition) = &condition {
-                                    match condition {
-                                        Condition::Equals { column, value } => {
-                                            let col_idx = columns
-                                                .iter()
-                                                .position(|c| c.name == *column)
-                                                .unwrap();
-
-                                            match &cell.values[col_idx] {
-                                                Record::Text(s) => s == value,
-                                                Record::Null => false,
-                                                _ => unimplemented!(),
-                                            }
-                                        }
-                                    }
-                                } else {
-                                    true
-                                }
-                            })
-                            .collect_vec();
-
-                        for cell in cells {
-                            count += 1;
-                            for col in selected_cols {
-                                match col {
-                                    col if col.to_lowercase().as_str() == "count(*)" => {}
-                                    col => {
-                                        let col_idx = columns
-                                            .iter()
-                                            .position(|c| c.name == *col)
-                                            .ok_or(anyhow!("nonexistent column"))?;
-                                        results.push(cell.values[col_idx].clone());
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    Page::InteriorTable { rmptr, cells } => {
-                        for cell in cells {
-                            count +=
-                                self.execute_select(&statement, cell.left_child as usize, results)?;
-                        }
-                        count += self.execute_select(&statement, rmptr as usize, results)?;
-                    }
-                    _ => Err(anyhow!("Invalid page type"))?,
-                }
+                constraints,
+                without_rowid,
+                strict,
+                ..
+            } => {
+                let key_order = without_rowid.then(|| {
+                    // Records hold the primary key columns first, then the rest.
+                    let pk = constraints.iter().find_map(|c| match c {
+                        TableConstraint::PrimaryKey(pk) => Some(
+                            pk.iter()
+                                .filter_map(|name| {
+                                    columns.iter().position(|c| c.name.eq_ignore_ascii_case(name))
+                                })
+                                .collect_vec(),
+                        ),
+                        _ => None,
+                    });
+                    let pk = pk
+                        .unwrap_or_else(|| columns.iter().positions(|c| c.primary_key).collect());
+                    let rest = (0..columns.len()).filter(|idx| !pk.contains(idx));
+                    (pk.iter().copied().chain(rest.collect_vec()).collect(), pk.len())
+                });
+                let (key_order, key_len) = match key_order {
+                    Some((order, len)) => (Some(order), len),
+                    None => (None, 0),
+                };
+                Ok(Table {
+                    columns,
+                    key_order,
+                    key_len,
+                    constraints,
+                    strict,
+                })
             }
-            Ok(count)
-        } else {
-            unreachable!()
+            _ => Err(anyhow!(msg!("invalid-table-schema"))),
         }
     }
 
     fn get_schema(&self, table_name: &str) -> Result<&Schema> {
-        self.schema
-            .iter()
-            .find(|s| s.name == table_name && s.kind == schema::Kind::Table)
-            .ok_or(anyhow!("Table not found"))
+        let tables = self.schema.iter().filter(|s| s.kind == schema::Kind::Table);
+        match tables.clone().find(|s| s.name.eq_ignore_ascii_case(table_name)) {
+            Some(schema) => Ok(schema),
+            None => Err(Error::no_such_table(table_name, tables.map(|s| s.name.as_str())))?,
+        }
     }
 
+    /// Root page of an index whose leading column is `column_name` and whose
+    /// order BINARY range scans can follow: ascending, default collation and
+    /// covering every row.
     fn get_index_rootpage(&self, tbl_name: &str, column_name: &str) -> Option<usize> {
-        let index_schemas = self
-            .schema
+        self.get_index(tbl_name, column_name).map(|index| index.rootpage)
+    }
+
+    /// An index whose leading column is `column_name` in BINARY order, which
+    /// the range scans can search.
+    fn get_index(&self, tbl_name: &str, column_name: &str) -> Option<&Index> {
+        // UTF-16 keys sort by their encoded bytes, not in the UTF-8 order the
+        // range scans compare with.
+        if self.text_encoding != TextEncoding::Utf8 {
+            return None;
+        }
+        self.indexes
             .iter()
-            .filter(|s| s.kind == schema::Kind::Index && s.tbl_name == tbl_name)
-            .collect_vec();
+            .filter(|index| index.table.eq_ignore_ascii_case(tbl_name) && !index.partial)
+            .find(|index| {
+                index.columns.first().is_some_and(|column| {
+                    column.name.eq_ignore_ascii_case(column_name)
+                        && !column.descending
+                        && column
+                            .collation
+                            .as_deref()
+                            .is_none_or(|c| c.eq_ignore_ascii_case("BINARY"))
+                })
+            })
+    }
 
-        for schema in index_schemas {
-            let create_statement = parse_sql(&schema.sql);
+    fn get_table_rootpage(&self, table_name: &str) -> Result<usize> {
+        Ok(self.get_schema(table_name)?.rootpage)
+    }
 
-            if let Ok(Statement::CreateIndex { columns, .. }) = create_statement {
-                for column in columns {
-                    if column == column_name {
-                        return Some(schema.rootpage);
-                    }
+    /// Number of rows in the table b-tree rooted at `page_num`, totalled from
+    /// the cell counts in the page headers without decoding any record.
+    fn count_rows(&self, page_num: usize, walk: &mut Walk) -> Result<usize> {
+        interrupt::check()?;
+        let page = self.read_raw_page(page_num)?;
+        let offset = match page_num {
+            1 => DB_HEADER_SIZE,
+            _ => 0,
+        };
+        let num_of_cells = u16::from_be_bytes([page[3 + offset], page[4 + offset]]) as usize;
+
+        match page[offset] {
+            // Index b-trees of WITHOUT ROWID tables keep rows in interior cells too.
+            10 | 13 => Ok(num_of_cells),
+            2 | 5 => {
+                let be_u32_at = |at: usize| {
+                    u32::from_be_bytes([page[at], page[at + 1], page[at + 2], page[at + 3]]) as usize
+                };
+                let right_most = be_u32_at(8 + offset);
+                walk.check_child(page_num, right_most)?;
+                let mut total = self.count_rows(right_most, walk)?;
+                for idx in 0..num_of_cells {
+                    let pointer = 12 + offset + 2 * idx;
+                    let cell = u16::from_be_bytes([page[pointer], page[pointer + 1]]) as usize;
+                    walk.check_child(page_num, be_u32_at(cell))?;
+                    total += self.count_rows(be_u32_at(cell), walk)?;
                 }
+                Ok(match page[offset] {
+                    2 => total + num_of_cells,
+                    _ => total,
+                })
             }
+            kind => Err(malformed(Some(page_num), msg!("unknown-page-type", page = page_num, kind = kind))),
         }
+    }
 
-        None
+    /// Number of rows with rowids in `low..=high` in the table b-tree rooted
+    /// at `page_num`. Subtrees wholly inside the range are counted from their
+    /// page headers; only leaves at its edges have their rowids read.
+    fn count_rows_between(&self, page_num: usize, low: i64, high: i64, walk: &mut Walk) -> Result<usize> {
+        if low > high {
+            return Ok(0);
+        }
+        interrupt::check()?;
+        let page = self.read_raw_page(page_num)?;
+        let offset = match page_num {
+            1 => DB_HEADER_SIZE,
+            _ => 0,
+        };
+        let num_of_cells = u16::from_be_bytes([page[3 + offset], page[4 + offset]]) as usize;
+        let interior = page[offset] == 5;
+        let pointer = |idx: usize| {
+            let at = offset + if interior { 12 } else { 8 } + 2 * idx;
+            u16::from_be_bytes([page[at], page[at + 1]]) as usize
+        };
+        let be_u32_at = |at: usize| u32::from_be_bytes([page[at], page[at + 1], page[at + 2], page[at + 3]]) as usize;
+
+        match page[offset] {
+            13 => {
+                let mut count = 0;
+                for idx in 0..num_of_cells {
+                    let (_, rest, _) = parse_varint(&page[pointer(idx)..])?;
+                    let rowid = parse_varint(rest)?.0 as i64;
+                    count += (low..=high).contains(&rowid) as usize;
+                }
+                Ok(count)
+            }
+            5 => {
+                // Child `idx` holds the rowids after the key of the cell before
+                // it, up to and including its own key.
+                let mut total = 0;
+                let mut floor = None;
+                for idx in 0..=num_of_cells {
+                    let (child, key) = match idx < num_of_cells {
+                        true => {
+                            let cell = pointer(idx);
+                            (be_u32_at(cell), Some(parse_varint(&page[cell + 4..])?.0 as i64))
+                        }
+                        false => (be_u32_at(8 + offset), None),
+                    };
+                    // The child's rowids lie in `first..=last`.
+                    let first = floor.map_or(i64::MIN, |floor: i64| floor.saturating_add(1));
+                    let last = key.unwrap_or(i64::MAX);
+                    if first <= high && low <= last {
+                        walk.check_child(page_num, child)?;
+                        total += match low <= first && last <= high {
+                            true => self.count_rows(child, walk)?,
+                            false => self.count_rows_between(child, low, high, walk)?,
+                        };
+                    }
+                    floor = key;
+                }
+                Ok(total)
+            }
+            kind => Err(malformed(Some(page_num), msg!("unknown-page-type", page = page_num, kind = kind))),
+        }
     }
 
-    fn get_table_rootpage(&self, table_name: &str) -> Result<usize> {
-        let schema = self
-            .schema
-            .iter()
-            .find(|s| s.name == table_name && s.kind == schema::Kind::Table)
-            .ok_or(anyhow!("Table not found"))?;
-        Ok(schema.rootpage)
+    fn read_raw_page(&self, page_num: usize) -> Result<Vec<u8>> {
+        if let Some(trace) = &self.read_trace {
+            trace.read(page_num);
+        }
+        let mut page = vec![0; self.page_size];
+        let offset = ((page_num - 1) * self.page_size) as u64;
+        self.db.read_exact_at(&mut page, offset).map_err(|e| match self.db.size() {
+            Ok(size) if offset + self.page_size as u64 > size => malformed(
+                Some(page_num),
+                msg!("page-past-end-of-file", page = page_num, size = size),
+            ),
+            _ => e,
+        })?;
+        Ok(page)
     }
 
-    fn read_page(&self, page_num: usize) -> Result<Page> {
+    /// Reads `pages` one after another, the way the statements of a read
+    /// trace read them, and returns how many bytes that came to.
+    pub fn replay_reads(&self, pages: &[usize]) -> Result<u64> {
         let mut page = vec![0; self.page_size];
-        self.db
-            .read_exact_at(&mut page, ((page_num - 1) * self.page_size) as u64)?;
+        for &page_num in pages {
+            interrupt::check()?;
+            self.db.read_exact_at(&mut page, ((page_num - 1) * self.page_size) as u64)?;
+        }
+        Ok((pages.len() * self.page_size) as u64)
+    }
+
+    /// Page `page_num` of a b-tree, with its cells decoded and payloads that
+    /// spill onto overflow pages read whole. Cell pointers, cells and records
+    /// that run off the page fail as [`Error::CorruptPage`].
+    pub fn read_page(&self, page_num: usize) -> Result<Page> {
+        let page = self.read_raw_page(page_num)?;
         let offset = match page_num {
             1 => DB_HEADER_SIZE,
             _ => 0,
         };
-        let kind = match page[0 + offset] {
+        let kind = match page[offset] {
             2 => Kind::InteriorIndex,
             5 => Kind::InteriorTable,
             10 => Kind::LeafIndex,
             13 => Kind::LeafTable,
-            _ => Err(anyhow!("Invalid page kind"))?,
+            kind => Err(malformed(Some(page_num), msg!("unknown-page-type", page = page_num, kind = kind)))?,
         };
 
-        let num_of_cells = u16::from_be_bytes([page[3 + offset], page[4 + offset]]);
-        let _start_idx = u16::from_be_bytes([page[5 + offset], page[6 + offset]]);
+        let num_of_cells = u16::from_be_bytes([page[3 + offset], page[4 + offset]]) as usize;
         let mut right_most = 0;
         if let Kind::InteriorTable | Kind::InteriorIndex = kind {
             right_most = u32::from_be_bytes([
@@ -386,129 +2526,72 @@ impl Database {
             ]);
         }
 
-        let mut cell_pointers = Vec::with_capacity(num_of_cells as usize);
         let header_end = match kind {
-            Kind::InteriorTable | Kind::InteriorIndex => 12 + offset as u16,
-            _ => 8 + offset as u16,
+            Kind::InteriorTable | Kind::InteriorIndex => 12 + offset,
+            _ => 8 + offset,
+        };
+        // Nothing of a cell lies in the reserved bytes at the end of the page.
+        let usable = page.get(..self.usable_size).unwrap_or(&page);
+        if header_end + 2 * num_of_cells > usable.len() {
+            Err(malformed(Some(page_num), msg!("pointers-past-end", page = page_num)))?
+        }
+        let cell_pointers = (0..num_of_cells).map(|i| {
+            let offset = header_end + i * 2;
+            u16::from_be_bytes([page[offset], page[offset + 1]]) as usize
+        });
+        let in_cell = |cell_idx: usize, e: anyhow::Error| malformed(Some(page_num), msg!("in-cell", cell = cell_idx, page = page_num, error = e));
+        let short = |cell_idx: usize| in_cell(cell_idx, anyhow!(msg!("cell-past-end")));
+        // Cell `cell_idx`, from `ptr` to the end of the usable space.
+        let cell_at = |cell_idx: usize, ptr: usize| {
+            let outside = || in_cell(cell_idx, anyhow!(msg!("cell-outside-content", offset = ptr)));
+            usable.get(ptr..).filter(|cell| ptr >= header_end + 2 * num_of_cells && !cell.is_empty()).ok_or_else(outside)
+        };
+        let child_at = |cell_idx: usize, cell: &'_ [u8]| -> Result<(u32, usize)> {
+            let child = cell.get(..4).ok_or_else(|| short(cell_idx))?;
+            Ok((u32::from_be_bytes(child.try_into()?), 4))
+        };
+        let varint_at = |cell_idx: usize, cell: &'_ [u8], at: usize| -> Result<(u64, usize)> {
+            let (value, _, len) = parse_varint(&cell[at.min(cell.len())..]).map_err(|e| in_cell(cell_idx, e))?;
+            Ok((value, at + len))
         };
 
-        cell_pointers.extend((0..num_of_cells).map(|i| {
-            let offset = (header_end + i * 2) as usize;
-            u16::from_be_bytes([page[offset], page[offset + 1]])
-        }));
+        let shared: Arc<[u8]> = Arc::from(page.as_slice());
+        // The record of cell `cell_idx`, whose `size` bytes start at `start`
+        // of the page and spill onto overflow pages when the page cannot hold
+        // them all.
+        let record = |cell_idx: usize, start: usize, size: u64, table_leaf: bool| -> Result<RawRecord> {
+            let size = size as usize;
+            let payload = &usable[start.min(usable.len())..];
+            let (bytes, start) = match local_payload(self.usable_size, size, table_leaf) == size {
+                true if payload.len() < size => Err(short(cell_idx))?,
+                true => (Arc::clone(&shared), start),
+                false => (Arc::from(self.full_payload(page_num, payload, size, table_leaf, self.usable_size)?), 0),
+            };
+            let fields = parse_record(&bytes[..start + size], start, self.schema_format).map_err(|e| in_cell(cell_idx, e))?;
+            Ok(RawRecord::new(bytes, fields, self.text_encoding, self.text_decoding))
+        };
 
         match kind {
             Kind::LeafTable => {
                 let mut cells = Vec::new();
-                for ptr in cell_pointers {
-                    let mut values = Vec::new();
-                    let cell = &page[ptr as usize..];
-                    let (_length, cell, _) = parse_varint(cell)?;
-                    let (id, cell, _) = parse_varint(cell)?;
-                    let (rec_header_size, mut cell, varint_size) = parse_varint(cell)?;
-                    let mut col_types = Vec::new();
-                    let mut cur_header_size = varint_size;
-                    while cur_header_size < rec_header_size as usize {
-                        let (column_type, remaining_cell, varint_size) = parse_varint(cell)?;
-                        let col_type = match column_type {
-                            0 => ColumnType::Null,
-                            1 => ColumnType::Int8,
-                            2 => ColumnType::Int16,
-                            3 => ColumnType::Int24,
-                            4 => ColumnType::Int32,
-                            5 => ColumnType::Int48,
-                            6 => ColumnType::Int64,
-                            7 => ColumnType::Float,
-                            8 => ColumnType::Zero,
-                            9 => ColumnType::One,
-                            10 => ColumnType::Reserved1,
-                            11 => ColumnType::Reserved2,
-                            n if n % 2 == 0 => ColumnType::Blob((n - 12) as usize / 2),
-                            n => ColumnType::Text((n - 13) as usize / 2),
-                        };
-                        col_types.push(col_type);
-                        cur_header_size += varint_size;
-                        cell = remaining_cell;
-                    }
-
-                    for (idx, col) in col_types.into_iter().enumerate() {
-                        match col {
-                            ColumnType::Null => {
-                                if idx == 0 {
-                                    values.push(Record::Int64(id as i64));
-                                } else {
-                                    values.push(Record::Null);
-                                }
-                            }
-                            ColumnType::Int8 => {
-                                let (rem, value) = be_i8::<_, ()>(cell)?;
-                                cell = rem;
-                                values.push(Record::Int8(value));
-                            }
-                            ColumnType::Int16 => {
-                                let (rem, value) = be_i16::<_, ()>(cell)?;
-                                cell = rem;
-                                values.push(Record::Int16(value));
-                            }
-                            ColumnType::Int24 => {
-                                let (rem, value) = be_i24::<_, ()>(cell)?;
-                                cell = rem;
-                                values.push(Record::Int24(value));
-                            }
-                            ColumnType::Int32 => {
-                                let (rem, value) = be_i32::<_, ()>(cell)?;
-                                cell = rem;
-                                values.push(Record::Int32(value));
-                            }
-                            ColumnType::Int48 => {
-                                let value = i64::from_be_bytes([
-                                    0, 0, cell[0], cell[1], cell[2], cell[3], cell[4], cell[5],
-                                ]);
-                                cell = &cell[6..];
-                                values.push(Record::Int48(value));
-                            }
-                            ColumnType::Int64 => {
-                                let (rem, value) = be_i64::<_, ()>(cell)?;
-                                cell = rem;
-                                values.push(Record::Int64(value));
-                            }
-                            ColumnType::Float => {
-                                let (rem, value) = be_f64::<_, ()>(cell)?;
-                                cell = rem;
-                                values.push(Record::Float(value));
-                            }
-                            ColumnType::Zero => {
-                                values.push(Record::Zero);
-                            }
-                            ColumnType::One => {
-                                values.push(Record::One);
-                            }
-                            ColumnType::Reserved1 => values.push(Record::Reserved1),
-                            ColumnType::Reserved2 => values.push(Record::Reserved2),
-                            ColumnType::Blob(len) => {
-                                let (blob, remaining) = cell.split_at(len);
-                                cell = remaining;
-                                values.push(Record::Blob(blob.to_vec()));
-                            }
-                            ColumnType::Text(len) => {
-                                let (text, remaining) = cell.split_at(len);
-                                let text = std::str::from_utf8(text)?;
-                                cell = remaining;
-                                values.push(Record::Text(text.to_string()));
-                            }
-                        }
-                    }
-                    cells.push(LeafTableCell { row_id: id, values });
+                for (cell_idx, ptr) in cell_pointers.enumerate() {
+                    let cell = cell_at(cell_idx, ptr)?;
+                    let (size, at) = varint_at(cell_idx, cell, 0)?;
+                    let (id, at) = varint_at(cell_idx, cell, at)?;
+                    cells.push(LeafTableCell {
+                        row_id: id,
+                        record: record(cell_idx, ptr + at, size, true)?,
+                    });
                 }
 
                 Ok(Page::LeafTable { cells })
             }
             Kind::InteriorTable => {
                 let mut cells = Vec::new();
-                for ptr in cell_pointers {
-                    let cell = &page[ptr as usize..];
-                    let (cell, left_child_pointer) = be_u32::<_, ()>(cell)?;
-                    let (id, _, _) = parse_varint(cell)?;
+                for (cell_idx, ptr) in cell_pointers.enumerate() {
+                    let cell = cell_at(cell_idx, ptr)?;
+                    let (left_child_pointer, at) = child_at(cell_idx, cell)?;
+                    let (id, _) = varint_at(cell_idx, cell, at)?;
                     cells.push(InteriorTableCell {
                         left_child: left_child_pointer,
                         row_id: id,
@@ -522,100 +2605,10 @@ impl Database {
             }
             Kind::LeafIndex => {
                 let mut cells = Vec::new();
-                for ptr in cell_pointers {
-                    let mut keys = Vec::new();
-                    let cell = &page[ptr as usize..];
-                    let (_len, cell, _) = parse_varint(cell)?;
-                    let (rec_header_size, mut cell, varint_size) = parse_varint(cell)?;
-                    let mut col_types = Vec::new();
-                    let mut cur_header_size = varint_size;
-                    while cur_header_size < rec_header_size as usize {
-                        let (column_type, remaining_cell, varint_size) = parse_varint(cell)?;
-                        let col_type = match column_type {
-                            0 => ColumnType::Null,
-                            1 => ColumnType::Int8,
-                            2 => ColumnType::Int16,
-                            3 => ColumnType::Int24,
-                            4 => ColumnType::Int32,
-                            5 => ColumnType::Int48,
-                            6 => ColumnType::Int64,
-                            7 => ColumnType::Float,
-                            8 => ColumnType::Zero,
-                            9 => ColumnType::One,
-                            10 => ColumnType::Reserved1,
-                            11 => ColumnType::Reserved2,
-                            n if n % 2 == 0 => ColumnType::Blob((n - 12) as usize / 2),
-                            n => ColumnType::Text((n - 13) as usize / 2),
-                        };
-                        col_types.push(col_type);
-                        cur_header_size += varint_size;
-                        cell = remaining_cell;
-                    }
-
-                    for col in col_types {
-                        match col {
-                            ColumnType::Null => {
-                                keys.push(Record::Null);
-                            }
-                            ColumnType::Int8 => {
-                                let (rem, value) = be_i8::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int8(value));
-                            }
-                            ColumnType::Int16 => {
-                                let (rem, value) = be_i16::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int16(value));
-                            }
-                            ColumnType::Int24 => {
-                                let (rem, value) = be_i24::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int24(value));
-                            }
-                            ColumnType::Int32 => {
-                                let (rem, value) = be_i32::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int32(value));
-                            }
-                            ColumnType::Int48 => {
-                                let value = i64::from_be_bytes([
-                                    0, 0, cell[0], cell[1], cell[2], cell[3], cell[4], cell[5],
-                                ]);
-                                cell = &cell[6..];
-                                keys.push(Record::Int48(value));
-                            }
-                            ColumnType::Int64 => {
-                                let (rem, value) = be_i64::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int64(value));
-                            }
-                            ColumnType::Float => {
-                                let (rem, value) = be_f64::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Float(value));
-                            }
-                            ColumnType::Zero => {
-                                keys.push(Record::Zero);
-                            }
-                            ColumnType::One => {
-                                keys.push(Record::One);
-                            }
-                            ColumnType::Reserved1 => keys.push(Record::Reserved1),
-                            ColumnType::Reserved2 => keys.push(Record::Reserved2),
-                            ColumnType::Blob(len) => {
-                                let (blob, remaining) = cell.split_at(len);
-                                cell = remaining;
-                                keys.push(Record::Blob(blob.to_vec()));
-                            }
-                            ColumnType::Text(len) => {
-                                let (text, remaining) = cell.split_at(len);
-                                let text = std::str::from_utf8(text)?;
-                                cell = remaining;
-                                keys.push(Record::Text(text.to_string()));
-                            }
-                        }
-                    }
-
+                for (cell_idx, ptr) in cell_pointers.enumerate() {
+                    let cell = cell_at(cell_idx, ptr)?;
+                    let (size, at) = varint_at(cell_idx, cell, 0)?;
+                    let keys = record(cell_idx, ptr + at, size, false)?.values()?;
                     cells.push(LeafIndexCell { keys });
                 }
 
@@ -623,101 +2616,11 @@ impl Database {
             }
             Kind::InteriorIndex => {
                 let mut cells = Vec::new();
-                for ptr in cell_pointers {
-                    let mut keys = Vec::new();
-                    let cell = &page[ptr as usize..];
-                    let (cell, left_child_pointer) = be_u32::<_, ()>(cell)?;
-                    let (_len, cell, _) = parse_varint(cell)?;
-                    let (rec_header_size, mut cell, varint_size) = parse_varint(cell)?;
-                    let mut col_types = Vec::new();
-                    let mut cur_header_size = varint_size;
-                    while cur_header_size < rec_header_size as usize {
-                        let (column_type, remaining_cell, varint_size) = parse_varint(cell)?;
-                        let col_type = match column_type {
-                            0 => ColumnType::Null,
-                            1 => ColumnType::Int8,
-                            2 => ColumnType::Int16,
-                            3 => ColumnType::Int24,
-                            4 => ColumnType::Int32,
-                            5 => ColumnType::Int48,
-                            6 => ColumnType::Int64,
-                            7 => ColumnType::Float,
-                            8 => ColumnType::Zero,
-                            9 => ColumnType::One,
-                            10 => ColumnType::Reserved1,
-                            11 => ColumnType::Reserved2,
-                            n if n % 2 == 0 => ColumnType::Blob((n - 12) as usize / 2),
-                            n => ColumnType::Text((n - 13) as usize / 2),
-                        };
-                        col_types.push(col_type);
-                        cur_header_size += varint_size;
-                        cell = remaining_cell;
-                    }
-
-                    for col in col_types {
-                        match col {
-                            ColumnType::Null => {
-                                keys.push(Record::Null);
-                            }
-                            ColumnType::Int8 => {
-                                let (rem, value) = be_i8::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int8(value));
-                            }
-                            ColumnType::Int16 => {
-                                let (rem, value) = be_i16::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int16(value));
-                            }
-                            ColumnType::Int24 => {
-                                let (rem, value) = be_i24::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int24(value));
-                            }
-                            ColumnType::Int32 => {
-                                let (rem, value) = be_i32::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int32(value));
-                            }
-                            ColumnType::Int48 => {
-                                let value = i64::from_be_bytes([
-                                    0, 0, cell[0], cell[1], cell[2], cell[3], cell[4], cell[5],
-                                ]);
-                                cell = &cell[6..];
-                                keys.push(Record::Int48(value));
-                            }
-                            ColumnType::Int64 => {
-                                let (rem, value) = be_i64::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Int64(value));
-                            }
-                            ColumnType::Float => {
-                                let (rem, value) = be_f64::<_, ()>(cell)?;
-                                cell = rem;
-                                keys.push(Record::Float(value));
-                            }
-                            ColumnType::Zero => {
-                                keys.push(Record::Zero);
-                            }
-                            ColumnType::One => {
-                                keys.push(Record::One);
-                            }
-                            ColumnType::Reserved1 => keys.push(Record::Reserved1),
-                            ColumnType::Reserved2 => keys.push(Record::Reserved2),
-                            ColumnType::Blob(len) => {
-                                let (blob, remaining) = cell.split_at(len);
-                                cell = remaining;
-                                keys.push(Record::Blob(blob.to_vec()));
-                            }
-                            ColumnType::Text(len) => {
-                                let (text, remaining) = cell.split_at(len);
-                                let text = std::str::from_utf8(text)?;
-                                cell = remaining;
-                                keys.push(Record::Text(text.to_string()));
-                            }
-                        }
-                    }
-
+                for (cell_idx, ptr) in cell_pointers.enumerate() {
+                    let cell = cell_at(cell_idx, ptr)?;
+                    let (left_child_pointer, at) = child_at(cell_idx, cell)?;
+                    let (size, at) = varint_at(cell_idx, cell, at)?;
+                    let keys = record(cell_idx, ptr + at, size, false)?.values()?;
                     cells.push(InteriorIndexCell {
                         left_child: left_child_pointer,
                         keys,
@@ -732,165 +2635,280 @@ impl Database {
         }
     }
 
-    fn table_count(&self) -> Result<usize> {
-        let mut count = 0;
-        for schema in &self.schema {
-            if schema.kind == schema::Kind::Table {
-                count += 1;
-            }
-        }
-        Ok(count)
+    fn schema_count(&self, kind: schema::Kind) -> usize {
+        self.schema.iter().filter(|s| s.kind == kind).count()
     }
 }
 
-struct DbLoader {
-    db: File,
-    page_size: usize,
+impl Drop for Database {
+    /// A transaction still open when the database closes is rolled back, as
+    /// sqlite does.
+    fn drop(&mut self) {
+        let _ = self.roll_back();
+    }
 }
 
-impl DbLoader {
-    fn new(db: File, page_size: u16) -> Self {
-        Self {
-            db,
-            page_size: page_size as usize,
+/// The page size the database header gives, where 1 stands for 65536.
+fn header_page_size(header: &[u8]) -> usize {
+    match u16::from_be_bytes([header[16], header[17]]) {
+        1 => 65536,
+        size => size as usize,
+    }
+}
+
+/// The page size of the database an empty file is, sqlite's default.
+const EMPTY_PAGE_SIZE: usize = 4096;
+
+/// How big the file of a database with header `header` should be: the pages
+/// the header counts when that count is current, or else the `size` it has
+/// rounded up to whole pages.
+fn expected_size(header: &[u8], page_size: usize, size: u64) -> u64 {
+    let field = |at: usize| u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+    let (change_counter, in_header, valid_for) = (field(24), field(28), field(92));
+    match in_header != 0 && change_counter == valid_for {
+        true => in_header as u64 * page_size as u64,
+        false => size.div_ceil(page_size as u64) * page_size as u64,
+    }
+}
+
+/// The schema entry that the row `rowid` of `sqlite_schema` holds in
+/// `values`: type, name, table name, root page and SQL. Views and triggers
+/// may have a NULL root page, and the indexes sqlite creates for UNIQUE and
+/// PRIMARY KEY constraints have NULL SQL; those read as 0 and empty.
+fn schema_entry(rowid: u64, values: Vec<Record>) -> Result<Schema> {
+    let unsupported = || {
+        Error::UnsupportedFeature(msg!("schema-entry-layout", rowid = rowid))
+    };
+    let [kind, name, tbl_name, rootpage, sql]: [Record; 5] = values.try_into().map_err(|_| unsupported())?;
+    let text = |value: Record| match value {
+        Record::Text(text) => Ok(text),
+        _ => Err(unsupported()),
+    };
+    let kind = match text(kind)?.as_str() {
+        "table" => schema::Kind::Table,
+        "index" => schema::Kind::Index,
+        "view" => schema::Kind::View,
+        "trigger" => schema::Kind::Trigger,
+        kind => Err(malformed(Some(1), msg!("unknown-schema-type", kind = kind)))?,
+    };
+    Ok(Schema {
+        kind,
+        name: text(name)?,
+        tbl_name: text(tbl_name)?,
+        rootpage: match rootpage {
+            Record::Null => 0,
+            rootpage => rootpage.integer().filter(|&page| page >= 0).ok_or_else(unsupported)? as usize,
+        },
+        sql: match sql {
+            Record::Null => String::new(),
+            sql => text(sql)?,
+        },
+    })
+}
+
+/// Column values of a table row, with the rowid filled in for an
+/// `INTEGER PRIMARY KEY` column (stored as NULL in the record). Rows written
+/// before an `ALTER TABLE ADD COLUMN` have fewer values than the table has
+/// columns; the missing ones read as the column's default. Only the values
+/// `wanted` marks are decoded; the others read as NULL.
+fn row_values(cell: LeafTableCell, columns: &[ColumnDef], wanted: Option<&[bool]>) -> Result<Vec<Record>> {
+    let record = &cell.record;
+    let mut values = match wanted {
+        None => record.values()?,
+        Some(wanted) => (0..record.len())
+            .map(|idx| match wanted.get(idx) {
+                Some(false) => Ok(Record::Null),
+                _ => record.get(idx),
+            })
+            .collect::<Result<_>>()?,
+    };
+    if values.len() < columns.len() {
+        let missing = columns[values.len()..].iter().map(ColumnDef::default_value);
+        values.extend(missing.collect_vec());
+    }
+    if let Some(idx) = columns.iter().position(|c| c.rowid_alias) {
+        if let Some(value @ Record::Null) = values.get_mut(idx) {
+            *value = Record::Int64(cell.row_id as i64);
         }
     }
+    real_affinity(&mut values, columns);
+    Ok(values)
+}
 
-    fn read_schema(&self) -> Result<Vec<Schema>> {
-        let mut page = vec![0; self.page_size];
-        self.db.read_exact_at(&mut page, 0)?;
-        let kind = match page[0 + DB_HEADER_SIZE] {
-            5 => unimplemented!(),
-            13 => Kind::LeafTable,
-            _ => Err(anyhow!("Invalid schema page kind"))?,
-        };
-
-        let num_of_cells = u16::from_be_bytes([page[3 + DB_HEADER_SIZE], page[4 + DB_HEADER_SIZE]]);
-        let _start_idx = u16::from_be_bytes([page[5 + DB_HEADER_SIZE], page[6 + DB_HEADER_SIZE]]);
-        let mut _right_most = 0;
-        if let Kind::InteriorTable = kind {
-            _right_most = u32::from_be_bytes([
-                page[8 + DB_HEADER_SIZE],
-                page[9 + DB_HEADER_SIZE],
-                page[10 + DB_HEADER_SIZE],
-                page[11 + DB_HEADER_SIZE],
-            ]);
+/// Reads the integers of REAL columns as the reals they stand for: sqlite
+/// stores a real without a fraction as an integer, which is smaller.
+fn real_affinity(values: &mut [Record], columns: &[ColumnDef]) {
+    for (value, column) in values.iter_mut().zip(columns) {
+        if let Some(v) = value.integer().filter(|_| column.affinity() == Affinity::Real) {
+            *value = Record::Float(v as f64);
         }
+    }
+}
 
-        let mut cell_pointers = Vec::with_capacity(num_of_cells as usize);
-        let header_end = match kind {
-            Kind::InteriorTable => 12 + DB_HEADER_SIZE as u16,
-            Kind::LeafTable => 8 + DB_HEADER_SIZE as u16,
-            _ => unreachable!(),
-        };
+/// The column of the joined row that `column` names in one of `sources`,
+/// whose columns make up `columns`.
+fn resolve_column(sources: &[Source], columns: &[ColumnDef], column: &ColumnRef) -> Result<ColumnRef> {
+    let mut found = sources.iter().filter_map(|source| {
+        let qualified = column
+            .table
+            .as_ref()
+            .is_none_or(|t| t.eq_ignore_ascii_case(&source.qualifier));
+        let idx = source.table.columns.iter().position(|c| c.name.eq_ignore_ascii_case(&column.name));
+        // `rowid`, `oid` and `_rowid_` name the rowid unless a column takes the name.
+        let idx = idx.or_else(|| {
+            let rowid = ["rowid", "oid", "_rowid_"];
+            rowid.iter().any(|n| n.eq_ignore_ascii_case(&column.name)).then_some(())?;
+            source.table.columns.iter().position(|c| c.rowid_alias)
+        });
+        idx.filter(|_| qualified).map(|idx| source.offset + idx)
+    });
+    match (found.next(), found.next()) {
+        (Some(idx), None) => Ok(ColumnRef::bare(&columns[idx].name)),
+        (Some(_), Some(_)) => Err(anyhow!(msg!("ambiguous-column", column = column))),
+        (None, _) => {
+            let table = column.table.as_deref().or(match sources {
+                [source] => Some(source.name.as_str()),
+                _ => None,
+            });
+            Err(Error::no_such_column(&column.to_string(), table, columns.iter().map(|c| c.name.as_str())))?
+        }
+    }
+}
 
-        cell_pointers.extend((0..num_of_cells).map(|i| {
-            let offset = (header_end + i * 2) as usize;
-            u16::from_be_bytes([page[offset], page[offset + 1]])
-        }));
+/// The number `N` that ends the name of an automatic index,
+/// `sqlite_autoindex_TABLE_N`, counting from 1.
+fn autoindex_number(name: &str) -> Option<usize> {
+    name.rsplit('_').next()?.parse().ok().filter(|&n| n > 0)
+}
 
-        match kind {
-            Kind::LeafTable => {
-                let mut schema = Vec::new();
-                for ptr in cell_pointers {
-                    let cell = &page[ptr as usize..];
-                    let (_length, cell, _) = parse_varint(cell)?;
-                    let (_id, cell, _) = parse_varint(cell)?;
-                    let (rec_header_size, mut cell, varint_size) = parse_varint(cell)?;
-                    let mut col_types = Vec::new();
-                    let mut cur_header_size = varint_size;
-                    while cur_header_size < rec_header_size as usize {
-                        let (column_type, remaining_cell, varint_size) = parse_varint(cell)?;
-                        let col_type = match column_type {
-                            0 => ColumnType::Null,
-                            1 => ColumnType::Int8,
-                            2 => ColumnType::Int16,
-                            3 => ColumnType::Int24,
-                            4 => ColumnType::Int32,
-                            5 => ColumnType::Int48,
-                            6 => ColumnType::Int64,
-                            7 => ColumnType::Float,
-                            8 => ColumnType::Zero,
-                            9 => ColumnType::One,
-                            10 => ColumnType::Reserved1,
-                            11 => ColumnType::Reserved2,
-                            n if n % 2 == 0 => ColumnType::Blob((n - 12) as usize / 2),
-                            n => ColumnType::Text((n - 13) as usize / 2),
-                        };
-                        col_types.push(col_type);
-                        cur_header_size += varint_size;
-                        cell = remaining_cell;
-                    }
+/// A declared type as `PRAGMA table_info` shows it: sqlite spells the type
+/// names STRICT tables allow in upper case, and others as written.
+fn pragma_type(declared: &str) -> String {
+    let strict = ["INT", "INTEGER", "REAL", "TEXT", "BLOB", "ANY"];
+    match strict.iter().find(|name| name.eq_ignore_ascii_case(declared)) {
+        Some(name) => name.to_string(),
+        None => declared.to_string(),
+    }
+}
 
-                    match col_types[..] {
-                        [ColumnType::Text(type_len), ColumnType::Text(name_len), ColumnType::Text(tbl_name_len), ColumnType::Int8 | ColumnType::Int24, ColumnType::Text(sql_len)] =>
-                        {
-                            let (text, cell) = cell.split_at(type_len);
-                            let kind = std::str::from_utf8(text)?;
-
-                            let kind = match kind {
-                                "table" => schema::Kind::Table,
-                                "index" => schema::Kind::Index,
-                                "view" => schema::Kind::View,
-                                "trigger" => schema::Kind::Trigger,
-                                _ => Err(anyhow!("Invalid kind"))?,
-                            };
-
-                            let (text, cell) = cell.split_at(name_len);
-                            let name = std::str::from_utf8(text)?;
-
-                            let (text, cell) = cell.split_at(tbl_name_len);
-                            let tbl_name = std::str::from_utf8(text)?;
-
-                            let (cell, rootpage) = match col_types[3] {
-                                ColumnType::Int8 => {
-                                    let (cell, rootpage) = be_i8::<_, ()>(cell)?;
-                                    (cell, rootpage as usize)
-                                }
-                                ColumnType::Int24 => {
-                                    let (cell, rootpage) = be_i24::<_, ()>(cell)?;
-                                    (cell, rootpage as usize)
-                                }
-                                _ => unreachable!(),
-                            };
-
-                            let (text, _) = cell.split_at(sql_len);
-                            let sql = std::str::from_utf8(text)?;
-
-                            schema.push(Schema {
-                                kind,
-                                name: name.to_owned(),
-                                tbl_name: tbl_name.to_owned(),
-                                rootpage,
-                                sql: sql.to_owned(),
-                            });
-                        }
-                        _ => Err(anyhow!("Invalid schema"))?,
-                    }
-                }
+/// A DEFAULT clause as `PRAGMA table_info` shows it: an expression without
+/// the parentheses it is written in.
+fn pragma_default(default: &str) -> &str {
+    let Some(inner) = default.strip_prefix('(').and_then(|d| d.strip_suffix(')')) else {
+        return default;
+    };
+    // `(1) + (2)` is not wrapped as a whole; quoted parentheses do not count.
+    let (mut depth, mut quote) = (0, None);
+    for c in inner.chars() {
+        match (c, quote) {
+            (_, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {}
+            ('\'' | '"', None) => quote = Some(c),
+            ('(', None) => depth += 1,
+            (')', None) if depth == 0 => return default,
+            (')', None) => depth -= 1,
+            _ => {}
+        }
+    }
+    inner.trim()
+}
 
-                Ok(schema)
-            }
-            Kind::InteriorTable => unimplemented!(),
-            _ => unreachable!(),
+/// Hands `rows` to `on_row` one at a time, until it breaks off.
+fn visit_rows(rows: &[Vec<Record>], on_row: &mut RowCallback) -> Result<ControlFlow<()>> {
+    for row in rows {
+        if on_row(row.clone())?.is_break() {
+            return Ok(ControlFlow::Break(()));
         }
     }
+    Ok(ControlFlow::Continue(()))
 }
 
-fn parse_varint(data: &[u8]) -> Result<(u64, &[u8], usize)> {
-    let mut result: u64 = 0;
+/// Column values of a WITHOUT ROWID table row from its record, whose fields
+/// hold the columns in `order`.
+fn without_rowid_values(keys: Vec<Record>, columns: &[ColumnDef], order: &[usize]) -> Vec<Record> {
+    let mut values = columns.iter().map(ColumnDef::default_value).collect_vec();
+    for (value, &idx) in keys.into_iter().zip(order) {
+        values[idx] = value;
+    }
+    real_affinity(&mut values, columns);
+    values
+}
 
-    for (idx, &byte) in data.iter().enumerate() {
-        if idx >= 10 {
-            return Err(anyhow!("Varint is too long"));
-        }
+/// Bytes of a payload kept on the b-tree page itself, as sqlite splits it,
+/// for pages with `usable` bytes.
+fn local_payload(usable: usize, payload_size: usize, table_leaf: bool) -> usize {
+    let max_local = match table_leaf {
+        true => usable - 35,
+        false => (usable - 12) * 64 / 255 - 23,
+    };
+    let min_local = (usable - 12) * 32 / 255 - 23;
+    if payload_size <= max_local {
+        return payload_size;
+    }
+    let local = min_local + (payload_size - min_local) % (usable - 4);
+    if local <= max_local {
+        local
+    } else {
+        min_local
+    }
+}
+
+/// sqlite's wording for a file whose structure does not add up, at `page`
+/// when a single page is to blame.
+fn malformed(page: Option<usize>, reason: String) -> anyhow::Error {
+    Error::CorruptPage { page, reason }.into()
+}
 
-        result = (result << 7) | (byte & 0x7F) as u64;
+/// The affinity sqlite converts both sides of `left` compared with `right`
+/// to: a column's when the other side is not a column, NUMERIC for two
+/// columns of which one is numeric, and none otherwise.
+fn comparison_affinity(left: &Expr, right: &Expr, columns: &[ColumnDef]) -> Option<Affinity> {
+    let affinity = |expr: &Expr| match expr {
+        Expr::Column(column) => columns.iter().find(|c| c.name.eq_ignore_ascii_case(&column.name)).map(ColumnDef::affinity),
+        _ => None,
+    };
+    let numeric = |affinity| matches!(affinity, Affinity::Integer | Affinity::Real | Affinity::Numeric);
+    match (affinity(left), affinity(right)) {
+        (Some(a), Some(b)) if numeric(a) || numeric(b) => Some(Affinity::Numeric),
+        (Some(_), Some(_)) => None,
+        (a, b) => a.or(b),
+    }
+}
 
-        if byte & 0x80 == 0 {
-            return Ok((result, &data[idx + 1..], idx + 1));
-        }
+/// `value` converted to `affinity` for a comparison, when there is one.
+fn coerce(affinity: Option<Affinity>, value: Record) -> Record {
+    match affinity {
+        Some(affinity) => functions::apply_affinity(affinity, value),
+        None => value,
     }
+}
+
+/// Whether `column` is declared `COLLATE NOCASE`.
+fn is_nocase(column: &str, columns: &[ColumnDef]) -> bool {
+    column_collation(column, columns, CaseFolding::Ascii) != Collation::Binary
+}
+
+/// The collation `column` is declared with, folding NOCASE text with `folding`.
+fn column_collation(column: &str, columns: &[ColumnDef], folding: CaseFolding) -> Collation {
+    let declared = columns.iter().find(|c| c.name.eq_ignore_ascii_case(column)).and_then(|c| c.collation.as_deref());
+    Collation::named(declared, folding)
+}
 
-    Err(anyhow!("Varint is incomplete"))
+/// Compares `key` with a bound over the columns both have, the way the index
+/// orders them.
+fn compare_key(key: &[Record], bound: &[Record], columns: &[IndexColumn]) -> Ordering {
+    for (idx, (a, b)) in key.iter().zip(bound).enumerate() {
+        let column = columns.get(idx);
+        // Indexes are kept in sqlite's order, which folds ASCII letters only.
+        let collation = Collation::named(column.and_then(|c| c.collation.as_deref()), CaseFolding::Ascii);
+        let order = collation.compare(a, b);
+        let order = match column.is_some_and(|c| c.descending) {
+            true => order.reverse(),
+            false => order,
+        };
+        if order.is_ne() {
+            return order;
+        }
+    }
+    Ordering::Equal
 }
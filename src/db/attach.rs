@@ -0,0 +1,139 @@
+//! ATTACH and DETACH: other database files opened alongside the main one,
+//! each with its own pages and schema, whose tables queries name as
+//! `schema.table`. Attached databases are only read; writes still go to the
+//! main one.
+
+use super::{schema, Database, Source};
+use crate::{error::Error, messages::msg, output::RowWriter, record::Record};
+use anyhow::{anyhow, Result};
+use std::sync::{atomic, Arc};
+
+impl Database {
+    pub(super) fn execute_attach(&mut self, path: &str, name: &str) -> Result<()> {
+        if self.in_transaction.load(atomic::Ordering::Relaxed) {
+            Err(anyhow!(msg!("attach-in-transaction")))?
+        }
+        let taken = ["main", "temp"].iter().any(|taken| taken.eq_ignore_ascii_case(name))
+            || self.attached.iter().any(|(attached, _)| attached.eq_ignore_ascii_case(name));
+        if taken {
+            Err(anyhow!(msg!("database-in-use", name = name)))?
+        }
+        let mut db = Database::load_db(path.to_string()).map_err(|e| anyhow!(msg!("cannot-open-database", path = path, error = e)))?;
+        db.copy_settings(self);
+        self.attached.push((name.to_string(), Arc::new(db)));
+        Ok(())
+    }
+
+    pub(super) fn execute_detach(&mut self, name: &str) -> Result<()> {
+        match self.attached.iter().position(|(attached, _)| attached.eq_ignore_ascii_case(name)) {
+            Some(idx) => {
+                self.attached.remove(idx);
+                Ok(())
+            }
+            None => Err(anyhow!(msg!("no-such-database", name = name))),
+        }
+    }
+
+    /// The attached database `table` is read from, or `None` for the main
+    /// one. A table named without a schema is looked for in the main
+    /// database first and then in the attached ones in the order they were
+    /// attached.
+    pub(super) fn catalog(&self, schema: Option<&str>, table: &str) -> Result<Option<Arc<Database>>> {
+        match schema {
+            Some(schema) if schema.eq_ignore_ascii_case("main") => Ok(None),
+            Some(schema) => {
+                let db = self.attached.iter().find(|(name, _)| name.eq_ignore_ascii_case(schema)).map(|(_, db)| db);
+                match db {
+                    Some(db) if db.defines(table) => Ok(Some(Arc::clone(db))),
+                    _ => {
+                        let tables = db.iter().flat_map(|db| db.schema.iter().filter(|s| s.kind == schema::Kind::Table));
+                        let qualified = tables.map(|s| format!("{}.{}", schema, s.name)).collect::<Vec<_>>();
+                        Err(Error::no_such_table(&format!("{}.{}", schema, table), qualified.iter().map(String::as_str)))?
+                    }
+                }
+            }
+            None if self.defines(table) => Ok(None),
+            None => Ok(self
+                .attached
+                .iter()
+                .find(|(_, db)| db.defines(table))
+                .map(|(_, db)| Arc::clone(db))),
+        }
+    }
+
+    /// The database `source`'s pages are read from.
+    pub(super) fn on<'a>(&'a self, source: &'a Source) -> &'a Database {
+        source.db.as_deref().unwrap_or(self)
+    }
+
+    /// `PRAGMA database_list`: the main database and every attached one,
+    /// with the files they were opened from.
+    pub(super) fn database_list(&self, out: &mut dyn RowWriter) -> Result<()> {
+        let header = ["seq", "name", "file"];
+        out.write_header(&header.map(String::from))?;
+        // Like sqlite, which keeps seq 1 for the temp database.
+        let main = std::iter::once((0, "main", self));
+        let attached = self.attached.iter().enumerate().map(|(idx, (name, db))| (idx + 2, name.as_str(), db.as_ref()));
+        for (seq, name, db) in main.chain(attached) {
+            out.write_row(&[
+                Record::Int64(seq as i64),
+                Record::Text(name.to_string()),
+                Record::Text(db.path().unwrap_or_default().to_string()),
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{record::Record, Database};
+    use std::{fs, path::PathBuf};
+
+    /// sample.db, with a database of its own holding only a `notes` table
+    /// attached as `n`, and the path of that database.
+    fn with_notes(name: &str) -> (Database, PathBuf) {
+        let path = std::env::temp_dir().join(format!("sqlite-lite-{}-{}.db", name, std::process::id()));
+        fs::write(&path, b"").unwrap();
+        let mut notes = Database::load_db(path.to_str().unwrap().to_string()).unwrap();
+        notes.query("CREATE TABLE notes(id INTEGER PRIMARY KEY, body TEXT)").unwrap();
+        notes.import_rows("notes", vec![vec![Record::Null, Record::Text("attached".to_string())]]).unwrap();
+        drop(notes);
+        let mut db = Database::load_db(concat!(env!("CARGO_MANIFEST_DIR"), "/sample.db").to_string()).unwrap();
+        db.query(&format!("ATTACH DATABASE '{}' AS n", path.display())).unwrap();
+        (db, path)
+    }
+
+    fn single(db: &mut Database, sql: &str) -> String {
+        db.query(sql).unwrap()[0].get_at(0).unwrap()
+    }
+
+    #[test]
+    fn tables_are_found_by_schema_or_name() {
+        let (mut db, path) = with_notes("attach-read");
+        assert_eq!(single(&mut db, "SELECT body FROM n.notes"), "attached");
+        // Not in the main database, so looked for in the attached ones.
+        assert_eq!(single(&mut db, "SELECT body FROM notes WHERE id = 1"), "attached");
+        assert_eq!(single(&mut db, "SELECT name FROM main.apples WHERE id = 2"), "Fuji");
+        assert!(db.query("SELECT name FROM n.apples").is_err());
+        drop(db);
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_file(format!("{}-journal", path.display()));
+    }
+
+    #[test]
+    fn names_are_taken_until_detached() {
+        let (mut db, path) = with_notes("attach-names");
+        let again = format!("ATTACH '{}' AS N", path.display());
+        assert!(db.query(&again).is_err());
+        assert!(db.query(&format!("ATTACH '{}' AS main", path.display())).is_err());
+        db.query("DETACH n").unwrap();
+        assert!(db.query("SELECT body FROM notes").is_err());
+        assert!(db.query("DETACH n").is_err());
+        db.query(&again).unwrap();
+        assert_eq!(single(&mut db, "SELECT body FROM N.notes"), "attached");
+        drop(db);
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_file(format!("{}-journal", path.display()));
+    }
+}
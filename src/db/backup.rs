@@ -0,0 +1,325 @@
+//! Online backup: copies the database page by page into another file while
+//! sqlite processes may go on writing to it. Each step copies a run of
+//! pages under sqlite's shared lock, which keeps writers from committing,
+//! and lets go of it before the next, so that they are only held up for a
+//! moment. A change counter that moved between two steps means a writer
+//! committed in between; the copy then starts over, handing on again the
+//! pages that no longer match.
+//!
+//! Incremental backups keep the SHA-256 of every page in a state file and
+//! write only the pages whose hash changed to a delta file, which
+//! [`apply_delta`] replays onto the copy the previous delta left behind.
+
+use super::Database;
+use crate::{interrupt, journal, lock::SharedLock, messages::msg, sha256::Sha256, source::PageSource};
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+};
+
+/// Pages copied each time the shared lock is taken.
+const STEP_PAGES: usize = 256;
+/// Times the copy starts over before giving up on a database that keeps changing.
+const MAX_RESTARTS: usize = 100;
+
+const STATE_MAGIC: &[u8; 8] = b"SQLHASH1";
+/// Magic, page size, change counter and page count.
+const STATE_HEADER: usize = 20;
+const DELTA_MAGIC: &[u8; 8] = b"SQLDELT1";
+/// Magic, page size, page count, record count, then the fingerprints of the
+/// database before and after.
+const DELTA_HEADER: usize = 84;
+
+/// What an incremental backup wrote, as [`Database::backup_delta`] reports it.
+#[derive(Debug)]
+pub struct Delta {
+    /// Size of the database, in pages.
+    pub pages: usize,
+    /// Pages the delta holds: the ones that changed, or every page when there
+    /// was no state to compare with.
+    pub changed: usize,
+}
+
+/// Page hashes of the database as the last incremental backup saw it.
+struct State {
+    page_size: usize,
+    change_counter: u32,
+    hashes: Vec<[u8; 32]>,
+}
+
+impl State {
+    fn read(path: &str) -> Result<Option<State>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => Err(e)?,
+        };
+        let invalid = || anyhow!(msg!("not-a-backup-state", path = path));
+        if bytes.len() < STATE_HEADER || &bytes[..8] != STATE_MAGIC {
+            Err(invalid())?
+        }
+        let page_count = u32_at(&bytes, 16) as usize;
+        let hashes = bytes[STATE_HEADER..].chunks_exact(32).map(|h| h.try_into().unwrap()).collect::<Vec<_>>();
+        if hashes.len() != page_count || bytes.len() != STATE_HEADER + 32 * page_count {
+            Err(invalid())?
+        }
+        Ok(Some(State {
+            page_size: u32_at(&bytes, 8) as usize,
+            change_counter: u32_at(&bytes, 12),
+            hashes,
+        }))
+    }
+
+    /// Replaces the file at `path` in one step, so that a crash leaves either
+    /// the old state or the new one.
+    fn write(&self, path: &str) -> Result<()> {
+        let mut bytes = Vec::with_capacity(STATE_HEADER + 32 * self.hashes.len());
+        bytes.extend_from_slice(STATE_MAGIC);
+        bytes.extend_from_slice(&(self.page_size as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.change_counter.to_be_bytes());
+        bytes.extend_from_slice(&(self.hashes.len() as u32).to_be_bytes());
+        bytes.extend(self.hashes.iter().flatten());
+        let temp = format!("{}.tmp", path);
+        let file = File::create(&temp)?;
+        file.write_all_at(&bytes, 0)?;
+        PageSource::sync(&file)?;
+        fs::rename(&temp, path)?;
+        Ok(())
+    }
+
+    fn fingerprint(&self) -> [u8; 32] {
+        fingerprint(&self.hashes)
+    }
+}
+
+impl Database {
+    /// Copies the database into the file at `path`, replacing whatever it
+    /// held, and returns how many pages were copied. Changes committed by
+    /// other processes in the meantime end up in the copy as well. A WAL
+    /// file that still holds changes is refused: the copy is taken under
+    /// the rollback journal's lock, which does not keep WAL commits out.
+    pub fn backup_to(&self, path: &str) -> Result<usize> {
+        self.check_backup_target(path)?;
+        let dest = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        let mut copy = vec![0; self.page_size];
+        let (page_count, _) = self.copy_pages(&mut |page_num, page| {
+            let offset = ((page_num - 1) * self.page_size) as u64;
+            // Pages an earlier pass already copied are left alone.
+            if dest.read_exact_at(&mut copy, offset).is_err() || copy != page {
+                dest.write_all_at(page, offset)?;
+            }
+            Ok(())
+        })?;
+        PageSource::truncate(&dest, (page_count * self.page_size) as u64)?;
+        PageSource::sync(&dest)?;
+        Ok(page_count)
+    }
+
+    /// Writes the pages that changed since the incremental backup that left
+    /// the state file at `state` to a new delta file at `delta`, then
+    /// updates the state. Without a state file every page goes into the
+    /// delta, which then recreates the whole database. Before hashing any
+    /// page, a change counter and size the same as in the state mean
+    /// nothing changed.
+    pub fn backup_delta(&self, state: &str, delta: &str) -> Result<Delta> {
+        self.check_backup_target(delta)?;
+        let base = State::read(state)?;
+        if let Some(base) = &base {
+            if base.page_size != self.page_size {
+                Err(anyhow!(msg!("backup-state-page-size", path = state, page_size = base.page_size)))?
+            }
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(delta)?;
+        let base_hashes = base.as_ref().map_or(&[][..], |base| &base.hashes);
+        let base_fingerprint = base.as_ref().map_or([0; 32], State::fingerprint);
+
+        let unchanged = base.as_ref().filter(|base| {
+            self.change_counter().is_ok_and(|counter| counter == base.change_counter)
+                && self.page_count().is_ok_and(|count| count == base.hashes.len())
+        });
+        if let Some(base) = unchanged {
+            write_delta_header(&file, self.page_size, base.hashes.len(), 0, &base_fingerprint, &base_fingerprint)?;
+            PageSource::sync(&file)?;
+            return Ok(Delta {
+                pages: base.hashes.len(),
+                changed: 0,
+            });
+        }
+
+        // A page handed on again after a restart goes over its earlier record.
+        let mut records = HashMap::new();
+        let mut hashes = Vec::new();
+        let (page_count, change_counter) = self.copy_pages(&mut |page_num, page| {
+            let mut hasher = Sha256::new();
+            hasher.update(page);
+            let hash = hasher.finish();
+            if hashes.len() < page_num {
+                hashes.resize(page_num, [0; 32]);
+            }
+            hashes[page_num - 1] = hash;
+            if base_hashes.get(page_num - 1) == Some(&hash) && !records.contains_key(&page_num) {
+                return Ok(());
+            }
+            let idx = records.len();
+            let idx = *records.entry(page_num).or_insert(idx);
+            let offset = (DELTA_HEADER + idx * (4 + self.page_size)) as u64;
+            file.write_all_at(&(page_num as u32).to_be_bytes(), offset)?;
+            file.write_all_at(page, offset + 4)
+        })?;
+        hashes.truncate(page_count);
+
+        let state_after = State {
+            page_size: self.page_size,
+            change_counter,
+            hashes,
+        };
+        write_delta_header(
+            &file,
+            self.page_size,
+            page_count,
+            records.len(),
+            &base_fingerprint,
+            &state_after.fingerprint(),
+        )?;
+        PageSource::sync(&file)?;
+        state_after.write(state)?;
+        Ok(Delta {
+            pages: page_count,
+            changed: records.keys().filter(|&&page_num| page_num <= page_count).count(),
+        })
+    }
+
+    fn check_backup_target(&self, path: &str) -> Result<()> {
+        if self.journal.lock().unwrap().is_some() {
+            Err(anyhow!(msg!("backup-in-transaction")))?
+        }
+        if let Some(source) = &self.path {
+            if let (Ok(a), Ok(b)) = (fs::canonicalize(source), fs::canonicalize(path)) {
+                if a == b {
+                    Err(anyhow!(msg!("backup-onto-itself", path = source)))?
+                }
+            }
+            if fs::metadata(format!("{}-wal", source)).is_ok_and(|m| m.len() > 0) {
+                Err(anyhow!(msg!("backup-wal-not-checkpointed", path = source)))?
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands every page of the database to `on_page` in order, a run at a
+    /// time under the shared lock, starting over from page 1 whenever a
+    /// writer committed between two runs. Returns the page count and the
+    /// change counter of the database the final pass copied.
+    fn copy_pages(&self, on_page: &mut dyn FnMut(usize, &[u8]) -> Result<()>) -> Result<(usize, u32)> {
+        let lock_file = self.path.as_ref().map(File::open).transpose()?;
+        let mut next = 1;
+        let mut counter = None;
+        let mut restarts = 0;
+        loop {
+            interrupt::check()?;
+            let lock = lock_file.as_ref().map(SharedLock::acquire).transpose()?;
+            if let (Some(lock), Some(source)) = (&lock, &self.path) {
+                // A journal no writer holds on to is what a crashed one left.
+                if !lock.writer_active()? && journal::is_hot(&format!("{}-journal", source))? {
+                    Err(anyhow!(msg!("backup-hot-journal", path = source)))?
+                }
+            }
+            let now = self.change_counter()?;
+            if counter.is_some_and(|counter| counter != now) {
+                restarts += 1;
+                if restarts > MAX_RESTARTS {
+                    Err(anyhow!(msg!("backup-kept-changing")))?
+                }
+                next = 1;
+            }
+            counter = Some(now);
+
+            let page_count = self.page_count()?;
+            let end = (next + STEP_PAGES).min(page_count + 1);
+            for page_num in next..end {
+                on_page(page_num, &self.read_raw_page(page_num)?)?;
+            }
+            next = end;
+            if next > page_count {
+                return Ok((page_count, now));
+            }
+        }
+    }
+}
+
+/// Replays the delta file at `delta` onto the database file at `target`,
+/// which must hold exactly what the database held when the backup before
+/// that delta was taken; a delta taken without a state file rewrites any
+/// file. Returns the number of pages written.
+pub fn apply_delta(delta: &str, target: &str) -> Result<usize> {
+    let file = File::open(delta)?;
+    let mut header = [0; DELTA_HEADER];
+    if file.read_exact_at(&mut header, 0).is_err() || &header[..8] != DELTA_MAGIC {
+        Err(anyhow!(msg!("not-a-delta", path = delta)))?
+    }
+    let page_size = u32_at(&header, 8) as usize;
+    let page_count = u32_at(&header, 12) as usize;
+    let records = u32_at(&header, 16) as usize;
+    let base: [u8; 32] = header[20..52].try_into().unwrap();
+    if file.size()? != (DELTA_HEADER + records * (4 + page_size)) as u64 {
+        Err(anyhow!(msg!("delta-cut-short", path = delta)))?
+    }
+
+    let dest = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(target)?;
+    if base != [0; 32] {
+        let size = dest.size()? as usize;
+        let mut page = vec![0; page_size];
+        let mut hashes = Vec::with_capacity(size / page_size);
+        for page_num in 0..size / page_size {
+            dest.read_exact_at(&mut page, (page_num * page_size) as u64)?;
+            let mut hasher = Sha256::new();
+            hasher.update(&page);
+            hashes.push(hasher.finish());
+        }
+        if !size.is_multiple_of(page_size) || fingerprint(&hashes) != base {
+            Err(anyhow!(msg!("delta-wrong-base", path = target, delta = delta)))?
+        }
+    }
+
+    let mut record = vec![0; 4 + page_size];
+    for idx in 0..records {
+        file.read_exact_at(&mut record, (DELTA_HEADER + idx * (4 + page_size)) as u64)?;
+        let page_num = u32_at(&record, 0) as usize;
+        dest.write_all_at(&record[4..], ((page_num - 1) * page_size) as u64)?;
+    }
+    PageSource::truncate(&dest, (page_count * page_size) as u64)?;
+    PageSource::sync(&dest)?;
+    Ok(records)
+}
+
+fn write_delta_header(
+    file: &File,
+    page_size: usize,
+    page_count: usize,
+    records: usize,
+    base: &[u8; 32],
+    after: &[u8; 32],
+) -> Result<()> {
+    let mut header = Vec::with_capacity(DELTA_HEADER);
+    header.extend_from_slice(DELTA_MAGIC);
+    for field in [page_size, page_count, records] {
+        header.extend_from_slice(&(field as u32).to_be_bytes());
+    }
+    header.extend_from_slice(base);
+    header.extend_from_slice(after);
+    file.write_all_at(&header, 0)
+}
+
+/// Tells databases apart by the hashes of all their pages.
+fn fingerprint(hashes: &[[u8; 32]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for hash in hashes {
+        hasher.update(hash);
+    }
+    hasher.finish()
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
@@ -0,0 +1,138 @@
+//! Table scans that decode rows in place. Text and blobs borrow from the
+//! page they are on, which for a memory-mapped file is the map itself, so a
+//! scan copies nothing per value; only rows that spill onto overflow pages
+//! are put together in a buffer of their own first.
+
+use super::{btree::Walk, local_payload, malformed, Database, Table};
+use crate::{
+    interrupt,
+    messages::msg,
+    record::{decode_borrowed, parse_varint, ValueRef},
+    sql::Affinity,
+    DB_HEADER_SIZE,
+};
+use anyhow::{anyhow, Result};
+use std::ops::ControlFlow;
+
+/// Receives each row of a borrowed scan with its rowid; the values only live
+/// as long as the call. `Break` stops the scan.
+type BorrowedRowCallback<'a> = dyn FnMut(i64, &[ValueRef<'_>]) -> Result<ControlFlow<()>> + 'a;
+
+/// Where a scan of a leaf page has got to: the next cell to read, and the
+/// part of an overflowing one stored on the page, to be finished off the
+/// page once the page is let go.
+struct Leaf {
+    next: usize,
+    spilled: Option<(i64, usize, Vec<u8>)>,
+}
+
+impl Database {
+    /// Visits every row of the rowid table `table` in rowid order, like
+    /// [`Database::scan_table`] but with the values borrowed from the file:
+    /// the INTEGER PRIMARY KEY column reads the rowid, REAL columns read
+    /// reals, and columns added after a row was written read their default.
+    pub fn scan_borrowed(&self, table: &str, on_row: &mut BorrowedRowCallback) -> Result<()> {
+        let Table { columns, key_order, .. } = self.get_table(table)?;
+        if key_order.is_some() {
+            Err(anyhow!(msg!("borrowed-without-rowid", table = table)))?
+        }
+        self.pin_snapshots()?;
+        let defaults = columns.iter().map(|c| ValueRef::from(c.default_value())).collect::<Vec<_>>();
+        let rowid_alias = columns.iter().position(|c| c.rowid_alias);
+        let reals = columns.iter().map(|c| c.affinity() == Affinity::Real).collect::<Vec<_>>();
+        let mut visit = |rowid: i64, mut values: Vec<ValueRef>| {
+            values.extend(defaults.iter().skip(values.len()).cloned());
+            if let Some(value @ ValueRef::Null) = rowid_alias.and_then(|idx| values.get_mut(idx)) {
+                *value = ValueRef::Integer(rowid);
+            }
+            for (value, _) in values.iter_mut().zip(&reals).filter(|(_, &real)| real) {
+                if let ValueRef::Integer(v) = value {
+                    *value = ValueRef::Float(*v as f64);
+                }
+            }
+            on_row(rowid, &values)
+        };
+
+        let mut walk = Walk::default();
+        let mut stack = vec![self.get_table_rootpage(table)?];
+        while let Some(page_num) = stack.pop() {
+            interrupt::check()?;
+            if let Some(trace) = &self.read_trace {
+                trace.read(page_num);
+            }
+            let mut leaf = Leaf { next: 0, spilled: None };
+            loop {
+                let mut children = Vec::new();
+                let mut flow = ControlFlow::Continue(());
+                self.db.with_bytes(((page_num - 1) * self.page_size) as u64, self.page_size, &mut |page| {
+                    let header = if page_num == 1 { DB_HEADER_SIZE } else { 0 };
+                    let short = || malformed(Some(page_num), msg!("cell-runs-past-end", page = page_num));
+                    let field = |at: usize, len: usize| page.get(header + at..header + at + len).ok_or_else(short);
+                    let cells = u16::from_be_bytes(field(3, 2)?.try_into()?) as usize;
+                    let pointer = |idx: usize, first: usize| -> Result<usize> {
+                        let at = field(first + 2 * idx, 2)?;
+                        Ok(u16::from_be_bytes([at[0], at[1]]) as usize)
+                    };
+                    match page.get(header) {
+                        Some(5) => {
+                            for idx in 0..cells {
+                                let cell = page.get(pointer(idx, 12)?..).filter(|c| c.len() >= 4).ok_or_else(short)?;
+                                children.push(u32::from_be_bytes(cell[..4].try_into()?) as usize);
+                            }
+                            children.push(u32::from_be_bytes(field(8, 4)?.try_into()?) as usize);
+                            Ok(())
+                        }
+                        Some(13) => {
+                            while leaf.next < cells {
+                                let cell = page.get(pointer(leaf.next, 8)?..).ok_or_else(short)?;
+                                let (size, cell, _) = parse_varint(cell)?;
+                                let (rowid, payload, _) = parse_varint(cell)?;
+                                let size = size as usize;
+                                let local = local_payload(self.usable_size, size, true);
+                                if local < size {
+                                    let stored = payload.get(..local + 4).ok_or_else(short)?;
+                                    leaf.spilled = Some((rowid as i64, size, stored.to_vec()));
+                                    return Ok(());
+                                }
+                                let record = payload.get(..size).ok_or_else(short)?;
+                                let values = self.decode_in_place(page_num, leaf.next, record)?;
+                                leaf.next += 1;
+                                flow = visit(rowid as i64, values)?;
+                                if flow.is_break() {
+                                    break;
+                                }
+                            }
+                            Ok(())
+                        }
+                        _ => Err(malformed(Some(page_num), msg!("not-a-table-page", page = page_num))),
+                    }
+                })?;
+                if flow.is_break() {
+                    return Ok(());
+                }
+                for &child in children.iter().rev() {
+                    walk.check_child(page_num, child)?;
+                    stack.push(child);
+                }
+                // The overflow pages are read after the page is let go, which
+                // a memory map must be before it can be redrawn.
+                let Some((rowid, size, stored)) = leaf.spilled.take() else {
+                    break;
+                };
+                let payload = self.full_payload(page_num, &stored, size, true, self.usable_size)?;
+                let values = self.decode_in_place(page_num, leaf.next, &payload)?;
+                leaf.next += 1;
+                if visit(rowid, values)?.is_break() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The values of the record `bytes` of cell `cell_idx` on `page_num`.
+    fn decode_in_place<'a>(&self, page_num: usize, cell_idx: usize, bytes: &'a [u8]) -> Result<Vec<ValueRef<'a>>> {
+        decode_borrowed(bytes, 0, self.schema_format, self.text_encoding, self.text_decoding)
+            .map_err(|e| malformed(Some(page_num), msg!("in-cell", cell = cell_idx, page = page_num, error = e)))
+    }
+}
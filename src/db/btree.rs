@@ -0,0 +1,405 @@
+//! Raw access to one b-tree, below the SQL layer: a [`Cursor`] hands out
+//! the cells of a table or index b-tree in key order, each with its rowid
+//! and its whole payload, for tools that scan or inspect trees themselves.
+
+use super::{
+    compare_key, integrity::decode_record, local_payload, malformed,
+    pages::{INTERIOR_INDEX, INTERIOR_TABLE, LEAF_INDEX, LEAF_TABLE, MAX_DEPTH},
+    parse_varint, Database, RowKey,
+};
+use crate::{error::Error, messages::msg, record::Record, DB_HEADER_SIZE};
+use anyhow::{anyhow, Result};
+use std::{collections::HashMap, rc::Rc};
+
+/// One entry of a b-tree: a row of a table, or a record of an index or a
+/// WITHOUT ROWID table.
+#[derive(Debug, Clone)]
+pub struct Cell {
+    /// Page the cell is on.
+    pub page: usize,
+    /// The rowid, for a cell of a table b-tree.
+    pub rowid: Option<i64>,
+    /// The record, with the part spilled onto overflow pages.
+    pub payload: Vec<u8>,
+}
+
+impl Cell {
+    /// The values of the record.
+    pub fn values(&self, db: &Database) -> Result<Vec<Record>> {
+        decode_record(&self.payload, db)
+    }
+}
+
+/// The b-tree rooted at a page, as found in the `rootpage` column of
+/// `sqlite_schema`.
+#[derive(Clone, Copy)]
+pub struct Btree<'a> {
+    db: &'a Database,
+    rootpage: usize,
+    table: bool,
+    usable: usize,
+}
+
+impl<'a> Btree<'a> {
+    /// The b-tree rooted at `rootpage`, which must be a b-tree page.
+    pub fn open(db: &'a Database, rootpage: usize) -> Result<Self> {
+        if rootpage == 0 || rootpage > db.page_count()? {
+            Err(anyhow!(msg!("page-not-in-database", page = rootpage)))?
+        }
+        let usable = db.page_size - db.read_raw_page(1)?[20] as usize;
+        let mut tree = Btree {
+            db,
+            rootpage,
+            table: false,
+            usable,
+        };
+        tree.table = tree.node(rootpage)?.is_table();
+        Ok(tree)
+    }
+
+    pub fn rootpage(&self) -> usize {
+        self.rootpage
+    }
+
+    /// Whether the tree holds a rowid table rather than an index or a
+    /// WITHOUT ROWID table.
+    pub fn is_table(&self) -> bool {
+        self.table
+    }
+
+    /// A cursor before the first cell.
+    pub fn cursor(&self) -> Cursor<'a> {
+        Cursor {
+            tree: *self,
+            stack: vec![Step::Page(self.rootpage)],
+            walk: Walk::default(),
+            writes: self.db.writes(),
+        }
+    }
+
+    fn node(&self, number: usize) -> Result<Node> {
+        let bytes = self.db.read_raw_page(number)?;
+        let header = if number == 1 { DB_HEADER_SIZE } else { 0 };
+        let kind = bytes[header];
+        let interior = match kind {
+            INTERIOR_TABLE | INTERIOR_INDEX => true,
+            LEAF_TABLE | LEAF_INDEX => false,
+            kind => Err(malformed(Some(number), msg!("unknown-page-type", page = number, kind = kind)))?,
+        };
+        let u16_at = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]) as usize;
+        let pointers = header + if interior { 12 } else { 8 };
+        let count = u16_at(header + 3);
+        if pointers + 2 * count > self.usable {
+            Err(malformed(Some(number), msg!("pointers-past-end", page = number)))?
+        }
+        let cells = (0..count).map(|idx| u16_at(pointers + 2 * idx)).collect::<Vec<_>>();
+        // Interior cells start with a 4-byte child page number.
+        let least = if interior { 4 } else { 1 };
+        if cells.iter().any(|&offset| offset < pointers || offset + least > self.usable) {
+            Err(malformed(Some(number), msg!("cell-starts-past-end", page = number)))?
+        }
+        let right = match interior {
+            true => u32::from_be_bytes([bytes[header + 8], bytes[header + 9], bytes[header + 10], bytes[header + 11]]),
+            false => 0,
+        };
+        Ok(Node {
+            number,
+            kind,
+            bytes,
+            cells,
+            right: right as usize,
+        })
+    }
+}
+
+/// A page of the tree and where its cells start.
+struct Node {
+    number: usize,
+    kind: u8,
+    bytes: Vec<u8>,
+    cells: Vec<usize>,
+    right: usize,
+}
+
+impl Node {
+    fn is_table(&self) -> bool {
+        matches!(self.kind, INTERIOR_TABLE | LEAF_TABLE)
+    }
+
+    fn is_interior(&self) -> bool {
+        matches!(self.kind, INTERIOR_TABLE | INTERIOR_INDEX)
+    }
+
+    /// Child `idx`: the left child of cell `idx`, or the right-most child
+    /// after the last cell.
+    fn child(&self, idx: usize) -> usize {
+        match self.cells.get(idx) {
+            Some(&offset) => u32::from_be_bytes(self.bytes[offset..offset + 4].try_into().unwrap()) as usize,
+            None => self.right,
+        }
+    }
+
+    fn rowid(&self, idx: usize) -> Result<i64> {
+        let cell = &self.bytes[self.cells[idx]..];
+        let (rowid, _, _) = match self.kind {
+            INTERIOR_TABLE => parse_varint(&cell[4..])?,
+            _ => parse_varint(parse_varint(cell)?.1)?,
+        };
+        Ok(rowid as i64)
+    }
+
+    fn cell(&self, tree: &Btree, idx: usize) -> Result<Cell> {
+        let cell = &self.bytes[self.cells[idx]..tree.usable];
+        let (rowid, payload) = match self.kind {
+            LEAF_TABLE => {
+                let (size, rest, _) = parse_varint(cell)?;
+                let (rowid, rest, _) = parse_varint(rest)?;
+                (Some(rowid as i64), tree.db.full_payload(self.number, rest, size as usize, true, tree.usable)?)
+            }
+            INTERIOR_INDEX => {
+                let (size, rest, _) = parse_varint(&cell[4..])?;
+                (None, tree.db.full_payload(self.number, rest, size as usize, false, tree.usable)?)
+            }
+            _ => {
+                let (size, rest, _) = parse_varint(cell)?;
+                (None, tree.db.full_payload(self.number, rest, size as usize, false, tree.usable)?)
+            }
+        };
+        Ok(Cell {
+            page: self.number,
+            rowid,
+            payload,
+        })
+    }
+
+    /// Whether the cell at `idx` sorts before `key`.
+    fn is_below(&self, tree: &Btree, idx: usize, key: &RowKey) -> Result<bool> {
+        Ok(match key {
+            RowKey::Rowid(rowid) => self.rowid(idx)? < *rowid,
+            RowKey::PrimaryKey(key) => compare_key(&self.cell(tree, idx)?.values(tree.db)?, key, &[]).is_lt(),
+        })
+    }
+}
+
+enum Step {
+    /// A page still to be read.
+    Page(usize),
+    Cell(Rc<Node>, usize),
+}
+
+/// A position in a [`Btree`]. `first`, `seek` and `next` move to a cell in
+/// key order and return it, or `None` past the last one; an error ends the
+/// walk.
+///
+/// The cursor holds copies of the pages it is on. A write to the database,
+/// from this handle or any statement run on it, leaves them stale, so the
+/// next `next` after one fails with [`Error::CursorInvalidated`]; `first`
+/// and `seek` position the cursor again and see every write made before.
+pub struct Cursor<'a> {
+    tree: Btree<'a>,
+    /// What is left to visit, the next step last.
+    stack: Vec<Step>,
+    /// The pages queued or read since the cursor was last positioned.
+    walk: Walk,
+    /// [`Database::writes`] when the cursor was last positioned.
+    writes: u64,
+}
+
+impl Cursor<'_> {
+    /// Moves to the first cell.
+    pub fn first(&mut self) -> Result<Option<Cell>> {
+        self.stack = vec![Step::Page(self.tree.rootpage)];
+        self.walk = Walk::default();
+        self.writes = self.tree.db.writes();
+        self.next().transpose()
+    }
+
+    /// Moves to the first cell whose key is not below `key`: a rowid in a
+    /// table b-tree, or leading values of the record in any other, compared
+    /// as BINARY in ascending order.
+    pub fn seek(&mut self, key: &RowKey) -> Result<Option<Cell>> {
+        match (key, self.tree.table) {
+            (RowKey::Rowid(_), false) => Err(anyhow!(msg!("rowid-search-on-index")))?,
+            (RowKey::PrimaryKey(_), true) => Err(anyhow!(msg!("key-search-on-table")))?,
+            _ => {}
+        }
+        self.stack.clear();
+        self.walk = Walk::default();
+        self.writes = self.tree.db.writes();
+        let mut number = self.tree.rootpage;
+        loop {
+            let node = Rc::new(self.read(number)?);
+            // Cells are in key order: find the first one not below the key.
+            let (mut low, mut high) = (0, node.cells.len());
+            while low < high {
+                let mid = (low + high) / 2;
+                match node.is_below(&self.tree, mid, key)? {
+                    true => low = mid + 1,
+                    false => high = mid,
+                }
+            }
+            if !node.is_interior() {
+                self.push_from(&node, low)?;
+                return self.next().transpose();
+            }
+            // The keys from `key` on start in the child left of that cell.
+            self.push_from(&node, low + 1)?;
+            if !node.is_table() && low < node.cells.len() {
+                self.stack.push(Step::Cell(Rc::clone(&node), low));
+            }
+            number = node.child(low);
+            self.walk.check_child(node.number, number)?;
+        }
+    }
+
+    fn read(&self, number: usize) -> Result<Node> {
+        let node = self.tree.node(number)?;
+        if node.is_table() != self.tree.table {
+            Err(malformed(Some(number), msg!("page-not-in-tree", page = number, root = self.tree.rootpage)))?
+        }
+        Ok(node)
+    }
+
+    /// Queues what `node` holds from child or cell `start` on: its children
+    /// and, in an index b-tree, the cells between them. A child already met
+    /// fails as a cycle rather than being visited again.
+    fn push_from(&mut self, node: &Rc<Node>, start: usize) -> Result<()> {
+        let count = node.cells.len();
+        if !node.is_interior() {
+            self.stack.extend((start..count).rev().map(|idx| Step::Cell(Rc::clone(node), idx)));
+            return Ok(());
+        }
+        for idx in (start..=count).rev() {
+            self.walk.check_child(node.number, node.child(idx))?;
+            self.stack.push(Step::Page(node.child(idx)));
+            if idx > start && !node.is_table() {
+                self.stack.push(Step::Cell(Rc::clone(node), idx - 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = Result<Cell>;
+
+    /// Moves to the cell after the current one.
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.stack.is_empty() && self.tree.db.writes() != self.writes {
+            self.stack.clear();
+            return Some(Err(Error::CursorInvalidated.into()));
+        }
+        while let Some(step) = self.stack.pop() {
+            let found = match step {
+                Step::Cell(node, idx) => return Some(node.cell(&self.tree, idx)),
+                Step::Page(number) => self.read(number).and_then(|node| self.push_from(&Rc::new(node), 0)),
+            };
+            if let Err(e) = found {
+                self.stack.clear();
+                return Some(Err(e));
+            }
+        }
+        None
+    }
+}
+
+impl Database {
+    /// The `size` bytes of a payload whose cell on `page_num` continues with
+    /// `stored`, read from its overflow pages as far as it spills onto them.
+    pub(super) fn full_payload(&self, page_num: usize, stored: &[u8], size: usize, table_leaf: bool, usable: usize) -> Result<Vec<u8>> {
+        let local = local_payload(usable, size, table_leaf);
+        let short = || malformed(Some(page_num), msg!("cell-runs-past-end", page = page_num));
+        let mut bytes = stored.get(..local).ok_or_else(short)?.to_vec();
+        if local == size {
+            return Ok(bytes);
+        }
+        let pointer = stored.get(local..local + 4).ok_or_else(short)?;
+        let mut next = u32::from_be_bytes([pointer[0], pointer[1], pointer[2], pointer[3]]) as usize;
+        // A chain longer than the file loops back on itself.
+        let mut pages_left = self.page_count()?;
+        while bytes.len() < size {
+            if next == 0 || pages_left == 0 {
+                Err(malformed(Some(page_num), msg!("overflow-ends-early", page = page_num)))?
+            }
+            let page = self.read_raw_page(next)?;
+            let take = (size - bytes.len()).min(usable - 4);
+            bytes.extend_from_slice(&page[4..4 + take]);
+            next = u32::from_be_bytes([page[0], page[1], page[2], page[3]]) as usize;
+            pages_left -= 1;
+        }
+        Ok(bytes)
+    }
+}
+
+/// The pages a recursive walk of one b-tree has reached, with their depth,
+/// so that a child pointer that loops back or leads ever deeper fails as
+/// corruption instead of overflowing the stack.
+#[derive(Default)]
+pub(super) struct Walk {
+    depths: HashMap<usize, usize>,
+}
+
+impl Walk {
+    /// Checks `child` before the walk goes from `parent` down into it: a
+    /// page met twice, or deeper than sqlite builds trees, means the pages
+    /// point in a cycle.
+    pub(super) fn check_child(&mut self, parent: usize, child: usize) -> Result<()> {
+        // The root is the one page not reached from another.
+        let depth = *self.depths.entry(parent).or_insert(1) + 1;
+        if depth > MAX_DEPTH {
+            Err(malformed(Some(child), msg!("btree-too-deep", depth = MAX_DEPTH, page = child)))?
+        }
+        if self.depths.insert(child, depth).is_some() {
+            Err(malformed(Some(child), msg!("page-in-tree-twice", page = child)))?
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Btree, Cursor};
+    use crate::{db::RowKey, record::Record, Database, Error};
+    use std::fs;
+
+    /// Writes a row through `db` while a cursor over its `apples` table is
+    /// open, after `first` has read the first row.
+    fn write_during_scan(name: &str, check: impl Fn(&Database, &mut Cursor)) {
+        let path = std::env::temp_dir().join(format!("sqlite-lite-{}-{}.db", name, std::process::id()));
+        fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/sample.db"), &path).unwrap();
+        let db = Database::load_db(path.to_str().unwrap().to_string()).unwrap();
+        let root = db.schema.iter().find(|s| s.name == "apples").unwrap().rootpage;
+        let mut cursor = Btree::open(&db, root).unwrap().cursor();
+        assert_eq!(cursor.first().unwrap().unwrap().rowid, Some(1));
+        let row = vec![Record::Null, Record::Text("Gala".to_string()), Record::Text("Red".to_string())];
+        db.import_rows("apples", vec![row]).unwrap();
+        check(&db, &mut cursor);
+        drop(db);
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_file(format!("{}-journal", path.display()));
+    }
+
+    #[test]
+    fn next_after_a_write_fails() {
+        write_during_scan("cursor-next", |_, cursor| {
+            let e = cursor.next().unwrap().unwrap_err();
+            assert!(matches!(e.downcast_ref::<Error>(), Some(Error::CursorInvalidated)), "{:?}", e);
+            // The walk is over; it does not go on with stale pages.
+            assert!(cursor.next().is_none());
+        });
+    }
+
+    #[test]
+    fn positioning_again_sees_the_write() {
+        write_during_scan("cursor-seek", |db, cursor| {
+            let cell = cursor.seek(&RowKey::Rowid(5)).unwrap().unwrap();
+            assert_eq!(cell.rowid, Some(5));
+            assert_eq!(cell.values(db).unwrap()[1], Record::Text("Gala".to_string()));
+            assert!(cursor.next().is_none());
+
+            assert_eq!(cursor.first().unwrap().unwrap().rowid, Some(1));
+            let rest = cursor.map(|cell| cell.unwrap().rowid.unwrap()).collect::<Vec<_>>();
+            assert_eq!(rest, [2, 3, 4, 5]);
+        });
+    }
+}
@@ -0,0 +1,246 @@
+//! Building a whole b-tree bottom-up from entries that arrive in key order,
+//! for CREATE INDEX, VACUUM and `.import` into an empty table. Each leaf is
+//! filled as far as it goes and written once, and every level above is laid
+//! out from the one below it, so no page is ever split and pages are
+//! allocated in the order they are written.
+
+use super::{
+    btree::Btree,
+    pages::{Cell, Pages, INTERIOR_INDEX, INTERIOR_TABLE, LEAF_INDEX, LEAF_TABLE},
+    Database,
+};
+use crate::{interrupt, messages::msg, record::encode_varint};
+use anyhow::{anyhow, Result};
+
+/// The kind of b-tree a [`BulkLoader`] builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Tree {
+    /// Rows keyed by rowid, all of them on the leaves.
+    Table,
+    /// Records that are their own keys, some of them on interior pages.
+    Index,
+}
+
+impl Tree {
+    pub fn leaf(self) -> u8 {
+        match self {
+            Tree::Table => LEAF_TABLE,
+            Tree::Index => LEAF_INDEX,
+        }
+    }
+
+    fn interior(self) -> u8 {
+        match self {
+            Tree::Table => INTERIOR_TABLE,
+            Tree::Index => INTERIOR_INDEX,
+        }
+    }
+}
+
+/// Writes the b-tree rooted at a given page from entries added in key order.
+/// Nothing may be added after [`BulkLoader::finish`].
+pub(super) struct BulkLoader<'p, 'a> {
+    pages: &'p mut Pages<'a>,
+    tree: Tree,
+    root: usize,
+    /// Cells of the leaf being filled, and the bytes they take on it.
+    leaf: Vec<Cell>,
+    used: usize,
+    /// An index leaf that is full, and the cell that did not fit on it, which
+    /// goes up between it and the next leaf once there is one.
+    held: Option<(Vec<Cell>, Cell)>,
+    /// The leaves written so far.
+    level: Level,
+    last_rowid: Option<i64>,
+}
+
+/// The pages of one level of a tree, in order, and what their parents hold
+/// between them: for a table the largest rowid under each page, for an index
+/// the record that sorts between a page and the next.
+#[derive(Default)]
+struct Level {
+    children: Vec<usize>,
+    dividers: Vec<Vec<u8>>,
+}
+
+impl<'p, 'a> BulkLoader<'p, 'a> {
+    /// A loader writing over `root`, which keeps its page number whatever the
+    /// tree grows to. The tree's other pages come from [`Pages::allocate`].
+    pub fn new(pages: &'p mut Pages<'a>, tree: Tree, root: usize) -> Self {
+        BulkLoader {
+            pages,
+            tree,
+            root,
+            leaf: Vec::new(),
+            used: 0,
+            held: None,
+            level: Level::default(),
+            last_rowid: None,
+        }
+    }
+
+    /// Adds the row `rowid` of a table, which must be above every rowid
+    /// added before it.
+    pub fn add_row(&mut self, rowid: i64, payload: &[u8]) -> Result<()> {
+        if self.last_rowid.is_some_and(|last| rowid <= last) {
+            Err(anyhow!(msg!("rowid-out-of-order", rowid = rowid, previous = self.last_rowid.unwrap())))?
+        }
+        self.last_rowid = Some(rowid);
+        let stored = self.pages.spill(payload, true)?;
+        let mut cell = Vec::with_capacity(stored.len() + 18);
+        encode_varint(payload.len() as u64, &mut cell);
+        encode_varint(rowid as u64, &mut cell);
+        cell.extend_from_slice(&stored);
+        if self.is_full(&cell) {
+            let cells = std::mem::take(&mut self.leaf);
+            self.write_leaf(cells)?;
+        }
+        self.push((rowid, cell));
+        Ok(())
+    }
+
+    /// Adds an index record, which must sort after every record added before
+    /// it.
+    pub fn add_record(&mut self, payload: &[u8]) -> Result<()> {
+        let mut cell = Vec::with_capacity(payload.len() + 9);
+        encode_varint(payload.len() as u64, &mut cell);
+        cell.extend_from_slice(&self.pages.spill(payload, false)?);
+        if let Some((cells, divider)) = self.held.take() {
+            self.write_leaf(cells)?;
+            self.level.dividers.push(divider.1);
+        }
+        if self.is_full(&cell) {
+            self.held = Some((std::mem::take(&mut self.leaf), (0, cell)));
+            self.used = 0;
+            return Ok(());
+        }
+        self.push((0, cell));
+        Ok(())
+    }
+
+    /// Writes what is left of the leaves and every level above them, the
+    /// top one on the root page.
+    pub fn finish(mut self) -> Result<()> {
+        // Only page 1, whose header takes room, can fail to hold a leaf.
+        let single_leaf = self.level.children.is_empty() && self.held.is_none();
+        if single_leaf && self.pages.fill(self.root, self.tree.leaf(), &self.leaf, None)? {
+            return Ok(());
+        }
+        if let Some((mut cells, divider)) = self.held.take() {
+            // Nothing came after the full leaf: its own last cell goes up
+            // instead, and the one that did not fit starts the last leaf.
+            let last = cells.pop().ok_or_else(|| anyhow!(msg!("index-cell-too-big")))?;
+            self.write_leaf(cells)?;
+            self.level.dividers.push(last.1);
+            self.leaf = vec![divider];
+        }
+        let cells = std::mem::take(&mut self.leaf);
+        self.write_leaf(cells)?;
+
+        let mut level = std::mem::take(&mut self.level);
+        let kind = self.tree.interior();
+        loop {
+            let last = level.children.len() - 1;
+            let cells = (0..last).map(|idx| interior_cell(&level, idx)).collect::<Vec<_>>();
+            if self.pages.fill(self.root, kind, &cells, Some(level.children[last]))? {
+                return Ok(());
+            }
+            level = self.write_level(&level)?;
+        }
+    }
+
+    /// Whether `cell` has to start a new leaf.
+    fn is_full(&self, cell: &[u8]) -> bool {
+        // Only the root may be page 1, so every leaf written here has the
+        // whole page past its header.
+        !self.leaf.is_empty() && self.used + cell.len() + 2 > self.pages.usable() - 8
+    }
+
+    fn push(&mut self, cell: Cell) {
+        self.used += cell.1.len() + 2;
+        self.leaf.push(cell);
+    }
+
+    /// Writes `cells` to a new leaf. A table leaf's largest rowid goes up
+    /// with it; an index leaf's divider is up to the caller.
+    fn write_leaf(&mut self, cells: Vec<Cell>) -> Result<()> {
+        self.used = 0;
+        let num = self.pages.allocate()?;
+        if !self.pages.fill(num, self.tree.leaf(), &cells, None)? {
+            Err(anyhow!(msg!("cells-do-not-fit-leaf", page = num)))?
+        }
+        self.level.children.push(num);
+        if self.tree == Tree::Table {
+            let mut key = Vec::new();
+            encode_varint(cells.last().map_or(0, |cell| cell.0) as u64, &mut key);
+            self.level.dividers.push(key);
+        }
+        Ok(())
+    }
+
+    /// Writes the pages of the level above `level`, each as full as it can
+    /// be, and returns it. A divider between two of them goes up with the
+    /// left one.
+    fn write_level(&mut self, level: &Level) -> Result<Level> {
+        let capacity = self.pages.usable() - 12;
+        let last = level.children.len() - 1;
+        // Runs of children, by the indexes of the first and the last: all
+        // but the last child of a run get a cell, and the last is its page's
+        // right child.
+        let mut runs = Vec::new();
+        let (mut start, mut used) = (0, 0);
+        for idx in 0..last {
+            let cost = 4 + level.dividers[idx].len() + 2;
+            if idx > start && used + cost > capacity {
+                runs.push((start, idx));
+                (start, used) = (idx + 1, 0);
+            } else {
+                used += cost;
+            }
+        }
+        runs.push((start, last));
+        // A last run of one child would leave its page without cells; the one
+        // before gives up a child.
+        if let [.., previous, (start, end)] = runs.as_mut_slice() {
+            if start == end {
+                previous.1 -= 1;
+                *start -= 1;
+            }
+        }
+
+        let mut upper = Level::default();
+        for (start, end) in runs {
+            let cells = (start..end).map(|idx| interior_cell(level, idx)).collect::<Vec<_>>();
+            let num = self.pages.allocate()?;
+            if !self.pages.fill(num, self.tree.interior(), &cells, Some(level.children[end]))? {
+                Err(anyhow!(msg!("cells-do-not-fit-interior", page = num)))?
+            }
+            upper.children.push(num);
+            if let Some(divider) = level.dividers.get(end) {
+                upper.dividers.push(divider.clone());
+            }
+        }
+        Ok(upper)
+    }
+}
+
+/// The interior cell pointing to child `idx` of `level`.
+fn interior_cell(level: &Level, idx: usize) -> Cell {
+    let mut cell = (level.children[idx] as u32).to_be_bytes().to_vec();
+    cell.extend_from_slice(&level.dividers[idx]);
+    (0, cell)
+}
+
+impl Database {
+    /// Hands every entry of the b-tree at `root` to `on_entry` in key order
+    /// with its whole payload, overflow pages included: each row of a table
+    /// with its rowid, each record of an index with 0.
+    pub(super) fn tree_entries(&self, root: usize, on_entry: &mut dyn FnMut(i64, Vec<u8>) -> Result<()>) -> Result<()> {
+        for cell in Btree::open(self, root)?.cursor() {
+            let cell = cell?;
+            interrupt::check()?;
+            on_entry(cell.rowid.unwrap_or(0), cell.payload)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,489 @@
+//! Carving deleted rows out of the space a table no longer uses: the
+//! freeblocks and unallocated middle of its leaf pages, and free and unused
+//! pages. Deleting a row leaves its record in place until something writes
+//! over it, with only the first four bytes of its cell given to the
+//! freeblock chain; whatever still reads as a record with the table's column
+//! types is a candidate. When those four bytes took the start of the record
+//! header too, it is rebuilt from the size of the freeblock. Records that
+//! spilled onto overflow pages are not carved.
+//!
+//! [`Database::slack`] reports the same space in every b-tree page, for
+//! looking at by hand.
+
+use super::{
+    freelist::{PageRole, PageUse},
+    parse_varint,
+    pages::{INTERIOR_INDEX, INTERIOR_TABLE, LEAF_INDEX, LEAF_TABLE},
+    Database, Table,
+};
+use crate::{
+    aggregate::{self, GroupKey},
+    interrupt,
+    messages::msg,
+    record::{ColumnType, Record, TextDecoding},
+    sql::{Affinity, ColumnDef},
+    DB_HEADER_SIZE,
+};
+use anyhow::{anyhow, Result};
+use std::{collections::HashSet, fmt::Display, ops::ControlFlow};
+
+/// Where on its page a carved row or slack was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// A freeblock of a b-tree page.
+    Freeblock,
+    /// Between the cell pointers and the cells of a b-tree page.
+    Unallocated,
+    /// A page of the freelist.
+    FreePage,
+    /// A page nothing points to.
+    UnusedPage,
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Region::Freeblock => write!(f, "freeblock"),
+            Region::Unallocated => write!(f, "unallocated"),
+            Region::FreePage => write!(f, "free page"),
+            Region::UnusedPage => write!(f, "unused page"),
+        }
+    }
+}
+
+/// A row that may once have been in the table.
+#[derive(Debug, Clone)]
+pub struct Carved {
+    pub page: usize,
+    /// Where the record, or the cell when all of it survived, starts.
+    pub offset: usize,
+    pub region: Region,
+    /// From 0 to 1: how likely the bytes are to really be a row of the table.
+    pub confidence: f64,
+    /// None when the freeblock chain wrote over it.
+    pub rowid: Option<i64>,
+    pub values: Vec<Record>,
+}
+
+/// Space inside a b-tree page that no cell uses, as found by
+/// [`Database::slack`].
+#[derive(Debug, Clone)]
+pub struct Slack {
+    pub page: usize,
+    /// The table or index the page belongs to.
+    pub tree: String,
+    /// [`Region::Freeblock`] or [`Region::Unallocated`].
+    pub region: Region,
+    pub offset: usize,
+    /// The bytes of the region, a freeblock's own 4-byte header included.
+    pub bytes: Vec<u8>,
+}
+
+impl Slack {
+    /// The bytes that may still hold deleted data: all of them, less a
+    /// freeblock's header.
+    pub fn contents(&self) -> &[u8] {
+        match self.region {
+            Region::Freeblock => self.bytes.get(4..).unwrap_or_default(),
+            _ => &self.bytes,
+        }
+    }
+
+    /// How many of [`Slack::contents`] are not zero.
+    pub fn nonzero(&self) -> usize {
+        self.contents().iter().filter(|&&b| b != 0).count()
+    }
+}
+
+/// Columns a record is read as and what they may hold.
+struct Signature<'a> {
+    columns: &'a [ColumnDef],
+    strict: bool,
+    /// Serial types 8 and 9 are only written from schema format 4 on.
+    constants: bool,
+    max_local: usize,
+}
+
+impl Database {
+    /// Rows of `table` that the unused space of the database still holds,
+    /// in page order. Copies of rows that are still in the table, as an
+    /// update that moved a row leaves behind, are left out.
+    pub fn carve(&self, table: &str) -> Result<Vec<Carved>> {
+        let Table {
+            columns,
+            key_order,
+            strict,
+            ..
+        } = self.get_table(table)?;
+        if key_order.is_some() {
+            Err(anyhow!(msg!("carve-without-rowid", table = table)))?
+        }
+        let rootpage = self.get_table_rootpage(table)?;
+        let header = self.read_raw_page(1)?;
+        let usable = self.page_size - header[20] as usize;
+        let signature = Signature {
+            columns: &columns,
+            strict,
+            constants: self.schema_format >= 4,
+            // Larger payloads spill onto overflow pages.
+            max_local: usable - 35,
+        };
+
+        let mut carved = Vec::new();
+        for (page_num, used) in (1..).zip(self.page_usage()?) {
+            interrupt::check()?;
+            let regions = match used {
+                PageUse::Tree { name, role: PageRole::Leaf } if name.eq_ignore_ascii_case(table) => {
+                    let page = self.read_raw_page(page_num)?;
+                    free_regions(&page, page_num, usable)
+                }
+                PageUse::FreeTrunk => {
+                    let page = self.read_raw_page(page_num)?;
+                    let leaves = u32::from_be_bytes([page[4], page[5], page[6], page[7]]) as usize;
+                    vec![(8 + 4 * leaves, usable, Region::FreePage)]
+                }
+                PageUse::FreeLeaf => vec![(0, usable, Region::FreePage)],
+                PageUse::Unused => vec![(0, usable, Region::UnusedPage)],
+                _ => continue,
+            };
+            let page = self.read_raw_page(page_num)?;
+            for (start, end, region) in regions {
+                let Some(bytes) = page.get(start..end.min(usable)) else {
+                    continue;
+                };
+                let mut found = |at, confidence, rowid, values| {
+                    carved.push(Carved {
+                        page: page_num,
+                        offset: start + at,
+                        region,
+                        confidence,
+                        rowid,
+                        values,
+                    })
+                };
+                let skip = match region {
+                    Region::Freeblock => match self.rebuild(bytes, &signature) {
+                        Some((len, confidence, values)) => {
+                            found(0, confidence, None, values);
+                            len
+                        }
+                        None => 4,
+                    },
+                    _ => 0,
+                };
+                self.carve_region(bytes, skip, &signature, &mut found)?;
+            }
+        }
+
+        // Without a rowid, a row is only known to be live by its values.
+        let mut live_values = HashSet::new();
+        if carved.iter().any(|row| row.rowid.is_none()) {
+            let _ = self.execute_select_range(rootpage, &columns, None, (i64::MIN, i64::MAX), &mut |row| {
+                live_values.insert(without_rowid_alias(&row, &columns));
+                Ok(ControlFlow::Continue(()))
+            })?;
+        }
+        let mut kept = Vec::with_capacity(carved.len());
+        for row in carved {
+            let live = match row.rowid {
+                Some(rowid) => self.is_live(rootpage, &columns, rowid, &row.values)?,
+                None => live_values.contains(&without_rowid_alias(&row.values, &columns)),
+            };
+            if !live {
+                kept.push(row);
+            }
+        }
+        Ok(kept)
+    }
+
+    /// The unallocated middle and the freeblocks of every b-tree page, in
+    /// page order. Empty regions are left out.
+    pub fn slack(&self) -> Result<Vec<Slack>> {
+        let usable = self.page_size - self.read_raw_page(1)?[20] as usize;
+        let mut slack = Vec::new();
+        for (page_num, used) in (1..).zip(self.page_usage()?) {
+            interrupt::check()?;
+            let PageUse::Tree { name, role: PageRole::Interior | PageRole::Leaf } = used else {
+                continue;
+            };
+            let page = self.read_raw_page(page_num)?;
+            for (start, end, region) in free_regions(&page, page_num, usable) {
+                match page.get(start..end.min(usable)) {
+                    Some(bytes) if !bytes.is_empty() => slack.push(Slack {
+                        page: page_num,
+                        tree: name.clone(),
+                        region,
+                        offset: start,
+                        bytes: bytes.to_vec(),
+                    }),
+                    _ => {}
+                }
+            }
+        }
+        Ok(slack)
+    }
+
+    /// The record of the cell freed into `block`, a freeblock whose four
+    /// header bytes wrote over the cell's payload size and rowid and the
+    /// start of the record header: its size in the block, how sure the
+    /// carving is and its values. The cell is taken to start the block and,
+    /// with a header of two bytes, to fill it, which gives the length of the
+    /// first value and so its serial type.
+    fn rebuild(&self, block: &[u8], signature: &Signature) -> Option<(usize, f64, Vec<Record>)> {
+        let rebuilt = |cell_header: usize| {
+            let columns = signature.columns;
+            // The record header's size, and the first serial type too when
+            // the cell header took two bytes.
+            let lost_types = 3 - cell_header;
+            let mut rest = block.get(4..)?;
+            let mut content = 0;
+            for _ in lost_types..columns.len() {
+                let (serial_type, after, _) = parse_varint(rest).ok()?;
+                content += content_len(serial_type)?;
+                rest = after;
+            }
+            let header_len = 1 + lost_types + (block.len() - 4 - rest.len());
+            let mut record = vec![u8::try_from(header_len).ok().filter(|&len| len < 0x80)?];
+            if lost_types == 1 {
+                let first = block.len().checked_sub(cell_header + header_len + content)?;
+                record.push(serial_type_for(&columns[0], first)?);
+            }
+            record.extend_from_slice(&block[4..]);
+            let len = self.record_len(&record, signature)?;
+            let (values, confidence) = self.read_record(&record[..len], signature)?;
+            // Filling the freeblock exactly is what a lone freed cell does.
+            let confidence = match cell_header + len == block.len() {
+                true => confidence,
+                false => confidence - 0.1,
+            };
+            Some((cell_header + len, confidence, values))
+        };
+        match (rebuilt(2), rebuilt(3)) {
+            (Some(two), Some(three)) if three.1 > two.1 => Some(three),
+            (two, three) => two.or(three),
+        }
+    }
+
+    /// Reports each record found in `bytes` from `from` on, taking the bytes
+    /// of one as used up: where it starts, how sure the carving is, its rowid
+    /// when the cell around it survived, and its values.
+    fn carve_region(
+        &self,
+        bytes: &[u8],
+        from: usize,
+        signature: &Signature,
+        found: &mut dyn FnMut(usize, f64, Option<i64>, Vec<Record>),
+    ) -> Result<()> {
+        let mut at = from;
+        while at < bytes.len() {
+            // A whole cell first: payload size, rowid, then the record filling it.
+            let cell = parse_varint(&bytes[at..]).ok().and_then(|(size, rest, size_len)| {
+                let (rowid, rest, rowid_len) = parse_varint(rest).ok()?;
+                let size = usize::try_from(size).ok().filter(|&size| size <= signature.max_local)?;
+                let (values, confidence) = self.read_record(rest.get(..size)?, signature)?;
+                Some((size_len + rowid_len + size, confidence, rowid as i64, values))
+            });
+            if let Some((len, confidence, rowid, values)) = cell {
+                found(at, (confidence + 0.3).min(1.0), Some(rowid), with_rowid(values, signature.columns, rowid));
+                at += len;
+                continue;
+            }
+            // Otherwise a record whose cell header was written over.
+            let record = self.record_len(&bytes[at..], signature).and_then(|len| {
+                let (values, confidence) = self.read_record(&bytes[at..at + len], signature)?;
+                Some((len, confidence, values))
+            });
+            match record {
+                Some((len, confidence, values)) => {
+                    found(at, confidence, None, values);
+                    at += len;
+                }
+                None => at += 1,
+            }
+        }
+        Ok(())
+    }
+
+    /// The length of the record at the start of `bytes` as its header has
+    /// it, when the header could belong to a row of the table.
+    fn record_len(&self, bytes: &[u8], signature: &Signature) -> Option<usize> {
+        let (header_len, mut rest, _) = parse_varint(bytes).ok()?;
+        let header_len = usize::try_from(header_len).ok()?;
+        if header_len < 2 || header_len > 9 * (signature.columns.len() + 1) || header_len > bytes.len() {
+            return None;
+        }
+        let mut len = header_len;
+        let mut read = bytes.len() - rest.len();
+        while read < header_len {
+            let (serial_type, after, _) = parse_varint(rest).ok()?;
+            len = len.checked_add(content_len(serial_type)?)?;
+            read = bytes.len() - after.len();
+            rest = after;
+        }
+        (read == header_len && len <= bytes.len()).then_some(len)
+    }
+
+    /// The values of `record`, which must be exactly one record, and how
+    /// likely it is to be a row of the table from 0 to 0.7; none when it
+    /// cannot be one.
+    fn read_record(&self, record: &[u8], signature: &Signature) -> Option<(Vec<Record>, f64)> {
+        if self.record_len(record, signature)? != record.len() {
+            return None;
+        }
+        let (header_len, mut header, _) = parse_varint(record).ok()?;
+        let mut content = &record[header_len as usize..];
+        let mut values = Vec::new();
+        let mut confidence = 0.7;
+        while record.len() - header.len() < header_len as usize {
+            let (serial_type, rest, _) = parse_varint(header).ok()?;
+            header = rest;
+            let column = signature.columns.get(values.len())?;
+            let (bytes, rest) = content.split_at(content_len(serial_type)?);
+            content = rest;
+            let value = match serial_type {
+                0 => Record::Null,
+                1..=6 => {
+                    // Big-endian and sign-extended from however many bytes.
+                    let value = bytes.iter().fold(-((bytes[0] >> 7) as i64), |v, &b| (v << 8) | b as i64);
+                    Record::Int64(value)
+                }
+                7 => Record::Float(f64::from_be_bytes(bytes.try_into().ok()?)),
+                8 | 9 if !signature.constants => return None,
+                8 => Record::Zero,
+                9 => Record::One,
+                10 | 11 => return None,
+                n if n % 2 == 0 => Record::Blob(bytes.to_vec()),
+                _ => TextDecoding::Strict.decode(self.text_encoding, bytes).ok()?,
+            };
+            confidence -= fit(column, &value, signature.strict)?;
+            values.push(value);
+        }
+        // Columns added later are missing from older rows, but a record of
+        // fewer columns is also more easily mistaken for one.
+        if values.len() < signature.columns.len() {
+            confidence -= 0.2;
+        }
+        if values.iter().all(|v| matches!(v, Record::Null)) {
+            confidence -= 0.4;
+        }
+        while values.len() < signature.columns.len() {
+            values.push(signature.columns[values.len()].default_value());
+        }
+        (confidence > 0.0).then_some((values, confidence))
+    }
+
+    /// Whether the row with `rowid` is still in the table at `rootpage`,
+    /// holding `values`.
+    fn is_live(&self, rootpage: usize, columns: &[ColumnDef], rowid: i64, values: &[Record]) -> Result<bool> {
+        let mut live = false;
+        let _ = self.execute_select_range(rootpage, columns, None, (rowid, rowid), &mut |row| {
+            live = row.len() == values.len() && row.iter().zip(values).all(|(a, b)| a.to_string() == b.to_string());
+            Ok(ControlFlow::Break(()))
+        })?;
+        Ok(live)
+    }
+}
+
+/// The unallocated middle and the freeblocks of the b-tree page `page`, as
+/// ranges of offsets.
+fn free_regions(page: &[u8], page_num: usize, usable: usize) -> Vec<(usize, usize, Region)> {
+    let header = if page_num == 1 { DB_HEADER_SIZE } else { 0 };
+    let pointers = match page[header] {
+        LEAF_TABLE | LEAF_INDEX => header + 8,
+        INTERIOR_TABLE | INTERIOR_INDEX => header + 12,
+        _ => return Vec::new(),
+    };
+    let u16_at = |at: usize| u16::from_be_bytes([page[at], page[at + 1]]) as usize;
+    let pointers_end = pointers + 2 * u16_at(header + 3);
+    let content = match u16_at(header + 5) {
+        0 => 65536,
+        content => content,
+    };
+    let mut regions = vec![(pointers_end, content, Region::Unallocated)];
+    let mut block = u16_at(header + 1);
+    // Freeblocks come in increasing order; anything else ends the chain.
+    let mut last = 0;
+    while block > last && block + 4 <= usable {
+        regions.push((block, block + u16_at(block + 2), Region::Freeblock));
+        last = block;
+        block = u16_at(block);
+    }
+    regions
+}
+
+/// Bytes of content serial type `serial_type` takes up; none for the
+/// reserved ones.
+pub(super) fn content_len(serial_type: u64) -> Option<usize> {
+    match serial_type {
+        0 | 8 | 9 => Some(0),
+        1..=4 => Some(serial_type as usize),
+        5 => Some(6),
+        6 | 7 => Some(8),
+        10 | 11 => None,
+        n => usize::try_from((n - 12) / 2).ok(),
+    }
+}
+
+/// The serial type of a value of `len` bytes in `column`, as sqlite would
+/// have stored it; none when it would not fit the one byte the rebuilt
+/// header has for it.
+fn serial_type_for(column: &ColumnDef, len: usize) -> Option<u8> {
+    let numeric = matches!(column.affinity(), Affinity::Integer | Affinity::Numeric | Affinity::Real);
+    let serial_type = match len {
+        0 => 0,
+        _ if column.rowid_alias => return None,
+        1..=4 if numeric => len,
+        6 if numeric => 5,
+        8 if column.affinity() == Affinity::Real => 7,
+        8 if numeric => 6,
+        _ if column.affinity() == Affinity::Blob => 12 + 2 * len,
+        _ => 13 + 2 * len,
+    };
+    u8::try_from(serial_type).ok().filter(|&t| t < 0x80)
+}
+
+/// How much less likely `value` makes the record to be a row, given the
+/// column it would be in; none when the column could never hold it.
+fn fit(column: &ColumnDef, value: &Record, strict: bool) -> Option<f64> {
+    if column.rowid_alias {
+        // The rowid is kept in the cell, never in the record.
+        return matches!(value, Record::Null).then_some(0.0);
+    }
+    let kind = match value {
+        Record::Null => return Some(0.0),
+        // Text that was really something else is full of control bytes.
+        Record::Text(text) if text.chars().any(|c| c.is_control() && !c.is_whitespace()) => return Some(0.3),
+        Record::Float(f) if !f.is_finite() => return None,
+        Record::Float(_) => ColumnType::Float,
+        Record::Text(_) => ColumnType::Text(0),
+        Record::Blob(_) => ColumnType::Blob(0),
+        _ => ColumnType::Int64,
+    };
+    match (column.affinity(), kind) {
+        (Affinity::Integer | Affinity::Numeric | Affinity::Real, ColumnType::Int64 | ColumnType::Float) => Some(0.0),
+        (Affinity::Text, ColumnType::Text(_)) => Some(0.0),
+        // A column declared without a type takes anything, which says little.
+        (Affinity::Blob, _) => Some(0.1),
+        // Text that did not look like a number stays text.
+        (Affinity::Integer | Affinity::Numeric | Affinity::Real, ColumnType::Text(_)) if !strict => Some(0.15),
+        (_, ColumnType::Blob(_)) if !strict => Some(0.15),
+        _ => None,
+    }
+}
+
+/// The key of a row's values other than its INTEGER PRIMARY KEY.
+fn without_rowid_alias(values: &[Record], columns: &[ColumnDef]) -> GroupKey {
+    let mut values = values.to_vec();
+    if let Some(idx) = columns.iter().position(|c| c.rowid_alias) {
+        values[idx] = Record::Null;
+    }
+    aggregate::group_key(&values)
+}
+
+/// `values` read from a cell with `rowid`, the INTEGER PRIMARY KEY filled in.
+fn with_rowid(mut values: Vec<Record>, columns: &[ColumnDef], rowid: i64) -> Vec<Record> {
+    if let Some(idx) = columns.iter().position(|c| c.rowid_alias) {
+        values[idx] = Record::Int64(rowid);
+    }
+    values
+}
@@ -0,0 +1,93 @@
+//! `checksum`: a hash of each table's rows that does not depend on how they
+//! are stored, for telling whether two databases hold the same data without
+//! diffing them. Each row is encoded as a record of its column values, in
+//! UTF-8 with the smallest integer types, and hashed on its own; the row
+//! hashes are added up, so the order rows are read in does not count, but
+//! how many times each one appears does.
+
+use super::{schema, Database};
+use crate::{
+    error::Error,
+    interrupt,
+    record::{serialize_record, TextEncoding},
+    sha256::Sha256,
+    sql::TableRef,
+};
+use anyhow::Result;
+use std::{fmt::Display, ops::ControlFlow};
+
+/// The checksum of one table's rows, from [`Database::checksums`].
+#[derive(Debug)]
+pub struct Checksum {
+    pub table: String,
+    pub rows: usize,
+    /// SHA-256 of the sum of the row hashes and the row count, in hex.
+    pub sha256: String,
+}
+
+impl Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}|{}|{}", self.table, self.rows, self.sha256)
+    }
+}
+
+impl Database {
+    /// The checksum of `table`, or of every table but sqlite's own in
+    /// schema order. Only column values count: not the rowids of tables
+    /// without an INTEGER PRIMARY KEY, which VACUUM may renumber, nor the
+    /// text encoding, page layout or column names.
+    pub fn checksums(&self, table: Option<&str>) -> Result<Vec<Checksum>> {
+        self.pin_snapshots()?;
+        let tables = self.schema.iter().filter(|s| s.kind == schema::Kind::Table);
+        let names = match table {
+            Some(table) => match tables.clone().find(|s| s.name.eq_ignore_ascii_case(table)) {
+                Some(s) => vec![s.name.as_str()],
+                None => Err(Error::no_such_table(table, tables.map(|s| s.name.as_str())))?,
+            },
+            None => tables
+                .map(|s| s.name.as_str())
+                .filter(|name| !name.to_lowercase().starts_with("sqlite_"))
+                .collect(),
+        };
+        names.into_iter().map(|name| self.checksum(name)).collect()
+    }
+
+    fn checksum(&self, table: &str) -> Result<Checksum> {
+        let from = TableRef {
+            schema: Some("main".to_string()),
+            name: table.to_string(),
+            alias: None,
+        };
+        let source = self.source(&from, 0)?;
+        // A hidden rowid column, when there is one, comes after these.
+        let declared = self.get_table(table)?.columns.len();
+        let mut sum = [0u8; 32];
+        let mut rows = 0;
+        let _ = self.scan_source(&source, &source.table.columns, None, &mut |row| {
+            interrupt::check()?;
+            let mut hash = Sha256::new();
+            hash.update(&serialize_record(&row[..declared], TextEncoding::Utf8, 4));
+            add(&mut sum, &hash.finish());
+            rows += 1;
+            Ok(ControlFlow::Continue(()))
+        })?;
+        let mut hash = Sha256::new();
+        hash.update(&sum);
+        hash.update(&(rows as u64).to_be_bytes());
+        Ok(Checksum {
+            table: table.to_string(),
+            rows,
+            sha256: hash.hex(),
+        })
+    }
+}
+
+/// Adds `digest` to `sum`, both big-endian numbers, modulo 2^256.
+fn add(sum: &mut [u8; 32], digest: &[u8; 32]) {
+    let mut carry = 0;
+    for (byte, &other) in sum.iter_mut().zip(digest).rev() {
+        let total = *byte as u16 + other as u16 + carry;
+        *byte = total as u8;
+        carry = total >> 8;
+    }
+}
@@ -0,0 +1,299 @@
+//! Compound selects: UNION ALL, UNION, INTERSECT and EXCEPT, and the ORDER
+//! BY and LIMIT that apply to the rows of the whole compound rather than to
+//! its last select. UNION ALL without ORDER BY streams each select's rows
+//! straight to the output, stopping the scans once LIMIT is reached; the
+//! other operators and sorting need every row first.
+
+use super::{plan::SelectPlan, Database};
+use crate::{
+    aggregate::{self, GroupKey},
+    functions::apply_affinity,
+    messages::msg,
+    output::RowWriter,
+    record::{compare_records, Collation, Record},
+    sql::{Affinity, CompoundOp, Expr, OrderingTerm, ResultColumn, Statement},
+};
+use anyhow::{anyhow, Result};
+use std::{cmp::Ordering, collections::HashSet};
+
+/// Passes rows on to the output once the compound has written its header,
+/// skipping the first `skip` and keeping at most `kept` of the rest.
+struct Sink<'a> {
+    out: &'a mut dyn RowWriter,
+    /// Result columns; any after them only sort the rows.
+    width: usize,
+    skip: usize,
+    kept: Option<usize>,
+}
+
+impl Sink<'_> {
+    fn done(&self) -> bool {
+        self.kept == Some(0)
+    }
+
+    /// Rows a select still needs to produce for the output to be complete.
+    fn wanted(&self) -> Option<usize> {
+        self.kept.map(|kept| kept.saturating_add(self.skip))
+    }
+}
+
+impl RowWriter for Sink<'_> {
+    fn write_header(&mut self, _names: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: &[Record]) -> Result<()> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return Ok(());
+        }
+        match &mut self.kept {
+            Some(0) => return Ok(()),
+            Some(kept) => *kept -= 1,
+            None => {}
+        }
+        self.out.write_row(&row[..self.width])
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.finish()
+    }
+}
+
+/// Keeps every row of a select, for the operators that need them all.
+#[derive(Default)]
+pub(super) struct Rows(pub Vec<Vec<Record>>);
+
+impl RowWriter for Rows {
+    fn write_header(&mut self, _names: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: &[Record]) -> Result<()> {
+        self.0.push(row.to_vec());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Runs a [`Statement::Compound`], writing its rows to `out`.
+    pub(super) fn execute_compound(&self, statement: &Statement, out: &mut dyn RowWriter) -> Result<()> {
+        let Statement::Compound {
+            selects,
+            operators,
+            order_by,
+            limit,
+            offset,
+        } = statement
+        else {
+            unreachable!()
+        };
+        let width = result_columns(&selects[0]).len();
+
+        // A lone select can sort by expressions it does not return: they are
+        // computed as extra columns, which the output leaves off.
+        let mut hidden = Vec::new();
+        let mut order = Vec::new();
+        for (n, term) in order_by.iter().enumerate() {
+            match order_column(n, term, selects, width)? {
+                Some(column) => order.push((column, term)),
+                None if selects.len() == 1 => {
+                    order.push((width + hidden.len(), term));
+                    hidden.push(ResultColumn {
+                        expr: term.expr.clone(),
+                        alias: None,
+                        text: term.expr.to_string(),
+                    });
+                }
+                None => Err(anyhow!(msg!("order-by-no-column", term = ordinal(n + 1))))?,
+            }
+        }
+        let plans = match hidden.is_empty() {
+            true => selects.iter().map(|s| self.plan_select(s)).collect::<Result<Vec<_>>>()?,
+            false => {
+                let mut select = selects[0].clone();
+                if let Statement::Select { columns, .. } = &mut select {
+                    columns.extend(hidden);
+                }
+                vec![self.plan_select(&select)?]
+            }
+        };
+        for (op, plan) in operators.iter().zip(&plans[1..]) {
+            if plan.selected.len() != width {
+                Err(anyhow!(msg!("compound-column-count", operator = op)))?
+            }
+        }
+
+        out.write_header(&plans[0].names[..width])?;
+        if self.smart_rendering {
+            out.set_renderings(&self.renderings(&plans[0])[..width]);
+        }
+        let limit = limit.as_ref().map(|e| self.limit_value(e)).transpose()?.flatten();
+        let skip = offset.as_ref().map(|e| self.limit_value(e)).transpose()?.flatten();
+        let mut sink = Sink {
+            out,
+            width,
+            skip: skip.unwrap_or(0),
+            kept: match (limit, self.max_rows) {
+                (Some(limit), Some(max)) => Some(limit.min(max)),
+                (limit, max) => limit.or(max),
+            },
+        };
+
+        if order.is_empty() && operators.iter().all(|op| *op == CompoundOp::UnionAll) {
+            for plan in &plans {
+                if sink.done() {
+                    break;
+                }
+                self.execute_select_plan(plan, sink.wanted(), &mut sink)?;
+            }
+            return Ok(());
+        }
+
+        // A term sorts text with the collation of the column it names in the
+        // first select.
+        let collations = order
+            .iter()
+            .map(|(column, _)| self.expr_collation(&plans[0].selected[*column], &plans[0].columns))
+            .collect::<Vec<_>>();
+        let mut rows = self.select_rows(&plans[0])?;
+        for (op, plan) in operators.iter().zip(&plans[1..]) {
+            let right = self.select_rows(plan)?;
+            rows = combine(*op, rows, right);
+        }
+        rows.sort_by(|a, b| {
+            order
+                .iter()
+                .zip(&collations)
+                .map(|((column, term), collation)| compare_terms(&a[*column], &b[*column], term, *collation))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+        for row in rows {
+            if sink.done() {
+                break;
+            }
+            sink.write_row(&row)?;
+        }
+        Ok(())
+    }
+
+    /// All the rows `plan` produces.
+    fn select_rows(&self, plan: &SelectPlan) -> Result<Vec<Vec<Record>>> {
+        let mut rows = Rows::default();
+        self.execute_select_plan(plan, None, &mut rows)?;
+        Ok(rows.0)
+    }
+
+    /// The number a LIMIT or OFFSET expression gives, taken with INTEGER
+    /// affinity so `'2'` and `2.0` count as 2; none when it is negative,
+    /// which sqlite takes for no limit and no offset.
+    fn limit_value(&self, e: &Expr) -> Result<Option<usize>> {
+        let value = self.evaluate(e, &[], &[])?;
+        match apply_affinity(Affinity::Integer, value).integer() {
+            Some(value) => Ok(usize::try_from(value).ok()),
+            None => Err(anyhow!(msg!("datatype-mismatch"))),
+        }
+    }
+}
+
+/// The result column ORDER BY term `n`, counting from 0, sorts by: the one
+/// numbered by an integer, the one aliased by a bare name, or the one of any
+/// select that is the same expression. None for anything else.
+fn order_column(n: usize, term: &OrderingTerm, selects: &[Statement], width: usize) -> Result<Option<usize>> {
+    if let Expr::Literal(value) = &term.expr {
+        if let Some(number) = value.integer() {
+            return match usize::try_from(number).ok().filter(|k| (1..=width).contains(k)) {
+                Some(k) => Ok(Some(k - 1)),
+                None => Err(anyhow!(msg!("order-by-out-of-range", term = ordinal(n + 1), count = width))),
+            };
+        }
+    }
+    if let Expr::Column(column) = &term.expr {
+        let aliased = result_columns(&selects[0])
+            .iter()
+            .position(|c| column.table.is_none() && c.alias.as_ref().is_some_and(|a| a.eq_ignore_ascii_case(&column.name)));
+        if aliased.is_some() {
+            return Ok(aliased);
+        }
+    }
+    Ok(selects.iter().find_map(|s| result_columns(s).iter().position(|c| c.expr == term.expr)))
+}
+
+fn result_columns(select: &Statement) -> &[ResultColumn] {
+    match select {
+        Statement::Select { columns, .. } => columns,
+        _ => unreachable!(),
+    }
+}
+
+/// `n` as sqlite writes it in errors: 1st, 2nd, 3rd, 4th and so on.
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// The rows of `op` applied to `left` and `right`. All but UNION ALL drop
+/// duplicate rows and, as sqlite's temporary b-trees do, sort what is left.
+fn combine(op: CompoundOp, mut left: Vec<Vec<Record>>, right: Vec<Vec<Record>>) -> Vec<Vec<Record>> {
+    let keys = |rows: &[Vec<Record>]| rows.iter().map(|row| aggregate::group_key(row)).collect::<HashSet<GroupKey>>();
+    let mut rows = match op {
+        CompoundOp::UnionAll => {
+            left.extend(right);
+            return left;
+        }
+        CompoundOp::Union => {
+            left.extend(right);
+            left
+        }
+        CompoundOp::Intersect => {
+            let right = keys(&right);
+            left.into_iter().filter(|row| right.contains(&aggregate::group_key(row))).collect()
+        }
+        CompoundOp::Except => {
+            let right = keys(&right);
+            left.into_iter().filter(|row| !right.contains(&aggregate::group_key(row))).collect()
+        }
+    };
+    let mut seen = HashSet::new();
+    rows.retain(|row| seen.insert(aggregate::group_key(row)));
+    rows.sort_by(|a, b| {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| compare_records(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    rows
+}
+
+/// How `a` and `b` order under `term`, comparing text with `collation`.
+fn compare_terms(a: &Record, b: &Record, term: &OrderingTerm, collation: Collation) -> Ordering {
+    match (matches!(a, Record::Null), matches!(b, Record::Null)) {
+        (true, true) => Ordering::Equal,
+        (true, false) if term.nulls_first => Ordering::Less,
+        (true, false) => Ordering::Greater,
+        (false, true) if term.nulls_first => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) if term.descending => collation.compare(a, b).reverse(),
+        (false, false) => collation.compare(a, b),
+    }
+}
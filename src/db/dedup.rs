@@ -0,0 +1,92 @@
+//! How much of their storage similar databases have in common: every page
+//! of each is hashed, and a page whose contents another database holds too
+//! is one a deduplicating backup store would only keep once.
+
+use super::Database;
+use crate::{interrupt, sha256::Sha256};
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+
+/// One database of a [`dedup_pages`] report.
+#[derive(Debug)]
+pub struct DedupFile {
+    /// The file the database was opened from, if any.
+    pub path: Option<String>,
+    pub pages: usize,
+    pub page_size: usize,
+    /// Pages whose contents another of the databases holds as well.
+    pub shared: usize,
+}
+
+impl DedupFile {
+    /// Pages whose contents no other of the databases holds.
+    pub fn unique(&self) -> usize {
+        self.pages - self.shared
+    }
+}
+
+/// What [`dedup_pages`] found.
+#[derive(Debug)]
+pub struct Dedup {
+    pub files: Vec<DedupFile>,
+    /// Pages with different contents, over all the databases.
+    pub distinct: usize,
+    /// Bytes that keeping each distinct page once saves: every copy of a
+    /// page after the first, within one database or across them.
+    pub shared_bytes: u64,
+}
+
+impl Dedup {
+    pub fn total_pages(&self) -> usize {
+        self.files.iter().map(|f| f.pages).sum()
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|f| (f.pages * f.page_size) as u64).sum()
+    }
+}
+
+/// Hashes every page of `databases` and counts the pages they share.
+/// Pages compare by their whole contents, so page 1, which holds each
+/// database's own header, is seldom shared.
+pub fn dedup_pages(databases: &[&Database]) -> Result<Dedup> {
+    // For each page contents: its size, how many copies there are, and in
+    // how many databases.
+    let mut seen: HashMap<[u8; 32], (usize, usize, usize)> = HashMap::new();
+    let mut hashes = Vec::with_capacity(databases.len());
+    for db in databases {
+        db.db.pin_snapshot()?;
+        let mut own = Vec::with_capacity(db.page_count()?);
+        let mut counted = HashSet::new();
+        for page_num in 1..=db.page_count()? {
+            interrupt::check()?;
+            let mut sha = Sha256::new();
+            sha.update(&db.read_raw_page(page_num)?);
+            let hash = sha.finish();
+            let entry = seen.entry(hash).or_insert((db.page_size, 0, 0));
+            entry.1 += 1;
+            // The first copy in a database counts the database.
+            if counted.insert(hash) {
+                entry.2 += 1;
+            }
+            own.push(hash);
+        }
+        hashes.push(own);
+    }
+
+    let files = databases
+        .iter()
+        .zip(&hashes)
+        .map(|(db, own)| DedupFile {
+            path: db.path().map(str::to_string),
+            pages: own.len(),
+            page_size: db.page_size,
+            shared: own.iter().filter(|hash| seen[*hash].2 > 1).count(),
+        })
+        .collect();
+    Ok(Dedup {
+        files,
+        distinct: seen.len(),
+        shared_bytes: seen.values().map(|&(size, copies, _)| ((copies - 1) * size) as u64).sum(),
+    })
+}
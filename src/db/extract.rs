@@ -0,0 +1,115 @@
+//! `extract`: chosen tables copied into a new database file, with their
+//! indexes and their `sqlite_sequence` rows, so that a slice of a large
+//! database can be shared without the rest of it. Every b-tree is written
+//! again with the bulk loader, from the cells of the source as they are.
+
+use super::{
+    bulk::{BulkLoader, Tree},
+    integrity::decode_record,
+    pages::{Pages, INTERIOR_INDEX, LEAF_INDEX},
+    snapshot::create_database,
+    Database,
+};
+use crate::{error::Error, messages::msg, page::schema, record::{serialize_record, Record}};
+use anyhow::{anyhow, Result};
+use std::fs;
+
+impl Database {
+    /// Copies `tables` into a new database at `path`, which must not exist
+    /// yet: their rows, their indexes, automatic ones included, and their
+    /// rows of `sqlite_sequence`. Triggers, which may name tables left
+    /// behind, are not copied, and neither are views. The new file has this
+    /// one's page size, text encoding and schema format. Returns how many
+    /// rows were copied.
+    pub fn extract_tables(&self, tables: &[String], path: &str) -> Result<usize> {
+        let mut chosen = Vec::new();
+        for table in tables {
+            let found = self.schema.iter().find(|s| s.kind != schema::Kind::Trigger && s.name.eq_ignore_ascii_case(table));
+            match found {
+                Some(s) if s.kind == schema::Kind::Table => chosen.push(s.name.as_str()),
+                Some(s) => Err(anyhow!(msg!("extract-not-a-table", name = s.name)))?,
+                None => {
+                    let candidates = self.schema.iter().filter(|s| s.kind == schema::Kind::Table);
+                    Err(Error::no_such_table(table, candidates.map(|s| s.name.as_str())))?
+                }
+            }
+        }
+        let is_chosen = |name: &Record| matches!(name, Record::Text(name) if chosen.contains(&name.as_str()));
+        let mut counters = false;
+        for table in &chosen {
+            counters |= self.get_table(table)?.columns.iter().any(|c| c.autoincrement);
+        }
+
+        let mut rows = Vec::new();
+        self.tree_entries(1, &mut |_, payload| {
+            rows.push(decode_record(&payload, self)?);
+            Ok(())
+        })?;
+        rows.retain(|row| match row.first() {
+            Some(Record::Text(kind)) if kind == "table" => {
+                is_chosen(&row[1]) || counters && matches!(&row[1], Record::Text(name) if name == "sqlite_sequence")
+            }
+            Some(Record::Text(kind)) if kind == "index" => is_chosen(&row[2]),
+            _ => false,
+        });
+
+        create_database(path, self.page_size)?;
+        let copied = Database::load_db(path.to_string()).and_then(|db| {
+            db.autocommit(|| {
+                let mut pages = Pages::new(&db)?;
+                // The cells are copied as they are, so the new file has to
+                // read them the same way.
+                pages.set_header_field(44, self.header_field(44)?.max(1) as usize)?;
+                pages.set_header_field(56, self.header_field(56)?.max(1) as usize)?;
+                let copied = self.copy_trees(&mut pages, &mut rows, &is_chosen)?;
+                let mut loader = BulkLoader::new(&mut pages, Tree::Table, 1);
+                for (rowid, row) in rows.iter().enumerate() {
+                    loader.add_row(rowid as i64 + 1, &serialize_record(row, self.text_encoding, self.schema_format))?;
+                }
+                loader.finish()?;
+                pages.set_header_field(40, 1)?;
+                db.write_pages(&pages.into_changed())?;
+                Ok(copied)
+            })
+        });
+        // A partial copy is no use to anyone.
+        if copied.is_err() {
+            let _ = fs::remove_file(path);
+        }
+        copied
+    }
+
+    /// Writes the b-tree of each of `rows`, entries of `sqlite_schema`, to
+    /// `pages` and points the entry at its new root. Of `sqlite_sequence`,
+    /// only the rows of tables `is_chosen` accepts are written. Returns how
+    /// many rows of the chosen tables there were.
+    fn copy_trees(&self, pages: &mut Pages, rows: &mut [Vec<Record>], is_chosen: &dyn Fn(&Record) -> bool) -> Result<usize> {
+        let mut copied = 0;
+        for row in rows {
+            let Some(root) = row.get(3).and_then(Record::integer).filter(|&root| root > 0) else {
+                continue;
+            };
+            let tree = match self.read_raw_page(root as usize)?[0] {
+                LEAF_INDEX | INTERIOR_INDEX => Tree::Index,
+                _ => Tree::Table,
+            };
+            let sequence = matches!(&row[1], Record::Text(name) if name == "sqlite_sequence");
+            let counted = matches!(&row[0], Record::Text(kind) if kind == "table") && !sequence;
+            let rootpage = pages.create_tree(tree.leaf())?;
+            let mut loader = BulkLoader::new(pages, tree, rootpage);
+            self.tree_entries(root as usize, &mut |rowid, payload| {
+                if sequence && !decode_record(&payload, self)?.first().is_some_and(is_chosen) {
+                    return Ok(());
+                }
+                copied += counted as usize;
+                match tree {
+                    Tree::Table => loader.add_row(rowid, &payload),
+                    Tree::Index => loader.add_record(&payload),
+                }
+            })?;
+            loader.finish()?;
+            row[3] = Record::Int64(rootpage as i64);
+        }
+        Ok(copied)
+    }
+}
@@ -0,0 +1,225 @@
+//! The freelist, and what every other page of the database is used for.
+//! The freelist is a chain of trunk pages starting from header offset 32;
+//! each trunk lists leaf pages, which hold nothing at all.
+
+use super::{
+    local_payload, malformed, parse_varint,
+    pages::{INTERIOR_INDEX, INTERIOR_TABLE, LEAF_INDEX, LEAF_TABLE},
+    Database,
+};
+use crate::{interrupt, messages::msg, page::schema, DB_HEADER_SIZE};
+use anyhow::Result;
+
+/// The pages of the freelist, in the order the chain lists them.
+#[derive(Debug, Default)]
+pub struct Freelist {
+    pub trunks: Vec<usize>,
+    pub leaves: Vec<usize>,
+}
+
+impl Freelist {
+    /// Every free page, trunks included, in page order.
+    pub fn pages(&self) -> Vec<usize> {
+        let mut pages = [self.trunks.as_slice(), self.leaves.as_slice()].concat();
+        pages.sort_unstable();
+        pages
+    }
+}
+
+/// What a page of the database is used for, as told by
+/// [`Database::page_usage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageUse {
+    /// A page of the b-tree of the table or index `name`.
+    Tree { name: String, role: PageRole },
+    FreeTrunk,
+    FreeLeaf,
+    /// A pointer-map page of an auto-vacuum database.
+    PointerMap,
+    /// The page holding sqlite's lock bytes, which is never used.
+    PendingByte,
+    /// Nothing points to it, which leaves it as wasted as a free page.
+    Unused,
+}
+
+/// The part a page plays in its b-tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageRole {
+    Interior,
+    Leaf,
+    /// Holds the part of a cell's payload that does not fit on its page.
+    Overflow,
+}
+
+impl Database {
+    /// Reads the freelist's chain of trunk pages. Pages past the end of the
+    /// file, and a chain that loops, make it malformed.
+    pub fn freelist(&self) -> Result<Freelist> {
+        let page_count = self.page_count()?;
+        if page_count == 0 {
+            return Ok(Freelist::default());
+        }
+        let header = self.read_raw_page(1)?;
+        let usable = self.page_size - header[20] as usize;
+        let in_range = |page_num: usize| (1..=page_count).contains(&page_num);
+        let mut freelist = Freelist::default();
+        let mut trunk = u32_at(&header, 32);
+        while trunk != 0 {
+            if !in_range(trunk) || freelist.trunks.contains(&trunk) {
+                Err(malformed(Some(trunk), msg!("trunk-out-of-place", page = trunk)))?
+            }
+            freelist.trunks.push(trunk);
+            let page = self.read_raw_page(trunk)?;
+            let count = u32_at(&page, 4);
+            if 8 + 4 * count > usable {
+                Err(malformed(Some(trunk), msg!("trunk-leaf-count", page = trunk, count = count)))?
+            }
+            for idx in 0..count {
+                let leaf = u32_at(&page, 8 + 4 * idx);
+                if !in_range(leaf) {
+                    Err(malformed(Some(leaf), msg!("freelist-leaf-past-end", page = leaf)))?
+                }
+                freelist.leaves.push(leaf);
+            }
+            trunk = u32_at(&page, 0);
+        }
+        Ok(freelist)
+    }
+
+    /// What each page is used for, starting with page 1: the b-tree of
+    /// `sqlite_schema` and of every table and index, the freelist, and the
+    /// pages nothing points to.
+    pub fn page_usage(&self) -> Result<Vec<PageUse>> {
+        if self.page_count()? == 0 {
+            return Ok(Vec::new());
+        }
+        let header = self.read_raw_page(1)?;
+        let usable = self.page_size - header[20] as usize;
+        let mut usage = UsageMap {
+            db: self,
+            pages: vec![PageUse::Unused; self.page_count()?],
+            usable,
+        };
+
+        usage.visit_tree(1, "sqlite_schema")?;
+        for entry in self.schema.iter().filter(|s| s.rootpage > 0) {
+            if matches!(entry.kind, schema::Kind::Table | schema::Kind::Index) {
+                usage.visit_tree(entry.rootpage, &entry.name)?;
+            }
+        }
+        let freelist = self.freelist()?;
+        for &trunk in &freelist.trunks {
+            usage.claim(trunk, PageUse::FreeTrunk)?;
+        }
+        for &leaf in &freelist.leaves {
+            usage.claim(leaf, PageUse::FreeLeaf)?;
+        }
+        let pending_byte = (1 << 30) / self.page_size + 1;
+        if pending_byte <= usage.pages.len() {
+            usage.claim(pending_byte, PageUse::PendingByte)?;
+        }
+        if u32_at(&header, 52) != 0 {
+            // One pointer-map page leads each run of the pages it describes.
+            for page_num in (2..=usage.pages.len()).step_by(usable / 5 + 1) {
+                usage.claim(page_num, PageUse::PointerMap)?;
+            }
+        }
+        Ok(usage.pages)
+    }
+}
+
+struct UsageMap<'a> {
+    db: &'a Database,
+    /// By page number, counting from 1.
+    pages: Vec<PageUse>,
+    usable: usize,
+}
+
+impl UsageMap<'_> {
+    /// Records what `page_num` is used for; a page used twice means the
+    /// database is malformed.
+    fn claim(&mut self, page_num: usize, used: PageUse) -> Result<()> {
+        match self.pages.get_mut(page_num.wrapping_sub(1)) {
+            Some(page) if *page == PageUse::Unused => {
+                *page = used;
+                Ok(())
+            }
+            Some(_) => Err(malformed(Some(page_num), msg!("page-used-twice", page = page_num))),
+            None => Err(malformed(Some(page_num), msg!("page-past-end", page = page_num))),
+        }
+    }
+
+    fn visit_tree(&mut self, page_num: usize, name: &str) -> Result<()> {
+        interrupt::check()?;
+        let page = self.db.read_raw_page(page_num)?;
+        let header = if page_num == 1 { DB_HEADER_SIZE } else { 0 };
+        let kind = page[header];
+        let role = match kind {
+            INTERIOR_INDEX | INTERIOR_TABLE => PageRole::Interior,
+            LEAF_INDEX | LEAF_TABLE => PageRole::Leaf,
+            kind => Err(malformed(Some(page_num), msg!("unknown-page-type", page = page_num, kind = kind)))?,
+        };
+        self.claim(page_num, PageUse::Tree { name: name.to_string(), role })?;
+
+        let u16_at = |at: usize| u16::from_be_bytes([page[at], page[at + 1]]) as usize;
+        let pointers = header + if role == PageRole::Interior { 12 } else { 8 };
+        for idx in 0..u16_at(header + 3) {
+            let offset = u16_at(pointers + 2 * idx);
+            let cell = page
+                .get(offset..self.usable)
+                .ok_or_else(|| malformed(Some(page_num), msg!("cell-starts-past-end", page = page_num)))?;
+            let payload = match kind {
+                INTERIOR_TABLE => None,
+                LEAF_TABLE => {
+                    let (size, rest, _) = parse_varint(cell)?;
+                    let (_, rest, _) = parse_varint(rest)?;
+                    Some((size as usize, rest, true))
+                }
+                LEAF_INDEX => {
+                    let (size, rest, _) = parse_varint(cell)?;
+                    Some((size as usize, rest, false))
+                }
+                _ => {
+                    let (size, rest, _) = parse_varint(&cell[4..])?;
+                    Some((size as usize, rest, false))
+                }
+            };
+            if role == PageRole::Interior {
+                self.visit_tree(u32_at(cell, 0), name)?;
+            }
+            if let Some((size, stored, table_leaf)) = payload {
+                self.visit_overflow(page_num, size, stored, table_leaf, name)?;
+            }
+        }
+        if role == PageRole::Interior {
+            self.visit_tree(u32_at(&page, header + 8), name)?;
+        }
+        Ok(())
+    }
+
+    /// Claims the overflow pages of a cell on `page_num` with a payload of
+    /// `size` bytes, continuing with `stored`.
+    fn visit_overflow(&mut self, page_num: usize, size: usize, stored: &[u8], table_leaf: bool, name: &str) -> Result<()> {
+        let local = local_payload(self.usable, size, table_leaf);
+        if local == size {
+            return Ok(());
+        }
+        let pointer = stored
+            .get(local..local + 4)
+            .ok_or_else(|| malformed(Some(page_num), msg!("cell-runs-past-end", page = page_num)))?;
+        let mut next = u32_at(pointer, 0);
+        let mut left = size - local;
+        while left > 0 {
+            // A page claimed twice ends a chain that loops.
+            self.claim(next, PageUse::Tree { name: name.to_string(), role: PageRole::Overflow })?;
+            let page = self.db.read_raw_page(next)?;
+            left = left.saturating_sub(self.usable - 4);
+            next = u32_at(&page, 0);
+        }
+        Ok(())
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> usize {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as usize
+}
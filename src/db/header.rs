@@ -0,0 +1,69 @@
+//! `header set`: changes to the fields of the database header people tune
+//! by hand, written through the journal like any other change so that a
+//! crash leaves the old header or the new one.
+
+use super::{pages::Pages, Database};
+use crate::{error::Error, messages::msg};
+use anyhow::{anyhow, Result};
+use std::ops::RangeInclusive;
+
+/// A header field `header set` can change: its name, where it is, the
+/// values it takes, and whether it needs `--force`.
+struct Field {
+    name: &'static str,
+    offset: usize,
+    values: RangeInclusive<i64>,
+    dangerous: bool,
+}
+
+const FIELDS: &[Field] = &[
+    Field { name: "user_version", offset: 60, values: i32::MIN as i64..=i32::MAX as i64, dangerous: false },
+    Field { name: "application_id", offset: 68, values: i32::MIN as i64..=i32::MAX as i64, dangerous: false },
+    Field { name: "change_counter", offset: 24, values: 0..=u32::MAX as i64, dangerous: false },
+    // Other connections trust their cached schema while it stays the same.
+    Field { name: "schema_cookie", offset: 40, values: 0..=u32::MAX as i64, dangerous: true },
+    // Records and text are read by these, so a wrong one misreads every row.
+    Field { name: "schema_format", offset: 44, values: 1..=4, dangerous: true },
+    Field { name: "text_encoding", offset: 56, values: 1..=3, dangerous: true },
+];
+
+impl Database {
+    /// Sets the header field `field` to `value`: `user_version`,
+    /// `application_id` or `change_counter`, or with `force`, which says
+    /// the caller knows the database may read differently afterwards,
+    /// `schema_cookie`, `schema_format` or `text_encoding`.
+    pub fn set_header(&self, field: &str, value: &str, force: bool) -> Result<()> {
+        let names = || FIELDS.iter().map(|f| f.name).collect::<Vec<_>>().join(", ");
+        let Some(field) = FIELDS.iter().find(|f| f.name.eq_ignore_ascii_case(field)) else {
+            Err(anyhow!(msg!("unknown-header-field", field = field, names = names())))?
+        };
+        let value = match value.parse::<i64>() {
+            Ok(value) if field.values.contains(&value) => value,
+            _ => Err(anyhow!(msg!(
+                "header-value-out-of-range",
+                field = field.name,
+                low = field.values.start(),
+                high = field.values.end(),
+                value = value
+            )))?,
+        };
+        if field.dangerous && !force {
+            Err(anyhow!(msg!("header-field-needs-force", field = field.name)))?
+        }
+        let header = self.read_raw_page(1)?;
+        if header[18] > 1 || header[19] > 1 {
+            Err(Error::UnsupportedFeature(msg!("header-wal")))?
+        }
+        // Committing counts as a change too, and takes the counter the rest
+        // of the way.
+        let value = match field.name {
+            "change_counter" => (value as u32).wrapping_sub(1),
+            _ => value as u32,
+        };
+        self.autocommit(|| {
+            let mut pages = Pages::new(self)?;
+            pages.set_header_field(field.offset, value as usize)?;
+            self.write_pages(&pages.into_changed())
+        })
+    }
+}
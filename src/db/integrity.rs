@@ -0,0 +1,424 @@
+//! `.integrity-check`: walks every b-tree named in the schema plus the
+//! freelist and reports what does not add up, page by page, instead of
+//! stopping at the first problem.
+
+use super::{compare_key, local_payload, parse_varint, Database, Table};
+use crate::{
+    interrupt,
+    messages::msg,
+    page::schema,
+    record::{parse_record, RawRecord, Record, TextDecoding},
+    sql::IndexColumn,
+    DB_HEADER_SIZE,
+};
+use anyhow::{anyhow, Result};
+use std::{cmp::Ordering, sync::Arc};
+
+#[derive(Clone, Copy)]
+enum Tree<'a> {
+    Table,
+    Index(&'a [IndexColumn]),
+    /// Keyed by the primary key, whose collations the checker does not
+    /// follow, so key order goes unchecked.
+    WithoutRowid,
+}
+
+impl Tree<'_> {
+    fn is_table(&self) -> bool {
+        matches!(self, Tree::Table)
+    }
+}
+
+/// The key a cell sorts by: its rowid in a table b-tree, its whole record
+/// in an index b-tree.
+#[derive(Clone)]
+enum Key {
+    Rowid(i64),
+    Record(Vec<Record>),
+}
+
+struct Checker<'a> {
+    db: &'a Database,
+    usable_size: usize,
+    page_count: usize,
+    /// Indexed by page number; slot 0 is unused.
+    used: Vec<bool>,
+    problems: Vec<String>,
+}
+
+/// A cell's payload, gathered from its page and its overflow chain.
+struct Payload {
+    bytes: Vec<u8>,
+    complete: bool,
+}
+
+impl Database {
+    /// Problems found in the b-trees, the freelist and the page accounting.
+    /// An empty list means the file is consistent.
+    pub fn integrity_check(&self) -> Result<Vec<String>> {
+        let page_count = self.page_count()?;
+        if page_count == 0 {
+            return Ok(Vec::new());
+        }
+        let header = self.read_raw_page(1)?;
+        let mut checker = Checker {
+            db: self,
+            usable_size: self.page_size - header[20] as usize,
+            page_count,
+            used: vec![false; page_count + 1],
+            problems: Vec::new(),
+        };
+
+        checker.check_tree(1, Tree::Table);
+        for entry in self.schema.iter().filter(|s| s.rootpage > 0) {
+            let tree = match entry.kind {
+                schema::Kind::Index => match self.indexes.iter().find(|i| i.name == entry.name) {
+                    Some(index) => Tree::Index(&index.columns),
+                    None => Tree::Index(&[]),
+                },
+                _ => match self.get_table(&entry.name) {
+                    Ok(Table { key_order: Some(_), .. }) => Tree::WithoutRowid,
+                    _ => Tree::Table,
+                },
+            };
+            checker.check_tree(entry.rootpage, tree);
+        }
+        checker.check_freelist(&header);
+        checker.check_unused(&header);
+
+        Ok(checker.problems)
+    }
+}
+
+impl Checker<'_> {
+    fn check_tree(&mut self, root: usize, tree: Tree) {
+        if let Err(e) = self.visit(root, root, tree, None, None) {
+            self.problems.push(format!("Tree {}: {}", root, e));
+        }
+    }
+
+    /// Claims `page_num` for one use. Returns false, after reporting why,
+    /// when it does not exist or something else already uses it.
+    fn claim(&mut self, page_num: usize, context: &str) -> bool {
+        if page_num == 0 || page_num > self.page_count {
+            self.problems
+                .push(format!("{}: invalid page number {}", context, page_num));
+            return false;
+        }
+        if self.used[page_num] {
+            self.problems
+                .push(format!("{}: 2nd reference to page {}", context, page_num));
+            return false;
+        }
+        self.used[page_num] = true;
+        true
+    }
+
+    /// Checks one page and its subtree, whose keys must lie above `low` and at
+    /// or below `high`. Returns the depth of the subtree.
+    fn visit(
+        &mut self,
+        root: usize,
+        page_num: usize,
+        tree: Tree,
+        low: Option<Key>,
+        high: Option<Key>,
+    ) -> Result<Option<usize>> {
+        interrupt::check()?;
+        let context = format!("Tree {} page {}", root, page_num);
+        if !self.claim(page_num, &format!("Tree {}", root)) {
+            return Ok(None);
+        }
+
+        let page = self.db.read_raw_page(page_num)?;
+        let offset = if page_num == 1 { DB_HEADER_SIZE } else { 0 };
+        let kind = page[offset];
+        let interior = match (kind, tree.is_table()) {
+            (5, true) | (2, false) => true,
+            (13, true) | (10, false) => false,
+            _ => {
+                self.problems.push(format!(
+                    "{}: page type {} does not belong in a {} b-tree",
+                    context,
+                    kind,
+                    if tree.is_table() { "table" } else { "index" }
+                ));
+                return Ok(None);
+            }
+        };
+
+        let num_cells = u16::from_be_bytes([page[offset + 3], page[offset + 4]]) as usize;
+        let header_end = offset + if interior { 12 } else { 8 };
+        let pointers_end = header_end + num_cells * 2;
+        if pointers_end > self.usable_size {
+            self.problems
+                .push(format!("{}: {} cells do not fit on the page", context, num_cells));
+            return Ok(None);
+        }
+
+        let mut previous = low;
+        let mut depths = Vec::new();
+        let mut extents = Vec::new();
+        for idx in 0..num_cells {
+            let at = header_end + idx * 2;
+            let ptr = u16::from_be_bytes([page[at], page[at + 1]]) as usize;
+            let cell_context = format!("{} cell {}", context, idx);
+            if ptr < pointers_end || ptr >= self.usable_size {
+                self.problems
+                    .push(format!("{}: offset {} is out of bounds", cell_context, ptr));
+                continue;
+            }
+
+            let cell = match self.read_cell(&page[ptr..self.usable_size], interior, tree, &cell_context) {
+                Ok(cell) => cell,
+                Err(e) => {
+                    self.problems.push(format!("{}: {}", cell_context, e));
+                    continue;
+                }
+            };
+            extents.push((ptr, ptr + cell.size, idx));
+
+            if let Some(key) = &cell.key {
+                if let Some(previous) = &previous {
+                    if self.compare(tree, previous, key).is_some_and(|o| o.is_ge()) {
+                        self.problems
+                            .push(format!("{}: key is out of order", cell_context));
+                    }
+                }
+                if let Some(Ordering::Greater) = high.as_ref().and_then(|h| self.compare(tree, key, h)) {
+                    self.problems
+                        .push(format!("{}: key is beyond its parent's bound", cell_context));
+                }
+            }
+            if let Some(child) = cell.left_child {
+                depths.extend(self.visit(root, child, tree, previous.clone(), cell.key.clone())?);
+            }
+            previous = cell.key.or(previous);
+        }
+
+        extents.sort_unstable();
+        for pair in extents.windows(2) {
+            if pair[1].0 < pair[0].1 {
+                self.problems.push(format!(
+                    "{}: cells {} and {} overlap",
+                    context, pair[0].2, pair[1].2
+                ));
+            }
+        }
+
+        if interior {
+            let at = offset + 8;
+            let right = u32::from_be_bytes([page[at], page[at + 1], page[at + 2], page[at + 3]]);
+            depths.extend(self.visit(root, right as usize, tree, previous, high)?);
+        }
+
+        if depths.iter().any(|&d| d != depths[0]) {
+            self.problems
+                .push(format!("{}: child page depth differs", context));
+        }
+        Ok(Some(depths.first().map_or(0, |d| d + 1)))
+    }
+
+    fn compare(&self, tree: Tree, a: &Key, b: &Key) -> Option<Ordering> {
+        match (tree, a, b) {
+            (Tree::Table, Key::Rowid(a), Key::Rowid(b)) => Some(a.cmp(b)),
+            (Tree::Index(columns), Key::Record(a), Key::Record(b)) => {
+                Some(compare_key(a, b, columns).then(a.len().cmp(&b.len())))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses the cell at the start of `cell`, following its overflow chain.
+    fn read_cell(&mut self, cell: &[u8], interior: bool, tree: Tree, context: &str) -> Result<Cell> {
+        let total = cell.len();
+        let (left_child, cell) = match interior {
+            true if cell.len() >= 4 => (
+                Some(u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]) as usize),
+                &cell[4..],
+            ),
+            true => Err(anyhow!(msg!("cell-past-end")))?,
+            false => (None, cell),
+        };
+
+        if interior && tree.is_table() {
+            let (rowid, rest, _) = parse_varint(cell)?;
+            return Ok(Cell {
+                left_child,
+                key: Some(Key::Rowid(rowid as i64)),
+                size: total - rest.len(),
+            });
+        }
+
+        let (payload_size, mut rest, _) = parse_varint(cell)?;
+        let mut rowid = None;
+        if tree.is_table() {
+            let (id, after, _) = parse_varint(rest)?;
+            rowid = Some(id as i64);
+            rest = after;
+        }
+
+        let payload_size = payload_size as usize;
+        let local = local_payload(self.usable_size, payload_size, tree.is_table());
+        let stored = if local < payload_size { local + 4 } else { local };
+        if rest.len() < stored {
+            Err(anyhow!(msg!("cell-past-end")))?
+        }
+        let size = total - rest.len() + stored;
+        let payload = self.read_payload(&rest[..stored], payload_size, local, context);
+
+        let values = match payload.complete {
+            true => self.check_record(&payload.bytes, context),
+            false => None,
+        };
+        let key = match rowid {
+            Some(rowid) => Some(Key::Rowid(rowid)),
+            None => values.map(Key::Record),
+        };
+        Ok(Cell {
+            left_child,
+            key,
+            size,
+        })
+    }
+
+    /// Gathers a payload from its local part and overflow chain, reporting
+    /// chains that end early, run long or reuse pages.
+    fn read_payload(&mut self, stored: &[u8], size: usize, local: usize, context: &str) -> Payload {
+        let mut bytes = stored[..local].to_vec();
+        if local == size {
+            return Payload {
+                bytes,
+                complete: true,
+            };
+        }
+
+        let mut next = u32::from_be_bytes([stored[local], stored[local + 1], stored[local + 2], stored[local + 3]]) as usize;
+        while bytes.len() < size {
+            if !self.claim(next, &format!("{} overflow", context)) {
+                return Payload {
+                    bytes,
+                    complete: false,
+                };
+            }
+            let page = match self.db.read_raw_page(next) {
+                Ok(page) => page,
+                Err(e) => {
+                    self.problems.push(format!("{}: {}", context, e));
+                    return Payload {
+                        bytes,
+                        complete: false,
+                    };
+                }
+            };
+            let take = (size - bytes.len()).min(self.usable_size - 4);
+            bytes.extend_from_slice(&page[4..4 + take]);
+            next = u32::from_be_bytes([page[0], page[1], page[2], page[3]]) as usize;
+        }
+        if next != 0 {
+            self.problems.push(format!(
+                "{}: overflow chain continues to page {} past the end of the payload",
+                context, next
+            ));
+        }
+        Payload {
+            bytes,
+            complete: true,
+        }
+    }
+
+    /// Checks that a record header parses and accounts for exactly the bytes
+    /// of the record, returning the values when it does.
+    fn check_record(&mut self, record: &[u8], context: &str) -> Option<Vec<Record>> {
+        match decode_record(record, self.db) {
+            Ok(values) => Some(values),
+            Err(e) => {
+                self.problems.push(format!("{}: {}", context, e));
+                None
+            }
+        }
+    }
+
+    fn check_freelist(&mut self, header: &[u8]) {
+        let field = |at: usize| u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]) as usize;
+        let expected = field(36);
+        let mut trunk = field(32);
+        let mut found = 0;
+        while trunk != 0 {
+            if !self.claim(trunk, "Freelist") {
+                break;
+            }
+            found += 1;
+            let page = match self.db.read_raw_page(trunk) {
+                Ok(page) => page,
+                Err(e) => {
+                    self.problems.push(format!("Freelist: {}", e));
+                    break;
+                }
+            };
+            let word = |at: usize| u32::from_be_bytes([page[at], page[at + 1], page[at + 2], page[at + 3]]) as usize;
+            let leaves = word(4);
+            if 8 + leaves * 4 > self.usable_size {
+                self.problems.push(format!(
+                    "Freelist: trunk page {} claims {} leaves",
+                    trunk, leaves
+                ));
+                break;
+            }
+            for idx in 0..leaves {
+                if self.claim(word(8 + idx * 4), &format!("Freelist trunk {}", trunk)) {
+                    found += 1;
+                }
+            }
+            trunk = word(0);
+        }
+        if found != expected {
+            self.problems.push(format!(
+                "Freelist: size is {} but should be {}",
+                found, expected
+            ));
+        }
+    }
+
+    /// Pages nothing claimed. Pointer-map pages of auto-vacuum databases and
+    /// the lock-byte page at 1 GiB are never part of a b-tree.
+    fn check_unused(&mut self, header: &[u8]) {
+        let lock_byte_page = (1 << 30) / self.db.page_size + 1;
+        if lock_byte_page <= self.page_count {
+            self.used[lock_byte_page] = true;
+        }
+        let auto_vacuum = u32::from_be_bytes([header[52], header[53], header[54], header[55]]) != 0;
+        if auto_vacuum {
+            let span = self.usable_size / 5 + 1;
+            for page_num in (2..=self.page_count).step_by(span) {
+                self.used[page_num] = true;
+            }
+        }
+
+        for page_num in 1..=self.page_count {
+            if !self.used[page_num] {
+                self.problems.push(format!("Page {}: never used", page_num));
+            }
+        }
+    }
+}
+
+struct Cell {
+    left_child: Option<usize>,
+    key: Option<Key>,
+    /// Bytes the cell takes up on its page.
+    size: usize,
+}
+
+/// Values of a complete record, or why its header does not describe it.
+pub(super) fn decode_record(record: &[u8], db: &Database) -> Result<Vec<Record>> {
+    let fields = parse_record(record, 0, 4)?;
+    let end = match fields.last() {
+        Some((column_type, start)) => start + column_type.size(),
+        None => parse_varint(record)?.0 as usize,
+    };
+    if end < record.len() {
+        Err(anyhow!(msg!("record-undescribed-bytes", bytes = record.len() - end)))?
+    }
+    RawRecord::new(Arc::from(record), fields, db.text_encoding, TextDecoding::Lossy).values()
+}
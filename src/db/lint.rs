@@ -0,0 +1,231 @@
+//! `.lint`: schema checks that need only the CREATE TABLE and CREATE INDEX
+//! statements in `sqlite_schema`, for the mistakes that cost space or time
+//! without ever failing a query.
+
+use super::{schema, Database, Index};
+use crate::sql::{parse_sql, Affinity, ColumnDef, Statement, TableConstraint};
+use anyhow::Result;
+use std::{collections::HashSet, fmt::Display};
+
+/// What a [`Lint`] is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// A foreign key no index starts with, so that deleting or updating a
+    /// parent row scans the whole child table.
+    UnindexedForeignKey,
+    /// A rowid table with no PRIMARY KEY, whose rows only have rowids, which
+    /// VACUUM may renumber.
+    NoPrimaryKey,
+    /// An index with the same columns as another index or a key.
+    DuplicateIndex,
+    /// A non-unique index whose columns start another index or key, which
+    /// serves every lookup it does.
+    RedundantIndex,
+    /// A TEXT PRIMARY KEY on a rowid table, stored once in the table and
+    /// again in its automatic index.
+    TextPrimaryKey,
+}
+
+impl Display for LintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LintKind::UnindexedForeignKey => "unindexed-foreign-key",
+            LintKind::NoPrimaryKey => "no-primary-key",
+            LintKind::DuplicateIndex => "duplicate-index",
+            LintKind::RedundantIndex => "redundant-index",
+            LintKind::TextPrimaryKey => "text-primary-key",
+        })
+    }
+}
+
+/// One finding of [`Database::lint`].
+#[derive(Debug)]
+pub struct Lint {
+    pub kind: LintKind,
+    pub table: String,
+    pub message: String,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} [{}]", self.table, self.message, self.kind)
+    }
+}
+
+/// An index, or a key sqlite keeps an index for without a CREATE INDEX:
+/// the rowid, a PRIMARY KEY or a UNIQUE constraint.
+struct Key<'a> {
+    what: String,
+    /// Column name in lowercase, collation in uppercase, and DESC.
+    columns: Vec<(String, String, bool)>,
+    unique: bool,
+    index: Option<&'a Index>,
+}
+
+impl Key<'_> {
+    fn starts_with(&self, other: &Key) -> bool {
+        self.columns.len() >= other.columns.len() && self.columns[..other.columns.len()] == other.columns[..]
+    }
+}
+
+impl Database {
+    /// Checks the schema of every table for the kinds of [`LintKind`], in
+    /// schema order. Tables whose CREATE TABLE does not parse, and sqlite's
+    /// own tables, are left out.
+    pub fn lint(&self) -> Result<Vec<Lint>> {
+        let mut lints = Vec::new();
+        for table in self.schema.iter().filter(|s| s.kind == schema::Kind::Table) {
+            if table.name.to_lowercase().starts_with("sqlite_") {
+                continue;
+            }
+            let Ok(Statement::CreateTable {
+                columns,
+                constraints,
+                without_rowid,
+                ..
+            }) = parse_sql(&table.sql)
+            else {
+                continue;
+            };
+            let mut lint = |kind, message| {
+                lints.push(Lint {
+                    kind,
+                    table: table.name.clone(),
+                    message,
+                })
+            };
+
+            let primary_key: Vec<&str> = constraints
+                .iter()
+                .find_map(|c| match c {
+                    TableConstraint::PrimaryKey(names) => Some(names.iter().map(String::as_str).collect()),
+                    _ => None,
+                })
+                .unwrap_or_else(|| columns.iter().filter(|c| c.primary_key).map(|c| c.name.as_str()).collect());
+            let rowid_alias = !without_rowid && columns.iter().any(|c| c.rowid_alias);
+
+            if primary_key.is_empty() && !without_rowid {
+                lint(LintKind::NoPrimaryKey, "has no PRIMARY KEY, so its rows are only known by rowid".to_string());
+            }
+            let text = |name: &&str| find(&columns, name).is_some_and(|c| c.affinity() == Affinity::Text);
+            if !without_rowid && !rowid_alias && primary_key.iter().any(text) {
+                lint(
+                    LintKind::TextPrimaryKey,
+                    format!(
+                        "PRIMARY KEY ({}) holds text on a rowid table, which stores every key twice; \
+                         consider WITHOUT ROWID or an INTEGER PRIMARY KEY",
+                        primary_key.join(", ")
+                    ),
+                );
+            }
+
+            let keys = keys(&columns, &constraints, &primary_key, rowid_alias, self.indexes_of(&table.name));
+            for (i, key) in keys.iter().enumerate() {
+                let Some(index) = key.index else { continue };
+                // An equal key that comes first, or is unique where this is not.
+                let duplicate = keys.iter().enumerate().find(|&(j, other)| {
+                    j != i && other.columns == key.columns && ((other.unique && !key.unique) || (other.unique == key.unique && j < i))
+                });
+                if let Some((_, other)) = duplicate {
+                    lint(
+                        LintKind::DuplicateIndex,
+                        format!("index {} has the same columns as {}", index.name, other.what),
+                    );
+                } else if !key.unique {
+                    if let Some(other) = keys.iter().find(|other| other.columns.len() > key.columns.len() && other.starts_with(key)) {
+                        lint(
+                            LintKind::RedundantIndex,
+                            format!("index {} is redundant, {} starts with its columns", index.name, other.what),
+                        );
+                    }
+                }
+            }
+
+            let foreign_keys = columns
+                .iter()
+                .filter_map(|c| Some((vec![c.name.clone()], c.references.as_ref()?)))
+                .chain(constraints.iter().filter_map(|c| match c {
+                    TableConstraint::ForeignKey { columns, references } => Some((columns.clone(), references)),
+                    _ => None,
+                }));
+            for (names, references) in foreign_keys {
+                let wanted: HashSet<String> = names.iter().map(|name| name.to_lowercase()).collect();
+                let covered = keys.iter().any(|key| {
+                    key.columns.len() >= wanted.len()
+                        && key.columns[..wanted.len()].iter().map(|(name, ..)| name).collect::<HashSet<_>>() == wanted.iter().collect()
+                });
+                if !covered {
+                    lint(
+                        LintKind::UnindexedForeignKey,
+                        format!(
+                            "foreign key ({}) references {} but no index starts with {}",
+                            names.join(", "),
+                            references.table,
+                            if names.len() == 1 { "it" } else { "them" }
+                        ),
+                    );
+                }
+            }
+        }
+        Ok(lints)
+    }
+
+    fn indexes_of<'a>(&'a self, table: &'a str) -> impl Iterator<Item = &'a Index> {
+        self.indexes.iter().filter(move |index| index.table.eq_ignore_ascii_case(table))
+    }
+}
+
+fn find<'a>(columns: &'a [ColumnDef], name: &str) -> Option<&'a ColumnDef> {
+    columns.iter().find(|c| c.name.eq_ignore_ascii_case(name))
+}
+
+/// The keys of a table: the rowid or PRIMARY KEY, its UNIQUE constraints,
+/// and then the indexes on it that have no WHERE clause.
+fn keys<'a>(
+    columns: &[ColumnDef],
+    constraints: &[TableConstraint],
+    primary_key: &[&str],
+    rowid_alias: bool,
+    indexes: impl Iterator<Item = &'a Index>,
+) -> Vec<Key<'a>> {
+    let key_column = |name: &str| {
+        let collation = find(columns, name).and_then(|c| c.collation.as_deref()).unwrap_or("BINARY");
+        (name.to_lowercase(), collation.to_uppercase(), false)
+    };
+    let constraint = |what: String, names: &[&str]| Key {
+        what,
+        columns: names.iter().map(|name| key_column(name)).collect(),
+        unique: true,
+        index: None,
+    };
+
+    let mut keys = Vec::new();
+    if !primary_key.is_empty() {
+        let what = match rowid_alias {
+            true => format!("the rowid ({})", primary_key[0]),
+            false => format!("the PRIMARY KEY ({})", primary_key.join(", ")),
+        };
+        keys.push(constraint(what, primary_key));
+    }
+    let unique = columns.iter().filter(|c| c.unique).map(|c| vec![c.name.as_str()]);
+    let unique = unique.chain(constraints.iter().filter_map(|c| match c {
+        TableConstraint::Unique(names) => Some(names.iter().map(String::as_str).collect()),
+        _ => None,
+    }));
+    keys.extend(unique.map(|names| constraint(format!("UNIQUE ({})", names.join(", ")), &names)));
+    keys.extend(indexes.filter(|index| !index.partial).map(|index| Key {
+        what: format!("index {}", index.name),
+        columns: index
+            .columns
+            .iter()
+            .map(|column| {
+                let (name, collation, _) = key_column(&column.name);
+                let collation = column.collation.as_ref().map_or(collation, |c| c.to_uppercase());
+                (name, collation, column.descending)
+            })
+            .collect(),
+        unique: index.unique,
+        index: Some(index),
+    }));
+    keys
+}
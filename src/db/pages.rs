@@ -0,0 +1,847 @@
+//! Table b-tree editing for writes. A statement's changes are made to copies
+//! of the pages it touches, held until the statement is known to succeed.
+//! Cells that no longer fit on their page split it: new pages come from the
+//! freelist or the end of the file, and their dividers go up to the parent,
+//! splitting it in turn, up to a root that grows the tree a level.
+
+use super::{local_payload, malformed, parse_varint, Database};
+use crate::{
+    error::Error,
+    messages::msg,
+    record::{encode_varint, serialize_record, Record},
+    Page, DB_HEADER_SIZE,
+};
+use anyhow::{anyhow, Result};
+use std::collections::{btree_map::Entry, BTreeMap};
+
+pub(super) const LEAF_TABLE: u8 = 13;
+pub(super) const LEAF_INDEX: u8 = 10;
+pub(super) const INTERIOR_TABLE: u8 = 5;
+pub(super) const INTERIOR_INDEX: u8 = 2;
+/// Most fragmented bytes a page header can count before the page has to be
+/// defragmented.
+const MAX_FRAGMENTED: usize = 60;
+/// sqlite locks the bytes from this offset on, so the page holding it is
+/// never used.
+const PENDING_BYTE: usize = 0x4000_0000;
+/// Deeper trees than sqlite ever builds mean the pages point in a cycle.
+pub(super) const MAX_DEPTH: usize = 20;
+
+/// A cell and the rowid it sorts by; 0 for the cells of index pages.
+pub(super) type Cell = (i64, Vec<u8>);
+
+/// The pages a statement has changed so far.
+pub(super) struct Pages<'a> {
+    db: &'a Database,
+    changed: BTreeMap<usize, Vec<u8>>,
+    page_count: usize,
+    usable: usize,
+}
+
+impl<'a> Pages<'a> {
+    pub fn new(db: &'a Database) -> Result<Self> {
+        let header = db.read_raw_page(1)?;
+        Ok(Pages {
+            db,
+            changed: BTreeMap::new(),
+            page_count: db.page_count()?,
+            usable: db.page_size - header[20] as usize,
+        })
+    }
+
+    /// Pages for a database written again from scratch over `db`'s file,
+    /// as VACUUM does: page 1 keeps the database header, with an empty
+    /// freelist, and every other page is allocated anew from page 2 on.
+    /// Unlike a statement's changes, these add up to the whole file.
+    pub fn rebuild(db: &'a Database) -> Result<Self> {
+        let header = db.read_raw_page(1)?;
+        let mut first = vec![0; db.page_size];
+        first[..DB_HEADER_SIZE].copy_from_slice(&header[..DB_HEADER_SIZE]);
+        set_u32(&mut first, 28, 1);
+        set_u32(&mut first, 32, 0);
+        set_u32(&mut first, 36, 0);
+        Ok(Pages {
+            db,
+            changed: BTreeMap::from([(1, first)]),
+            page_count: 1,
+            usable: db.page_size - header[20] as usize,
+        })
+    }
+
+    /// Bytes of each page that cells can use.
+    pub fn usable(&self) -> usize {
+        self.usable
+    }
+
+    /// Pages in the database once the changes are written.
+    pub fn page_count(&self) -> usize {
+        self.page_count
+    }
+
+    /// The changed pages, by page number.
+    pub fn into_changed(self) -> BTreeMap<usize, Vec<u8>> {
+        self.changed
+    }
+
+    /// Replaces the cell of `rowid` in the table b-tree at `root` with `cell`:
+    /// in its slot when it fits there, otherwise by laying its page out again
+    /// and splitting it if need be.
+    pub fn replace_cell(&mut self, root: usize, rowid: i64, cell: Vec<u8>) -> Result<()> {
+        let leaf = self.find_leaf(root, rowid)?;
+        let mut page = self.page(leaf)?;
+        let Some(idx) = page.find(rowid)? else {
+            Err(malformed(Some(leaf), msg!("rowid-not-on-leaf", page = leaf, rowid = rowid)))?
+        };
+        if page.replace_in_place(idx, &cell)? {
+            return Ok(());
+        }
+        let mut cells = page.cells()?;
+        cells[idx].1 = cell;
+        self.store(root, leaf, cells, None)
+    }
+
+    /// Adds `cell`, the leaf cell of `rowid`, to the table b-tree at `root`.
+    pub fn insert_cell(&mut self, root: usize, rowid: i64, cell: Vec<u8>) -> Result<()> {
+        let leaf = self.find_leaf(root, rowid)?;
+        let mut cells = self.page(leaf)?.cells()?;
+        let at = cells.partition_point(|&(key, _)| key < rowid);
+        if cells.get(at).is_some_and(|&(key, _)| key == rowid) {
+            Err(anyhow!(msg!("rowid-taken", rowid = rowid)))?
+        }
+        cells.insert(at, (rowid, cell));
+        self.store(root, leaf, cells, None)
+    }
+
+    /// Whether the table b-tree at `root` has a row `rowid`.
+    pub fn contains(&self, root: usize, rowid: i64) -> Result<bool> {
+        let leaf = self.find_leaf(root, rowid)?;
+        let mut bytes = self.read(leaf)?;
+        Ok(self.view(leaf, &mut bytes).find(rowid)?.is_some())
+    }
+
+    /// The rowid one past the largest in the table b-tree at `root`, or 1 for
+    /// an empty table.
+    pub fn next_rowid(&self, root: usize) -> Result<i64> {
+        let mut page_num = root;
+        for _ in 0..MAX_DEPTH {
+            let mut bytes = self.read(page_num)?;
+            let page = self.view(page_num, &mut bytes);
+            match page.kind() {
+                LEAF_TABLE => {
+                    let last = match page.cell_count() {
+                        0 => 0,
+                        count => page.cell_at(page.cell_offset(count - 1))?.0,
+                    };
+                    return last.checked_add(1).ok_or_else(|| anyhow!(msg!("database-full")));
+                }
+                INTERIOR_TABLE => page_num = page.right_child(),
+                kind => Err(malformed(Some(page_num), msg!("wrong-page-type-in-table", page = page_num, kind = kind)))?,
+            }
+        }
+        Err(malformed(Some(root), msg!("tree-at-page-too-deep", root = root)))
+    }
+
+    /// Allocates the root page of a new, empty b-tree of pages of `kind`.
+    pub fn create_tree(&mut self, kind: u8) -> Result<usize> {
+        let root = self.allocate()?;
+        let mut page = self.page(root)?;
+        page.set_kind(kind);
+        page.lay_out(&[], None);
+        Ok(root)
+    }
+
+    /// Whether the b-tree at `root` holds no cells at all.
+    pub fn is_empty(&self, root: usize) -> Result<bool> {
+        let mut bytes = self.read(root)?;
+        let page = self.view(root, &mut bytes);
+        Ok(!page.is_interior() && page.cell_count() == 0)
+    }
+
+    /// Whether the b-tree at `root` still fits on that one page.
+    pub fn is_single_page(&self, root: usize) -> Result<bool> {
+        let mut bytes = self.read(root)?;
+        Ok(!self.view(root, &mut bytes).is_interior())
+    }
+
+    /// The 4-byte database header field at `offset`.
+    pub fn header_field(&self, offset: usize) -> Result<usize> {
+        Ok(u32_at(&self.read(1)?, offset))
+    }
+
+    pub fn set_header_field(&mut self, offset: usize, value: usize) -> Result<()> {
+        set_u32(self.bytes(1)?, offset, value);
+        Ok(())
+    }
+
+    /// Makes `page_num` a page of `kind` holding `cells`, and `right` as its
+    /// last child when it is an interior page. Returns false, leaving the
+    /// page untouched, when they do not fit.
+    pub fn fill(&mut self, page_num: usize, kind: u8, cells: &[Cell], right: Option<usize>) -> Result<bool> {
+        let mut page = self.page(page_num)?;
+        let old = page.kind();
+        page.set_kind(kind);
+        let fits = page.lay_out(cells, right);
+        if !fits {
+            page.set_kind(old);
+        }
+        Ok(fits)
+    }
+
+    /// The part of `payload` a cell keeps on its page: all of it when it
+    /// fits, otherwise its first bytes followed by the number of the first
+    /// of the new overflow pages holding the rest.
+    pub fn spill(&mut self, payload: &[u8], table_leaf: bool) -> Result<Vec<u8>> {
+        let local = local_payload(self.usable, payload.len(), table_leaf);
+        let mut stored = payload[..local].to_vec();
+        if local == payload.len() {
+            return Ok(stored);
+        }
+        let chunks = payload[local..].chunks(self.usable - 4).collect::<Vec<_>>();
+        let overflow = chunks.iter().map(|_| self.allocate()).collect::<Result<Vec<_>>>()?;
+        for (idx, chunk) in chunks.iter().enumerate() {
+            let page = self.bytes(overflow[idx])?;
+            set_u32(page, 0, overflow.get(idx + 1).copied().unwrap_or(0));
+            page[4..4 + chunk.len()].copy_from_slice(chunk);
+        }
+        stored.extend_from_slice(&(overflow[0] as u32).to_be_bytes());
+        Ok(stored)
+    }
+
+    /// Lays `cells` out on `page_num`, with `right` as the last child when it
+    /// is an interior page. When they do not fit, the lower ones go to new
+    /// pages whose dividers are added to the parent. An overflowing root
+    /// moves its content to a new child first, so that it keeps its page
+    /// number.
+    fn store(&mut self, root: usize, page_num: usize, cells: Vec<Cell>, right: Option<usize>) -> Result<()> {
+        if self.page(page_num)?.lay_out(&cells, right) {
+            return Ok(());
+        }
+        let page_num = match page_num == root {
+            true => {
+                let child = self.allocate()?;
+                let kind = self.page(root)?.kind();
+                self.page(child)?.set_kind(kind);
+                let mut root_page = self.page(root)?;
+                root_page.set_kind(INTERIOR_TABLE);
+                root_page.lay_out(&[], Some(child));
+                child
+            }
+            false => page_num,
+        };
+
+        let (route, dividers) = match right {
+            None => {
+                let mut groups = self.split_leaf(cells);
+                let upper = groups.pop().unwrap();
+                let mut dividers = Vec::new();
+                for group in groups {
+                    let num = self.allocate()?;
+                    let mut page = self.page(num)?;
+                    page.set_kind(LEAF_TABLE);
+                    if !page.lay_out(&group, None) {
+                        Err(anyhow!(msg!("cells-do-not-fit-split", page = num)))?
+                    }
+                    let key = group.last().unwrap().0;
+                    dividers.push((key, interior_cell(num, key)));
+                }
+                let route = upper[0].0;
+                if !self.page(page_num)?.lay_out(&upper, None) {
+                    Err(anyhow!(msg!("cells-do-not-fit-split", page = page_num)))?
+                }
+                (route, dividers)
+            }
+            Some(right) => {
+                // The middle key moves up; the cells below it go to a new page
+                // whose last child is the middle cell's.
+                let middle = cells.len() / 2;
+                let (key, ref cell) = cells[middle];
+                let num = self.allocate()?;
+                let mut page = self.page(num)?;
+                page.set_kind(INTERIOR_TABLE);
+                let fits = page.lay_out(&cells[..middle], Some(left_child(cell)))
+                    && self.page(page_num)?.lay_out(&cells[middle + 1..], Some(right));
+                if !fits {
+                    Err(anyhow!(msg!("cells-do-not-fit-split", page = page_num)))?
+                }
+                (key.saturating_add(1), vec![(key, interior_cell(num, key))])
+            }
+        };
+        self.insert_dividers(root, page_num, route, dividers)
+    }
+
+    /// Adds `dividers`, the interior cells of pages holding the keys just
+    /// below those of `child`, to the parent of `child` ahead of its own
+    /// entry. `route` is a key in `child`'s range.
+    fn insert_dividers(&mut self, root: usize, child: usize, route: i64, dividers: Vec<Cell>) -> Result<()> {
+        let parent = self.parent_of(root, child, route)?;
+        let page = self.page(parent)?;
+        let right = page.right_child();
+        let mut cells = page.cells()?;
+        let at = match cells.iter().position(|(_, cell)| left_child(cell) == child) {
+            Some(at) => at,
+            None if right == child => cells.len(),
+            None => Err(malformed(Some(parent), msg!("page-does-not-point", parent = parent, page = child)))?,
+        };
+        cells.splice(at..at, dividers);
+        self.store(root, parent, cells, Some(right))
+    }
+
+    /// Groups leaf `cells`, in order, into pages: two of about the same size
+    /// when two can hold them, as many full ones as it takes otherwise. Every
+    /// group holds at least one cell.
+    fn split_leaf(&self, mut cells: Vec<Cell>) -> Vec<Vec<Cell>> {
+        // Split pages are never page 1, so the whole page past the header is free.
+        let capacity = self.usable - 8;
+        let cost = |cell: &Cell| cell.1.len() + 2;
+        let total: usize = cells.iter().map(cost).sum();
+
+        let mut lower = 0;
+        let mut best: Option<(usize, usize)> = None;
+        for (idx, cell) in cells.iter().enumerate().take(cells.len() - 1) {
+            lower += cost(cell);
+            let upper = total - lower;
+            if lower <= capacity && upper <= capacity && best.is_none_or(|(_, diff)| lower.abs_diff(upper) < diff) {
+                best = Some((idx + 1, lower.abs_diff(upper)));
+            }
+        }
+        if let Some((at, _)) = best {
+            let upper = cells.split_off(at);
+            return vec![cells, upper];
+        }
+
+        let mut groups: Vec<Vec<Cell>> = vec![Vec::new()];
+        let mut used = 0;
+        for cell in cells {
+            let group = groups.last_mut().unwrap();
+            if !group.is_empty() && used + cost(&cell) > capacity {
+                groups.push(Vec::new());
+                used = 0;
+            }
+            used += cost(&cell);
+            groups.last_mut().unwrap().push(cell);
+        }
+        groups
+    }
+
+    /// A page for a tree to grow into: the last leaf of the first freelist
+    /// trunk, the trunk itself once it has no leaves left, or else a new page
+    /// at the end of the file. Its content starts out zeroed.
+    pub fn allocate(&mut self) -> Result<usize> {
+        let page_size = self.db.page_size;
+        let leaves_per_trunk = self.usable / 4 - 2;
+        let page_count = self.page_count;
+        let header = self.bytes(1)?;
+        if u32_at(header, 52) != 0 {
+            Err(Error::UnsupportedFeature(msg!("grow-auto-vacuum")))?
+        }
+
+        let trunk = u32_at(header, 32);
+        let num = if trunk != 0 {
+            if trunk > page_count {
+                Err(malformed(Some(trunk), msg!("trunk-past-end", page = trunk)))?
+            }
+            let free = u32_at(header, 36);
+            set_u32(header, 36, free.saturating_sub(1));
+            let trunk_page = self.bytes(trunk)?;
+            let leaves = u32_at(trunk_page, 4);
+            if leaves > leaves_per_trunk {
+                Err(malformed(Some(trunk), msg!("trunk-leaf-count", page = trunk, count = leaves)))?
+            }
+            match leaves {
+                0 => {
+                    let next = u32_at(trunk_page, 0);
+                    set_u32(self.bytes(1)?, 32, next);
+                    trunk
+                }
+                _ => {
+                    let leaf = u32_at(trunk_page, 8 + 4 * (leaves - 1));
+                    set_u32(trunk_page, 4, leaves - 1);
+                    if leaf == 0 || leaf > page_count {
+                        Err(malformed(Some(leaf), msg!("freelist-leaf-past-end", page = leaf)))?
+                    }
+                    leaf
+                }
+            }
+        } else {
+            let mut num = page_count + 1;
+            if num == PENDING_BYTE / page_size + 1 {
+                num += 1;
+            }
+            set_u32(header, 28, num);
+            self.page_count = num;
+            num
+        };
+        self.changed.insert(num, vec![0; page_size]);
+        Ok(num)
+    }
+
+    /// The leaf page of the table b-tree at `root` that holds, or would
+    /// hold, `rowid`.
+    fn find_leaf(&self, root: usize, rowid: i64) -> Result<usize> {
+        let mut page_num = root;
+        for _ in 0..MAX_DEPTH {
+            let mut bytes = self.read(page_num)?;
+            let page = self.view(page_num, &mut bytes);
+            match page.kind() {
+                LEAF_TABLE => return Ok(page_num),
+                INTERIOR_TABLE => page_num = page.child_for(rowid)?,
+                kind => Err(malformed(Some(page_num), msg!("wrong-page-type-in-table", page = page_num, kind = kind)))?,
+            }
+        }
+        Err(malformed(Some(root), msg!("tree-at-page-too-deep", root = root)))
+    }
+
+    /// The interior page pointing to `child`, found by following `route`,
+    /// a key in `child`'s range, down from `root`.
+    fn parent_of(&self, root: usize, child: usize, route: i64) -> Result<usize> {
+        let mut page_num = root;
+        for _ in 0..MAX_DEPTH {
+            let mut bytes = self.read(page_num)?;
+            let page = self.view(page_num, &mut bytes);
+            if page.kind() != INTERIOR_TABLE {
+                break;
+            }
+            match page.child_for(route)? {
+                next if next == child => return Ok(page_num),
+                next => page_num = next,
+            }
+        }
+        Err(malformed(Some(child), msg!("page-not-under-root", page = child, root = root)))
+    }
+
+    fn read(&self, page_num: usize) -> Result<Vec<u8>> {
+        match self.changed.get(&page_num) {
+            Some(bytes) => Ok(bytes.clone()),
+            None => self.db.read_raw_page(page_num),
+        }
+    }
+
+    fn bytes(&mut self, page_num: usize) -> Result<&mut Vec<u8>> {
+        Ok(match self.changed.entry(page_num) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(self.db.read_raw_page(page_num)?),
+        })
+    }
+
+    fn page(&mut self, page_num: usize) -> Result<TreePage<'_>> {
+        let usable = self.usable;
+        let bytes = self.bytes(page_num)?;
+        Ok(TreePage::new(bytes, page_num, usable))
+    }
+
+    fn view<'b>(&self, page_num: usize, bytes: &'b mut [u8]) -> TreePage<'b> {
+        TreePage::new(bytes, page_num, self.usable)
+    }
+}
+
+impl Database {
+    /// The bytes of `page` as page `page_num` of this database, the inverse of
+    /// [`Database::read_page`]: the header, the cell pointers in order and
+    /// the cells packed against the end of the usable space, with no
+    /// freeblocks. Page 1 starts with the current database header. Records
+    /// are encoded as sqlite writes them.
+    pub fn serialize_page(&self, page_num: usize, page: &Page) -> Result<Vec<u8>> {
+        let header = self.read_raw_page(1)?;
+        let usable = self.page_size - header[20] as usize;
+        // A record and the size before it, refused when it would not fit
+        // on the page whole.
+        let payload = |values: &[Record], table_leaf: bool| {
+            let record = serialize_record(values, self.text_encoding, self.schema_format);
+            if local_payload(usable, record.len(), table_leaf) < record.len() {
+                Err(Error::UnsupportedFeature(msg!("serialize-overflowing-cells", page = page_num)))?
+            }
+            let mut size = Vec::new();
+            encode_varint(record.len() as u64, &mut size);
+            Ok::<_, anyhow::Error>((size, record))
+        };
+
+        let (kind, cells, right) = match page {
+            Page::LeafTable { cells } => {
+                let cells = cells.iter().map(|c| {
+                    let (mut cell, record) = payload(&c.record.values()?, true)?;
+                    encode_varint(c.row_id, &mut cell);
+                    cell.extend(record);
+                    Ok((c.row_id as i64, cell))
+                });
+                (LEAF_TABLE, cells.collect::<Result<Vec<Cell>>>()?, None)
+            }
+            Page::InteriorTable { rmptr, cells } => {
+                let cells = cells.iter().map(|c| (c.row_id as i64, interior_cell(c.left_child as usize, c.row_id as i64)));
+                (INTERIOR_TABLE, cells.collect(), Some(*rmptr as usize))
+            }
+            Page::LeafIndex { cells } => {
+                let cells = cells.iter().map(|c| {
+                    let (mut cell, record) = payload(&c.keys, false)?;
+                    cell.extend(record);
+                    Ok((0, cell))
+                });
+                (LEAF_INDEX, cells.collect::<Result<Vec<Cell>>>()?, None)
+            }
+            Page::InteriorIndex { rmptr, cells } => {
+                let cells = cells.iter().map(|c| {
+                    let (size, record) = payload(&c.keys, false)?;
+                    let mut cell = c.left_child.to_be_bytes().to_vec();
+                    cell.extend(size);
+                    cell.extend(record);
+                    Ok((0, cell))
+                });
+                (INTERIOR_INDEX, cells.collect::<Result<Vec<Cell>>>()?, Some(*rmptr as usize))
+            }
+        };
+
+        let mut bytes = vec![0; self.page_size];
+        if page_num == 1 {
+            bytes[..DB_HEADER_SIZE].copy_from_slice(&header[..DB_HEADER_SIZE]);
+        }
+        let mut tree_page = TreePage::new(&mut bytes, page_num, usable);
+        tree_page.set_kind(kind);
+        if !tree_page.lay_out(&cells, right) {
+            Err(anyhow!(msg!("cells-do-not-fit-page", page = page_num)))?
+        }
+        Ok(bytes)
+    }
+}
+
+/// One page of a b-tree, though only table b-tree cells are ever read back.
+/// Offsets count from the start of the page, page 1's database header
+/// included.
+pub(super) struct TreePage<'a> {
+    pub bytes: &'a mut [u8],
+    pub number: usize,
+    /// Where the b-tree page header starts.
+    pub header: usize,
+    pub usable: usize,
+}
+
+impl<'a> TreePage<'a> {
+    pub fn new(bytes: &'a mut [u8], number: usize, usable: usize) -> Self {
+        TreePage {
+            bytes,
+            number,
+            header: if number == 1 { DB_HEADER_SIZE } else { 0 },
+            usable,
+        }
+    }
+
+    pub fn kind(&self) -> u8 {
+        self.bytes[self.header]
+    }
+
+    fn set_kind(&mut self, kind: u8) {
+        self.bytes[self.header] = kind;
+    }
+
+    fn is_interior(&self) -> bool {
+        matches!(self.kind(), INTERIOR_TABLE | INTERIOR_INDEX)
+    }
+
+    pub fn u16_at(&self, at: usize) -> usize {
+        u16::from_be_bytes([self.bytes[at], self.bytes[at + 1]]) as usize
+    }
+
+    pub fn set_u16(&mut self, at: usize, value: usize) {
+        self.bytes[at..at + 2].copy_from_slice(&(value as u16).to_be_bytes());
+    }
+
+    /// Start of the cell pointer array, right after the page header.
+    pub fn pointers(&self) -> usize {
+        self.header + if self.is_interior() { 12 } else { 8 }
+    }
+
+    pub fn cell_count(&self) -> usize {
+        self.u16_at(self.header + 3)
+    }
+
+    fn cell_offset(&self, idx: usize) -> usize {
+        self.u16_at(self.pointers() + 2 * idx)
+    }
+
+    fn right_child(&self) -> usize {
+        u32_at(self.bytes, self.header + 8)
+    }
+
+    /// Start of the cell content area; 0 in the header stands for 65536.
+    pub fn content_start(&self) -> usize {
+        match self.u16_at(self.header + 5) {
+            0 => 65536,
+            start => start,
+        }
+    }
+
+    fn set_content_start(&mut self, start: usize) {
+        self.set_u16(self.header + 5, start % 65536);
+    }
+
+    /// The rowid and size in bytes of the cell at `offset`.
+    fn cell_at(&self, offset: usize) -> Result<(i64, usize)> {
+        let cell = self.bytes.get(offset..self.usable).unwrap_or_default();
+        if self.kind() == INTERIOR_TABLE {
+            let (rowid, _, len) = parse_varint(cell.get(4..).unwrap_or_default())?;
+            return Ok((rowid as i64, 4 + len));
+        }
+        let (payload_size, rest, _) = parse_varint(cell)?;
+        let (rowid, rest, _) = parse_varint(rest)?;
+        let payload_size = payload_size as usize;
+        if local_payload(self.usable, payload_size, true) < payload_size {
+            Err(Error::UnsupportedFeature(msg!("overflowing-row-on-page", page = self.number)))?
+        }
+        if rest.len() < payload_size {
+            Err(malformed(Some(self.number), msg!("cell-runs-past-end", page = self.number)))?
+        }
+        Ok((rowid as i64, cell.len() - rest.len() + payload_size))
+    }
+
+    fn cells(&self) -> Result<Vec<Cell>> {
+        (0..self.cell_count())
+            .map(|idx| {
+                let offset = self.cell_offset(idx);
+                let (rowid, size) = self.cell_at(offset)?;
+                Ok((rowid, self.bytes[offset..offset + size].to_vec()))
+            })
+            .collect()
+    }
+
+    /// Index of the cell holding `rowid`, if the page has one.
+    fn find(&self, rowid: i64) -> Result<Option<usize>> {
+        for idx in 0..self.cell_count() {
+            if self.cell_at(self.cell_offset(idx))?.0 == rowid {
+                return Ok(Some(idx));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The child of an interior page whose subtree holds `rowid`: that of
+    /// the first cell whose key is at or above it.
+    fn child_for(&self, rowid: i64) -> Result<usize> {
+        for idx in 0..self.cell_count() {
+            let offset = self.cell_offset(idx);
+            if rowid <= self.cell_at(offset)?.0 {
+                return Ok(u32_at(self.bytes, offset));
+            }
+        }
+        Ok(self.right_child())
+    }
+
+    /// Writes `cell` over cell `idx` when it is no bigger, giving the bytes
+    /// it leaves over back to the page. Returns false, leaving the page
+    /// untouched, when it is bigger.
+    fn replace_in_place(&mut self, idx: usize, cell: &[u8]) -> Result<bool> {
+        let offset = self.cell_offset(idx);
+        let (_, old_size) = self.cell_at(offset)?;
+        if cell.len() > old_size {
+            return Ok(false);
+        }
+        self.bytes[offset..offset + cell.len()].copy_from_slice(cell);
+        self.free(offset + cell.len(), old_size - cell.len())?;
+        Ok(true)
+    }
+
+    /// Gives `size` bytes at `start` back to the page: as fragmented bytes
+    /// when too few to make a freeblock, otherwise as a freeblock merged
+    /// with its neighbours.
+    fn free(&mut self, start: usize, size: usize) -> Result<()> {
+        if size == 0 {
+            return Ok(());
+        }
+        if size < 4 {
+            let fragmented = self.bytes[self.header + 7] as usize + size;
+            if fragmented <= MAX_FRAGMENTED {
+                self.bytes[self.header + 7] = fragmented as u8;
+                return Ok(());
+            }
+            let cells = self.cells()?;
+            let right = self.is_interior().then(|| self.right_child());
+            self.lay_out(&cells, right);
+            return Ok(());
+        }
+
+        let mut blocks = self.freeblocks()?;
+        blocks.push((start, size));
+        blocks.sort_unstable();
+        // Fewer than 4 bytes between two freeblocks are fragmented bytes, which
+        // merging the blocks takes back.
+        let mut fragmented = self.bytes[self.header + 7] as usize;
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(blocks.len());
+        for (start, size) in blocks {
+            match merged.last_mut() {
+                Some(last) if start < last.0 + last.1 => {
+                    Err(malformed(Some(self.number), msg!("freeblocks-overlap", page = self.number)))?
+                }
+                Some(last) if start - (last.0 + last.1) < 4 => {
+                    fragmented = fragmented.saturating_sub(start - (last.0 + last.1));
+                    last.1 = start + size - last.0;
+                }
+                _ => merged.push((start, size)),
+            }
+        }
+        self.bytes[self.header + 7] = fragmented as u8;
+        // A freeblock starting the content area just becomes unallocated space.
+        if merged.first().is_some_and(|&(start, _)| start == self.content_start()) {
+            let (start, size) = merged.remove(0);
+            self.set_content_start(start + size);
+        }
+
+        let mut link = self.header + 1;
+        for &(start, size) in &merged {
+            self.set_u16(link, start);
+            self.set_u16(start + 2, size);
+            link = start;
+        }
+        self.set_u16(link, 0);
+        Ok(())
+    }
+
+    /// The page's freeblocks as (offset, size), in the order of the list,
+    /// which sqlite keeps sorted by offset.
+    fn freeblocks(&self) -> Result<Vec<(usize, usize)>> {
+        let mut blocks = Vec::new();
+        let mut next = self.u16_at(self.header + 1);
+        while next != 0 {
+            let previous = blocks.last().map_or(0, |&(start, _)| start);
+            if next <= previous || next + 4 > self.usable {
+                Err(malformed(Some(self.number), msg!("freeblocks-out-of-order", page = self.number)))?
+            }
+            blocks.push((next, self.u16_at(next + 2)));
+            next = self.u16_at(next);
+        }
+        Ok(blocks)
+    }
+
+    /// Rewrites the page to hold `cells`, in order, packed against its end
+    /// with no free space in between, and `right` as the last child of an
+    /// interior page. Returns false, leaving the page untouched, when they
+    /// do not fit.
+    fn lay_out(&mut self, cells: &[Cell], right: Option<usize>) -> bool {
+        let pointers = self.pointers();
+        let content: usize = cells.iter().map(|(_, cell)| cell.len()).sum();
+        if pointers + 2 * cells.len() + content > self.usable {
+            return false;
+        }
+
+        let mut end = self.usable;
+        for (idx, (_, cell)) in cells.iter().enumerate() {
+            end -= cell.len();
+            self.bytes[end..end + cell.len()].copy_from_slice(cell);
+            self.set_u16(pointers + 2 * idx, end);
+        }
+        self.bytes[pointers + 2 * cells.len()..end].fill(0);
+        self.set_u16(self.header + 1, 0);
+        self.set_u16(self.header + 3, cells.len());
+        self.set_content_start(end);
+        self.bytes[self.header + 7] = 0;
+        if let Some(right) = right {
+            set_u32(self.bytes, self.header + 8, right);
+        }
+        true
+    }
+}
+
+/// The cell of an interior table page pointing to `child`, whose rowids go
+/// up to `key`.
+fn interior_cell(child: usize, key: i64) -> Vec<u8> {
+    let mut cell = (child as u32).to_be_bytes().to_vec();
+    encode_varint(key as u64, &mut cell);
+    cell
+}
+
+fn left_child(cell: &[u8]) -> usize {
+    u32_at(cell, 0)
+}
+
+pub(super) fn u32_at(bytes: &[u8], at: usize) -> usize {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]) as usize
+}
+
+fn set_u32(bytes: &mut [u8], at: usize, value: usize) {
+    bytes[at..at + 4].copy_from_slice(&(value as u32).to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{INTERIOR_INDEX, INTERIOR_TABLE};
+    use crate::{Database, Page, DB_HEADER_SIZE};
+    use std::{fs, path::PathBuf, process::Command};
+
+    /// Serializes every b-tree page of the database at `path` as read and
+    /// checks it holds sqlite's cells, byte for byte and in the same order,
+    /// under the same header.
+    fn round_trip(path: &str) {
+        let db = Database::load_db(path.to_string()).unwrap();
+        let mut pending = db.schema.iter().map(|s| s.rootpage).filter(|&root| root > 0).collect::<Vec<_>>();
+        pending.push(1);
+        let mut checked = 0;
+        while let Some(page_num) = pending.pop() {
+            let page = db.read_page(page_num).unwrap();
+            match &page {
+                Page::InteriorTable { rmptr, cells } => {
+                    pending.extend(cells.iter().map(|c| c.left_child as usize).chain([*rmptr as usize]))
+                }
+                Page::InteriorIndex { rmptr, cells } => {
+                    pending.extend(cells.iter().map(|c| c.left_child as usize).chain([*rmptr as usize]))
+                }
+                _ => {}
+            }
+            let original = db.read_raw_page(page_num).unwrap();
+            let bytes = db.serialize_page(page_num, &page).unwrap();
+            let header = if page_num == 1 { DB_HEADER_SIZE } else { 0 };
+            assert_eq!(bytes[..header], original[..header], "database header on page {}", page_num);
+            let interior = matches!(original[header], INTERIOR_TABLE | INTERIOR_INDEX);
+            let pointers = header + if interior { 12 } else { 8 };
+            let fields = |bytes: &[u8]| {
+                let mut fields = vec![bytes[header], bytes[header + 3], bytes[header + 4]];
+                if interior {
+                    fields.extend(&bytes[header + 8..pointers]);
+                }
+                fields
+            };
+            assert_eq!(fields(&bytes), fields(&original), "page header of page {}", page_num);
+
+            let pointer = |bytes: &[u8], idx: usize| u16::from_be_bytes([bytes[pointers + 2 * idx], bytes[pointers + 2 * idx + 1]]) as usize;
+            let mut end = db.usable_size;
+            for idx in 0..u16::from_be_bytes([bytes[header + 3], bytes[header + 4]]) as usize {
+                let (at, was) = (pointer(&bytes, idx), pointer(&original, idx));
+                assert_eq!(bytes[at..end], original[was..was + end - at], "cell {} of page {}", idx, page_num);
+                end = at;
+            }
+            checked += 1;
+        }
+        assert!(checked > 1, "{} has no b-tree pages past the schema", path);
+    }
+
+    /// A database sqlite3 built from `sql`, or `None` when sqlite3 is not
+    /// installed.
+    fn sqlite3(name: &str, sql: &str) -> Option<PathBuf> {
+        let path = std::env::temp_dir().join(format!("sqlite-lite-{}-{}.db", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        match Command::new("sqlite3").arg(&path).arg(sql).status() {
+            Ok(status) if status.success() => Some(path),
+            Ok(status) => panic!("sqlite3 failed with {}", status),
+            Err(_) => {
+                eprintln!("sqlite3 is not installed; skipping");
+                None
+            }
+        }
+    }
+
+    #[test]
+    fn sample_database_round_trips() {
+        round_trip(concat!(env!("CARGO_MANIFEST_DIR"), "/sample.db"));
+    }
+
+    #[test]
+    fn indexes_built_after_the_rows_round_trip() {
+        let sql = "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT, score REAL, tag);
+            WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 3000)
+            INSERT INTO t SELECT x, printf('name %d', (x * 7919) % 3000), x / 3.0, CASE WHEN x % 3 = 0 THEN x'00ff' ELSE x END FROM n;
+            CREATE INDEX t_name ON t(name);
+            CREATE INDEX t_tag_score ON t(tag, score);
+            CREATE TABLE w(k TEXT PRIMARY KEY, v) WITHOUT ROWID;
+            INSERT INTO w SELECT name, id FROM t;";
+        let Some(path) = sqlite3("indexes", sql) else {
+            return;
+        };
+        round_trip(path.to_str().unwrap());
+        fs::remove_file(path).unwrap();
+    }
+}
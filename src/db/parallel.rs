@@ -0,0 +1,76 @@
+//! Full table scans shared between threads. The rowids are cut into as many
+//! ranges as there are threads, each spanning about as many leaves; every
+//! thread decodes and filters the rows of its range, and they are handed on
+//! range by range, so the rows come in the same order a scan on one thread
+//! would give them.
+
+use super::{Database, RowCallback, Source};
+use crate::{
+    record::Record,
+    sql::{ColumnDef, Condition},
+};
+use anyhow::Result;
+use itertools::Itertools;
+use std::{mem, ops::ControlFlow, sync::mpsc, thread};
+
+/// Rows a thread hands on at once.
+const BATCH: usize = 256;
+
+/// Batches a thread may get ahead of the rows being handed on.
+const QUEUED: usize = 4;
+
+type Batch = Result<Vec<Vec<Record>>>;
+
+impl Database {
+    /// Visits the rows of `source` that match `condition`, in rowid order,
+    /// with `threads` threads reading them. A table too small to split is
+    /// read by a single one.
+    pub(super) fn scan_parallel(
+        &self,
+        source: &Source,
+        columns: &[ColumnDef],
+        condition: Option<&Condition>,
+        threads: usize,
+        on_row: &mut RowCallback,
+    ) -> Result<ControlFlow<()>> {
+        let on = self.on(source);
+        let ranges = on.tree_rowid_ranges(source.rootpage, threads)?;
+        thread::scope(|scope| {
+            let receivers = ranges
+                .into_iter()
+                .map(|(low, high)| {
+                    let (sender, receiver) = mpsc::sync_channel::<Batch>(QUEUED);
+                    scope.spawn(move || {
+                        let mut batch = Vec::with_capacity(BATCH);
+                        let scanned = on.execute_select_range(source.rootpage, columns, source.wanted.as_deref(), (low, high), &mut |row| {
+                            if let Some(condition) = condition {
+                                if !self.matches(condition, columns, &mut |e| self.evaluate(e, columns, &row))? {
+                                    return Ok(ControlFlow::Continue(()));
+                                }
+                            }
+                            batch.push(row);
+                            if batch.len() == BATCH && sender.send(Ok(mem::take(&mut batch))).is_err() {
+                                // Nobody wants the rows any more: the scan stopped early.
+                                return Ok(ControlFlow::Break(()));
+                            }
+                            Ok(ControlFlow::Continue(()))
+                        });
+                        let _ = sender.send(scanned.map(|_| batch));
+                    });
+                    receiver
+                })
+                .collect_vec();
+            // Dropping the receivers that are left stops their threads.
+            for receiver in receivers {
+                for batch in receiver {
+                    for row in batch? {
+                        if on_row(row)?.is_break() {
+                            return Ok(ControlFlow::Break(()));
+                        }
+                    }
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        })
+    }
+}
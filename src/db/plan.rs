@@ -0,0 +1,408 @@
+//! Query planning: a SELECT is resolved against the schema and the way its
+//! rows will be found is chosen before any row is read, so that `EXPLAIN
+//! QUERY PLAN` can describe exactly what running it would do.
+
+use super::{
+    btree::Walk,
+    malformed, parse_varint,
+    pages::{INTERIOR_INDEX, INTERIOR_TABLE, LEAF_INDEX, LEAF_TABLE, MAX_DEPTH},
+    resolve_column, Database, Probe, Source,
+};
+use crate::{
+    interrupt,
+    messages::msg,
+    output::RowWriter,
+    record::Record,
+    sql::{ColumnDef, ColumnRef, Condition, Expr, Statement},
+    DB_HEADER_SIZE,
+};
+use anyhow::Result;
+use itertools::Itertools;
+use std::ops::Bound;
+
+/// How the rows of a SELECT's first table are found. The tables it is
+/// joined with use their lookups, or are scanned.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Access {
+    /// Every row, in rowid order or, WITHOUT ROWID, in key order.
+    Scan,
+    /// The rows with rowids in `low..=high`.
+    RowidRange(i64, i64),
+    /// The rows with the listed rowids, which are sorted.
+    Rowids(Vec<usize>),
+    /// The rows that `index` has leading keys in one of `ranges` for.
+    IndexSearch {
+        index: String,
+        column: String,
+        rootpage: usize,
+        ranges: Vec<KeyRange>,
+        /// Each range holds a single key.
+        equality: bool,
+    },
+    /// No row is read: `count(*)` is totalled from the page headers, over the
+    /// rowids in the range when there is one.
+    CountRows(Option<(i64, i64)>),
+}
+
+/// Bounds on the leading key of an index.
+pub(super) type KeyRange = (Bound<Record>, Bound<Record>);
+
+/// A SELECT with its columns resolved and the way its rows are found chosen.
+pub(super) struct SelectPlan {
+    pub sources: Vec<Source>,
+    pub columns: Vec<ColumnDef>,
+    /// Names of the result columns.
+    pub names: Vec<String>,
+    pub selected: Vec<Expr>,
+    /// WHERE and every ON condition together.
+    pub condition: Option<Condition>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Condition>,
+    pub access: Access,
+    /// Rows are folded into groups rather than written as they are read.
+    pub aggregate: bool,
+}
+
+impl Database {
+    /// Resolves the SELECT `statement` and chooses how to find its rows.
+    /// Unknown tables and columns are reported here, before any row is read.
+    pub(super) fn plan_select(&self, statement: &Statement) -> Result<SelectPlan> {
+        let Statement::Select {
+            from,
+            joins,
+            columns: result_columns,
+            condition,
+            group_by,
+            having,
+        } = statement
+        else {
+            unreachable!()
+        };
+        let tables = std::iter::once(from).chain(joins.iter().map(|j| &j.table)).collect_vec();
+        let mut sources = Vec::new();
+        let mut columns = Vec::new();
+        for table in &tables {
+            let source = self.source(table, columns.len())?;
+            // Joined rows name their columns `table.column` to keep them apart.
+            columns.extend(source.table.columns.iter().map(|c| ColumnDef {
+                name: match joins.is_empty() {
+                    true => c.name.clone(),
+                    false => format!("{}.{}", table.qualifier(), c.name),
+                },
+                ..c.clone()
+            }));
+            sources.push(source);
+        }
+
+        // Unknown columns are reported before the scan, even for empty tables.
+        let mut resolve = |column: &ColumnRef| resolve_column(&sources, &columns, column);
+        let selected = result_columns
+            .iter()
+            .map(|c| c.expr.map_columns(&mut resolve))
+            .collect::<Result<Vec<_>>>()?;
+        let group_by = group_by
+            .iter()
+            .map(|e| e.map_columns(&mut resolve))
+            .collect::<Result<Vec<_>>>()?;
+        // An inner join's ON condition filters exactly like WHERE does.
+        let condition = condition
+            .iter()
+            .chain(joins.iter().filter_map(|j| j.on.as_ref()))
+            .map(|c| c.map_columns(&mut resolve))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .reduce(|a, b| Condition::And(Box::new(a), Box::new(b)));
+        let having = having.as_ref().map(|c| c.map_columns(&mut resolve)).transpose()?;
+
+        // Only the columns the statement mentions are decoded from each row.
+        let mut used = vec![false; columns.len()];
+        let mut mark = |column: &ColumnRef| {
+            if let Some(idx) = columns.iter().position(|c| c.name.eq_ignore_ascii_case(&column.name)) {
+                used[idx] = true;
+            }
+            Ok(column.clone())
+        };
+        for expr in selected.iter().chain(&group_by) {
+            expr.map_columns(&mut mark)?;
+        }
+        for condition in condition.iter().chain(&having) {
+            condition.map_columns(&mut mark)?;
+        }
+        for source in &mut sources {
+            let own = &used[source.offset..source.offset + source.table.columns.len()];
+            if own.contains(&false) {
+                source.wanted = Some(own.to_vec());
+            }
+        }
+
+        let conjuncts = condition.as_ref().map(Condition::conjuncts).unwrap_or_default();
+        let lookups = sources.iter().map(|s| self.join_lookup(s, &columns, &conjuncts)).collect_vec();
+        for (source, lookup) in sources.iter_mut().zip(lookups) {
+            source.lookup = lookup;
+        }
+
+        // HAVING without GROUP BY treats the whole input as one group.
+        let aggregate = !group_by.is_empty() || having.is_some() || selected.iter().any(|e| e.is_aggregate());
+        // Counting rows needs no row decoded when all the condition asks of
+        // them is a range of rowids.
+        let count_range = match &condition {
+            _ if sources[0].view.is_some() => None,
+            None => Some(None),
+            Some(_) if sources[0].table.key_order.is_some() => None,
+            Some(condition) => self.exact_rowid_range(&columns, condition).map(Some),
+        };
+        let counted = joins.is_empty()
+            && group_by.is_empty()
+            && having.is_none()
+            && selected.iter().all(|e| e.is_count_rows());
+        let access = match (count_range, joins.is_empty()) {
+            (Some(range), _) if counted => Access::CountRows(range),
+            (_, true) => self.access(&sources[0], &columns, condition.as_ref()),
+            (_, false) => Access::Scan,
+        };
+
+        // Like sqlite's column_name(): the alias, then a plain column's name
+        // as its table declares it, then the expression as written.
+        let names = result_columns
+            .iter()
+            .zip(&selected)
+            .map(|(result, expr)| {
+                let declared = match (&result.alias, expr) {
+                    (None, Expr::Column(column)) => columns.iter().position(|c| c.name.eq_ignore_ascii_case(&column.name)).and_then(|idx| {
+                        let source = sources.iter().rfind(|s| s.offset <= idx)?;
+                        Some(source.table.columns[idx - source.offset].name.clone())
+                    }),
+                    _ => None,
+                };
+                declared.unwrap_or_else(|| result.name().to_string())
+            })
+            .collect();
+
+        Ok(SelectPlan {
+            sources,
+            columns,
+            names,
+            selected,
+            condition,
+            group_by,
+            having,
+            access,
+            aggregate,
+        })
+    }
+
+    /// How to find the rows of `source` that may match `condition`: through
+    /// an index, a list of rowids or a rowid range when the condition allows
+    /// it, otherwise by visiting all of them.
+    pub(super) fn access(&self, source: &Source, columns: &[ColumnDef], condition: Option<&Condition>) -> Access {
+        // Indexes on WITHOUT ROWID tables point at primary keys, not rowids.
+        let Some(condition) = condition.filter(|_| source.table.key_order.is_none() && source.view.is_none()) else {
+            return Access::Scan;
+        };
+        if let Some(access) = self.on(source).index_access(&source.name, columns, condition) {
+            return access;
+        }
+        if let Some(keys) = self.rowid_list(columns, condition) {
+            return Access::Rowids(keys);
+        }
+        match self.rowid_range(columns, condition) {
+            Some((low, high)) => Access::RowidRange(low, high),
+            None => Access::Scan,
+        }
+    }
+
+    /// Runs `EXPLAIN QUERY PLAN` for `statement`: one row for each table, in
+    /// the order they are visited, and for the grouping step, if any. Each
+    /// table's row estimates the pages visiting it reads, counting a table
+    /// joined in once for every row of the tables before it.
+    pub(super) fn explain(&self, statement: &Statement, out: &mut dyn RowWriter) -> Result<()> {
+        let plan = self.plan_select(statement)?;
+        let header = ["id", "parent", "notused", "detail", "pages"];
+        out.write_header(&header.map(String::from))?;
+
+        let mut steps = Vec::new();
+        // Rows the tables so far are estimated to yield.
+        let mut rows = 1usize;
+        for (idx, source) in plan.sources.iter().enumerate() {
+            let (detail, pages, found) = match (idx, &source.lookup) {
+                (0, _) => self.explain_access(source, &plan.access)?,
+                (_, Some(lookup)) => {
+                    let on = self.on(source);
+                    let table_pages = on.tree_depth(source.rootpage)?;
+                    match lookup.probe {
+                        Probe::Rowid => (search(source, "INTEGER PRIMARY KEY", "rowid=?"), table_pages, 1),
+                        Probe::Index(rootpage) => {
+                            let index = on.indexes.iter().find(|index| index.rootpage == rootpage);
+                            let (name, column) = index
+                                .map(|index| (index.name.as_str(), index.columns[0].name.as_str()))
+                                .unwrap_or_default();
+                            let using = format!("INDEX {}", name);
+                            let pages = on.tree_depth(rootpage)? + table_pages;
+                            (search(source, &using, &format!("{}=?", column)), pages, 1)
+                        }
+                    }
+                }
+                (_, None) => self.explain_access(source, &Access::Scan)?,
+            };
+            steps.push((detail, Some(rows.saturating_mul(pages))));
+            rows = rows.saturating_mul(found);
+        }
+        if !plan.group_by.is_empty() {
+            steps.push(("USE TEMP B-TREE FOR GROUP BY".to_string(), None));
+        } else if plan.aggregate && !matches!(plan.access, Access::CountRows(_)) {
+            steps.push(("AGGREGATE INTO ONE ROW".to_string(), None));
+        }
+
+        for (id, (detail, pages)) in steps.into_iter().enumerate() {
+            out.write_row(&[
+                Record::Int64(id as i64 + 1),
+                Record::Int64(0),
+                Record::Int64(0),
+                Record::Text(detail),
+                pages.map_or(Record::Null, |pages| Record::Int64(pages as i64)),
+            ])?;
+        }
+        Ok(())
+    }
+
+    /// The detail line for finding `source`'s rows by `access`, with the
+    /// pages that reads and the rows it finds.
+    fn explain_access(&self, source: &Source, access: &Access) -> Result<(String, usize, usize)> {
+        // A view's rows were read while planning, so visiting them reads no page.
+        if let Some(rows) = &source.view {
+            return Ok((format!("SCAN {}", source.qualifier), 0, rows.len()));
+        }
+        let (on, rootpage) = (self.on(source), source.rootpage);
+        Ok(match access {
+            Access::Scan => (
+                format!("SCAN {}", source.qualifier),
+                on.tree_pages(rootpage, i64::MIN, i64::MAX, &mut Walk::default())?,
+                on.count_rows(rootpage, &mut Walk::default())?,
+            ),
+            Access::RowidRange(low, high) => (
+                search(source, "INTEGER PRIMARY KEY", &rowid_bounds(*low, *high)),
+                on.tree_pages(rootpage, *low, *high, &mut Walk::default())?,
+                on.count_rows_between(rootpage, *low, *high, &mut Walk::default())?,
+            ),
+            Access::Rowids(keys) => {
+                let lookups = keys.len().saturating_mul(on.tree_depth(rootpage)?);
+                let pages = lookups.min(on.tree_pages(rootpage, i64::MIN, i64::MAX, &mut Walk::default())?);
+                (search(source, "INTEGER PRIMARY KEY", "rowid=?"), pages, keys.len())
+            }
+            Access::IndexSearch {
+                index,
+                column,
+                rootpage: index_root,
+                ranges,
+                equality,
+            } => {
+                let bounds = match equality {
+                    true => format!("{}=?", column),
+                    false => format!("{}>? AND {}<?", column, column),
+                };
+                // Each row is looked up from the root, unless that would read
+                // more pages than the whole table has.
+                let found = on.index_rowids(*index_root, ranges)?.len();
+                let lookups = found.saturating_mul(on.tree_depth(rootpage)?);
+                let pages = on.tree_depth(*index_root)? + lookups.min(on.tree_pages(rootpage, i64::MIN, i64::MAX, &mut Walk::default())?);
+                (search(source, &format!("INDEX {}", index), &bounds), pages, found)
+            }
+            Access::CountRows(range) => {
+                let (low, high) = range.unwrap_or((i64::MIN, i64::MAX));
+                let detail = match range {
+                    None => format!("COUNT ROWS OF {} FROM PAGE HEADERS", source.qualifier),
+                    Some(_) => format!(
+                        "COUNT ROWS OF {} FROM PAGE HEADERS ({})",
+                        source.qualifier,
+                        rowid_bounds(low, high)
+                    ),
+                };
+                (detail, on.tree_pages(rootpage, low, high, &mut Walk::default())?, 1)
+            }
+        })
+    }
+
+    /// Pages of the b-tree rooted at `page_num` that a walk over the rowids
+    /// in `low..=high` reads. Index b-trees have no rowids to bound the walk,
+    /// so every one of their pages counts.
+    fn tree_pages(&self, page_num: usize, low: i64, high: i64, walk: &mut Walk) -> Result<usize> {
+        interrupt::check()?;
+        let page = self.read_raw_page(page_num)?;
+        let offset = match page_num {
+            1 => DB_HEADER_SIZE,
+            _ => 0,
+        };
+        let num_of_cells = u16::from_be_bytes([page[3 + offset], page[4 + offset]]) as usize;
+        let be_u32_at = |at: usize| u32::from_be_bytes([page[at], page[at + 1], page[at + 2], page[at + 3]]) as usize;
+
+        match page[offset] {
+            LEAF_INDEX | LEAF_TABLE => Ok(1),
+            kind @ (INTERIOR_INDEX | INTERIOR_TABLE) => {
+                let mut total = 1;
+                let mut floor = None;
+                for idx in 0..=num_of_cells {
+                    let (child, key) = match idx < num_of_cells {
+                        true => {
+                            let pointer = 12 + offset + 2 * idx;
+                            let cell = u16::from_be_bytes([page[pointer], page[pointer + 1]]) as usize;
+                            let key = match kind {
+                                INTERIOR_TABLE => Some(parse_varint(&page[cell + 4..])?.0 as i64),
+                                _ => None,
+                            };
+                            (be_u32_at(cell), key)
+                        }
+                        false => (be_u32_at(8 + offset), None),
+                    };
+                    // The child's rowids lie in `first..=last`.
+                    let first = floor.map_or(i64::MIN, |floor: i64| floor.saturating_add(1));
+                    let last = key.unwrap_or(i64::MAX);
+                    if first <= high && low <= last {
+                        walk.check_child(page_num, child)?;
+                        total += self.tree_pages(child, low, high, walk)?;
+                    }
+                    floor = key.or(floor);
+                }
+                Ok(total)
+            }
+            kind => Err(malformed(Some(page_num), msg!("unknown-page-type", page = page_num, kind = kind))),
+        }
+    }
+
+    /// Pages read going from the root of the b-tree at `page_num` down to a leaf.
+    fn tree_depth(&self, page_num: usize) -> Result<usize> {
+        let mut page_num = page_num;
+        for depth in 1..=MAX_DEPTH {
+            let page = self.read_raw_page(page_num)?;
+            let offset = match page_num {
+                1 => DB_HEADER_SIZE,
+                _ => 0,
+            };
+            match page[offset] {
+                LEAF_INDEX | LEAF_TABLE => return Ok(depth),
+                INTERIOR_INDEX | INTERIOR_TABLE => {
+                    let at = 8 + offset;
+                    page_num = u32::from_be_bytes([page[at], page[at + 1], page[at + 2], page[at + 3]]) as usize;
+                }
+                kind => Err(malformed(Some(page_num), msg!("unknown-page-type", page = page_num, kind = kind)))?,
+            }
+        }
+        Err(malformed(Some(page_num), msg!("btree-too-deep-at", page = page_num)))
+    }
+}
+
+/// `SEARCH table USING using (bounds)`, as sqlite words an index search.
+fn search(source: &Source, using: &str, bounds: &str) -> String {
+    format!("SEARCH {} USING {} ({})", source.qualifier, using, bounds)
+}
+
+/// The comparisons an inclusive rowid range stands for; sqlite writes `>`
+/// and `<` whether or not the bounds are included.
+fn rowid_bounds(low: i64, high: i64) -> String {
+    match (low == i64::MIN, high == i64::MAX) {
+        _ if low == high => "rowid=?",
+        (false, true) => "rowid>?",
+        (true, false) => "rowid<?",
+        _ => "rowid>? AND rowid<?",
+    }
+    .to_string()
+}
@@ -0,0 +1,123 @@
+//! Statements parsed once and run many times, each time with the values
+//! their `?`, `?N` and `:name` parameters are bound to. The values go into
+//! the statement as constants before it runs, so `id = ?` plans the same
+//! rowid or index lookup that `id = 5` would.
+
+use super::{Database, RowCollector};
+use crate::{
+    messages::msg,
+    output::RowWriter,
+    record::{FromRow, Record, Row},
+    sql::{parse_sql, Expr, Statement},
+};
+use anyhow::{anyhow, Result};
+
+/// A parsed statement and the values bound to its parameters, from
+/// [`Database::prepare`].
+#[derive(Debug, Clone)]
+pub struct Prepared {
+    statement: Statement,
+    /// The name of each parameter as written, by number counting from 1;
+    /// none for a bare `?`.
+    names: Vec<Option<String>>,
+    /// NULL until bound.
+    values: Vec<Record>,
+}
+
+impl Prepared {
+    fn new(mut statement: Statement) -> Self {
+        let mut names = Vec::new();
+        statement.visit_mut(&mut |e| {
+            if let Expr::Parameter { index, name } = e {
+                if names.len() < *index {
+                    names.resize(*index, None);
+                }
+                if names[*index - 1].is_none() {
+                    names[*index - 1] = name.clone();
+                }
+            }
+        });
+        let values = vec![Record::Null; names.len()];
+        Self {
+            statement,
+            names,
+            values,
+        }
+    }
+
+    /// The highest parameter number in the statement.
+    pub fn parameter_count(&self) -> usize {
+        self.names.len()
+    }
+
+    /// The name parameter `index` was written with, such as `:id` or `?2`.
+    pub fn parameter_name(&self, index: usize) -> Option<&str> {
+        self.names.get(index.checked_sub(1)?)?.as_deref()
+    }
+
+    /// The number of the parameter written as `name`, prefix included.
+    pub fn parameter_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n.as_deref() == Some(name)).map(|i| i + 1)
+    }
+
+    /// Binds parameter `index`, counting from 1, to `value` until it is bound
+    /// again or cleared.
+    pub fn bind(&mut self, index: usize, value: Record) -> Result<()> {
+        let count = self.parameter_count();
+        let slot = index
+            .checked_sub(1)
+            .and_then(|i| self.values.get_mut(i))
+            .ok_or_else(|| anyhow!(msg!("parameter-out-of-range", index = index, count = count)))?;
+        *slot = value;
+        Ok(())
+    }
+
+    /// Binds the parameter written as `name`, such as `:id`.
+    pub fn bind_named(&mut self, name: &str, value: Record) -> Result<()> {
+        let index = self
+            .parameter_index(name)
+            .ok_or_else(|| anyhow!(msg!("no-such-parameter", name = name)))?;
+        self.bind(index, value)
+    }
+
+    /// Sets every parameter back to NULL.
+    pub fn clear_bindings(&mut self) {
+        self.values.fill(Record::Null);
+    }
+
+    /// The statement with each parameter replaced by its value.
+    fn bound(&self) -> Statement {
+        let mut statement = self.statement.clone();
+        statement.visit_mut(&mut |e| {
+            if let Expr::Parameter { index, .. } = e {
+                *e = Expr::Literal(self.values[*index - 1].clone());
+            }
+        });
+        statement
+    }
+}
+
+impl Database {
+    /// Parses `sql` once, to run as often as needed with
+    /// [`Database::execute_prepared`] or [`Database::query_prepared`].
+    pub fn prepare(&self, sql: &str) -> Result<Prepared> {
+        Ok(Prepared::new(parse_sql(sql)?))
+    }
+
+    /// Runs a prepared statement with the values bound to it.
+    pub fn execute_prepared(&mut self, prepared: &Prepared, out: &mut dyn RowWriter) -> Result<()> {
+        self.execute_statement(&prepared.bound(), out)
+    }
+
+    /// Runs a prepared statement and collects the rows it produces.
+    pub fn query_prepared(&mut self, prepared: &Prepared) -> Result<Vec<Row>> {
+        let mut rows = RowCollector::default();
+        self.execute_prepared(prepared, &mut rows)?;
+        Ok(rows.rows)
+    }
+
+    /// Runs a prepared statement and converts each row it produces into a `T`.
+    pub fn query_prepared_as<T: FromRow>(&mut self, prepared: &Prepared) -> Result<Vec<T>> {
+        self.query_prepared(prepared)?.iter().map(T::from_row).collect()
+    }
+}
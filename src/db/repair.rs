@@ -0,0 +1,408 @@
+//! `.repair`: fixes for single b-tree pages whose header no longer matches
+//! their cells, for databases that fail `.integrity-check` at one known
+//! page. Each repair first checks that the page has the problem it fixes,
+//! then saves the page as it was to a backup file, and only then writes it.
+
+use super::{
+    carve::content_len,
+    local_payload, malformed,
+    pages::{u32_at, TreePage, INTERIOR_INDEX, INTERIOR_TABLE, LEAF_INDEX, LEAF_TABLE},
+    parse_varint, Database,
+};
+use crate::{error::Error, messages::msg, output::json_string};
+use anyhow::{anyhow, Result};
+use std::{fs::OpenOptions, io::Write, str::FromStr};
+
+/// What [`Database::repair_page`] fixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repair {
+    /// Sets the cell count to the number of cell pointers, from the first
+    /// on, that point at well-formed cells that overlap neither each other
+    /// nor a freeblock, in rowid order on table pages.
+    CellCount,
+    /// Writes the cell pointer array again from the cells found by scanning
+    /// the cell content area, in rowid order. Only for table b-tree pages,
+    /// whose cells carry their order.
+    CellPointers,
+    /// Replaces a freeblock list that runs out of order, off the page or
+    /// into a cell with one made from the gaps between the cells; gaps of
+    /// fewer than 4 bytes count as fragmented.
+    Freeblocks,
+}
+
+impl FromStr for Repair {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "cell-count" => Ok(Repair::CellCount),
+            "cell-pointers" => Ok(Repair::CellPointers),
+            "freeblocks" => Ok(Repair::Freeblocks),
+            _ => Err(anyhow!(msg!("unknown-repair", repair = s))),
+        }
+    }
+}
+
+/// What a repair changed.
+#[derive(Debug)]
+pub struct Repaired {
+    pub page: usize,
+    /// The cell count before the repair and after it, or for
+    /// [`Repair::Freeblocks`] the offset of the first freeblock.
+    pub before: usize,
+    pub after: usize,
+}
+
+/// The checks a repair makes of a b-tree page as it is on disk, trusting
+/// nothing in it.
+impl TreePage<'_> {
+    fn is_table(&self) -> bool {
+        matches!(self.kind(), INTERIOR_TABLE | LEAF_TABLE)
+    }
+
+    /// The rowid, on a table page, and the size of a well-formed cell at
+    /// `offset` whose child and overflow pages are among the first
+    /// `page_count`; `None` when the bytes there cannot be one.
+    fn cell(&self, offset: usize, page_count: usize) -> Option<(Option<i64>, usize)> {
+        let cell = self.bytes.get(offset..self.usable)?;
+        let page = |number: usize| (1..=page_count).contains(&number).then_some(());
+        let (child, rest) = match self.kind() {
+            INTERIOR_TABLE | INTERIOR_INDEX => {
+                if cell.len() < 4 {
+                    return None;
+                }
+                page(u32_at(self.bytes, offset))?;
+                (4, &cell[4..])
+            }
+            _ => (0, cell),
+        };
+        if self.kind() == INTERIOR_TABLE {
+            let (rowid, _, len) = parse_varint(rest).ok()?;
+            return Some((Some(rowid as i64), child + len));
+        }
+
+        let (size, rest, size_len) = parse_varint(rest).ok()?;
+        let (rowid, rest, rowid_len) = match self.kind() {
+            LEAF_TABLE => parse_varint(rest).map(|(rowid, rest, len)| (Some(rowid as i64), rest, len)).ok()?,
+            _ => (None, rest, 0),
+        };
+        let size = usize::try_from(size).ok()?;
+        let local = local_payload(self.usable, size, self.kind() == LEAF_TABLE);
+        let stored = if local < size { local + 4 } else { local };
+        let payload = rest.get(..stored)?;
+        if local < size {
+            page(u32_at(self.bytes, offset + child + size_len + rowid_len + local))?;
+        }
+        record_fits(&payload[..local], size).then_some((rowid, child + size_len + rowid_len + stored))
+    }
+
+    /// The freeblock list as (offset, size), or where it goes wrong, checked
+    /// more closely than [`TreePage::freeblocks`] checks it.
+    fn freeblock_list(&self) -> std::result::Result<Vec<(usize, usize)>, String> {
+        let mut blocks: Vec<(usize, usize)> = Vec::new();
+        let mut next = self.u16_at(self.header + 1);
+        while next != 0 {
+            let end = blocks.last().map_or(self.content_start(), |&(start, size)| start + size);
+            if next < end {
+                return Err(msg!("freeblock-out-of-order", offset = next));
+            }
+            if next + 4 > self.usable {
+                return Err(msg!("freeblock-past-end", offset = next));
+            }
+            let size = self.u16_at(next + 2);
+            if size < 4 || next + size > self.usable {
+                return Err(msg!("freeblock-size", offset = next, size = size));
+            }
+            blocks.push((next, size));
+            next = self.u16_at(next);
+        }
+        Ok(blocks)
+    }
+
+    /// The cells the pointer array points at, from the first on, up to the
+    /// first pointer that is not to a well-formed cell that overlaps neither
+    /// the ones before it nor a freeblock, or breaks rowid order.
+    fn pointed_cells(&self, freeblocks: &[(usize, usize)], page_count: usize) -> Vec<(usize, Option<i64>, usize)> {
+        let mut cells: Vec<(usize, Option<i64>, usize)> = Vec::new();
+        let content_start = self.content_start();
+        let mut at = self.pointers();
+        while at + 2 <= content_start.min(self.usable) {
+            let offset = self.u16_at(at);
+            let Some((rowid, size)) = (offset >= content_start).then(|| self.cell(offset, page_count)).flatten() else {
+                break;
+            };
+            let overlaps = |&(start, len): &(usize, usize)| start < offset + size && offset < start + len;
+            let taken = cells.iter().map(|&(start, _, len)| (start, len)).chain(freeblocks.iter().copied());
+            let in_order = !self.is_table() || cells.last().is_none_or(|&(_, previous, _)| previous < rowid);
+            if taken.clone().any(|block| overlaps(&block)) || !in_order {
+                break;
+            }
+            cells.push((offset, rowid, size));
+            at += 2;
+        }
+        cells
+    }
+
+    /// The cells found walking the content area from its start, stepping
+    /// over freeblocks and the fragmented bytes the header counts.
+    fn scanned_cells(&self, freeblocks: &[(usize, usize)], page_count: usize) -> Result<Vec<(usize, Option<i64>, usize)>> {
+        let mut cells = Vec::new();
+        let mut fragmented = self.bytes[self.header + 7] as usize;
+        let mut offset = self.content_start();
+        while offset < self.usable {
+            if let Some(&(_, size)) = freeblocks.iter().find(|&&(start, _)| start == offset) {
+                offset += size;
+                continue;
+            }
+            match self.cell(offset, page_count) {
+                Some((rowid, size)) => {
+                    cells.push((offset, rowid, size));
+                    offset += size;
+                }
+                None if fragmented > 0 => {
+                    fragmented -= 1;
+                    offset += 1;
+                }
+                None => Err(malformed(Some(self.number), msg!("neither-cell-nor-free", page = self.number, offset = offset)))?,
+            }
+        }
+        Ok(cells)
+    }
+}
+
+/// Whether the local part of a payload of `size` bytes starts with a record
+/// header whose serial types add up to that size. Headers that spill onto
+/// an overflow page are taken on trust.
+fn record_fits(local: &[u8], size: usize) -> bool {
+    let Ok((header_size, _, len)) = parse_varint(local) else {
+        return false;
+    };
+    let header_size = header_size as usize;
+    if header_size < len || header_size > size {
+        return false;
+    }
+    let Some(mut types) = local.get(len..header_size) else {
+        return true;
+    };
+    let mut total = header_size;
+    while !types.is_empty() {
+        let Ok((serial_type, rest, _)) = parse_varint(types) else {
+            return false;
+        };
+        match content_len(serial_type) {
+            Some(len) => total = total.saturating_add(len),
+            None => return false,
+        }
+        types = rest;
+    }
+    total == size
+}
+
+impl Database {
+    /// Makes `repair` to b-tree page `page_num`, after checking that the page
+    /// needs it and saving the page as it was to a new file at `backup`. The
+    /// page is written like any other change, through the journal.
+    pub fn repair_page(&mut self, page_num: usize, repair: Repair, backup: &str) -> Result<Repaired> {
+        if page_num == 0 || page_num > self.page_count()? {
+            Err(anyhow!(msg!("page-not-in-database", page = page_num)))?
+        }
+        let header = self.read_raw_page(1)?;
+        if header[18] > 1 || header[19] > 1 {
+            Err(Error::UnsupportedFeature(msg!("repair-wal")))?
+        }
+        let original = self.read_raw_page(page_num)?;
+        let page_count = self.page_count()?;
+        let mut page = original.clone();
+        let mut view = TreePage::new(&mut page, page_num, self.page_size - header[20] as usize);
+        if !matches!(view.kind(), INTERIOR_TABLE | LEAF_TABLE | INTERIOR_INDEX | LEAF_INDEX) {
+            Err(anyhow!(msg!("not-a-btree-page", page = page_num, kind = view.kind())))?
+        }
+        let nothing = |reason: String| anyhow!(msg!("nothing-to-repair", page = page_num, reason = reason));
+        let freeblocks = |view: &TreePage| {
+            view.freeblock_list().map_err(|e| anyhow!(msg!("empty-freeblocks-first", page = page_num, error = e)))
+        };
+
+        let (before, after) = match repair {
+            Repair::CellCount => {
+                let freeblocks = freeblocks(&view)?;
+                let (before, count) = (view.cell_count(), view.pointed_cells(&freeblocks, page_count).len());
+                if count == before {
+                    Err(nothing(msg!("cell-count-matches")))?
+                }
+                view.set_u16(view.header + 3, count);
+                (before, count)
+            }
+            Repair::CellPointers => {
+                if !view.is_table() {
+                    Err(Error::UnsupportedFeature(msg!("repair-index-pointers", page = page_num)))?
+                }
+                let freeblocks = freeblocks(&view)?;
+                let mut cells = view.scanned_cells(&freeblocks, page_count)?;
+                cells.sort_unstable_by_key(|&(_, rowid, _)| rowid);
+                if cells.windows(2).any(|pair| pair[0].1 == pair[1].1) {
+                    Err(malformed(Some(page_num), msg!("duplicate-rowid-cells", page = page_num)))?
+                }
+                let before = view.cell_count();
+                let pointed = view.pointed_cells(&freeblocks, page_count);
+                if pointed.len() == before && pointed == cells {
+                    Err(nothing(msg!("cell-pointers-match")))?
+                }
+                let (pointers, content_start) = (view.pointers(), view.content_start());
+                let end = pointers + 2 * cells.len();
+                if end > content_start {
+                    Err(anyhow!(msg!("pointers-do-not-fit", page = page_num, count = cells.len())))?
+                }
+                for (idx, &(offset, _, _)) in cells.iter().enumerate() {
+                    view.set_u16(pointers + 2 * idx, offset);
+                }
+                let old_end = (pointers + 2 * before).min(content_start);
+                if old_end > end {
+                    view.bytes[end..old_end].fill(0);
+                }
+                view.set_u16(view.header + 3, cells.len());
+                (before, cells.len())
+            }
+            Repair::Freeblocks => {
+                if let Ok(blocks) = view.freeblock_list() {
+                    let pointed = view.pointed_cells(&[], page_count);
+                    let overlaps = blocks.iter().any(|&(start, size)| {
+                        pointed.iter().any(|&(offset, _, len)| start < offset + len && offset < start + size)
+                    });
+                    if !overlaps {
+                        Err(nothing(msg!("freeblocks-sound")))?
+                    }
+                }
+                let mut cells = view.pointed_cells(&[], page_count);
+                if cells.len() != view.cell_count() {
+                    Err(anyhow!(msg!("repair-cell-count-first", page = page_num)))?
+                }
+                cells.sort_unstable();
+                let mut gaps = Vec::new();
+                let mut end = view.content_start();
+                for &(offset, _, size) in cells.iter().chain([&(view.usable, None, 0)]) {
+                    if offset > end {
+                        gaps.push((end, offset - end));
+                    }
+                    end = end.max(offset + size);
+                }
+                let fragmented: usize = gaps.iter().filter(|&&(_, size)| size < 4).map(|&(_, size)| size).sum();
+                if fragmented > u8::MAX as usize {
+                    Err(anyhow!(msg!("fragmented-do-not-fit", page = page_num, bytes = fragmented)))?
+                }
+                let before = view.u16_at(view.header + 1);
+                let mut link = view.header + 1;
+                for &(start, size) in gaps.iter().filter(|&&(_, size)| size >= 4) {
+                    view.set_u16(link, start);
+                    view.set_u16(start + 2, size);
+                    link = start;
+                }
+                view.set_u16(link, 0);
+                view.bytes[view.header + 7] = fragmented as u8;
+                (before, view.u16_at(view.header + 1))
+            }
+        };
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(backup)
+            .map_err(|e| anyhow!(msg!("cannot-create-backup", path = backup, error = e)))?;
+        file.write_all(&original)?;
+        file.sync_all()?;
+
+        let name = match repair {
+            Repair::CellCount => "cell-count",
+            Repair::CellPointers => "cell-pointers",
+            Repair::Freeblocks => "freeblocks",
+        };
+        self.autocommit(|| {
+            self.audit(
+                "REPAIR",
+                &[("page", page_num.to_string()), ("repair", json_string(name)), ("backup", json_string(backup))],
+            )?;
+            self.write_pages(&[(page_num, page)].into())
+        })?;
+        self.reload_schema()?;
+        Ok(Repaired {
+            page: page_num,
+            before,
+            after,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repair;
+    use crate::Database;
+    use std::fs;
+
+    /// A copy of sample.db whose page holding the rows of `apples` was
+    /// changed by `damage`, with the page's number.
+    fn damaged(name: &str, damage: impl Fn(&mut [u8])) -> (Database, usize, String) {
+        let path = std::env::temp_dir().join(format!("sqlite-lite-{}-{}.db", name, std::process::id()));
+        fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/sample.db"), &path).unwrap();
+        let db = Database::load_db(path.to_str().unwrap().to_string()).unwrap();
+        let root = db.schema.iter().find(|s| s.name == "apples").unwrap().rootpage;
+        let mut bytes = fs::read(&path).unwrap();
+        damage(&mut bytes[(root - 1) * db.page_size..root * db.page_size]);
+        drop(db);
+        fs::write(&path, bytes).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        (Database::load_db(path.clone()).unwrap(), root, path)
+    }
+
+    fn apples(db: &mut Database) -> Vec<i64> {
+        db.query("SELECT id FROM apples").unwrap().iter().map(|row| row.get_at(0).unwrap()).collect()
+    }
+
+    fn clean_up(db: Database, path: &str, backup: &str) {
+        drop(db);
+        fs::remove_file(path).unwrap();
+        let _ = fs::remove_file(format!("{}-journal", path));
+        let _ = fs::remove_file(backup);
+    }
+
+    #[test]
+    fn cell_count_drops_pointers_past_the_cells() {
+        let (mut db, root, path) = damaged("repair-count", |page| page[4] = 9);
+        assert!(!db.integrity_check().unwrap().is_empty());
+        let backup = format!("{}.page", path);
+        let repaired = db.repair_page(root, Repair::CellCount, &backup).unwrap();
+        assert_eq!((repaired.before, repaired.after), (9, 4));
+        assert_eq!(db.integrity_check().unwrap(), Vec::<String>::new());
+        assert_eq!(apples(&mut db), [1, 2, 3, 4]);
+        assert_eq!(fs::read(&backup).unwrap()[4], 9);
+        // The page is sound now, so there is nothing left to repair.
+        assert!(db.repair_page(root, Repair::CellCount, &format!("{}.again", path)).is_err());
+        clean_up(db, &path, &backup);
+    }
+
+    #[test]
+    fn cell_pointers_are_found_again_in_the_content_area() {
+        // Pointers to the last two cells are lost, and the first two swapped.
+        let (mut db, root, path) = damaged("repair-pointers", |page| {
+            page.copy_within(8..10, 16);
+            page.copy_within(10..12, 8);
+            page.copy_within(16..18, 10);
+            page[12..16].fill(0);
+            page[4] = 2;
+        });
+        let backup = format!("{}.page", path);
+        let repaired = db.repair_page(root, Repair::CellPointers, &backup).unwrap();
+        assert_eq!((repaired.before, repaired.after), (2, 4));
+        assert_eq!(db.integrity_check().unwrap(), Vec::<String>::new());
+        assert_eq!(apples(&mut db), [1, 2, 3, 4]);
+        clean_up(db, &path, &backup);
+    }
+
+    #[test]
+    fn the_backup_must_be_new() {
+        let (mut db, root, path) = damaged("repair-backup", |page| page[4] = 9);
+        let backup = format!("{}.page", path);
+        fs::write(&backup, b"").unwrap();
+        assert!(db.repair_page(root, Repair::CellCount, &backup).is_err());
+        assert_eq!(fs::read(&backup).unwrap(), b"");
+        clean_up(db, &path, &backup);
+    }
+}
@@ -0,0 +1,86 @@
+//! `--into`: the rows of a SELECT written to a new database file as its only
+//! table, through the same write path as CREATE TABLE and `.import`, so the
+//! result can be opened by sqlite and shared like any other database.
+
+use super::{compound::Rows, pages::LEAF_TABLE, Database};
+use crate::{
+    messages::msg,
+    sql::{parse_sql, quote_identifier, Statement},
+    DB_HEADER_SIZE,
+};
+use anyhow::{anyhow, Result};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+};
+
+impl Database {
+    /// Runs the SELECT `statement` and writes every row it produces, with no
+    /// `--max-rows` cap, to `table` in a new database at `path`, which must
+    /// not exist yet. The table's columns are named like the result columns
+    /// and declare the types of the table columns they come straight from;
+    /// other columns declare none, so their values are stored as they are.
+    /// Returns how many rows were written.
+    pub fn select_into(&self, statement: &Statement, path: &str, table: &str) -> Result<usize> {
+        if !matches!(statement, Statement::Select { .. } | Statement::Compound { .. }) {
+            Err(anyhow!(msg!("into-needs-select-statement")))?
+        }
+        self.pin_snapshots()?;
+        let (columns, rows) = self.read_select(statement)?;
+
+        let definitions = columns.iter().map(|column| match column.declared_type().as_str() {
+            "" => quote_identifier(&column.name, true),
+            declared => format!("{} {}", quote_identifier(&column.name, true), declared),
+        });
+        let sql = format!("CREATE TABLE {}({})", quote_identifier(table, true), definitions.collect::<Vec<_>>().join(", "));
+
+        create_database(path, self.page_size)?;
+        let written = Database::load_db(path.to_string()).and_then(|mut db| {
+            db.execute_statement(&parse_sql(&sql)?, &mut Rows::default())?;
+            db.import_rows(table, rows)
+        });
+        // Half a result is no use to anyone.
+        if written.is_err() {
+            let _ = fs::remove_file(path);
+        }
+        written
+    }
+}
+
+/// Writes a database at `path` with no tables yet.
+pub(super) fn create_database(path: &str, page_size: usize) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| anyhow!(msg!("cannot-create", path = path, error = e)))?;
+    file.write_all(&empty_database(page_size))?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Page 1 of a database with no tables yet: the header and an empty
+/// `sqlite_schema`, as sqlite leaves a new file once it is given a page size.
+pub(super) fn empty_database(page_size: usize) -> Vec<u8> {
+    let mut page = vec![0; page_size];
+    page[..16].copy_from_slice(b"SQLite format 3\0");
+    // 65536 does not fit in two bytes and is written as 1.
+    let size = if page_size == 65536 { 1 } else { page_size as u16 };
+    page[16..18].copy_from_slice(&size.to_be_bytes());
+    // Rollback journal for reading and writing, no reserved bytes, and the
+    // payload fractions every database has.
+    page[18..24].copy_from_slice(&[1, 1, 0, 64, 32, 32]);
+    let set_u32 = |page: &mut [u8], offset: usize, value: u32| page[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    // Change counter, page count, schema format 4, UTF-8 and the change
+    // counter the version number is valid for.
+    set_u32(&mut page, 24, 1);
+    set_u32(&mut page, 28, 1);
+    set_u32(&mut page, 44, 4);
+    set_u32(&mut page, 56, 1);
+    set_u32(&mut page, 92, 1);
+    page[DB_HEADER_SIZE] = LEAF_TABLE;
+    // Cell content starts at the end of the page; 65536 is written as 0.
+    page[DB_HEADER_SIZE + 5..DB_HEADER_SIZE + 7].copy_from_slice(&(page_size as u16).to_be_bytes());
+    page
+}
+
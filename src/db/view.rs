@@ -0,0 +1,109 @@
+//! Views: a table named in FROM that `sqlite_schema` defines with CREATE
+//! VIEW is read by running the SELECT it stands for, whose rows then stand
+//! in for the table's, under the view's column names.
+
+use super::{compound::Rows, schema, Database, Source, Table};
+use crate::{
+    messages::msg,
+    record::Record,
+    sql::{parse_sql, ColumnDef, Expr, Statement, TableRef},
+};
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+
+impl Database {
+    /// Whether the schema has a table or a view named `name`.
+    pub(super) fn defines(&self, name: &str) -> bool {
+        let kinds = [schema::Kind::Table, schema::Kind::View];
+        self.schema.iter().any(|s| kinds.contains(&s.kind) && s.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The source for `table` when the schema defines it as a view, with
+    /// the rows of the view's SELECT already read.
+    pub(super) fn view_source(&self, table: &TableRef, offset: usize) -> Result<Option<Source>> {
+        let Some(view) = self.schema.iter().find(|s| s.kind == schema::Kind::View && s.name.eq_ignore_ascii_case(&table.name)) else {
+            return Ok(None);
+        };
+        let parsed = parse_sql(&view.sql).map_err(|e| anyhow!(msg!("cannot-read-view", name = view.name, error = e)))?;
+        let Statement::CreateView { columns: names, select, .. } = parsed else {
+            Err(anyhow!(msg!("malformed-view", name = view.name, sql = view.sql)))?
+        };
+        {
+            let mut expanding = self.expanding.lock().unwrap();
+            if expanding.contains(&view.name) {
+                Err(anyhow!(msg!("circular-view", name = view.name)))?
+            }
+            expanding.push(view.name.clone());
+        }
+        let read = self.read_select(&select);
+        self.expanding.lock().unwrap().pop();
+        let (mut columns, rows) = read?;
+
+        if !names.is_empty() {
+            if names.len() != columns.len() {
+                Err(anyhow!(msg!("view-column-count", expected = names.len(), name = view.name, found = columns.len())))?
+            }
+            for (column, name) in columns.iter_mut().zip(names) {
+                column.name = name;
+            }
+        }
+        Ok(Some(Source {
+            name: table.name.clone(),
+            qualifier: table.qualifier().to_string(),
+            rootpage: 0,
+            table: Table {
+                columns,
+                key_order: None,
+                key_len: 0,
+                constraints: Vec::new(),
+                strict: false,
+            },
+            offset,
+            lookup: None,
+            wanted: None,
+            db: None,
+            view: Some(rows.into()),
+        }))
+    }
+
+    /// The result columns and every row of a SELECT, such as a view's. A
+    /// column that is just a column of a table keeps its declared type and
+    /// collation.
+    pub(super) fn read_select(&self, select: &Statement) -> Result<(Vec<ColumnDef>, Vec<Vec<Record>>)> {
+        let first = match select {
+            Statement::Compound { selects, .. } => &selects[0],
+            select => select,
+        };
+        let plan = self.plan_select(first)?;
+        // Like sqlite, a name taken already gets a number: `x`, `x:1`, `x:2`.
+        let mut taken = HashSet::new();
+        let columns = plan
+            .names
+            .iter()
+            .zip(&plan.selected)
+            .map(|(name, expr)| {
+                let mut unique = name.clone();
+                for n in 1.. {
+                    if taken.insert(unique.to_lowercase()) {
+                        break;
+                    }
+                    unique = format!("{}:{}", name, n);
+                }
+                let base = match expr {
+                    Expr::Column(column) => plan.columns.iter().find(|c| c.name.eq_ignore_ascii_case(&column.name)),
+                    _ => None,
+                };
+                ColumnDef {
+                    collation: base.and_then(|c| c.collation.clone()),
+                    ..ColumnDef::plain(&unique, base.and_then(|c| c.type_name.clone()))
+                }
+            })
+            .collect();
+        let mut rows = Rows::default();
+        match select {
+            Statement::Compound { .. } => self.execute_compound(select, &mut rows)?,
+            _ => self.execute_select_plan(&plan, None, &mut rows)?,
+        }
+        Ok((columns, rows.0))
+    }
+}
@@ -0,0 +1,918 @@
+//! Writes to table b-trees, and the transactions around them. `UPDATE`
+//! rewrites the cells of the rows it changes on their leaf pages, splitting
+//! the pages that no longer hold them. `CREATE TABLE` adds an empty b-tree
+//! and its row in `sqlite_schema`, and `ALTER TABLE ... RENAME TO` rewrites
+//! the rows there that name the table. `CREATE INDEX`, `VACUUM` and imports
+//! into an empty table write whole b-trees with the bulk loader.
+
+use super::{
+    bulk::{BulkLoader, Tree},
+    compare_key,
+    integrity::decode_record,
+    local_payload, malformed,
+    pages::{Pages, INTERIOR_INDEX, LEAF_INDEX, LEAF_TABLE},
+    resolve_column,
+    snapshot::empty_database,
+    Database, Source,
+};
+use crate::{
+    audit::row_change,
+    error::Error,
+    functions::{apply_affinity, Prng},
+    interrupt,
+    journal::Journal,
+    lock::{SharedLock, WriteLock},
+    messages::msg,
+    page::schema,
+    record::{encode_varint, serialize_record, Record, TextEncoding},
+    source::PageSource,
+    output::json_string,
+    sql::{rename_table, ColumnRef, Condition, Expr, IndexColumn, Statement, TableConstraint, TableRef},
+};
+use anyhow::{anyhow, Result};
+use std::{collections::BTreeMap, fs::OpenOptions, ops::ControlFlow, sync::{atomic, RwLock}};
+
+impl Database {
+    /// Runs `UPDATE table SET ... WHERE condition` and returns how many rows
+    /// it changed. Every changed page is written only once all rows are known
+    /// to fit, so a failing statement leaves the file as it was.
+    pub(super) fn execute_update(
+        &self,
+        table: &str,
+        assignments: &[(String, Expr)],
+        condition: Option<&Condition>,
+    ) -> Result<usize> {
+        self.check_not_wal(table)?;
+        let mut pages = Pages::new(self)?;
+
+        let from = TableRef {
+            schema: Some("main".to_string()),
+            name: table.to_string(),
+            alias: None,
+        };
+        let source = self.source(&from, 0)?;
+        let columns = &source.table.columns;
+        let mut resolve = |column: &ColumnRef| resolve_column(std::slice::from_ref(&source), columns, column);
+        let targets = assignments
+            .iter()
+            .map(|(name, expr)| {
+                let column = resolve(&ColumnRef::bare(name))?;
+                let idx = columns.iter().position(|c| c.name.eq_ignore_ascii_case(&column.name)).unwrap();
+                Ok((idx, expr.map_columns(&mut resolve)?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let condition = condition.map(|c| c.map_columns(&mut resolve)).transpose()?;
+        self.check_writable("UPDATE", &source)?;
+        self.check_updatable(&source, targets.iter().map(|(idx, _)| *idx))?;
+
+        // The hidden rowid column, when there is one, is not part of the record.
+        let stored = self.get_table(table)?.columns.len();
+        let rowid_idx = columns.iter().position(|c| c.rowid_alias).unwrap();
+        let mut rows = Vec::new();
+        let _ = self.scan_source(&source, columns, condition.as_ref(), &mut |row| {
+            interrupt::check()?;
+            if let Some(condition) = &condition {
+                if !self.matches(condition, columns, &mut |e| self.evaluate(e, columns, &row))? {
+                    return Ok(ControlFlow::Continue(()));
+                }
+            }
+            let new_values = targets
+                .iter()
+                .map(|(idx, expr)| Ok((*idx, self.evaluate(expr, columns, &row)?)))
+                .collect::<Result<Vec<_>>>()?;
+            let rowid = match row[rowid_idx] {
+                Record::Int64(rowid) => rowid,
+                _ => Err(malformed(None, msg!("row-without-rowid", table = table)))?,
+            };
+            let before = self.audit_log.is_some().then(|| row.clone());
+            let mut values = row;
+            for (idx, value) in new_values {
+                values[idx] = apply_affinity(columns[idx].affinity(), value);
+            }
+            rows.push((rowid, before, values));
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        let changed = rows.len();
+        let mut changes = Vec::new();
+        for (rowid, before, mut values) in rows {
+            values.truncate(stored);
+            if let Some(before) = before {
+                let names = columns[..stored].iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+                changes.push(row_change(rowid, &names, Some(&before), &values));
+            }
+            for (value, column) in values.iter_mut().zip(columns) {
+                match (column.rowid_alias, &value) {
+                    // The record stores NULL in place of the rowid.
+                    (true, _) => *value = Record::Null,
+                    (false, Record::Null) if column.not_null => {
+                        Err(anyhow!(msg!("not-null-failed", table = table, column = column.name)))?
+                    }
+                    _ => (),
+                }
+            }
+            let payload = serialize_record(&values, self.text_encoding, self.schema_format);
+            if local_payload(pages.usable(), payload.len(), true) < payload.len() {
+                Err(unsupported("UPDATE", table, msg!("overflowing-rows-unsupported")))?
+            }
+            pages.replace_cell(source.rootpage, rowid, table_cell(rowid, &payload))?;
+        }
+
+        self.audit("UPDATE", &[("table", json_string(table)), ("rows", format!("[{}]", changes.join(",")))])?;
+        self.write_pages(&pages.into_changed())?;
+        Ok(changed)
+    }
+
+    /// Runs `CREATE TABLE`: allocates the root page of the new table and adds
+    /// its row to `sqlite_schema`, bumping the schema cookie so that other
+    /// connections read the schema again. The schema of this one is reloaded
+    /// by the caller.
+    pub(super) fn execute_create_table(&self, statement: &Statement) -> Result<()> {
+        let Statement::CreateTable {
+            table,
+            columns,
+            constraints,
+            without_rowid,
+            temporary,
+            if_not_exists,
+            sql,
+            ..
+        } = statement
+        else {
+            unreachable!()
+        };
+        // An empty file is an empty database, which just has no header yet.
+        if self.db.size()? == 0 {
+            self.db.write_all_at(&empty_database(self.page_size), 0)?;
+            self.db.sync()?;
+            self.wrote();
+        }
+        self.check_not_wal(table)?;
+        if *temporary {
+            Err(unsupported("CREATE TABLE", table, msg!("temp-tables-unsupported")))?
+        }
+        if table.get(..7).is_some_and(|prefix| prefix.eq_ignore_ascii_case("sqlite_")) {
+            Err(anyhow!(msg!("reserved-name", name = table)))?
+        }
+        let existing = self
+            .schema
+            .iter()
+            .find(|s| s.kind != schema::Kind::Trigger && s.name.eq_ignore_ascii_case(table));
+        match existing.map(|s| &s.kind) {
+            Some(schema::Kind::Index) => Err(anyhow!(msg!("index-name-taken", name = table)))?,
+            Some(_) if *if_not_exists => return Ok(()),
+            Some(schema::Kind::View) => Err(anyhow!(msg!("view-exists", name = table)))?,
+            Some(_) => Err(anyhow!(msg!("table-exists", name = table)))?,
+            None => (),
+        }
+        for (idx, column) in columns.iter().enumerate() {
+            if columns[..idx].iter().any(|c| c.name.eq_ignore_ascii_case(&column.name)) {
+                Err(anyhow!(msg!("duplicate-column", column = column.name)))?
+            }
+        }
+        let table_pk = constraints.iter().any(|c| matches!(c, TableConstraint::PrimaryKey(_)));
+        if *without_rowid && !table_pk && !columns.iter().any(|c| c.primary_key) {
+            Err(anyhow!(msg!("primary-key-missing", table = table)))?
+        }
+        // sqlite keeps AUTOINCREMENT counters in sqlite_sequence, and backs
+        // keys other than the rowid with an index of their own.
+        if columns.iter().any(|c| c.autoincrement) {
+            Err(unsupported("CREATE TABLE", table, msg!("autoincrement-unsupported")))?
+        }
+        let rowid_key = columns.iter().any(|c| c.rowid_alias);
+        let needs_index = columns.iter().any(|c| c.unique || (c.primary_key && !rowid_key && !without_rowid))
+            || constraints.iter().any(|c| match c {
+                TableConstraint::Unique(_) => true,
+                TableConstraint::PrimaryKey(_) => !rowid_key && !without_rowid,
+                _ => false,
+            });
+        if needs_index {
+            Err(unsupported("CREATE TABLE", table, msg!("constraint-indexes-unsupported")))?
+        }
+
+        let mut pages = Pages::new(self)?;
+        let rootpage = pages.create_tree(if *without_rowid { LEAF_INDEX } else { LEAF_TABLE })?;
+        // A database without tables yet may have neither a format nor an
+        // encoding; the first table settles both, as in sqlite.
+        if pages.header_field(44)? == 0 {
+            pages.set_header_field(44, 4)?;
+        }
+        if pages.header_field(56)? == 0 {
+            pages.set_header_field(56, 1)?;
+        }
+        let row = [
+            Record::Text("table".to_string()),
+            Record::Text(table.clone()),
+            Record::Text(table.clone()),
+            Record::Int64(rootpage as i64),
+            Record::Text(sql.clone()),
+        ];
+        self.add_schema_row(&mut pages, "CREATE TABLE", row)?;
+        self.audit("CREATE TABLE", &[("table", json_string(table)), ("sql", json_string(sql))])?;
+        self.write_pages(&pages.into_changed())
+    }
+
+    /// Runs `CREATE INDEX`: reads the key of every row of the table, sorts
+    /// them and writes the new index b-tree with the bulk loader, then adds
+    /// its row to `sqlite_schema`. The schema is reloaded by the caller.
+    pub(super) fn execute_create_index(&self, statement: &Statement) -> Result<()> {
+        let Statement::CreateIndex {
+            index_name,
+            table,
+            columns,
+            unique,
+            if_not_exists,
+            partial,
+            sql,
+        } = statement
+        else {
+            unreachable!()
+        };
+        self.check_not_wal(table)?;
+        if index_name.get(..7).is_some_and(|prefix| prefix.eq_ignore_ascii_case("sqlite_")) {
+            Err(anyhow!(msg!("reserved-name", name = index_name)))?
+        }
+        let existing = self
+            .schema
+            .iter()
+            .find(|s| s.kind != schema::Kind::Trigger && s.name.eq_ignore_ascii_case(index_name));
+        match existing.map(|s| &s.kind) {
+            Some(schema::Kind::Index) if *if_not_exists => return Ok(()),
+            Some(schema::Kind::Index) => Err(anyhow!(msg!("index-exists", name = index_name)))?,
+            Some(_) => Err(anyhow!(msg!("table-name-taken", name = index_name)))?,
+            None => (),
+        }
+        let tbl_name = self.get_schema(table)?.name.clone();
+        let target = self.get_table(table)?;
+        if *partial {
+            Err(unsupported("CREATE INDEX", index_name, msg!("partial-indexes-unsupported")))?
+        }
+        if target.key_order.is_some() {
+            Err(unsupported("CREATE INDEX", index_name, msg!("without-rowid-indexes-unsupported")))?
+        }
+        // UTF-16 keys sort by their encoded bytes, which the key comparison
+        // does not do.
+        if self.text_encoding != TextEncoding::Utf8 {
+            Err(unsupported("CREATE INDEX", index_name, msg!("utf16-indexes-unsupported")))?
+        }
+
+        let source = self.source(&TableRef { schema: Some("main".to_string()), name: tbl_name.clone(), alias: None }, 0)?;
+        let table_columns = &source.table.columns;
+        // A column without COLLATE sorts by the collation of its table column.
+        let mut key_columns = Vec::new();
+        let mut positions = Vec::new();
+        for column in columns {
+            let Some(idx) = table_columns.iter().position(|c| c.name.eq_ignore_ascii_case(&column.name)) else {
+                Err(anyhow!(msg!("no-such-column", column = column.name)))?
+            };
+            let collation = column.collation.clone().or_else(|| table_columns[idx].collation.clone());
+            if let Some(name) = collation.as_deref().filter(|c| !["BINARY", "NOCASE"].iter().any(|k| c.eq_ignore_ascii_case(k))) {
+                Err(unsupported("CREATE INDEX", index_name, msg!("collation-unsupported", collation = name)))?
+            }
+            positions.push(idx);
+            key_columns.push(IndexColumn {
+                name: column.name.clone(),
+                collation,
+                descending: column.descending && self.schema_format >= 4,
+            });
+        }
+
+        let rowid_idx = table_columns.iter().position(|c| c.rowid_alias).unwrap();
+        let mut entries = Vec::new();
+        let _ = self.scan_source(&source, table_columns, None, &mut |row| {
+            interrupt::check()?;
+            let mut entry = positions.iter().map(|&idx| row[idx].clone()).collect::<Vec<_>>();
+            entry.push(row[rowid_idx].clone());
+            entries.push(entry);
+            Ok(ControlFlow::Continue(()))
+        })?;
+        // The rowid sorts last, as BINARY ascending, settling equal keys.
+        entries.sort_by(|a, b| compare_key(a, b, &key_columns));
+        if *unique {
+            let keys = columns.len();
+            // NULLs are distinct from each other, even in a UNIQUE index.
+            let duplicate = entries.windows(2).any(|pair| {
+                compare_key(&pair[0][..keys], &pair[1][..keys], &key_columns).is_eq()
+                    && !pair[0][..keys].iter().any(|value| matches!(value, Record::Null))
+            });
+            if duplicate {
+                let names = columns.iter().map(|c| format!("{}.{}", tbl_name, c.name)).collect::<Vec<_>>();
+                Err(anyhow!(msg!("unique-failed", columns = names.join(", "))))?
+            }
+        }
+
+        let mut pages = Pages::new(self)?;
+        let rootpage = pages.create_tree(LEAF_INDEX)?;
+        let mut loader = BulkLoader::new(&mut pages, Tree::Index, rootpage);
+        for entry in &entries {
+            loader.add_record(&serialize_record(entry, self.text_encoding, self.schema_format))?;
+        }
+        loader.finish()?;
+        let row = [
+            Record::Text("index".to_string()),
+            Record::Text(index_name.clone()),
+            Record::Text(tbl_name),
+            Record::Int64(rootpage as i64),
+            Record::Text(sql.clone()),
+        ];
+        self.add_schema_row(&mut pages, "CREATE INDEX", row)?;
+        self.audit(
+            "CREATE INDEX",
+            &[("index", json_string(index_name)), ("table", json_string(table)), ("sql", json_string(sql))],
+        )?;
+        self.write_pages(&pages.into_changed())
+    }
+
+    /// Runs `ALTER TABLE table RENAME TO new_name` as sqlite does unless
+    /// `legacy_alter_table` is on: renames the table and its automatic
+    /// indexes, moves its indexes and triggers over to the new name, and
+    /// rewrites the references to it in all of the schema's SQL, in views
+    /// and in other tables' triggers and foreign keys too. Its row in
+    /// `sqlite_sequence`, if it has one, follows. The schema is reloaded by
+    /// the caller.
+    pub(super) fn execute_rename_table(&self, table: &str, new_name: &str) -> Result<()> {
+        self.check_not_wal(table)?;
+        if table.get(..7).is_some_and(|prefix| prefix.eq_ignore_ascii_case("sqlite_")) {
+            Err(anyhow!(msg!("table-not-alterable", table = table)))?
+        }
+        let named = |name: &str| {
+            self.schema
+                .iter()
+                .find(|s| s.kind != schema::Kind::Trigger && s.name.eq_ignore_ascii_case(name))
+        };
+        let old = match named(table) {
+            Some(s) if s.kind == schema::Kind::Table => s.name.clone(),
+            Some(s) if s.kind == schema::Kind::View => Err(anyhow!(msg!("view-not-alterable", name = s.name)))?,
+            _ => {
+                let tables = self.schema.iter().filter(|s| s.kind == schema::Kind::Table);
+                Err(Error::no_such_table(table, tables.map(|s| s.name.as_str())))?
+            }
+        };
+        if new_name.get(..7).is_some_and(|prefix| prefix.eq_ignore_ascii_case("sqlite_")) {
+            Err(anyhow!(msg!("reserved-name", name = new_name)))?
+        }
+        if named(new_name).is_some() {
+            Err(anyhow!(msg!("name-taken", name = new_name)))?
+        }
+
+        let mut pages = Pages::new(self)?;
+        let single_page = pages.is_single_page(1)?;
+        let schema_format = pages.header_field(44)? as u32;
+        let autoindex = format!("sqlite_autoindex_{}_", old);
+        let mut rows = Vec::new();
+        self.tree_entries(1, &mut |rowid, payload| {
+            rows.push((rowid, payload));
+            Ok(())
+        })?;
+        for (rowid, payload) in rows {
+            let mut row = decode_record(&payload, self)?;
+            let mut changed = false;
+            if let [Record::Text(kind), Record::Text(name), Record::Text(tbl_name), _, sql] = &mut row[..] {
+                if tbl_name.eq_ignore_ascii_case(&old) {
+                    *tbl_name = new_name.to_string();
+                    changed = true;
+                }
+                let number = name.get(autoindex.len()..).filter(|_| {
+                    name.get(..autoindex.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(&autoindex))
+                });
+                match (kind.as_str(), number) {
+                    ("table", _) if name.eq_ignore_ascii_case(&old) => *name = new_name.to_string(),
+                    ("index", Some(number)) => *name = format!("sqlite_autoindex_{}_{}", new_name, number),
+                    _ => (),
+                }
+                if let Record::Text(sql) = sql {
+                    let rewritten = rename_table(sql, &old, new_name);
+                    changed |= rewritten != *sql;
+                    *sql = rewritten;
+                }
+            }
+            if !changed {
+                continue;
+            }
+            let rewritten = serialize_record(&row, self.text_encoding, schema_format);
+            if [&payload, &rewritten].iter().any(|p| local_payload(pages.usable(), p.len(), true) < p.len()) {
+                Err(unsupported("ALTER TABLE", table, msg!("overflowing-definitions-unsupported")))?
+            }
+            pages.replace_cell(1, rowid, table_cell(rowid, &rewritten))?;
+        }
+        if single_page && !pages.is_single_page(1)? {
+            Err(unsupported("ALTER TABLE", table, msg!("schema-outgrows-page-1")))?
+        }
+
+        if let Some(sequence) = self.schema.iter().find(|s| s.kind == schema::Kind::Table && s.name == "sqlite_sequence") {
+            let mut rows = Vec::new();
+            self.tree_entries(sequence.rootpage, &mut |rowid, payload| {
+                rows.push((rowid, decode_record(&payload, self)?));
+                Ok(())
+            })?;
+            for (rowid, mut row) in rows {
+                if !matches!(row.first(), Some(Record::Text(name)) if name.eq_ignore_ascii_case(&old)) {
+                    continue;
+                }
+                row[0] = Record::Text(new_name.to_string());
+                let payload = serialize_record(&row, self.text_encoding, schema_format);
+                pages.replace_cell(sequence.rootpage, rowid, table_cell(rowid, &payload))?;
+            }
+        }
+        let cookie = pages.header_field(40)?;
+        pages.set_header_field(40, (cookie as u32).wrapping_add(1) as usize)?;
+        self.audit("ALTER TABLE", &[("table", json_string(&old)), ("name", json_string(new_name))])?;
+        self.write_pages(&pages.into_changed())
+    }
+
+    /// Runs `VACUUM`: writes every b-tree again with the bulk loader into a
+    /// new image of the whole database, which has no free pages and no free
+    /// space on its pages but the last of each level, then writes that image
+    /// over the file and cuts off the pages it no longer needs. The schema
+    /// is reloaded by the caller.
+    pub(super) fn execute_vacuum(&self) -> Result<()> {
+        let header = self.read_raw_page(1)?;
+        if header[18] > 1 || header[19] > 1 {
+            Err(Error::UnsupportedFeature(msg!("vacuum-wal")))?
+        }
+        // Auto-vacuum databases keep pointer maps, which the new image would need too.
+        if header[52..56] != [0; 4] {
+            Err(Error::UnsupportedFeature(msg!("vacuum-auto-vacuum")))?
+        }
+        let mut rows = Vec::new();
+        self.tree_entries(1, &mut |rowid, payload| {
+            rows.push((rowid, decode_record(&payload, self)?));
+            Ok(())
+        })?;
+
+        let mut pages = Pages::rebuild(self)?;
+        for (_, row) in &mut rows {
+            // Views and triggers have no b-tree.
+            let Some(root) = row.get(3).and_then(Record::integer).filter(|&root| root > 0) else {
+                continue;
+            };
+            let tree = match self.read_raw_page(root as usize)?[0] {
+                LEAF_INDEX | INTERIOR_INDEX => Tree::Index,
+                _ => Tree::Table,
+            };
+            let rootpage = pages.create_tree(tree.leaf())?;
+            let mut loader = BulkLoader::new(&mut pages, tree, rootpage);
+            self.tree_entries(root as usize, &mut |rowid, payload| match tree {
+                Tree::Table => loader.add_row(rowid, &payload),
+                Tree::Index => loader.add_record(&payload),
+            })?;
+            loader.finish()?;
+            row[3] = Record::Int64(rootpage as i64);
+        }
+        let schema_format = pages.header_field(44)? as u32;
+        let mut loader = BulkLoader::new(&mut pages, Tree::Table, 1);
+        for (rowid, row) in &rows {
+            loader.add_row(*rowid, &serialize_record(row, self.text_encoding, schema_format))?;
+        }
+        loader.finish()?;
+        let cookie = pages.header_field(40)?;
+        pages.set_header_field(40, (cookie as u32).wrapping_add(1) as usize)?;
+
+        let page_count = pages.page_count();
+        self.audit("VACUUM", &[])?;
+        self.write_pages(&pages.into_changed())?;
+        self.truncate_pages(page_count)
+    }
+
+    /// Appends `rows` to `table`, each a value per column in declaration
+    /// order that the column's affinity then applies to, as `.import` reads
+    /// them from CSV. A NULL for an INTEGER PRIMARY KEY picks the next
+    /// rowid. Rows going into an empty table are written with the bulk
+    /// loader; otherwise each is inserted in turn. Returns how many rows
+    /// there were; should any of them fail, none is added.
+    pub fn import_rows(&self, table: &str, rows: Vec<Vec<Record>>) -> Result<usize> {
+        self.autocommit(|| self.execute_import(table, rows))
+    }
+
+    fn execute_import(&self, table: &str, rows: Vec<Vec<Record>>) -> Result<usize> {
+        self.check_not_wal(table)?;
+        let from = TableRef {
+            schema: Some("main".to_string()),
+            name: table.to_string(),
+            alias: None,
+        };
+        let source = self.source(&from, 0)?;
+        self.check_writable("INSERT INTO", &source)?;
+        let on_table = |s: &&schema::Schema| s.tbl_name.eq_ignore_ascii_case(table);
+        if let Some(index) = self.schema.iter().filter(on_table).find(|s| s.kind == schema::Kind::Index) {
+            Err(unsupported("INSERT INTO", table, msg!("indexed-tables-unsupported", index = index.name)))?
+        }
+        let columns = self.get_table(table)?.columns;
+        let mut pages = Pages::new(self)?;
+        let root = source.rootpage;
+
+        let mut next_rowid = pages.next_rowid(root)?;
+        let mut cells = Vec::with_capacity(rows.len());
+        let mut images = Vec::new();
+        for row in rows {
+            interrupt::check()?;
+            if row.len() != columns.len() {
+                Err(anyhow!(msg!("value-count", table = table, columns = columns.len(), values = row.len())))?
+            }
+            let mut rowid = None;
+            let mut values = Vec::with_capacity(row.len());
+            for (value, column) in row.into_iter().zip(&columns) {
+                let value = apply_affinity(column.affinity(), value);
+                match (column.rowid_alias, &value) {
+                    (true, Record::Null) => (),
+                    (true, value) => rowid = Some(value.integer().ok_or_else(|| anyhow!(msg!("datatype-mismatch")))?),
+                    (false, Record::Null) if column.not_null => {
+                        Err(anyhow!(msg!("not-null-failed", table = table, column = column.name)))?
+                    }
+                    _ => (),
+                }
+                // The record stores NULL in place of the rowid.
+                values.push(if column.rowid_alias { Record::Null } else { value });
+            }
+            let rowid = match rowid {
+                Some(rowid) => rowid,
+                None => next_rowid,
+            };
+            next_rowid = next_rowid.max(rowid.checked_add(1).ok_or_else(|| anyhow!(msg!("database-full")))?);
+            if self.audit_log.is_some() {
+                let mut image = values.clone();
+                if let Some(idx) = columns.iter().position(|c| c.rowid_alias) {
+                    image[idx] = Record::Int64(rowid);
+                }
+                images.push((rowid, image));
+            }
+            cells.push((rowid, serialize_record(&values, self.text_encoding, self.schema_format)));
+        }
+        cells.sort_by_key(|&(rowid, _)| rowid);
+        let key = columns.iter().find(|c| c.rowid_alias).map_or("rowid", |c| c.name.as_str());
+        let duplicate = || anyhow!(msg!("unique-column-failed", table = table, column = key));
+        if cells.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            Err(duplicate())?
+        }
+
+        let count = cells.len();
+        if pages.is_empty(root)? {
+            let mut loader = BulkLoader::new(&mut pages, Tree::Table, root);
+            for (rowid, payload) in &cells {
+                loader.add_row(*rowid, payload)?;
+            }
+            loader.finish()?;
+        } else {
+            for (rowid, payload) in cells {
+                if local_payload(pages.usable(), payload.len(), true) < payload.len() {
+                    Err(unsupported("INSERT INTO", table, msg!("overflowing-rows-unsupported")))?
+                }
+                if pages.contains(root, rowid)? {
+                    Err(duplicate())?
+                }
+                pages.insert_cell(root, rowid, table_cell(rowid, &payload))?;
+            }
+        }
+        if self.audit_log.is_some() {
+            let names = columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+            images.sort_by_key(|&(rowid, _)| rowid);
+            let changes = images.iter().map(|(rowid, image)| row_change(*rowid, &names, None, image));
+            let rows = format!("[{}]", changes.collect::<Vec<_>>().join(","));
+            self.audit("INSERT", &[("table", json_string(table)), ("rows", rows)])?;
+        }
+        self.write_pages(&pages.into_changed())?;
+        Ok(count)
+    }
+
+    /// Adds `row` to `sqlite_schema` and bumps the schema cookie, so that
+    /// other connections read the schema again.
+    fn add_schema_row(&self, pages: &mut Pages, statement: &str, row: [Record; 5]) -> Result<()> {
+        let name = match &row[1] {
+            Record::Text(name) => name.clone(),
+            _ => unreachable!(),
+        };
+        let payload = serialize_record(&row, self.text_encoding, pages.header_field(44)? as u32);
+        if local_payload(pages.usable(), payload.len(), true) < payload.len() {
+            Err(unsupported(statement, &name, msg!("overflowing-definitions-unsupported")))?
+        }
+        let rowid = pages.next_rowid(1)?;
+        pages.insert_cell(1, rowid, table_cell(rowid, &payload))?;
+        if !pages.is_single_page(1)? {
+            Err(unsupported(statement, &name, msg!("schema-outgrows-page-1")))?
+        }
+        let cookie = pages.header_field(40)?;
+        pages.set_header_field(40, (cookie as u32).wrapping_add(1) as usize)
+    }
+
+    /// Runs `write` as a statement of its own unless BEGIN opened a
+    /// transaction: committing it when it succeeds, rolling it back when
+    /// it fails.
+    pub(super) fn autocommit<T>(&self, write: impl FnOnce() -> Result<T>) -> Result<T> {
+        let result = write();
+        if self.in_transaction.load(atomic::Ordering::Relaxed) {
+            return result;
+        }
+        match result {
+            Ok(value) => self.commit().map(|()| value),
+            Err(e) => self.roll_back().and(Err(e)),
+        }
+    }
+
+    /// Writes `pages` over the database, saving what they replace to the
+    /// journal first. The first write of a transaction starts its journal.
+    pub(super) fn write_pages(&self, pages: &BTreeMap<usize, Vec<u8>>) -> Result<()> {
+        if pages.is_empty() {
+            return Ok(());
+        }
+        let mut journal = self.journal.lock().unwrap();
+        if journal.is_none() {
+            *journal = Some(self.start_journal()?);
+        }
+        let journal = journal.as_mut().unwrap();
+        for &page_num in pages.keys() {
+            if journal.needs(page_num) {
+                journal.save(page_num, &self.read_raw_page(page_num)?)?;
+            }
+        }
+        journal.sync()?;
+        self.lock_exclusive()?;
+        self.wrote();
+        for (page_num, bytes) in pages {
+            self.db
+                .write_all_at(bytes, ((page_num - 1) * self.page_size) as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Notes a write to the file, which open cursors may not have seen.
+    fn wrote(&self) {
+        self.writes.fetch_add(1, atomic::Ordering::SeqCst);
+    }
+
+    /// Cuts the database down to `page_count` pages, saving the pages it
+    /// drops to the journal first so that a rollback can put them back.
+    fn truncate_pages(&self, page_count: usize) -> Result<()> {
+        // The header already holds the new count.
+        let old_count = (self.db.size()? / self.page_size as u64) as usize;
+        if page_count >= old_count {
+            return Ok(());
+        }
+        let mut journal = self.journal.lock().unwrap();
+        if journal.is_none() {
+            *journal = Some(self.start_journal()?);
+        }
+        let journal = journal.as_mut().unwrap();
+        for page_num in page_count + 1..=old_count {
+            if journal.needs(page_num) {
+                journal.save(page_num, &self.read_raw_page(page_num)?)?;
+            }
+        }
+        journal.sync()?;
+        self.lock_exclusive()?;
+        self.wrote();
+        self.db.truncate((page_count * self.page_size) as u64)
+    }
+
+    /// Takes the exclusive lock of the open transaction, if it has file
+    /// locks, before it writes to the file.
+    fn lock_exclusive(&self) -> Result<()> {
+        match self.write_lock.lock().unwrap().as_mut() {
+            Some(lock) => lock.exclusive(),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts the journal of a transaction, taking the reserved lock on the
+    /// file first so that no other writer starts one too.
+    fn start_journal(&self) -> Result<Journal> {
+        if let Some(path) = &self.path {
+            let mut lock = WriteLock::new(SharedLock::acquire(OpenOptions::new().read(true).write(true).open(path)?)?);
+            lock.reserve()?;
+            *self.write_lock.lock().unwrap() = Some(lock);
+        }
+        let file: Box<dyn PageSource> = match &self.path {
+            Some(path) => Box::new(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(format!("{}-journal", path))?,
+            ),
+            None => Box::new(RwLock::new(Vec::new())),
+        };
+        let nonce = Prng::new().next_u64() as u32;
+        let mut journal = Journal::create(file, self.page_size, self.page_count()?, nonce)?;
+        // Committing changes the header on page 1.
+        journal.save(1, &self.read_raw_page(1)?)?;
+        Ok(journal)
+    }
+
+    /// Ends the open transaction, keeping what it wrote. Until the journal is
+    /// gone, a crash still rolls the transaction back.
+    pub(super) fn commit(&self) -> Result<()> {
+        let Some(journal) = self.journal.lock().unwrap().take() else {
+            return self.end_audit(true);
+        };
+        // Keeping the two equal tells readers the page count in the header is current.
+        let counter = self.change_counter()?.wrapping_add(1).to_be_bytes();
+        let written = self
+            .lock_exclusive()
+            .and_then(|()| self.db.write_all_at(&counter, 24))
+            .and_then(|()| self.db.write_all_at(&counter, 92))
+            .and_then(|()| self.db.sync());
+        let ended = match written {
+            Ok(()) => journal.commit().and_then(|()| self.end_audit(true)),
+            Err(e) => self.end_journal(journal).and_then(|()| self.end_audit(false)).and(Err(e)),
+        };
+        self.write_lock.lock().unwrap().take();
+        ended
+    }
+
+    /// Ends the open transaction, putting back every page it wrote.
+    pub(super) fn roll_back(&self) -> Result<()> {
+        if let Some(journal) = self.journal.lock().unwrap().take() {
+            let ended = self.end_journal(journal);
+            self.write_lock.lock().unwrap().take();
+            ended?;
+        }
+        self.end_audit(false)
+    }
+
+    /// Rolls `journal` back, unless the transaction never got to write to
+    /// the file, when there is nothing to put back.
+    fn end_journal(&self, journal: Journal) -> Result<()> {
+        let wrote = self.write_lock.lock().unwrap().as_ref().is_none_or(WriteLock::is_exclusive);
+        if !wrote {
+            return journal.commit();
+        }
+        self.wrote();
+        journal.roll_back(self.db.as_ref())
+    }
+
+    /// Logs `statement` to the audit log, if there is one, before it writes
+    /// anything; `fields` are the JSON members that describe it.
+    pub(super) fn audit(&self, statement: &str, fields: &[(&str, String)]) -> Result<()> {
+        match &self.audit_log {
+            Some(log) => log.statement(self.path(), statement, fields),
+            None => Ok(()),
+        }
+    }
+
+    fn end_audit(&self, committed: bool) -> Result<()> {
+        match &self.audit_log {
+            Some(log) => log.end(self.path(), committed),
+            None => Ok(()),
+        }
+    }
+
+    fn check_not_wal(&self, table: &str) -> Result<()> {
+        let header = self.read_raw_page(1)?;
+        if header[18] > 1 || header[19] > 1 {
+            Err(Error::UnsupportedFeature(msg!("write-wal", table = table)))?
+        }
+        Ok(())
+    }
+
+    /// Refuses rows this writer cannot store faithfully: ones it would have
+    /// to run a trigger for, check a constraint beyond NOT NULL on, or put
+    /// somewhere other than a rowid table.
+    fn check_writable(&self, statement: &str, source: &Source) -> Result<()> {
+        let table = &source.table;
+        let name = source.name.as_str();
+        if source.view.is_some() {
+            Err(anyhow!(msg!("cannot-modify-view", name = name)))?
+        }
+        if table.key_order.is_some() {
+            Err(unsupported(statement, name, msg!("without-rowid-unsupported")))?
+        }
+        if table.strict {
+            Err(unsupported(statement, name, msg!("strict-unsupported")))?
+        }
+        if table.columns.iter().any(|c| c.generated.is_some()) {
+            Err(unsupported(statement, name, msg!("generated-columns-unsupported")))?
+        }
+        let checks = table.constraints.iter().any(|c| matches!(c, TableConstraint::Check(_)));
+        if checks || table.columns.iter().any(|c| !c.checks.is_empty()) {
+            Err(unsupported(statement, name, msg!("check-unsupported")))?
+        }
+        let on_table = |s: &&schema::Schema| s.tbl_name.eq_ignore_ascii_case(name);
+        if self.schema.iter().filter(on_table).any(|s| s.kind == schema::Kind::Trigger) {
+            Err(unsupported(statement, name, msg!("triggers-unsupported")))?
+        }
+        Ok(())
+    }
+
+    /// Refuses updates that would have to maintain an index, on top of what
+    /// [`Database::check_writable`] refuses.
+    fn check_updatable(&self, source: &Source, targets: impl Iterator<Item = usize>) -> Result<()> {
+        let table = &source.table;
+        let name = source.name.as_str();
+        let on_table = |s: &&schema::Schema| s.tbl_name.eq_ignore_ascii_case(name);
+        let indexes = self.indexes.iter().filter(|i| i.table.eq_ignore_ascii_case(name));
+        if let Some(index) = indexes.clone().find(|i| i.partial) {
+            Err(unsupported("UPDATE", name, msg!("partial-index-update-unsupported", index = index.name)))?
+        }
+        // Indexes on expressions do not parse, so nothing says what they cover.
+        let mut index_entries = self.schema.iter().filter(on_table).filter(|s| s.kind == schema::Kind::Index);
+        if let Some(entry) = index_entries.find(|s| !self.indexes.iter().any(|i| i.name.eq_ignore_ascii_case(&s.name)))
+        {
+            Err(unsupported("UPDATE", name, msg!("index-update-unsupported", index = entry.name)))?
+        }
+
+        for idx in targets {
+            let column = &table.columns[idx];
+            if column.rowid_alias {
+                Err(Error::UnsupportedFeature(msg!("update-rowid", table = name)))?
+            }
+            let indexed = indexes
+                .clone()
+                .find(|i| i.columns.iter().any(|c| c.name.eq_ignore_ascii_case(&column.name)))
+                .map(|i| i.name.clone());
+            let keyed = table.constraints.iter().any(|c| match c {
+                TableConstraint::PrimaryKey(names) | TableConstraint::Unique(names) => {
+                    names.iter().any(|n| n.eq_ignore_ascii_case(&column.name))
+                }
+                _ => false,
+            });
+            let indexed = match indexed {
+                Some(index) => Some(index),
+                None if keyed || column.unique || column.primary_key => Some(msg!("key-on-column", column = column.name)),
+                None => None,
+            };
+            if let Some(index) = indexed {
+                Err(Error::UnsupportedFeature(msg!("update-indexed-column", table = name, column = column.name, index = index)))?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The leaf cell of a table b-tree holding `payload` as the row `rowid`.
+fn table_cell(rowid: i64, payload: &[u8]) -> Vec<u8> {
+    let mut cell = Vec::with_capacity(payload.len() + 18);
+    encode_varint(payload.len() as u64, &mut cell);
+    encode_varint(rowid as u64, &mut cell);
+    cell.extend_from_slice(payload);
+    cell
+}
+
+/// Why `statement` cannot run on `table`: `reason`, a message saying what is
+/// not supported.
+fn unsupported(statement: &str, table: &str, reason: String) -> anyhow::Error {
+    Error::UnsupportedFeature(msg!("cannot-statement", statement = statement, table = table, reason = reason)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Database;
+    use std::{fs, path::PathBuf, process::Command};
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sqlite-lite-{}-{}.db", name, std::process::id()))
+    }
+
+    fn column(db: &mut Database, sql: &str) -> Vec<String> {
+        db.query(sql).unwrap().iter().map(|row| row.get_at(0).unwrap()).collect()
+    }
+
+    #[test]
+    fn rename_moves_the_rows_and_the_sequence() {
+        let path = temp("rename-sample");
+        fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/sample.db"), &path).unwrap();
+        let mut db = Database::load_db(path.to_str().unwrap().to_string()).unwrap();
+        db.query("ALTER TABLE apples RENAME TO fruit").unwrap();
+        assert_eq!(column(&mut db, "SELECT name FROM fruit WHERE id = 2"), ["Fuji"]);
+        assert!(db.query("SELECT name FROM apples").is_err());
+        assert_eq!(column(&mut db, "SELECT name FROM sqlite_sequence WHERE name <> 'oranges'"), ["fruit"]);
+        assert!(db.query("ALTER TABLE fruit RENAME TO oranges").is_err());
+        assert!(db.query("ALTER TABLE fruit RENAME TO sqlite_fruit").is_err());
+
+        // And the file says so when opened again.
+        drop(db);
+        let mut db = Database::load_db(path.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(column(&mut db, "SELECT name FROM fruit WHERE id = 4"), ["Golden Delicious"]);
+        assert_eq!(db.integrity_check().unwrap(), Vec::<String>::new());
+        drop(db);
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_file(format!("{}-journal", path.display()));
+    }
+
+    /// sqlite3 finds the renamed table's indexes, automatic ones included,
+    /// and its triggers under the new name.
+    #[test]
+    fn sqlite_reads_what_rename_wrote() {
+        let path = temp("rename-sqlite");
+        let _ = fs::remove_file(&path);
+        let sql = "CREATE TABLE t(id INTEGER PRIMARY KEY, a TEXT UNIQUE, b);
+            CREATE INDEX t_b ON t(b);
+            CREATE TABLE log(v);
+            CREATE TRIGGER t_log AFTER UPDATE OF b ON t BEGIN INSERT INTO log VALUES (new.b); END;
+            INSERT INTO t VALUES (1, 'x', 10), (2, 'y', 20);";
+        if Command::new("sqlite3").arg(&path).arg(sql).status().is_err() {
+            eprintln!("sqlite3 is not installed; skipping");
+            return;
+        }
+        let mut db = Database::load_db(path.to_str().unwrap().to_string()).unwrap();
+        db.query("ALTER TABLE t RENAME TO u").unwrap();
+        drop(db);
+
+        let sql = "PRAGMA integrity_check; SELECT name, tbl_name FROM sqlite_schema WHERE type <> 'table' ORDER BY name;
+            UPDATE u SET b = 30 WHERE a = 'y'; SELECT v FROM log;";
+        let output = Command::new("sqlite3").arg(&path).arg(sql).output().unwrap();
+        let expected = "ok\nsqlite_autoindex_u_1|u\nt_b|u\nt_log|u\n30\n";
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected, "{}", String::from_utf8_lossy(&output.stderr));
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_file(format!("{}-journal", path.display()));
+    }
+}
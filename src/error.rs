@@ -0,0 +1,125 @@
+//! The failures a caller may want to tell apart. Functions return
+//! [`anyhow::Result`], and an [`Error`] rides inside whenever one of these is
+//! the cause, for `downcast_ref` to find; other failures carry only their
+//! message.
+
+use crate::messages::message;
+use itertools::Itertools;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A statement named a table the schema does not have. The closest
+    /// existing name, if any is close enough, rides along as a suggestion.
+    #[error("{}", message("no-such-table", &[("name", .name)]))]
+    TableNotFound {
+        name: String,
+        suggestion: Option<String>,
+    },
+    /// A statement named a column that none of the tables it reads has;
+    /// `table` is the one it was looked for in, when just one was.
+    #[error("{}", message("no-such-column", &[("column", .column)]))]
+    ColumnNotFound {
+        column: String,
+        table: Option<String>,
+        suggestion: Option<String>,
+    },
+    /// The structure of the file does not add up; `page` is where, when a
+    /// single page is to blame.
+    #[error("{}", message("malformed", &[("reason", .reason)]))]
+    CorruptPage { page: Option<usize>, reason: String },
+    /// The file is shorter than the pages its header counts: it should have
+    /// `expected` bytes but has `actual`.
+    #[error("{}", message("truncated", &[("expected", .expected), ("actual", .actual)]))]
+    TruncatedDatabase { expected: u64, actual: u64 },
+    /// The database was written to after a [`Cursor`](crate::db::btree::Cursor) or
+    /// [`Database::table_pages`](crate::Database::table_pages) walk started,
+    /// which may have moved the cells it had yet to visit. `first` or `seek`
+    /// positions a cursor again, over what the write left.
+    #[error("{}", message("cursor-invalidated", &[]))]
+    CursorInvalidated,
+    /// The database or statement uses something this crate cannot do yet.
+    #[error("{0}")]
+    UnsupportedFeature(String),
+    /// A statement failed to parse at byte `offset`, which is `line` and
+    /// `column` counting from 1.
+    #[error(
+        "{}",
+        message("parse-error", &[("line", .line), ("column", .column), ("expected", .expected)])
+    )]
+    SqlParse {
+        offset: usize,
+        line: usize,
+        column: usize,
+        expected: String,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Error {
+    pub fn no_such_table<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Self {
+        Error::TableNotFound {
+            name: name.to_string(),
+            suggestion: closest(name, candidates),
+        }
+    }
+
+    pub fn no_such_column<'a>(column: &str, table: Option<&str>, candidates: impl IntoIterator<Item = &'a str>) -> Self {
+        Error::ColumnNotFound {
+            column: column.to_string(),
+            table: table.map(str::to_string),
+            suggestion: closest(column, candidates),
+        }
+    }
+
+    /// The table or column name as written in the statement, for a failed
+    /// lookup of one.
+    pub fn lookup_name(&self) -> Option<&str> {
+        match self {
+            Error::TableNotFound { name, .. } => Some(name),
+            Error::ColumnNotFound { column, .. } => Some(column),
+            _ => None,
+        }
+    }
+
+    pub fn suggestion(&self) -> Option<&str> {
+        match self {
+            Error::TableNotFound { suggestion, .. } | Error::ColumnNotFound { suggestion, .. } => suggestion.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// The candidate nearest to `name` by case-insensitive edit distance, as long
+/// as no more than a third of it (and at least one character) has to change.
+fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= limit)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Edit distance over characters, ignoring ASCII case, where swapping two
+/// adjacent characters counts as one edit (optimal string alignment).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().map(|c| c.to_ascii_lowercase()).collect_vec();
+    let b = b.chars().map(|c| c.to_ascii_lowercase()).collect_vec();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
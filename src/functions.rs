@@ -0,0 +1,681 @@
+use crate::{
+    messages::msg,
+    record::{compare_records, Record},
+    sql::{Affinity, BinaryOp},
+};
+use anyhow::{anyhow, Result};
+use std::{
+    cmp::Ordering,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{self, AtomicU64},
+};
+
+/// Pseudo-random generator backing `random()` and `randomblob()`.
+///
+/// Seeded from the process' hash randomness by default; a fixed seed makes
+/// every query that uses the random functions reproducible.
+pub struct Prng {
+    state: AtomicU64,
+}
+
+impl Prng {
+    pub fn new() -> Self {
+        Self::with_seed(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            state: AtomicU64::new(seed),
+        }
+    }
+
+    pub fn reseed(&self, seed: u64) {
+        self.state.store(seed, atomic::Ordering::Relaxed);
+    }
+
+    /// splitmix64
+    pub fn next_u64(&self) -> u64 {
+        const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut z = self.state.fetch_add(GAMMA, atomic::Ordering::Relaxed).wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn fill(&self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+impl Default for Prng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn call_scalar(
+    name: &str,
+    args: &[Record],
+    rng: &Prng,
+    folding: CaseFolding,
+) -> Result<Record> {
+    match (name.to_lowercase().as_str(), args) {
+        ("random", []) => Ok(Record::Int64(rng.next_u64() as i64)),
+        ("randomblob", [n]) => {
+            // sqlite returns a single byte for lengths below one
+            let len = record_to_i64(n).unwrap_or(0).max(1) as usize;
+            let mut blob = vec![0; len];
+            rng.fill(&mut blob);
+            Ok(Record::Blob(blob))
+        }
+        ("zeroblob", [n]) => Ok(Record::Blob(vec![
+            0;
+            record_to_i64(n).unwrap_or(0).max(0) as usize
+        ])),
+        ("typeof", [value]) => Ok(Record::Text(type_name(value).to_string())),
+        ("length", [value]) => Ok(match value {
+            Record::Null => Record::Null,
+            Record::Blob(b) => Record::Int64(b.len() as i64),
+            Record::Text(s) => Record::Int64(s.chars().count() as i64),
+            other => Record::Int64(record_to_text(other).unwrap_or_default().chars().count() as i64),
+        }),
+        ("like", [pattern, value]) => match (record_to_text(pattern), record_to_text(value)) {
+            (Some(pattern), Some(value)) => Ok(Record::Int64(like(&pattern, &value, folding) as i64)),
+            _ => Ok(Record::Null),
+        },
+        ("printf" | "format", [format, args @ ..]) => match record_to_text(format) {
+            Some(format) => Ok(Record::Text(printf(&format, args))),
+            None => Ok(Record::Null),
+        },
+        ("quote", [value]) => Ok(Record::Text(quote(value))),
+        (name @ ("min" | "max"), [_, _, ..]) => {
+            let wanted = if name == "min" { Ordering::Less } else { Ordering::Greater };
+            let mut best = &args[0];
+            for arg in args {
+                if let Record::Null = arg {
+                    return Ok(Record::Null);
+                }
+                if compare_records(arg, best) == wanted {
+                    best = arg;
+                }
+            }
+            Ok(best.clone())
+        }
+        ("unicode", [value]) => Ok(record_to_text(value)
+            .and_then(|s| s.chars().next())
+            .map_or(Record::Null, |c| Record::Int64(c as i64))),
+        ("char", _) => Ok(Record::Text(
+            args.iter()
+                .map(|arg| {
+                    let code = record_to_i64(arg).unwrap_or(0);
+                    char::from_u32(code as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+                })
+                .collect(),
+        )),
+        (
+            "random" | "randomblob" | "zeroblob" | "typeof" | "length" | "like" | "printf"
+            | "format" | "quote" | "unicode",
+            _,
+        ) => Err(anyhow!(msg!("wrong-argument-count", function = name))),
+        _ => Err(anyhow!(msg!("no-such-function", function = name))),
+    }
+}
+
+/// How case-insensitive text matching (LIKE and the NOCASE collation) folds
+/// letters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CaseFolding {
+    /// Only `A`-`Z` fold onto `a`-`z`, exactly as in sqlite.
+    #[default]
+    Ascii,
+    /// Every character folds by its Unicode lowercase mapping. Stock sqlite
+    /// does not do this, so results can differ from it for non-ASCII text.
+    Unicode,
+}
+
+impl CaseFolding {
+    pub fn eq_char(&self, a: char, b: char) -> bool {
+        match self {
+            CaseFolding::Ascii => a.eq_ignore_ascii_case(&b),
+            CaseFolding::Unicode => a == b || a.to_lowercase().eq(b.to_lowercase()),
+        }
+    }
+
+    pub fn eq(&self, a: &str, b: &str) -> bool {
+        a.chars().count() == b.chars().count()
+            && a.chars().zip(b.chars()).all(|(a, b)| self.eq_char(a, b))
+    }
+
+    pub fn fold(&self, s: &str) -> String {
+        match self {
+            CaseFolding::Ascii => s.to_ascii_lowercase(),
+            CaseFolding::Unicode => s.chars().flat_map(char::to_lowercase).collect(),
+        }
+    }
+}
+
+/// `text LIKE pattern`: `%` matches any run of characters, `_` exactly one,
+/// and letters match regardless of case as `folding` folds them.
+pub fn like(pattern: &str, text: &str, folding: CaseFolding) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    like_chars(&pattern, &text, folding)
+}
+
+/// Matches left to right, remembering only the last `%` seen: when a later
+/// character fails to match, that `%` takes one more character of `text` and
+/// matching resumes after it. Earlier `%`s never need to be revisited, so
+/// this takes at most `pattern.len() * text.len()` steps.
+fn like_chars(pattern: &[char], text: &[char], folding: CaseFolding) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut last_percent = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                p += 1;
+                last_percent = Some((p, t));
+            }
+            Some('_') => (p, t) = (p + 1, t + 1),
+            Some(&c) if folding.eq_char(c, text[t]) => (p, t) = (p + 1, t + 1),
+            _ => match last_percent {
+                Some((after, from)) => {
+                    (p, t) = (after, from + 1);
+                    last_percent = Some((after, from + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '%')
+}
+
+/// Text key range `[low, high)` that contains every value matching the
+/// literal prefix of a LIKE pattern, regardless of ASCII case. `None` when
+/// the prefix is empty or could match a number, which sorts before text.
+pub fn like_prefix_range(pattern: &str) -> Option<(String, String)> {
+    let prefix: String = pattern.chars().take_while(|&c| c != '%' && c != '_').collect();
+    let first = prefix.chars().next()?;
+    if first.is_ascii_digit() || matches!(first, '-' | '+' | '.' | ' ') {
+        return None;
+    }
+
+    let low = prefix.to_ascii_uppercase();
+    let mut high = prefix.to_ascii_lowercase();
+    let last = high.pop()?;
+    high.push(char::from_u32(last as u32 + 1).unwrap_or(char::MAX));
+    Some((low, high))
+}
+
+/// A value converted for arithmetic.
+#[derive(Clone, Copy)]
+enum Number {
+    Integer(i64),
+    Real(f64),
+}
+
+impl Number {
+    fn real(self) -> f64 {
+        match self {
+            Number::Integer(v) => v as f64,
+            Number::Real(v) => v,
+        }
+    }
+}
+
+/// Converts an operand the way sqlite's arithmetic does: text and blobs are
+/// read for their leading number, zero when there is none. NULL stays NULL.
+fn to_number(value: &Record) -> Option<Number> {
+    let text = match value {
+        Record::Null => return None,
+        Record::Float(v) => return Some(Number::Real(*v)),
+        Record::Text(s) => s.clone(),
+        Record::Blob(b) => String::from_utf8_lossy(b).into_owned(),
+        other => return Some(Number::Integer(other.integer().unwrap_or(0))),
+    };
+
+    let text = text.trim_start();
+    let bytes = text.as_bytes();
+    let digits = |from: usize| from + bytes[from..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let start = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let mut end = digits(start);
+    let mut any_digits = end > start;
+    let mut real = false;
+    if bytes.get(end) == Some(&b'.') {
+        let fraction_end = digits(end + 1);
+        any_digits |= fraction_end > end + 1;
+        end = fraction_end;
+        real = true;
+    }
+    if !any_digits {
+        return Some(Number::Integer(0));
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent_end = digits(end + 1 + sign);
+        if exponent_end > end + 1 + sign {
+            end = exponent_end;
+            real = true;
+        }
+    }
+
+    let number = &text[..end];
+    match number.parse::<i64>() {
+        Ok(v) if !real => Some(Number::Integer(v)),
+        _ => Some(Number::Real(number.parse().unwrap_or(0.0))),
+    }
+}
+
+/// Converts a value about to be stored in a column with `affinity`, like
+/// sqlite does: TEXT columns turn numbers into text; NUMERIC, INTEGER and
+/// REAL ones turn text that is entirely a number into that number and keep
+/// reals without a fraction as integers, except that REAL ones store every
+/// number as a real. BLOB columns store values as they are.
+pub fn apply_affinity(affinity: Affinity, value: Record) -> Record {
+    let number = match (affinity, value) {
+        (_, Record::Null) => return Record::Null,
+        (Affinity::Blob, value) | (_, value @ Record::Blob(_)) => return value,
+        (Affinity::Text, value @ Record::Text(_)) => return value,
+        (Affinity::Text, value) => return Record::Text(record_to_text(&value).unwrap_or_default()),
+        (_, Record::Text(s)) => match well_formed_number(&s) {
+            Some(number) => number,
+            None => return Record::Text(s),
+        },
+        (_, Record::Float(v)) => Number::Real(v),
+        (_, value) => Number::Integer(value.integer().unwrap_or_default()),
+    };
+    match (affinity, number) {
+        (Affinity::Real, number) => Record::Float(number.real()),
+        // Only reals that convert to an integer and back unchanged.
+        (_, Number::Real(v)) if v.fract() == 0.0 && v.abs() < 9.2e18 => Record::Int64(v as i64),
+        (_, Number::Real(v)) => Record::Float(v),
+        (_, Number::Integer(v)) => Record::Int64(v),
+    }
+}
+
+/// The number `text` spells out in full, give or take surrounding spaces.
+fn well_formed_number(text: &str) -> Option<Number> {
+    let text = text.trim();
+    let numeric = |c: char| c.is_ascii_digit() || "+-.eE".contains(c);
+    if !text.contains(|c: char| c.is_ascii_digit()) || !text.chars().all(numeric) {
+        return None;
+    }
+    match text.parse::<i64>() {
+        Ok(v) => Some(Number::Integer(v)),
+        Err(_) => text.parse().ok().map(Number::Real),
+    }
+}
+
+/// `left op right` with sqlite's rules: integer arithmetic falls back to real
+/// on overflow, division by zero is NULL, `%` works on the integer parts.
+pub fn arithmetic(op: BinaryOp, left: &Record, right: &Record) -> Record {
+    if op == BinaryOp::Concat {
+        return match (record_to_text(left), record_to_text(right)) {
+            (Some(a), Some(b)) => Record::Text(a + &b),
+            _ => Record::Null,
+        };
+    }
+    let (Some(a), Some(b)) = (to_number(left), to_number(right)) else {
+        return Record::Null;
+    };
+
+    match (a, b) {
+        (Number::Integer(a), Number::Integer(b)) => {
+            let result = match op {
+                BinaryOp::Add => a.checked_add(b),
+                BinaryOp::Sub => a.checked_sub(b),
+                BinaryOp::Mul => a.checked_mul(b),
+                BinaryOp::Div | BinaryOp::Rem if b == 0 => return Record::Null,
+                BinaryOp::Div => a.checked_div(b),
+                BinaryOp::Rem => Some(a.checked_rem(b).unwrap_or(0)),
+                BinaryOp::Concat => unreachable!(),
+            };
+            match result {
+                Some(v) => Record::Int64(v),
+                None => arithmetic(op, &Record::Float(a as f64), &Record::Float(b as f64)),
+            }
+        }
+        (a, b) => {
+            let (a, b) = (a.real(), b.real());
+            let result = match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Div if b == 0.0 => return Record::Null,
+                BinaryOp::Div => a / b,
+                BinaryOp::Rem => {
+                    let (a, b) = (a as i64, b as i64);
+                    if b == 0 {
+                        return Record::Null;
+                    }
+                    a.checked_rem(b).unwrap_or(0) as f64
+                }
+                BinaryOp::Concat => unreachable!(),
+            };
+            Record::Float(result)
+        }
+    }
+}
+
+/// Unary minus.
+pub fn negate(value: &Record) -> Record {
+    match to_number(value) {
+        None => Record::Null,
+        Some(Number::Integer(v)) => match v.checked_neg() {
+            Some(v) => Record::Int64(v),
+            None => Record::Float(-(v as f64)),
+        },
+        Some(Number::Real(v)) => Record::Float(-v),
+    }
+}
+
+/// Storage class of a value as reported by `typeof()`.
+pub fn type_name(value: &Record) -> &'static str {
+    match value {
+        Record::Null => "null",
+        Record::Float(_) => "real",
+        Record::Text(_) => "text",
+        Record::Blob(_) => "blob",
+        _ => "integer",
+    }
+}
+
+/// SQL literal for a value, as produced by `quote()`.
+pub fn quote(value: &Record) -> String {
+    match value {
+        Record::Null => "NULL".to_string(),
+        Record::Float(v) => format_real(*v),
+        Record::Text(s) => format!("'{}'", s.replace('\'', "''")),
+        Record::Blob(b) => format!("X'{}'", b.iter().map(|b| format!("{:02X}", b)).collect::<String>()),
+        other => record_to_i64(other).unwrap_or(0).to_string(),
+    }
+}
+
+/// Renders a REAL the way sqlite does (`%!.15g`), always keeping a decimal point.
+pub fn format_real(value: f64) -> String {
+    if value.is_nan() {
+        return "NULL".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    let formatted = format_general(value, 15, false, false);
+    match formatted.find('e') {
+        _ if formatted.contains('.') => formatted,
+        Some(pos) => format!("{}.0{}", &formatted[..pos], &formatted[pos..]),
+        None => format!("{}.0", formatted),
+    }
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    alt: bool,
+    comma: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn pad(&self, sign: &str, body: String, numeric: bool) -> String {
+        let len = sign.chars().count() + body.chars().count();
+        if len >= self.width {
+            return format!("{}{}", sign, body);
+        }
+        let fill = self.width - len;
+        if self.left {
+            format!("{}{}{}", sign, body, " ".repeat(fill))
+        } else if self.zero && numeric {
+            format!("{}{}{}", sign, "0".repeat(fill), body)
+        } else {
+            format!("{}{}{}", " ".repeat(fill), sign, body)
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+/// sqlite's printf(): C-style conversions plus `%q`, `%Q` and `%w`.
+pub fn printf(format: &str, args: &[Record]) -> String {
+    let mut out = String::new();
+    let mut args = args.iter();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '0' => spec.zero = true,
+                '#' | '!' => spec.alt = true,
+                ',' => spec.comma = true,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        if chars.peek() == Some(&'*') {
+            chars.next();
+            let width = args.next().and_then(record_to_i64).unwrap_or(0);
+            spec.left |= width < 0;
+            spec.width = width.unsigned_abs() as usize;
+        } else {
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                spec.width = spec.width * 10 + digit as usize;
+                chars.next();
+            }
+        }
+
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                let precision = args.next().and_then(record_to_i64).unwrap_or(0);
+                spec.precision = Some(precision.max(0) as usize);
+            } else {
+                let mut precision = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    precision = precision * 10 + digit as usize;
+                    chars.next();
+                }
+                spec.precision = Some(precision);
+            }
+        }
+
+        while chars.peek() == Some(&'l') {
+            chars.next();
+        }
+
+        let Some(conversion) = chars.next() else {
+            break;
+        };
+        let arg = match conversion {
+            '%' => None,
+            _ => args.next(),
+        };
+
+        let formatted = match conversion {
+            '%' => "%".to_string(),
+            'd' | 'i' | 'u' => {
+                let value = arg.and_then(record_to_i64).unwrap_or(0);
+                let mut digits = value.unsigned_abs().to_string();
+                if let Some(precision) = spec.precision {
+                    if digits.len() < precision {
+                        digits = format!("{}{}", "0".repeat(precision - digits.len()), digits);
+                    }
+                }
+                if spec.comma {
+                    digits = group_thousands(&digits);
+                }
+                spec.pad(spec.sign(value < 0), digits, true)
+            }
+            'x' | 'X' | 'o' => {
+                let value = arg.and_then(record_to_i64).unwrap_or(0) as u64;
+                let (digits, prefix) = match conversion {
+                    'x' => (format!("{:x}", value), "0x"),
+                    'X' => (format!("{:X}", value), "0X"),
+                    _ => (format!("{:o}", value), "0"),
+                };
+                let prefix = if spec.alt && value != 0 { prefix } else { "" };
+                spec.pad(prefix, digits, true)
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = arg.and_then(record_to_f64).unwrap_or(0.0);
+                let precision = spec.precision.unwrap_or(6);
+                let body = match conversion {
+                    'f' | 'F' => format!("{:.*}", precision, value.abs()),
+                    'e' | 'E' => format_exponent(value.abs(), precision, conversion == 'E'),
+                    _ => format_general(value.abs(), precision, spec.alt, conversion == 'G'),
+                };
+                spec.pad(spec.sign(value.is_sign_negative() && value != 0.0), body, true)
+            }
+            'c' => {
+                let c = arg
+                    .and_then(record_to_text)
+                    .and_then(|s| s.chars().next())
+                    .map(String::from)
+                    .unwrap_or_default();
+                spec.pad("", c, false)
+            }
+            's' | 'z' | 'q' | 'Q' | 'w' => {
+                let text = arg.and_then(record_to_text);
+                let mut body = match (conversion, &text) {
+                    ('Q', None) => "NULL".to_string(),
+                    (_, None) => String::new(),
+                    (_, Some(text)) => text.clone(),
+                };
+                if let Some(precision) = spec.precision {
+                    body = body.chars().take(precision).collect();
+                }
+                let body = match (conversion, text) {
+                    ('q', _) => body.replace('\'', "''"),
+                    ('Q', Some(_)) => format!("'{}'", body.replace('\'', "''")),
+                    ('w', _) => body.replace('"', "\"\""),
+                    _ => body,
+                };
+                spec.pad("", body, false)
+            }
+            other => format!("%{}", other),
+        };
+        out.push_str(&formatted);
+    }
+
+    out
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut grouped = String::new();
+    for (idx, c) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    grouped
+}
+
+/// `%e`: Rust writes `1.5e2`, C wants `1.500000e+02`.
+fn format_exponent(value: f64, precision: usize, upper: bool) -> String {
+    let formatted = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let sign = if exponent < 0 { '-' } else { '+' };
+    let e = if upper { 'E' } else { 'e' };
+    format!("{}{}{}{:02}", mantissa, e, sign, exponent.abs())
+}
+
+/// `%g`: shortest of `%e`/`%f` at the given number of significant digits.
+fn format_general(value: f64, precision: usize, alt: bool, upper: bool) -> String {
+    let precision = precision.max(1);
+    if value == 0.0 {
+        return if alt {
+            format!("{:.*}", precision - 1, 0.0)
+        } else {
+            "0".to_string()
+        };
+    }
+
+    let exponent = format!("{:.*e}", precision - 1, value.abs())
+        .split_once('e')
+        .and_then(|(_, e)| e.parse::<i32>().ok())
+        .unwrap_or(0);
+
+    let formatted = if exponent < -4 || exponent >= precision as i32 {
+        format_exponent(value, precision - 1, upper)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+    };
+
+    if alt {
+        return formatted;
+    }
+    match formatted.find(['e', 'E']) {
+        Some(pos) => {
+            let (mantissa, exponent) = formatted.split_at(pos);
+            format!("{}{}", trim_fraction(mantissa), exponent)
+        }
+        None => trim_fraction(&formatted).to_string(),
+    }
+}
+
+fn trim_fraction(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+fn record_to_f64(record: &Record) -> Option<f64> {
+    match record {
+        Record::Float(v) => Some(*v),
+        Record::Text(_) => to_number(record).map(Number::real),
+        Record::Null | Record::Blob(_) => None,
+        other => record_to_i64(other).map(|v| v as f64),
+    }
+}
+
+fn record_to_text(record: &Record) -> Option<String> {
+    match record {
+        Record::Null => None,
+        Record::Text(s) => Some(s.clone()),
+        Record::Float(v) => Some(format_real(*v)),
+        Record::Blob(b) => Some(String::from_utf8_lossy(b).into_owned()),
+        other => record_to_i64(other).map(|v| v.to_string()),
+    }
+}
+
+/// Text is read for its leading number, as `CAST(.. AS INTEGER)` does.
+fn record_to_i64(record: &Record) -> Option<i64> {
+    match record {
+        Record::Float(v) => Some(*v as i64),
+        Record::Text(_) => to_number(record).map(|number| match number {
+            Number::Integer(v) => v,
+            Number::Real(v) => v as i64,
+        }),
+        other => other.integer(),
+    }
+}
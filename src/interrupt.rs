@@ -0,0 +1,41 @@
+//! Ctrl-C handling: the first SIGINT asks the running statement to stop at
+//! its next row, a second one before the flag is cleared exits outright.
+
+use crate::messages::msg;
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+const SIGINT: i32 = 2;
+
+extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    fn _exit(status: i32) -> !;
+}
+
+extern "C" fn on_interrupt(_signum: i32) {
+    if INTERRUPTS.fetch_add(1, Ordering::SeqCst) > 0 {
+        // Only async-signal-safe calls are allowed here.
+        unsafe { _exit(130) };
+    }
+}
+
+pub fn install() {
+    unsafe {
+        signal(SIGINT, on_interrupt);
+    }
+}
+
+/// Forgets earlier interrupts; called before each statement runs.
+pub fn clear() {
+    INTERRUPTS.store(0, Ordering::SeqCst);
+}
+
+/// Fails with `interrupted` once Ctrl-C has been pressed.
+pub fn check() -> Result<()> {
+    match INTERRUPTS.load(Ordering::SeqCst) {
+        0 => Ok(()),
+        _ => Err(anyhow!(msg!("interrupted"))),
+    }
+}
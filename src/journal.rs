@@ -0,0 +1,272 @@
+//! Rollback journal: before a transaction first changes a page, the page's
+//! original content goes to `<database>-journal`. Committing truncates the
+//! journal; a journal found non-empty on open while no writer holds the
+//! reserved lock belongs to a transaction that never finished, and playing
+//! it back puts the database as it was. The layout is sqlite's, so sqlite
+//! rolls back what this crate leaves behind and the other way round.
+
+use crate::{messages::msg, source::PageSource};
+use anyhow::{anyhow, Result};
+use std::{collections::HashSet, fs::File};
+
+const MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+/// The header fills the first sector; page records follow it.
+const SECTOR_SIZE: usize = 512;
+/// Record count in a header whose records must be counted from the file size.
+const COUNT_FROM_SIZE: u32 = 0xffff_ffff;
+
+/// The journal of an open transaction.
+pub struct Journal {
+    file: Box<dyn PageSource>,
+    page_size: usize,
+    /// Salt of the record checksums, so records left over from an earlier
+    /// transaction do not pass for this one's.
+    nonce: u32,
+    /// Size of the database in pages when the transaction began.
+    db_pages: usize,
+    saved: HashSet<usize>,
+}
+
+impl Journal {
+    /// Starts an empty journal in `file` for a database of `db_pages` pages.
+    pub fn create(file: Box<dyn PageSource>, page_size: usize, db_pages: usize, nonce: u32) -> Result<Self> {
+        file.truncate(0)?;
+        let journal = Journal {
+            file,
+            page_size,
+            nonce,
+            db_pages,
+            saved: HashSet::new(),
+        };
+        journal.write_header(0)?;
+        Ok(journal)
+    }
+
+    /// Whether `page_num` still has to be saved before it is overwritten.
+    /// Pages past the original end of the database have nothing to restore.
+    pub fn needs(&self, page_num: usize) -> bool {
+        page_num <= self.db_pages && !self.saved.contains(&page_num)
+    }
+
+    /// Records `original` as the content `page_num` had when the transaction
+    /// began. The record only counts once [`Journal::sync`] has run.
+    pub fn save(&mut self, page_num: usize, original: &[u8]) -> Result<()> {
+        if !self.needs(page_num) {
+            return Ok(());
+        }
+        let mut record = Vec::with_capacity(self.page_size + 8);
+        record.extend_from_slice(&(page_num as u32).to_be_bytes());
+        record.extend_from_slice(original);
+        record.extend_from_slice(&checksum(self.nonce, original).to_be_bytes());
+        let offset = SECTOR_SIZE + self.saved.len() * (self.page_size + 8);
+        self.file.write_all_at(&record, offset as u64)?;
+        self.saved.insert(page_num);
+        Ok(())
+    }
+
+    /// Makes the saved pages durable. The pages they came from must not be
+    /// overwritten before this returns.
+    pub fn sync(&self) -> Result<()> {
+        self.write_header(self.saved.len() as u32)?;
+        self.file.sync()
+    }
+
+    /// Ends the transaction, keeping its changes.
+    pub fn commit(self) -> Result<()> {
+        self.file.truncate(0)?;
+        self.file.sync()
+    }
+
+    /// Ends the transaction, putting `db` back as it was when it began.
+    pub fn roll_back(self, db: &dyn PageSource) -> Result<()> {
+        self.sync()?;
+        play_back(self.file.as_ref(), db)?;
+        self.commit()
+    }
+
+    fn write_header(&self, records: u32) -> Result<()> {
+        let mut header = vec![0; SECTOR_SIZE];
+        header[..8].copy_from_slice(&MAGIC);
+        header[8..12].copy_from_slice(&records.to_be_bytes());
+        header[12..16].copy_from_slice(&self.nonce.to_be_bytes());
+        header[16..20].copy_from_slice(&(self.db_pages as u32).to_be_bytes());
+        header[20..24].copy_from_slice(&(SECTOR_SIZE as u32).to_be_bytes());
+        header[24..28].copy_from_slice(&(self.page_size as u32).to_be_bytes());
+        self.file.write_all_at(&header, 0)
+    }
+}
+
+/// Copies the pages saved in `journal` back into `db` and cuts `db` to its
+/// size before the transaction. sqlite starts a new header, at the next
+/// sector, each time it syncs a journal in the middle of a transaction;
+/// every such segment is played. Reading stops at the first record whose
+/// checksum fails, as one torn by a crash would. Returns false, without
+/// touching `db`, when `journal` holds no journal.
+pub fn play_back(journal: &dyn PageSource, db: &dyn PageSource) -> Result<bool> {
+    let size = journal.size()? as usize;
+    let mut offset = 0;
+    let mut original = None;
+    let mut header = [0; 28];
+    'segments: while offset + header.len() <= size {
+        journal.read_exact_at(&mut header, offset as u64)?;
+        if header[..8] != MAGIC {
+            break;
+        }
+        let field = |at: usize| u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+        let (records, nonce, db_pages) = (field(8), field(12), field(16) as usize);
+        let (sector_size, page_size) = (field(20) as usize, field(24) as usize);
+        let valid_size = |size: usize, min: usize| size.is_power_of_two() && (min..=65536).contains(&size);
+        if !valid_size(sector_size, 32) || !valid_size(page_size, 512) {
+            Err(anyhow!(msg!("corrupt-journal")))?
+        }
+        // Only the first header knows the size the database started with.
+        let (db_pages, _) = *original.get_or_insert((db_pages, page_size));
+
+        let record_size = page_size + 8;
+        let start = offset + sector_size;
+        let available = size.saturating_sub(start) / record_size;
+        let records = match records {
+            COUNT_FROM_SIZE => available,
+            records => (records as usize).min(available),
+        };
+        let mut record = vec![0; record_size];
+        for idx in 0..records {
+            journal.read_exact_at(&mut record, (start + idx * record_size) as u64)?;
+            let page_num = u32::from_be_bytes([record[0], record[1], record[2], record[3]]) as usize;
+            let (page, sum) = record[4..].split_at(page_size);
+            if checksum(nonce, page).to_be_bytes() != sum {
+                break 'segments;
+            }
+            if page_num > 0 && page_num <= db_pages {
+                db.write_all_at(page, ((page_num - 1) * page_size) as u64)?;
+            }
+        }
+        offset = (start + records * record_size).next_multiple_of(sector_size);
+    }
+
+    let Some((db_pages, page_size)) = original else {
+        return Ok(false);
+    };
+    db.truncate((db_pages * page_size) as u64)?;
+    db.sync()?;
+    Ok(true)
+}
+
+/// Whether the file at `path` holds a journal whose pages a rollback would
+/// put back, as opposed to none at all, or one that was committed by
+/// truncating it or zeroing its header.
+pub fn is_hot(path: &str) -> Result<bool> {
+    let Ok(file) = File::open(path) else {
+        return Ok(false);
+    };
+    let mut magic = [0; 8];
+    if file.size()? < SECTOR_SIZE as u64 {
+        return Ok(false);
+    }
+    file.read_exact_at(&mut magic, 0)?;
+    Ok(magic == MAGIC)
+}
+
+/// sqlite's record checksum: the nonce plus every 200th byte of the page,
+/// counting down from 200 bytes before its end.
+fn checksum(nonce: u32, page: &[u8]) -> u32 {
+    (1..=page.len().saturating_sub(200))
+        .rev()
+        .step_by(200)
+        .fold(nonce, |sum, at| sum.wrapping_add(page[at] as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_hot, play_back, Journal};
+    use crate::source::PageSource;
+    use std::{fs, fs::File, process::Command, sync::RwLock};
+
+    const PAGE_SIZE: usize = 512;
+
+    /// A database of `pages` pages, each filled with its own number.
+    fn database(pages: usize) -> RwLock<Vec<u8>> {
+        RwLock::new((1..=pages).flat_map(|n| vec![n as u8; PAGE_SIZE]).collect())
+    }
+
+    fn page(db: &RwLock<Vec<u8>>, page_num: usize) -> Vec<u8> {
+        db.read().unwrap()[(page_num - 1) * PAGE_SIZE..page_num * PAGE_SIZE].to_vec()
+    }
+
+    fn journal_file(name: &str) -> (File, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("sqlite-lite-{}-{}-journal", name, std::process::id()));
+        let file = File::options().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        (file, path)
+    }
+
+    #[test]
+    fn rolling_back_restores_the_pages_and_the_size() {
+        let db = database(3);
+        let before = db.read().unwrap().clone();
+        let mut journal = Journal::create(Box::new(RwLock::new(Vec::new())), PAGE_SIZE, 3, 7).unwrap();
+        journal.save(2, &page(&db, 2)).unwrap();
+        journal.sync().unwrap();
+        db.write_all_at(&[0xee; PAGE_SIZE], PAGE_SIZE as u64).unwrap();
+        // Saving a page again keeps the content it had first.
+        journal.save(2, &[0xee; PAGE_SIZE]).unwrap();
+        // A page the database grew by has nothing to restore.
+        assert!(!journal.needs(4));
+        db.write_all_at(&[4; PAGE_SIZE], 3 * PAGE_SIZE as u64).unwrap();
+        journal.roll_back(&db).unwrap();
+        assert!(*db.read().unwrap() == before);
+    }
+
+    #[test]
+    fn a_torn_record_ends_the_playback() {
+        let (file, path) = journal_file("torn");
+        let mut journal = Journal::create(Box::new(file.try_clone().unwrap()), PAGE_SIZE, 3, 7).unwrap();
+        journal.save(1, &page(&database(3), 1)).unwrap();
+        journal.save(3, &page(&database(3), 3)).unwrap();
+        journal.sync().unwrap();
+        assert!(is_hot(path.to_str().unwrap()).unwrap());
+        // The first byte the checksum covers, in the second record's page.
+        let record = 512 + PAGE_SIZE + 8;
+        file.write_all_at(&[0], (record + 4 + PAGE_SIZE - 200) as u64).unwrap();
+
+        let db = database(3);
+        db.write_all_at(&vec![0xee; 3 * PAGE_SIZE], 0).unwrap();
+        assert!(play_back(&file, &db).unwrap());
+        assert_eq!(page(&db, 1), [1; PAGE_SIZE]);
+        assert_eq!(page(&db, 3), [0xee; PAGE_SIZE]);
+
+        journal.commit().unwrap();
+        assert!(!is_hot(path.to_str().unwrap()).unwrap());
+        assert!(!play_back(&file, &db).unwrap());
+        fs::remove_file(path).unwrap();
+    }
+
+    /// The rollback journal is sqlite's: sqlite3 plays back one left behind.
+    #[test]
+    fn sqlite_rolls_back_a_journal_left_behind() {
+        let path = std::env::temp_dir().join(format!("sqlite-lite-hot-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let sql = "PRAGMA page_size = 512; CREATE TABLE t(v); INSERT INTO t VALUES ('kept');";
+        if Command::new("sqlite3").arg(&path).arg(sql).status().is_err() {
+            eprintln!("sqlite3 is not installed; skipping");
+            return;
+        }
+        let db = File::options().read(true).write(true).open(&path).unwrap();
+        let pages = db.size().unwrap() as usize / PAGE_SIZE;
+        let journal_path = format!("{}-journal", path.display());
+        let file = File::options().read(true).write(true).create(true).truncate(true).open(&journal_path).unwrap();
+        let mut journal = Journal::create(Box::new(file), PAGE_SIZE, pages, 0x5eed).unwrap();
+        let mut original = vec![0; PAGE_SIZE];
+        db.read_exact_at(&mut original, PAGE_SIZE as u64).unwrap();
+        journal.save(2, &original).unwrap();
+        journal.sync().unwrap();
+        // The table's page is torn apart and the transaction never ends.
+        db.write_all_at(&[0xee; PAGE_SIZE], PAGE_SIZE as u64).unwrap();
+        drop(journal);
+
+        let output = Command::new("sqlite3").arg(&path).arg("SELECT v FROM t").output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "kept\n", "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(!is_hot(&journal_path).unwrap());
+        fs::remove_file(&path).unwrap();
+        let _ = fs::remove_file(&journal_path);
+    }
+}
@@ -0,0 +1,30 @@
+pub mod aggregate;
+pub mod audit;
+pub mod cell;
+pub mod csv;
+pub mod db;
+pub mod error;
+pub mod functions;
+pub mod interrupt;
+pub mod journal;
+pub mod lock;
+pub mod manifest;
+pub mod messages;
+#[cfg(unix)]
+pub mod mmap;
+pub mod output;
+pub mod page;
+pub mod profile;
+pub mod record;
+pub mod sha256;
+pub mod shell;
+pub mod source;
+pub mod sql;
+pub mod trace;
+pub mod wal;
+
+pub use db::Database;
+pub use error::Error;
+pub use page::Page;
+
+pub const DB_HEADER_SIZE: usize = 100;
@@ -0,0 +1,220 @@
+//! sqlite's file locks, for reading a database that a live sqlite process
+//! may be writing, and for writing one it may be reading. sqlite takes
+//! POSIX advisory locks on bytes past 1 GiB, which no page uses: readers
+//! share a read lock on the 510 bytes of the shared range; a writer holds
+//! the reserved byte while it fills its journal, and needs the pending byte
+//! and then a write lock on the whole shared range before it touches the
+//! file. Holding a shared lock therefore keeps the file as a committed
+//! transaction left it.
+//!
+//! The wal-index of a WAL database is locked byte by byte the same way;
+//! see [`crate::wal`].
+//!
+//! Only Linux is supported; elsewhere the lock is taken without locking
+//! anything, since `struct flock` and the `fcntl` commands differ by system.
+
+use crate::messages::msg;
+use anyhow::{anyhow, Result};
+use std::{borrow::Borrow, fs::File, thread, time::Duration};
+
+const PENDING_BYTE: i64 = 0x4000_0000;
+const RESERVED_BYTE: i64 = PENDING_BYTE + 1;
+const SHARED_FIRST: i64 = PENDING_BYTE + 2;
+const SHARED_SIZE: i64 = 510;
+
+/// How long to keep trying while a writer holds the file.
+pub(crate) const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A shared lock on a database file, released when dropped. It keeps the
+/// file, or a reference to it, for as long as it is held.
+pub struct SharedLock<F: Borrow<File>> {
+    file: F,
+}
+
+impl<F: Borrow<File>> SharedLock<F> {
+    /// Takes a shared lock on `file` the way sqlite does: through the
+    /// pending byte, which keeps new readers out while a writer waits for
+    /// the ones already there to finish. Fails with `database is locked`
+    /// once a writer has kept it for [`BUSY_TIMEOUT`].
+    pub fn acquire(file: F) -> Result<Self> {
+        wait(|| {
+            if !sys::lock(file.borrow(), sys::READ, PENDING_BYTE, 1)? {
+                return Ok(false);
+            }
+            let shared = sys::lock(file.borrow(), sys::READ, SHARED_FIRST, SHARED_SIZE);
+            sys::lock(file.borrow(), sys::UNLOCK, PENDING_BYTE, 1)?;
+            shared
+        })?;
+        Ok(SharedLock { file })
+    }
+
+    /// Whether some process holds the reserved lock, which a writer takes
+    /// for as long as its journal is in use.
+    pub fn writer_active(&self) -> Result<bool> {
+        sys::is_locked(self.file.borrow(), RESERVED_BYTE, 1)
+    }
+}
+
+impl<F: Borrow<File>> Drop for SharedLock<F> {
+    fn drop(&mut self) {
+        let _ = sys::lock(self.file.borrow(), sys::UNLOCK, SHARED_FIRST, SHARED_SIZE);
+    }
+}
+
+/// A writer's locks on a database file, on top of its shared lock: the
+/// reserved lock, which only one writer holds at a time, and the exclusive
+/// lock, which no reader can share. All of them are released when dropped.
+pub struct WriteLock {
+    shared: SharedLock<File>,
+    exclusive: bool,
+}
+
+impl WriteLock {
+    pub fn new(shared: SharedLock<File>) -> Self {
+        WriteLock { shared, exclusive: false }
+    }
+
+    /// Takes the reserved lock, which a writer holds before it creates its
+    /// journal. Fails with `database is locked` once another writer has kept
+    /// it for [`BUSY_TIMEOUT`].
+    pub fn reserve(&mut self) -> Result<()> {
+        wait(|| sys::lock(&self.shared.file, sys::WRITE, RESERVED_BYTE, 1))
+    }
+
+    /// Takes the pending lock, which keeps new readers out, and then the
+    /// exclusive lock once the readers already there have finished, as a
+    /// writer must before it writes to the file. Fails with `database is
+    /// locked` when they have not after [`BUSY_TIMEOUT`].
+    pub fn exclusive(&mut self) -> Result<()> {
+        if self.exclusive {
+            return Ok(());
+        }
+        let file = &self.shared.file;
+        wait(|| sys::lock(file, sys::WRITE, PENDING_BYTE, 1))?;
+        wait(|| sys::lock(file, sys::WRITE, SHARED_FIRST, SHARED_SIZE))?;
+        self.exclusive = true;
+        Ok(())
+    }
+
+    /// Whether [`WriteLock::exclusive`] took the exclusive lock, so that the
+    /// file may have been written.
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        // The shared range goes with the shared lock.
+        let _ = sys::lock(&self.shared.file, sys::UNLOCK, PENDING_BYTE, 2);
+    }
+}
+
+/// Calls `attempt` until it takes its lock, every 10ms for up to
+/// [`BUSY_TIMEOUT`], and then fails with `database is locked`.
+fn wait(mut attempt: impl FnMut() -> Result<bool>) -> Result<()> {
+    let mut waited = Duration::ZERO;
+    while !attempt()? {
+        if waited >= BUSY_TIMEOUT {
+            Err(anyhow!(msg!("database-locked")))?
+        }
+        let pause = Duration::from_millis(10);
+        thread::sleep(pause);
+        waited += pause;
+    }
+    Ok(())
+}
+
+/// Locks `len` bytes of `file` from `start` without waiting, `exclusive`ly
+/// or shared with other readers; false when another process holds a lock in
+/// the way. The locks belong to the process, and closing any descriptor of
+/// the file releases all of them.
+pub(crate) fn try_lock(file: &File, exclusive: bool, start: i64, len: i64) -> Result<bool> {
+    sys::lock(file, if exclusive { sys::WRITE } else { sys::READ }, start, len)
+}
+
+pub(crate) fn unlock(file: &File, start: i64, len: i64) -> Result<()> {
+    sys::lock(file, sys::UNLOCK, start, len).map(|_| ())
+}
+
+/// Whether another process holds any lock on `len` bytes of `file` from `start`.
+pub(crate) fn is_locked(file: &File, start: i64, len: i64) -> Result<bool> {
+    sys::is_locked(file, start, len)
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use anyhow::Result;
+    use std::{fs::File, io, os::fd::AsRawFd};
+
+    pub const READ: i16 = 0;
+    pub const WRITE: i16 = 1;
+    pub const UNLOCK: i16 = 2;
+    const F_GETLK: i32 = 5;
+    const F_SETLK: i32 = 6;
+    const SEEK_SET: i16 = 0;
+
+    #[repr(C)]
+    struct Flock {
+        l_type: i16,
+        l_whence: i16,
+        l_start: i64,
+        l_len: i64,
+        l_pid: i32,
+    }
+
+    extern "C" {
+        fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+    }
+
+    fn flock(kind: i16, start: i64, len: i64) -> Flock {
+        Flock {
+            l_type: kind,
+            l_whence: SEEK_SET,
+            l_start: start,
+            l_len: len,
+            l_pid: 0,
+        }
+    }
+
+    /// Sets a lock of `kind` on `len` bytes from `start`; false when another
+    /// process holds a lock in the way.
+    pub fn lock(file: &File, kind: i16, start: i64, len: i64) -> Result<bool> {
+        let mut lock = flock(kind, start, len);
+        if unsafe { fcntl(file.as_raw_fd(), F_SETLK, &mut lock as *mut Flock) } == 0 {
+            return Ok(true);
+        }
+        match io::Error::last_os_error() {
+            e if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::PermissionDenied) => Ok(false),
+            e => Err(e.into()),
+        }
+    }
+
+    /// Whether another process holds any lock on `len` bytes from `start`.
+    pub fn is_locked(file: &File, start: i64, len: i64) -> Result<bool> {
+        // Asking about a write lock finds read locks as well.
+        let mut lock = flock(WRITE, start, len);
+        if unsafe { fcntl(file.as_raw_fd(), F_GETLK, &mut lock as *mut Flock) } != 0 {
+            Err(io::Error::last_os_error())?
+        }
+        Ok(lock.l_type != UNLOCK)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use anyhow::Result;
+    use std::fs::File;
+
+    pub const READ: i16 = 0;
+    pub const WRITE: i16 = 1;
+    pub const UNLOCK: i16 = 2;
+
+    pub fn lock(_file: &File, _kind: i16, _start: i64, _len: i64) -> Result<bool> {
+        Ok(true)
+    }
+
+    pub fn is_locked(_file: &File, _start: i64, _len: i64) -> Result<bool> {
+        Ok(false)
+    }
+}
@@ -1,37 +1,195 @@
 use anyhow::{anyhow, Result};
-use db::Database;
-use page::Page;
-use sql::parse_sql;
+use sqlite_starter_rust::{
+    audit::AuditLog,
+    functions::CaseFolding,
+    interrupt,
+    messages::message,
+    output::Format,
+    profile::Profile,
+    record::TextDecoding,
+    shell::{self, Shell},
+    sql::parse_sql,
+    trace::ReadTrace,
+    Database,
+};
 
-mod cell;
-mod db;
-mod page;
-mod record;
-mod sql;
-
-pub const DB_HEADER_SIZE: usize = 100;
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", message("error", &[("message", &format!("{:?}", e))]));
+        std::process::exit(1)
+    }
+}
 
-fn main() -> Result<()> {
+fn run() -> Result<()> {
+    let mut seed = None;
+    let mut format = None;
+    let mut decoding = None;
+    let mut max_rows = None;
+    let mut bail = false;
+    let mut raw_blobs = false;
+    let mut case_folding = CaseFolding::Ascii;
+    let mut audit_log = None;
+    let mut smart_rendering = false;
+    let mut scan_threads = None;
+    let mut mapped = false;
+    let mut partial = false;
+    let mut profile = None;
+    let mut into = None;
+    let mut read_trace = None;
+    let mut tables = None;
+    let mut force = false;
+    let mut positional = Vec::new();
     let mut args = std::env::args().skip(1);
-    let db_path = args
-        .next()
-        .ok_or(anyhow!("Missing <database path> and <command>"))?;
-    let command = args.next().ok_or(anyhow!("Missing <command>"))?;
+    let missing = |option: &str| anyhow!(message("missing-value", &[("option", &option)]));
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let value = args.next().ok_or_else(|| missing("--seed"))?;
+                seed = Some(value.parse::<u64>()?);
+            }
+            "--format" => {
+                let value = args.next().ok_or_else(|| missing("--format"))?;
+                format = Some(value.parse()?);
+            }
+            "--decode" => {
+                let value = args.next().ok_or_else(|| missing("--decode"))?;
+                decoding = Some(value.parse::<TextDecoding>()?);
+            }
+            "--max-rows" => {
+                let value = args.next().ok_or_else(|| missing("--max-rows"))?;
+                max_rows = Some(value.parse::<usize>()?);
+            }
+            "--scan-parallel" => {
+                let value = args.next().ok_or_else(|| missing("--scan-parallel"))?;
+                scan_threads = Some(value.parse::<usize>()?);
+            }
+            "--profile" => {
+                let value = args.next().ok_or_else(|| missing("--profile"))?;
+                profile = Some(Profile::find(&value)?);
+            }
+            "--into" => {
+                let value = args.next().ok_or_else(|| missing("--into"))?;
+                into = Some(value);
+            }
+            "--trace-reads" => {
+                let value = args.next().ok_or_else(|| missing("--trace-reads"))?;
+                read_trace = Some(ReadTrace::create(&value)?);
+            }
+            "--tables" => {
+                let value = args.next().ok_or_else(|| missing("--tables"))?;
+                tables = Some(value.split(',').map(|t| t.trim().to_string()).collect::<Vec<_>>());
+            }
+            "--audit-log" => {
+                let value = args.next().ok_or_else(|| missing("--audit-log"))?;
+                audit_log = Some(AuditLog::open(&value)?);
+            }
+            // Diverges from sqlite on purpose: opt-in only.
+            "--unicode-case" => case_folding = CaseFolding::Unicode,
+            "--smart-render" => smart_rendering = true,
+            "--force" => force = true,
+            "--mmap" => mapped = true,
+            "--partial" => partial = true,
+            "--raw-blob" => raw_blobs = true,
+            "--bail" => bail = true,
+            "--continue-on-error" => bail = false,
+            _ => positional.push(arg),
+        }
+    }
 
-    let db = Database::load_db(db_path)?;
+    // `lint DB` checks the schema of DB, failing when it finds anything.
+    let lint = positional.len() == 2 && positional[0] == "lint";
+    // `extract DB --tables A,B OUT` copies tables A and B of DB into a new OUT.
+    let extract = positional.len() == 3 && positional[0] == "extract";
+    // `checksum DB [TABLE]` prints a hash of the rows of TABLE, or of every table.
+    let checksum = (2..=3).contains(&positional.len()) && positional[0] == "checksum";
+    // `header set DB FIELD VALUE` changes a field of DB's header.
+    let header = positional.len() == 5 && positional[0] == "header" && positional[1] == "set";
+    let skip = if header { 2 } else { (lint || extract || checksum) as usize };
+    let mut positional = positional.into_iter().skip(skip);
+    let db_path = positional.next().ok_or_else(|| anyhow!(message("missing-database", &[])))?;
 
-    match command.as_str() {
-        ".dbinfo" => {
-            db.info()?;
+    let mut db = match (mapped, partial) {
+        (true, true) => Err(anyhow!(message("mmap-and-partial", &[])))?,
+        #[cfg(unix)]
+        (true, false) => Database::load_db_mapped(db_path)?,
+        #[cfg(not(unix))]
+        (true, false) => Err(anyhow!(message("mmap-unix-only", &[])))?,
+        (false, true) => Database::load_db_partial(db_path)?,
+        (false, false) => Database::load_db(db_path)?,
+    };
+    if let Some(seed) = seed {
+        db.set_seed(seed);
+    }
+    if let Some(decoding) = decoding {
+        db.set_text_decoding(decoding);
+    }
+    db.set_max_rows(max_rows);
+    db.set_case_folding(case_folding);
+    db.set_audit_log(audit_log);
+    db.set_read_trace(read_trace);
+    // Profiles are for reading by eye: their times show in table output.
+    db.set_smart_rendering(smart_rendering || profile.is_some());
+    if let Some(profile) = profile {
+        let missing = profile.missing_tables(&db);
+        if !missing.is_empty() {
+            eprintln!("{}", message("profile-tables-missing", &[("profile", &profile.name), ("tables", &missing.join(", "))]));
         }
-        ".tables" => {
-            db.tables()?;
+    }
+    db.set_profile(profile);
+    db.set_scan_parallel(scan_threads);
+
+    interrupt::install();
+    if lint {
+        let lints = db.lint()?;
+        for lint in &lints {
+            println!("{}", lint);
         }
-        sql => {
-            let statement = parse_sql(sql)?;
-            db.execute_statement(&statement)?;
+        if !lints.is_empty() {
+            std::process::exit(1)
         }
+        return Ok(());
+    }
+    if header {
+        let field = positional.next().unwrap();
+        db.set_header(&field, &positional.next().unwrap(), force)?;
+        return Ok(());
+    }
+    if checksum {
+        for checksum in db.checksums(positional.next().as_deref())? {
+            println!("{}", checksum);
+        }
+        return Ok(());
+    }
+    if extract {
+        let tables = tables.ok_or_else(|| anyhow!(message("extract-needs-tables", &[])))?;
+        db.extract_tables(&tables, &positional.next().unwrap())?;
+        return Ok(());
+    }
+    if let Some(path) = into {
+        let command = positional.next().ok_or_else(|| anyhow!(message("into-needs-select", &[])))?;
+        db.select_into(&parse_sql(&command)?, &path, "result")?;
+        return Ok(());
+    }
+    let format = format.unwrap_or(match profile {
+        Some(_) => Format::Table,
+        None => Format::List,
+    });
+    let mut shell = Shell::new(db, format);
+    shell.set_bail(bail);
+    shell.set_raw_blobs(raw_blobs);
+    let result = match positional.next() {
+        Some(command) => shell.execute(&command),
+        None => shell.repl(),
+    };
+    match result {
+        // The reader went away (`| head`); that is not an error worth reporting.
+        Err(e) if shell::is_broken_pipe(&e) => Ok(()),
+        Err(e) if !e.is::<shell::Bailed>() => Err(e),
+        _ if shell.failures() > 0 => {
+            // Dropping the shell rolls back a transaction left open.
+            drop(shell);
+            std::process::exit(1)
+        }
+        _ => Ok(()),
     }
-
-    Ok(())
 }
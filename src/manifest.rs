@@ -0,0 +1,95 @@
+//! Manifests of exported files: what each file holds and where it came from,
+//! so that a consumer can check it got every row and notice the source changing.
+
+use crate::{output::json_string, sha256::Sha256};
+use anyhow::Result;
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+};
+
+/// Passes writes through to `inner`, hashing and counting the bytes.
+pub struct Checksummed<W> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> Checksummed<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Number of bytes written so far.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// SHA-256 of the bytes written so far, in hex.
+    pub fn sha256(&self) -> String {
+        self.hasher.clone().hex()
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// One exported file. `schema_cookie` and `change_counter` come from the
+/// source's header: a consumer seeing them differ between the files of one
+/// export knows the database changed in between.
+pub struct Entry {
+    pub table: String,
+    pub file: String,
+    pub format: String,
+    pub rows: usize,
+    pub bytes: u64,
+    pub sha256: String,
+    pub schema_cookie: u32,
+    pub change_counter: u32,
+    /// Cursor key the export resumed after, if any.
+    pub after: Option<String>,
+    /// Key of the last row in the file.
+    pub last: Option<String>,
+    /// Whether the export reached the end of the table, or of its shard.
+    pub complete: bool,
+}
+
+impl Entry {
+    /// Appends the entry to the manifest at `path` as one line of JSON.
+    pub fn append_to(&self, path: &str) -> Result<()> {
+        let key = |key: &Option<String>| key.as_deref().map_or("null".to_string(), json_string);
+        let line = format!(
+            "{{\"table\":{},\"file\":{},\"format\":{},\"rows\":{},\"bytes\":{},\"sha256\":{},\
+             \"schema_cookie\":{},\"change_counter\":{},\"after\":{},\"last\":{},\"complete\":{}}}\n",
+            json_string(&self.table),
+            json_string(&self.file),
+            json_string(&self.format),
+            self.rows,
+            self.bytes,
+            json_string(&self.sha256),
+            self.schema_cookie,
+            self.change_counter,
+            key(&self.after),
+            key(&self.last),
+            self.complete,
+        );
+        let mut manifest = OpenOptions::new().create(true).append(true).open(path)?;
+        manifest.write_all(line.as_bytes())?;
+        manifest.sync_all()?;
+        Ok(())
+    }
+}
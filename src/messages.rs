@@ -0,0 +1,375 @@
+//! The messages users read, by key, in the language the environment asks
+//! for: `SQLITE_LITE_LANG`, or else `LC_ALL`, `LC_MESSAGES` or `LANG` as
+//! gettext looks them up. A message is a template whose `{name}`s are filled
+//! in. English is built in, and so is German; a file `LANG.messages` in the
+//! directory `SQLITE_LITE_MESSAGES` names adds another language or replaces
+//! a built-in one, one `key = template` to a line. A key a catalog leaves
+//! out reads in English.
+
+use std::{collections::HashMap, fmt::Display, fs, path::Path, sync::OnceLock};
+
+const ENGLISH: &[(&str, &str)] = &[
+    ("error", "Error: {message}"),
+    ("error-near-line", "Error: near line {line} (statement {statement}): {message}"),
+    ("no-such-table", "no such table: {name}"),
+    ("no-such-column", "no such column: {column}"),
+    ("did-you-mean", "did you mean `{name}`?"),
+    ("malformed", "database disk image is malformed: {reason}"),
+    ("truncated", "database is truncated: expected {expected} bytes but the file has {actual}"),
+    ("parse-error", "error at {line}:{column}: expected {expected}"),
+    ("cursor-invalidated", "the database changed under an open cursor; position it again"),
+    ("unknown-command", "unknown command or invalid arguments: \"{command}\". Enter \".help\" for help"),
+    ("import-too-few", "{path}:{line}: expected {expected} columns but found {found} - filling the rest with NULL"),
+    ("import-too-many", "{path}:{line}: expected {expected} columns but found {found} - extras ignored"),
+    ("unknown-format", "unknown output format: {format} (expected list, csv, json, table or insert)"),
+    ("wrong-argument-count", "wrong number of arguments to function {function}()"),
+    ("no-such-function", "no such function: {function}"),
+    ("integer-overflow", "integer overflow"),
+    ("database-locked", "database is locked"),
+    ("corrupt-journal", "the journal header is corrupt"),
+    ("read-past-end", "read past the end of the database at offset {offset}"),
+    ("readonly-database", "attempt to write a readonly database"),
+    ("interrupted", "interrupted"),
+    ("unterminated-field", "line {line}: unterminated quoted field"),
+    ("cannot-create", "cannot create \"{path}\": {error}"),
+    ("cannot-open", "cannot open \"{path}\": {error}"),
+    ("trace-not-a-page", "{path}:{line}: not a page number: {text}"),
+    ("unknown-profile", "unknown profile: {name} (expected one of {names})"),
+    ("no-such-profile-query", "no query {name} in profile {profile}"),
+    ("serial-type-needs-format-4", "serial type {serial_type} needs schema format 4"),
+    ("reserved-serial-type", "reserved serial type {serial_type}"),
+    ("record-past-end", "record starts past the end of the page"),
+    ("record-header-size", "record header size {size} is out of bounds"),
+    ("record-too-short", "record is shorter than its header describes"),
+    ("value-out-of-range", "value {index} out of range: the record has {count}"),
+    ("unsupported-text-encoding", "unsupported text encoding {encoding}"),
+    ("invalid-text", "invalid {encoding} in text value: {error}"),
+    ("unknown-text-decoding", "unknown text decoding: {decoding} (expected strict, lossy or blob)"),
+    ("cannot-read-as", "cannot read {value_type} value as {expected}"),
+    ("integer-does-not-fit", "integer {value} does not fit in {expected}"),
+    ("in-column", "column {column}: {error}"),
+    ("column-out-of-range", "column index {index} out of range: the row has {count} columns"),
+    ("varint-too-long", "Varint is too long"),
+    ("varint-incomplete", "Varint is incomplete"),
+    ("too-many-variables", "too many SQL variables"),
+    ("expected-one-of", "one of {tokens}"),
+    ("missing-value", "Missing value for {option}"),
+    ("missing-database", "Missing <database path>"),
+    ("mmap-and-partial", "--mmap and --partial cannot be used together"),
+    ("mmap-unix-only", "--mmap is only available on Unix"),
+    ("profile-tables-missing", "warning: tables missing for profile {profile}: {tables}"),
+    ("extract-needs-tables", "extract needs --tables to choose what to copy"),
+    ("into-needs-select", "--into needs a SELECT to run"),
+    ("usage", "Usage: {usage}"),
+    ("cannot-open-database", "unable to open database \"{path}\": {error}"),
+    ("no-profile", "no profile: open the database with --profile NAME"),
+    ("not-a-page-number", "not a page number: {text}"),
+    ("jobs-not-positive", "--jobs needs a number above 0, not {value}"),
+    ("manifest-needs-output", "--manifest describes the file written with --output"),
+    ("jobs-need-output", "--jobs names the files it writes after the --output file"),
+    ("cursor-file-with-jobs", "--cursor-file cannot resume an export written with --jobs"),
+    ("unterminated-substitution", "unterminated $( in: {command}"),
+    ("no-such-variable", "no such variable: {name}"),
+    ("cursor-file-table-mode", "--cursor-file needs rows written as they are read, which table mode does not do"),
+    ("cannot-read", "cannot read \"{path}\": {error}"),
+    ("jobs-need-file", "--jobs opens the database file once per job; there is none"),
+    ("export-job-panicked", "an export job panicked"),
+    ("empty-file", "{path}: empty file"),
+    ("cannot-run", "cannot run \"{command}\": {error}"),
+    ("attach-in-transaction", "cannot ATTACH database within transaction"),
+    ("database-in-use", "database {name} is already in use"),
+    ("no-such-database", "no such database: {name}"),
+    ("not-a-backup-state", "{path} is not a backup state file"),
+    ("backup-state-page-size", "{path} holds the pages of a database with {page_size}-byte pages"),
+    ("backup-in-transaction", "cannot back up a database in the middle of a transaction that wrote to it"),
+    ("backup-onto-itself", "cannot back up {path} onto itself"),
+    ("backup-wal-not-checkpointed", "cannot back up {path}: its WAL file holds changes not yet checkpointed"),
+    ("backup-hot-journal", "cannot back up {path}: a crashed writer left a journal to roll back first"),
+    ("backup-kept-changing", "cannot back up: the database kept changing while it was copied"),
+    ("not-a-delta", "{path} is not a delta file"),
+    ("delta-cut-short", "{path} is not a delta file: it is cut short"),
+    ("delta-wrong-base", "{path} does not hold the database {delta} was taken against"),
+    ("cannot-read-view", "cannot read view {name}: {error}"),
+    ("malformed-view", "malformed view {name}: {sql}"),
+    ("circular-view", "view {name} is circularly defined"),
+    ("view-column-count", "expected {expected} columns for '{name}' but got {found}"),
+    ("order-by-no-column", "{term} ORDER BY term does not match any column in the result set"),
+    ("compound-column-count", "SELECTs to the left and right of {operator} do not have the same number of result columns"),
+    ("datatype-mismatch", "datatype mismatch"),
+    ("order-by-out-of-range", "{term} ORDER BY term out of range - should be between 1 and {count}"),
+    ("into-needs-select-statement", "only a SELECT can be written into a new database"),
+    ("parameter-out-of-range", "parameter {index} out of range: the statement has {count}"),
+    ("no-such-parameter", "no such parameter: {name}"),
+    ("extract-not-a-table", "cannot extract {name}: only tables can be extracted"),
+    ("borrowed-without-rowid", "cannot scan {table} in place: it is a WITHOUT ROWID table"),
+    ("carve-without-rowid", "cannot carve {table}: it is a WITHOUT ROWID table"),
+    ("rowid-out-of-order", "rowid {rowid} comes after rowid {previous}"),
+    ("index-cell-too-big", "an index cell does not fit on a page"),
+    ("cells-do-not-fit-leaf", "page {page}: cells do not fit on a new leaf"),
+    ("cells-do-not-fit-interior", "page {page}: cells do not fit on a new interior page"),
+    ("unknown-header-field", "unknown header field {field}; it can be one of {names}"),
+    ("header-value-out-of-range", "{field} must be an integer from {low} to {high}, not {value}"),
+    ("header-field-needs-force", "changing {field} can make the database unreadable; use --force to change it anyway"),
+    ("header-wal", "cannot set header fields: WAL databases are not supported yet"),
+    ("cell-past-end", "cell runs past the end of the page"),
+    ("record-undescribed-bytes", "{bytes} bytes of the record are not described by its header"),
+    ("unknown-repair", "unknown repair: {repair} (expected cell-count, cell-pointers or freeblocks)"),
+    ("neither-cell-nor-free", "page {page}: the bytes at {offset} are neither a cell nor free space"),
+    ("page-not-in-database", "page {page} is not in the database"),
+    ("repair-wal", "cannot repair pages of WAL databases: they are not supported yet"),
+    ("not-a-btree-page", "page {page} is not a b-tree page (type {kind})"),
+    ("nothing-to-repair", "page {page}: nothing to repair: {reason}"),
+    ("cell-count-matches", "the cell count matches the cell pointers"),
+    ("cell-pointers-match", "the cell pointers point at every cell"),
+    ("freeblocks-sound", "the freeblock list is sound"),
+    ("empty-freeblocks-first", "page {page}: {error}; empty the freeblock list first"),
+    ("repair-index-pointers", "page {page}: rebuilding the cell pointers of index pages is not supported yet"),
+    ("duplicate-rowid-cells", "page {page}: two cells hold the same rowid"),
+    ("pointers-do-not-fit", "page {page}: {count} cell pointers do not fit before the cells"),
+    ("repair-cell-count-first", "page {page}: the cell pointers do not match the cell count; repair that first"),
+    ("fragmented-do-not-fit", "page {page}: {bytes} fragmented bytes do not fit in the page header"),
+    ("cannot-create-backup", "cannot create backup \"{path}\": {error}"),
+    ("freeblock-out-of-order", "freeblock at {offset} is out of order"),
+    ("freeblock-past-end", "freeblock at {offset} is past the end of the page"),
+    ("freeblock-size", "freeblock at {offset} has a size of {size}"),
+    ("unknown-page-type", "page {page} has unknown type {kind}"),
+    ("pointers-past-end", "page {page}: the cell pointers run past the end of the page"),
+    ("cell-starts-past-end", "page {page}: a cell starts past the end of the page"),
+    ("page-not-in-tree", "page {page} does not belong in the b-tree at page {root}"),
+    ("cell-runs-past-end", "page {page}: a cell runs past the end of the page"),
+    ("overflow-ends-early", "page {page}: an overflow chain ends early"),
+    ("btree-too-deep", "b-tree is more than {depth} pages deep at page {page}"),
+    ("page-in-tree-twice", "page {page} is in the b-tree more than once"),
+    ("rowid-not-on-leaf", "page {page}: rowid {rowid} is not on its leaf"),
+    ("rowid-taken", "rowid {rowid} is already in the table"),
+    ("wrong-page-type-in-table", "page {page} has type {kind} inside a table b-tree"),
+    ("tree-at-page-too-deep", "the b-tree at page {root} is too deep"),
+    ("cells-do-not-fit-split", "page {page}: cells do not fit after a split"),
+    ("page-does-not-point", "page {parent} does not point to page {page}"),
+    ("trunk-past-end", "freelist trunk {page} is past the end of the file"),
+    ("trunk-leaf-count", "freelist trunk {page} holds {count} leaves"),
+    ("freelist-leaf-past-end", "freelist leaf {page} is past the end of the file"),
+    ("page-not-under-root", "page {page} is not in the b-tree at page {root}"),
+    ("cells-do-not-fit-page", "page {page}: the cells do not fit on one page"),
+    ("freeblocks-overlap", "page {page}: freeblocks overlap"),
+    ("freeblocks-out-of-order", "page {page}: the freeblock list is out of order"),
+    ("trunk-out-of-place", "freelist trunk {page} is out of place"),
+    ("page-used-twice", "page {page} is used twice"),
+    ("page-past-end", "page {page} is past the end of the file"),
+    ("btree-too-deep-at", "the b-tree is too deep at page {page}"),
+    ("row-without-rowid", "{table} has a row without a rowid"),
+    ("not-null-failed", "NOT NULL constraint failed: {table}.{column}"),
+    ("reserved-name", "object name reserved for internal use: {name}"),
+    ("index-name-taken", "there is already an index named {name}"),
+    ("view-exists", "view {name} already exists"),
+    ("table-exists", "table {name} already exists"),
+    ("duplicate-column", "duplicate column name: {column}"),
+    ("primary-key-missing", "PRIMARY KEY missing on table {table}"),
+    ("index-exists", "index {name} already exists"),
+    ("table-name-taken", "there is already a table named {name}"),
+    ("unique-failed", "UNIQUE constraint failed: {columns}"),
+    ("table-not-alterable", "table {table} may not be altered"),
+    ("view-not-alterable", "view {name} may not be altered"),
+    ("name-taken", "there is already another table or index with this name: {name}"),
+    ("value-count", "table {table} has {columns} columns but {values} values were supplied"),
+    ("unique-column-failed", "UNIQUE constraint failed: {table}.{column}"),
+    ("cannot-modify-view", "cannot modify {name} because it is a view"),
+    ("cannot-statement", "cannot {statement} {table}: {reason}"),
+    ("in-cell", "cell {cell} of page {page}: {error}"),
+    ("not-an-index-page", "page {page} is not an index b-tree page"),
+    ("not-a-table-page", "page {page} is not a table b-tree page"),
+    ("is-without-rowid", "{table} is a WITHOUT ROWID table"),
+    ("resume-rowid-table", "cannot resume {table} after {key}: its rows are keyed by rowid"),
+    ("resume-without-rowid", "cannot resume {table} after {key}: it is a WITHOUT ROWID table"),
+    ("index-on-without-rowid", "{index} indexes a WITHOUT ROWID table"),
+    ("no-such-index", "no such index: {name}"),
+    ("invalid-row-key", "invalid row key: {key}"),
+    ("aggregate-misuse", "misuse of aggregate function {function}()"),
+    ("cell-outside-content", "it starts at {offset}, outside the cell content area"),
+    ("ambiguous-column", "ambiguous column name: {column}"),
+    ("pragma-needs-argument", "PRAGMA {pragma} needs a table or index name"),
+    ("unsupported-pragma", "unsupported pragma: {pragma}"),
+    ("unknown-schema-type", "sqlite_schema has an entry of unknown type {kind}"),
+    ("schema-entry-layout", "sqlite_schema entry {rowid} has a layout that is not supported yet"),
+    ("unsupported-schema-format", "unsupported schema format {format}"),
+    ("create-view-unsupported", "cannot CREATE VIEW {name}: creating views is not supported yet"),
+    ("page-past-end-of-file", "page {page} is past the end of the file, which has {size} bytes"),
+    ("overflowing-rows-unsupported", "rows that spill onto overflow pages are not supported yet"),
+    ("temp-tables-unsupported", "TEMP tables are not supported yet"),
+    ("autoincrement-unsupported", "AUTOINCREMENT columns are not supported yet"),
+    ("constraint-indexes-unsupported", "UNIQUE and PRIMARY KEY constraints that need an index are not supported yet"),
+    ("partial-indexes-unsupported", "partial indexes are not supported yet"),
+    ("without-rowid-indexes-unsupported", "indexes on WITHOUT ROWID tables are not supported yet"),
+    ("utf16-indexes-unsupported", "indexes in UTF-16 databases are not supported yet"),
+    ("overflowing-definitions-unsupported", "definitions that spill onto overflow pages are not supported yet"),
+    ("schema-outgrows-page-1", "schemas that outgrow page 1 are not supported yet"),
+    ("without-rowid-unsupported", "WITHOUT ROWID tables are not supported yet"),
+    ("strict-unsupported", "STRICT tables are not supported yet"),
+    ("generated-columns-unsupported", "tables with generated columns are not supported yet"),
+    ("check-unsupported", "CHECK constraints are not supported yet"),
+    ("triggers-unsupported", "triggers are not supported yet"),
+    ("collation-unsupported", "the {collation} collation is not supported yet"),
+    ("indexed-tables-unsupported", "tables with indexes such as {index} are not supported yet"),
+    ("partial-index-update-unsupported", "partial indexes such as {index} are not supported yet"),
+    ("index-update-unsupported", "indexes such as {index} are not supported yet"),
+    ("vacuum-wal", "cannot VACUUM: WAL databases are not supported yet"),
+    ("vacuum-auto-vacuum", "cannot VACUUM: auto-vacuum databases are not supported yet"),
+    ("write-wal", "cannot write to {table}: WAL databases are not supported yet"),
+    ("update-rowid", "cannot UPDATE {table}: changing the rowid is not supported yet"),
+    ("key-on-column", "the key on {column}"),
+    ("update-indexed-column", "cannot UPDATE {table}.{column}: it is covered by {index}, and updating indexes is not supported yet"),
+    ("database-full", "database or disk is full"),
+    ("grow-auto-vacuum", "cannot write: growing auto-vacuum databases is not supported yet"),
+    ("serialize-overflowing-cells", "page {page}: cells that spill onto overflow pages cannot be serialized"),
+    ("overflowing-row-on-page", "page {page}: rows that spill onto overflow pages are not supported yet"),
+    ("rowid-search-on-index", "only table b-trees can be searched by rowid"),
+    ("key-search-on-table", "table b-trees can only be searched by rowid"),
+    ("not-a-database", "file is not a database"),
+    ("index-entry-without-rowid", "index entry does not end in a rowid"),
+    ("invalid-table-schema", "Invalid table schema"),
+    ("unreadable-header", "unable to read database header"),
+    ("vacuum-in-transaction", "cannot VACUUM from within a transaction"),
+    ("nested-transaction", "cannot start a transaction within a transaction"),
+    ("commit-without-transaction", "cannot commit - no transaction is active"),
+    ("rollback-without-transaction", "cannot rollback - no transaction is active"),
+];
+
+const GERMAN: &str = include_str!("../messages/de.messages");
+
+/// The templates of one language, by key.
+struct Catalog {
+    templates: HashMap<String, String>,
+}
+
+impl Catalog {
+    fn from_env() -> Self {
+        let language = ["SQLITE_LITE_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+            .map(|locale| language(&locale))
+            .unwrap_or_default();
+        let mut templates = HashMap::new();
+        if language == "de" {
+            templates.extend(parse_catalog(GERMAN));
+        }
+        if let Ok(dir) = std::env::var("SQLITE_LITE_MESSAGES") {
+            if let Ok(text) = fs::read_to_string(Path::new(&dir).join(format!("{}.messages", language))) {
+                templates.extend(parse_catalog(&text));
+            }
+        }
+        Catalog { templates }
+    }
+}
+
+/// The language part of a locale such as `de_DE.UTF-8`; `C` and `POSIX` are
+/// English.
+fn language(locale: &str) -> String {
+    let language = locale.split(['_', '.', '@']).next().unwrap_or_default().to_lowercase();
+    match language.as_str() {
+        "c" | "posix" => "en".to_string(),
+        _ => language,
+    }
+}
+
+/// The `key = template` lines of a catalog file. Blank lines and those
+/// starting with `#` are left out.
+fn parse_catalog(text: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, template)| (key.trim().to_string(), template.trim().to_string()))
+}
+
+/// [`message`] with its arguments written `name = value`, as in
+/// `msg!("no-such-index", name = index)`.
+macro_rules! msg {
+    ($key:literal $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::messages::message($key, &[$((stringify!($name), &$value as &dyn std::fmt::Display)),*])
+    };
+}
+pub(crate) use msg;
+
+/// Message `key` with each `{name}` of `args` filled in, in the language of
+/// the environment.
+pub fn message(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    static CATALOG: OnceLock<Catalog> = OnceLock::new();
+    let catalog = CATALOG.get_or_init(Catalog::from_env);
+    let template = match catalog.templates.get(key) {
+        Some(template) => template.as_str(),
+        None => ENGLISH.iter().find(|(k, _)| *k == key).map_or(key, |(_, template)| template),
+    };
+    // In one pass, so that a value is never read as a template itself.
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some((before, after)) = rest.split_once('{') {
+        let Some((name, after)) = after.split_once('}') else { break };
+        text.push_str(before);
+        match args.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => text.push_str(&value.to_string()),
+            None => text.push_str(&format!("{{{}}}", name)),
+        }
+        rest = after;
+    }
+    text.push_str(rest);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_catalog, ENGLISH, GERMAN};
+    use std::{collections::BTreeSet, fs, path::Path};
+
+    /// The `{name}`s of a template.
+    fn placeholders(template: &str) -> BTreeSet<&str> {
+        template.split('{').skip(1).filter_map(|rest| rest.split_once('}')).map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn german_translates_every_message_with_its_placeholders() {
+        let german = parse_catalog(GERMAN).collect::<Vec<_>>();
+        for (key, template) in &german {
+            let english = ENGLISH.iter().find(|(k, _)| k == key).map(|(_, t)| t);
+            let english = english.unwrap_or_else(|| panic!("de.messages has a key English does not: {}", key));
+            assert_eq!(placeholders(template), placeholders(english), "{}", key);
+        }
+        for (key, _) in ENGLISH {
+            assert!(german.iter().any(|(k, _)| k == key), "de.messages leaves out {}", key);
+        }
+    }
+
+    /// Every key the sources pass to `msg!` or `message` has an English
+    /// template, and every template is looked up somewhere.
+    #[test]
+    fn every_key_in_use_has_a_template() {
+        fn sources(dir: &Path, found: &mut Vec<String>) {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    sources(&path, found);
+                } else if path.extension().is_some_and(|ext| ext == "rs") {
+                    found.push(fs::read_to_string(path).unwrap());
+                }
+            }
+        }
+        let mut texts = Vec::new();
+        sources(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut texts);
+        let mut used = BTreeSet::new();
+        for text in &texts {
+            for call in ["msg!(", "message("] {
+                for rest in text.split(call).skip(1) {
+                    let Some(rest) = rest.trim_start().strip_prefix('"') else { continue };
+                    let key = rest.split('"').next().unwrap();
+                    if key.is_empty() || !key.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+                        continue;
+                    }
+                    assert!(ENGLISH.iter().any(|(k, _)| *k == key), "no English template for {}", key);
+                    used.insert(key);
+                }
+            }
+        }
+        for (key, _) in ENGLISH {
+            assert!(used.contains(key), "{} is never looked up", key);
+        }
+    }
+}
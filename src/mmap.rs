@@ -0,0 +1,160 @@
+//! Reading a database file through a memory map rather than a system call
+//! per page. The map follows the file as it grows and shrinks: it is
+//! redrawn when a read runs past its end and before every statement, so it
+//! covers the file as it stood when the statement began. A file another
+//! process cuts short while a statement is reading it can still fault, so
+//! the map is for read-heavy use of databases nobody vacuums meanwhile, and
+//! only used when asked for with [`crate::Database::load_db_mapped`].
+
+use crate::{error::Error, messages::msg, source::PageSource};
+use anyhow::{anyhow, Result};
+use std::{fs::File, io, os::unix::io::AsRawFd, ptr, slice, sync::RwLock};
+
+const PROT_READ: i32 = 1;
+const MAP_SHARED: i32 = 1;
+const MAP_FAILED: *mut u8 = !0 as *mut u8;
+
+extern "C" {
+    fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut u8;
+    fn munmap(addr: *mut u8, len: usize) -> i32;
+}
+
+/// The bytes of the file mapped into memory; none for an empty file, which
+/// cannot be mapped.
+struct Map {
+    addr: *mut u8,
+    len: usize,
+}
+
+// The mapping is read only and shared with the file, which may be read from
+// any thread.
+unsafe impl Send for Map {}
+unsafe impl Sync for Map {}
+
+impl Map {
+    fn new(file: &File) -> Result<Self> {
+        let len = usize::try_from(file.metadata().map_err(Error::Io)?.len())?;
+        if len == 0 {
+            return Ok(Map {
+                addr: ptr::null_mut(),
+                len,
+            });
+        }
+        let addr = unsafe { mmap(ptr::null_mut(), len, PROT_READ, MAP_SHARED, file.as_raw_fd(), 0) };
+        if addr == MAP_FAILED {
+            Err(Error::Io(io::Error::last_os_error()))?
+        }
+        Ok(Map { addr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self.len {
+            0 => &[],
+            len => unsafe { slice::from_raw_parts(self.addr, len) },
+        }
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { munmap(self.addr, self.len) };
+        }
+    }
+}
+
+/// A database file read through a memory map. Writes go to the file, where
+/// the map sees them straight away.
+pub struct Mapped {
+    file: File,
+    map: RwLock<Map>,
+}
+
+impl Mapped {
+    pub fn new(file: File) -> Result<Self> {
+        let map = RwLock::new(Map::new(&file)?);
+        Ok(Mapped { file, map })
+    }
+
+    /// Maps the file again, at the size it has now.
+    fn remap(&self) -> Result<()> {
+        let mut map = self.map.write().unwrap();
+        *map = Map::new(&self.file)?;
+        Ok(())
+    }
+
+    /// Copies the bytes at `offset` into `buf`; false when the map ends first.
+    fn copy_at(&self, buf: &mut [u8], offset: u64) -> Result<bool> {
+        self.lend(offset, buf.len(), &mut |found| {
+            buf.copy_from_slice(found);
+            Ok(())
+        })
+    }
+
+    /// Calls `f` with the `len` mapped bytes at `offset`, holding the map in
+    /// place meanwhile; false, without calling it, when the map ends first.
+    fn lend(&self, offset: u64, len: usize, f: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<bool> {
+        let map = self.map.read().unwrap();
+        let start = usize::try_from(offset)?;
+        match start.checked_add(len).and_then(|end| map.bytes().get(start..end)) {
+            Some(found) => f(found).map(|()| true),
+            None => Ok(false),
+        }
+    }
+}
+
+impl PageSource for Mapped {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        if self.copy_at(buf, offset)? {
+            return Ok(());
+        }
+        // The file may have grown since it was mapped.
+        self.remap()?;
+        match self.copy_at(buf, offset)? {
+            true => Ok(()),
+            false => Err(anyhow!(msg!("read-past-end", offset = offset))),
+        }
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> Result<()> {
+        PageSource::write_all_at(&self.file, buf, offset)
+    }
+
+    fn size(&self) -> Result<u64> {
+        PageSource::size(&self.file)
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        // Nothing may be read through the part of the map that goes away.
+        let mut map = self.map.write().unwrap();
+        *map = Map {
+            addr: ptr::null_mut(),
+            len: 0,
+        };
+        PageSource::truncate(&self.file, size)?;
+        *map = Map::new(&self.file)?;
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        PageSource::sync(&self.file)
+    }
+
+    fn pin_snapshot(&self) -> Result<()> {
+        if self.map.read().unwrap().len as u64 != PageSource::size(&self.file)? {
+            self.remap()?;
+        }
+        Ok(())
+    }
+
+    fn with_bytes(&self, offset: u64, len: usize, f: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        if self.lend(offset, len, f)? {
+            return Ok(());
+        }
+        self.remap()?;
+        match self.lend(offset, len, f)? {
+            true => Ok(()),
+            false => Err(anyhow!(msg!("read-past-end", offset = offset))),
+        }
+    }
+}
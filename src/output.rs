@@ -0,0 +1,498 @@
+use crate::{
+    functions::{self, format_real},
+    messages::msg,
+    record::Record,
+    sql::quote_identifier,
+};
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use std::{fmt::Display, io::Write, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+    List,
+    Csv,
+    Json,
+    Table,
+    /// `INSERT INTO` statements for the named table.
+    Insert(String),
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "list" => Ok(Format::List),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "table" => Ok(Format::Table),
+            "insert" => Ok(Format::Insert("table".to_string())),
+            _ => Err(anyhow!(msg!("unknown-format", format = s))),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::List => write!(f, "list"),
+            Format::Csv => write!(f, "csv"),
+            Format::Json => write!(f, "json"),
+            Format::Table => write!(f, "table"),
+            Format::Insert(_) => write!(f, "insert"),
+        }
+    }
+}
+
+/// Sink for the rows of one result set. A new output format only needs an
+/// implementation of this trait and a [`Format`] variant to select it.
+pub trait RowWriter {
+    fn write_header(&mut self, names: &[String]) -> Result<()>;
+    fn write_row(&mut self, row: &[Record]) -> Result<()>;
+    /// Pushes what has been written so far to the underlying output.
+    fn flush(&mut self) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+    /// How to show each column of the rows to come, when smart rendering is
+    /// on. Only the formats meant for reading by eye, table and json, use it.
+    fn set_renderings(&mut self, _renderings: &[Option<Rendering>]) {}
+    /// Whether blobs are written as their bytes, like sqlite3 does, rather
+    /// than as `X'..'` literals. Only list, csv and table output use it.
+    fn set_raw_blobs(&mut self, _raw: bool) {}
+}
+
+/// A friendlier way to show a column's values, chosen by its declared type.
+/// Values it has no other form for are shown as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rendering {
+    /// BOOLEAN or BOOL: 0 and 1 as false and true.
+    Boolean,
+    /// DATE: seconds since the epoch as the day in UTC.
+    Date,
+    /// DATETIME or TIMESTAMP: seconds since the epoch as the time in UTC.
+    DateTime,
+    /// Apple's Core Data time, seconds since 2001 or, in the larger values
+    /// newer iOS versions write, nanoseconds, as the time in UTC.
+    AppleTime,
+    /// WebKit's time, as Chrome keeps it: microseconds since 1601 as the
+    /// time in UTC. Zero stands for never and is shown as it is.
+    WebKitTime,
+    /// Milliseconds since the epoch, as Android keeps them, as the time in UTC.
+    UnixMillis,
+}
+
+impl Rendering {
+    /// The rendering for a column declared as `declared_type`, if it has one.
+    pub fn for_declared_type(declared_type: &str) -> Option<Rendering> {
+        match declared_type.to_ascii_uppercase().as_str() {
+            "BOOLEAN" | "BOOL" => Some(Rendering::Boolean),
+            "DATE" => Some(Rendering::Date),
+            "DATETIME" | "TIMESTAMP" => Some(Rendering::DateTime),
+            _ => None,
+        }
+    }
+
+    /// `value` in the other form, when it has one.
+    fn render(&self, value: &Record) -> Option<String> {
+        let value = match (self, value) {
+            // Safari keeps its times as reals.
+            (Rendering::AppleTime, Record::Float(f)) if f.is_finite() => f.floor() as i64,
+            (_, Record::Float(_) | Record::Text(_) | Record::Blob(_) | Record::Null) => return None,
+            (_, value) => value.integer()?,
+        };
+        match self {
+            Rendering::Boolean => match value {
+                0 => Some("false".to_string()),
+                1 => Some("true".to_string()),
+                _ => None,
+            },
+            Rendering::Date => {
+                let (year, month, day) = civil_from_days(value.div_euclid(86400));
+                Some(format!("{:04}-{:02}-{:02}", year, month, day))
+            }
+            Rendering::DateTime => Some(date_time(value)),
+            Rendering::AppleTime => {
+                let seconds = match value.unsigned_abs() >= APPLE_NANOSECONDS {
+                    true => value.div_euclid(1_000_000_000),
+                    false => value,
+                };
+                Some(date_time(seconds.checked_add(978_307_200)?))
+            }
+            Rendering::WebKitTime => match value {
+                0 => None,
+                value => Some(date_time(value.div_euclid(1_000_000) - 11_644_473_600)),
+            },
+            Rendering::UnixMillis => Some(date_time(value.div_euclid(1000))),
+        }
+    }
+}
+
+/// Apple times this large are nanoseconds: in seconds they would be past
+/// the year 5000.
+const APPLE_NANOSECONDS: u64 = 100_000_000_000;
+
+/// `seconds` since the epoch as the time in UTC.
+fn date_time(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let seconds = seconds.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The year, month and day of the day `days` after 1970-01-01.
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    // Howard Hinnant's civil_from_days, which counts in eras of 400 years.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+
+pub fn writer<'a>(format: &Format, out: impl Write + 'a) -> Box<dyn RowWriter + 'a> {
+    match format {
+        Format::List => Box::new(ListWriter { out, raw_blobs: false }),
+        Format::Csv => Box::new(CsvWriter { out, raw_blobs: false }),
+        Format::Json => Box::new(JsonWriter {
+            out,
+            names: Vec::new(),
+            renderings: Vec::new(),
+            rows: 0,
+        }),
+        Format::Table => Box::new(TableWriter {
+            out,
+            names: Vec::new(),
+            renderings: Vec::new(),
+            rows: Vec::new(),
+            raw_blobs: false,
+        }),
+        Format::Insert(table) => Box::new(InsertWriter {
+            out,
+            table: quote_identifier(table, false),
+        }),
+    }
+}
+
+/// `a|b|c`, one row per line.
+struct ListWriter<W> {
+    out: W,
+    raw_blobs: bool,
+}
+
+impl<W: Write> RowWriter for ListWriter<W> {
+    fn write_header(&mut self, _names: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: &[Record]) -> Result<()> {
+        writeln!(self.out, "{}", row.iter().map(|value| text(value, self.raw_blobs)).join("|"))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn set_raw_blobs(&mut self, raw: bool) {
+        self.raw_blobs = raw;
+    }
+}
+
+/// RFC 4180 style: NULL is an empty field, anything that could confuse a
+/// reader is double-quoted.
+struct CsvWriter<W> {
+    out: W,
+    raw_blobs: bool,
+}
+
+impl<W: Write> RowWriter for CsvWriter<W> {
+    fn write_header(&mut self, _names: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: &[Record]) -> Result<()> {
+        let fields = row.iter().map(|value| match value {
+            Record::Null => String::new(),
+            value => csv_quote(&text(value, self.raw_blobs)),
+        });
+        writeln!(self.out, "{}", fields.format(","))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn set_raw_blobs(&mut self, raw: bool) {
+        self.raw_blobs = raw;
+    }
+}
+
+fn csv_quote(field: &str) -> String {
+    let needs_quote = field.is_empty()
+        || field
+            .bytes()
+            .any(|b| b <= b' ' || b >= 0x7f || b == b'"' || b == b',');
+    if needs_quote {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// An array of objects keyed by column name, one object per line.
+struct JsonWriter<W> {
+    out: W,
+    names: Vec<String>,
+    renderings: Vec<Option<Rendering>>,
+    rows: usize,
+}
+
+impl<W: Write> RowWriter for JsonWriter<W> {
+    fn write_header(&mut self, names: &[String]) -> Result<()> {
+        self.names = names.to_vec();
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: &[Record]) -> Result<()> {
+        let prefix = if self.rows == 0 { "[" } else { ",\n" };
+        let fields = self.names.iter().zip(row).enumerate().map(|(idx, (name, value))| {
+            let rendering = self.renderings.get(idx).copied().flatten();
+            let value = match (rendering, rendering.and_then(|r| r.render(value))) {
+                (Some(Rendering::Boolean), Some(rendered)) => rendered,
+                (_, Some(rendered)) => json_string(&rendered),
+                (_, None) => json_value(value),
+            };
+            format!("{}:{}", json_string(name), value)
+        });
+        write!(self.out, "{}{{{}}}", prefix, fields.format(","))?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.rows > 0 {
+            writeln!(self.out, "]")?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn set_renderings(&mut self, renderings: &[Option<Rendering>]) {
+        self.renderings = renderings.to_vec();
+    }
+}
+
+pub fn json_value(value: &Record) -> String {
+    match value {
+        Record::Null => "null".to_string(),
+        Record::Float(v) if v.is_finite() => format_real(*v),
+        Record::Float(_) => "null".to_string(),
+        Record::Text(s) => json_string(s),
+        Record::Blob(b) => json_string(&b.iter().map(|&b| b as char).collect::<String>()),
+        other => other.to_string(),
+    }
+}
+
+pub fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// One `INSERT INTO table VALUES(...);` per row, values as SQL literals.
+struct InsertWriter<W> {
+    out: W,
+    table: String,
+}
+
+impl<W: Write> RowWriter for InsertWriter<W> {
+    fn write_header(&mut self, _names: &[String]) -> Result<()> {
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: &[Record]) -> Result<()> {
+        // Unlike quote(), sqlite3 writes blob literals in lower case here,
+        // and reals with every digit they need to read back the same.
+        let values = row.iter().map(|value| match value {
+            Record::Blob(b) => format!("X'{}'", b.iter().map(|b| format!("{:02x}", b)).join("")),
+            Record::Float(v) if v.is_nan() => "NULL".to_string(),
+            // Too large for a double, so it reads back as infinity.
+            Record::Float(v) if v.is_infinite() => format!("{}9.0e+999", if *v < 0.0 { "-" } else { "" }),
+            Record::Float(v) => format!("{:?}", v),
+            value => functions::quote(value),
+        });
+        writeln!(self.out, "INSERT INTO {} VALUES({});", self.table, values.format(","))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Box-drawn table; rows are buffered so every column can be padded to its
+/// widest value.
+struct TableWriter<W> {
+    out: W,
+    names: Vec<String>,
+    renderings: Vec<Option<Rendering>>,
+    rows: Vec<Vec<String>>,
+    raw_blobs: bool,
+}
+
+impl<W: Write> RowWriter for TableWriter<W> {
+    fn write_header(&mut self, names: &[String]) -> Result<()> {
+        self.names = names.to_vec();
+        Ok(())
+    }
+
+    fn write_row(&mut self, row: &[Record]) -> Result<()> {
+        self.rows.push(
+            row.iter()
+                .enumerate()
+                .map(|(idx, value)| {
+                    let rendered = self.renderings.get(idx).copied().flatten().and_then(|r| r.render(value));
+                    match (rendered, value) {
+                        (Some(rendered), _) => rendered,
+                        (None, Record::Null) => String::new(),
+                        (None, value) => text(value, self.raw_blobs),
+                    }
+                })
+                .collect(),
+        );
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut widths = self.names.iter().map(|n| width(n)).collect_vec();
+        for row in &self.rows {
+            for (idx, value) in row.iter().enumerate() {
+                let widest = value.lines().map(width).max().unwrap_or(0);
+                widths[idx] = widths[idx].max(widest);
+            }
+        }
+
+        let separator = format!(
+            "+{}+",
+            widths.iter().map(|w| "-".repeat(w + 2)).join("+")
+        );
+        let header = self
+            .names
+            .iter()
+            .zip(&widths)
+            .map(|(name, &w)| {
+                let left = (w - width(name)) / 2;
+                let right = w - width(name) - left;
+                format!(" {}{}{} ", " ".repeat(left), name, " ".repeat(right))
+            })
+            .join("|");
+        writeln!(self.out, "{}", separator)?;
+        writeln!(self.out, "|{}|", header)?;
+        writeln!(self.out, "{}", separator)?;
+
+        let multiline = self.rows.iter().flatten().any(|v| v.contains('\n'));
+        for row in &self.rows {
+            let lines = row.iter().map(|v| v.split('\n').collect_vec()).collect_vec();
+            let height = lines.iter().map(Vec::len).max().unwrap_or(1);
+            for line in 0..height {
+                let cells = lines
+                    .iter()
+                    .zip(&widths)
+                    .map(|(value, &w)| {
+                        let text = value.get(line).copied().unwrap_or_default();
+                        format!(" {}{} ", text, " ".repeat(w - width(text)))
+                    })
+                    .join("|");
+                writeln!(self.out, "|{}|", cells)?;
+            }
+            if multiline {
+                writeln!(self.out, "{}", separator)?;
+            }
+        }
+        if !multiline {
+            writeln!(self.out, "{}", separator)?;
+        }
+
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn set_renderings(&mut self, renderings: &[Option<Rendering>]) {
+        self.renderings = renderings.to_vec();
+    }
+
+    fn set_raw_blobs(&mut self, raw: bool) {
+        self.raw_blobs = raw;
+    }
+}
+
+fn width(s: &str) -> usize {
+    s.chars().count()
+}
+
+/// Text rendering of a value; raw blobs are printed as C strings, like sqlite3.
+fn text(value: &Record, raw_blobs: bool) -> String {
+    match value {
+        Record::Blob(b) if raw_blobs => {
+            let end = b.iter().position(|&b| b == 0).unwrap_or(b.len());
+            String::from_utf8_lossy(&b[..end]).into_owned()
+        }
+        value => value.to_string(),
+    }
+}
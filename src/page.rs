@@ -31,31 +31,31 @@ impl Display for Page {
         match self {
             Page::InteriorIndex { rmptr, cells } => {
                 writeln!(f, "Interior Index Page")?;
-                write!(f, "rightmost pointer: {}\n", rmptr)?;
+                writeln!(f, "rightmost pointer: {}", rmptr)?;
                 for cell in cells {
-                    write!(f, "{}\n", cell)?;
+                    writeln!(f, "{}", cell)?;
                 }
                 Ok(())
             }
             Page::InteriorTable { rmptr, cells } => {
                 writeln!(f, "Interior Table Page")?;
-                write!(f, "rightmost pointer: {}\n", rmptr)?;
+                writeln!(f, "rightmost pointer: {}", rmptr)?;
                 for cell in cells {
-                    write!(f, "{}\n", cell)?;
+                    writeln!(f, "{}", cell)?;
                 }
                 Ok(())
             }
             Page::LeafIndex { cells } => {
                 writeln!(f, "Leaf Index Page")?;
                 for cell in cells {
-                    write!(f, "{}\n", cell)?;
+                    writeln!(f, "{}", cell)?;
                 }
                 Ok(())
             }
             Page::LeafTable { cells } => {
                 writeln!(f, "Leaf Table Page")?;
                 for cell in cells {
-                    write!(f, "{}\n", cell)?;
+                    writeln!(f, "{}", cell)?;
                 }
                 Ok(())
             }
@@ -77,7 +77,9 @@ pub mod schema {
         pub kind: Kind,
         pub name: String,
         pub tbl_name: String,
+        /// 0 for views and triggers, which have no b-tree.
         pub rootpage: usize,
+        /// Empty for the indexes sqlite creates itself.
         pub sql: String,
     }
 }
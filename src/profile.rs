@@ -0,0 +1,159 @@
+//! Profiles of the databases that phone apps keep: the tables they are
+//! recognised by, the columns that hold times in the app's own epoch, and
+//! canned queries for what an examiner usually wants out of them. A profile
+//! renders its columns whether or not they are declared with a type that
+//! smart rendering knows.
+
+use crate::{messages::msg, output::Rendering, Database};
+use anyhow::{anyhow, Result};
+
+pub struct Profile {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Tables every database the app writes has.
+    pub tables: &'static [&'static str],
+    /// How to show columns, by table and column name.
+    pub columns: &'static [(&'static str, &'static str, Rendering)],
+    pub queries: &'static [Query],
+}
+
+/// A canned query, run with `.profile NAME` in the shell.
+pub struct Query {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+pub const PROFILES: &[Profile] = &[
+    Profile {
+        name: "ios-messages",
+        description: "iMessage and SMS history (sms.db)",
+        tables: &["message", "handle", "chat", "chat_message_join", "attachment"],
+        columns: &[
+            ("message", "date", Rendering::AppleTime),
+            ("message", "date_read", Rendering::AppleTime),
+            ("message", "date_delivered", Rendering::AppleTime),
+            ("message", "is_from_me", Rendering::Boolean),
+            ("message", "is_read", Rendering::Boolean),
+            ("attachment", "created_date", Rendering::AppleTime),
+        ],
+        queries: &[
+            Query {
+                name: "messages",
+                description: "messages with the address they were sent to or from, oldest first",
+                sql: "SELECT message.ROWID, handle.id, message.service, message.is_from_me, message.date, message.text \
+                      FROM message JOIN handle ON message.handle_id = handle.ROWID ORDER BY message.date",
+            },
+            Query {
+                name: "chats",
+                description: "conversations and how many messages each has",
+                sql: "SELECT chat.ROWID, chat.chat_identifier, chat.display_name, count(*) \
+                      FROM chat JOIN chat_message_join ON chat_message_join.chat_id = chat.ROWID \
+                      GROUP BY chat.ROWID",
+            },
+            Query {
+                name: "attachments",
+                description: "attached files, oldest first",
+                sql: "SELECT ROWID, created_date, mime_type, filename, total_bytes FROM attachment ORDER BY created_date",
+            },
+        ],
+    },
+    Profile {
+        name: "ios-safari",
+        description: "Safari browsing history (History.db)",
+        tables: &["history_items", "history_visits"],
+        columns: &[("history_visits", "visit_time", Rendering::AppleTime)],
+        queries: &[
+            Query {
+                name: "visits",
+                description: "every visit with its page, oldest first",
+                sql: "SELECT history_visits.visit_time, history_items.url, history_visits.title \
+                      FROM history_visits JOIN history_items ON history_visits.history_item = history_items.id \
+                      ORDER BY history_visits.visit_time",
+            },
+            Query {
+                name: "top-sites",
+                description: "pages by how often they were visited",
+                sql: "SELECT url, visit_count FROM history_items ORDER BY visit_count DESC",
+            },
+        ],
+    },
+    Profile {
+        name: "android-chrome",
+        description: "Chrome browsing history (History)",
+        tables: &["urls", "visits", "downloads", "keyword_search_terms"],
+        columns: &[
+            ("urls", "last_visit_time", Rendering::WebKitTime),
+            ("urls", "hidden", Rendering::Boolean),
+            ("visits", "visit_time", Rendering::WebKitTime),
+            ("downloads", "start_time", Rendering::WebKitTime),
+            ("downloads", "end_time", Rendering::WebKitTime),
+        ],
+        queries: &[
+            Query {
+                name: "visits",
+                description: "every visit with its page, oldest first",
+                sql: "SELECT visits.visit_time, urls.url, urls.title \
+                      FROM visits JOIN urls ON visits.url = urls.id ORDER BY visits.visit_time",
+            },
+            Query {
+                name: "searches",
+                description: "terms typed into search engines",
+                sql: "SELECT keyword_search_terms.term, urls.url, urls.last_visit_time \
+                      FROM keyword_search_terms JOIN urls ON keyword_search_terms.url_id = urls.id \
+                      ORDER BY urls.last_visit_time",
+            },
+            Query {
+                name: "downloads",
+                description: "downloaded files, oldest first",
+                sql: "SELECT start_time, end_time, target_path, total_bytes, tab_url FROM downloads ORDER BY start_time",
+            },
+        ],
+    },
+    Profile {
+        name: "android-calls",
+        description: "call log (calllog.db or contacts2.db)",
+        tables: &["calls"],
+        columns: &[("calls", "date", Rendering::UnixMillis)],
+        queries: &[Query {
+            name: "calls",
+            description: "calls with their number, direction and length in seconds, oldest first",
+            sql: "SELECT date, number, name, type, duration FROM calls ORDER BY date",
+        }],
+    },
+];
+
+impl Profile {
+    /// The profile called `name`.
+    pub fn find(name: &str) -> Result<&'static Profile> {
+        PROFILES.iter().find(|p| p.name.eq_ignore_ascii_case(name)).ok_or_else(|| {
+            let names = PROFILES.iter().map(|p| p.name).collect::<Vec<_>>();
+            anyhow!(msg!("unknown-profile", name = name, names = names.join(", ")))
+        })
+    }
+
+    /// The rendering for `column` of `table`, if the profile has one.
+    pub fn rendering(&self, table: &str, column: &str) -> Option<Rendering> {
+        self.columns
+            .iter()
+            .find(|(t, c, _)| t.eq_ignore_ascii_case(table) && c.eq_ignore_ascii_case(column))
+            .map(|&(_, _, rendering)| rendering)
+    }
+
+    /// The canned query called `name`.
+    pub fn query(&self, name: &str) -> Result<&'static Query> {
+        self.queries
+            .iter()
+            .find(|q| q.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!(msg!("no-such-profile-query", name = name, profile = self.name)))
+    }
+
+    /// The tables the profile expects that `db` does not have.
+    pub fn missing_tables(&self, db: &Database) -> Vec<&'static str> {
+        self.tables
+            .iter()
+            .copied()
+            .filter(|table| !db.schema().iter().any(|s| s.name.eq_ignore_ascii_case(table)))
+            .collect()
+    }
+}
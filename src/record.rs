@@ -1,6 +1,12 @@
-use std::fmt::Display;
+use crate::{
+    error::Error,
+    functions::{format_real, type_name, CaseFolding},
+    messages::msg,
+};
+use anyhow::{anyhow, Result};
+use std::{borrow::Cow, cmp::Ordering, fmt::Display, rc::Rc, str::FromStr, sync::Arc};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum ColumnType {
     Null,
     Int8,
@@ -12,12 +18,308 @@ pub enum ColumnType {
     Float,
     Zero,
     One,
-    Reserved1,
-    Reserved2,
     Blob(usize),
     Text(usize),
 }
 
+impl ColumnType {
+    /// Bytes the value takes up in the body of a record.
+    pub fn size(&self) -> usize {
+        match self {
+            ColumnType::Null | ColumnType::Zero | ColumnType::One => 0,
+            ColumnType::Int8 => 1,
+            ColumnType::Int16 => 2,
+            ColumnType::Int24 => 3,
+            ColumnType::Int32 => 4,
+            ColumnType::Int48 => 6,
+            ColumnType::Int64 | ColumnType::Float => 8,
+            ColumnType::Blob(len) | ColumnType::Text(len) => *len,
+        }
+    }
+}
+
+impl ColumnType {
+    /// The type serial type `serial_type` stands for. The 0 and 1 constants
+    /// need `schema_format` 4.
+    pub fn from_serial(serial_type: u64, schema_format: u32) -> Result<Self> {
+        Ok(match serial_type {
+            0 => ColumnType::Null,
+            1 => ColumnType::Int8,
+            2 => ColumnType::Int16,
+            3 => ColumnType::Int24,
+            4 => ColumnType::Int32,
+            5 => ColumnType::Int48,
+            6 => ColumnType::Int64,
+            7 => ColumnType::Float,
+            8 | 9 if schema_format < 4 => Err(anyhow!(msg!("serial-type-needs-format-4", serial_type = serial_type)))?,
+            8 => ColumnType::Zero,
+            9 => ColumnType::One,
+            10 | 11 => Err(anyhow!(msg!("reserved-serial-type", serial_type = serial_type)))?,
+            n if n % 2 == 0 => ColumnType::Blob((n - 12) as usize / 2),
+            n => ColumnType::Text((n - 13) as usize / 2),
+        })
+    }
+}
+
+/// Reads the header of the record that starts at `start` in `bytes` and
+/// ends with them: the type of each value and where in `bytes` it starts,
+/// as a [`RawRecord`] takes them.
+pub fn parse_record(bytes: &[u8], start: usize, schema_format: u32) -> Result<Vec<(ColumnType, usize)>> {
+    let record = bytes.get(start..).ok_or_else(|| anyhow!(msg!("record-past-end")))?;
+    let (header_size, mut header, varint_size) = parse_varint(record)?;
+    let header_size = header_size as usize;
+    if header_size < varint_size || header_size > record.len() {
+        Err(anyhow!(msg!("record-header-size", size = header_size)))?
+    }
+    header = &header[..header_size - varint_size];
+
+    let mut fields = Vec::new();
+    let mut at = start + header_size;
+    while !header.is_empty() {
+        let (serial_type, rest, _) = parse_varint(header)?;
+        header = rest;
+        let column_type = ColumnType::from_serial(serial_type, schema_format)?;
+        fields.push((column_type, at));
+        at += column_type.size();
+    }
+    if at > bytes.len() {
+        Err(anyhow!(msg!("record-too-short")))?
+    }
+    Ok(fields)
+}
+
+/// A record whose header has been read but whose values are only decoded
+/// when asked for, so that a query pays for the columns it uses and not for
+/// the rest.
+#[derive(Debug, Clone)]
+pub struct RawRecord {
+    /// The page the record is on.
+    bytes: Arc<[u8]>,
+    /// The type of each value and where in `bytes` it starts.
+    fields: Vec<(ColumnType, usize)>,
+    encoding: TextEncoding,
+    decoding: TextDecoding,
+}
+
+impl RawRecord {
+    /// The record with `fields` in `bytes`, whose values must all lie within
+    /// them.
+    pub fn new(bytes: Arc<[u8]>, fields: Vec<(ColumnType, usize)>, encoding: TextEncoding, decoding: TextDecoding) -> Self {
+        RawRecord {
+            bytes,
+            fields,
+            encoding,
+            decoding,
+        }
+    }
+
+    /// Number of values in the record.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Decodes value `idx`, counting from 0.
+    pub fn get(&self, idx: usize) -> Result<Record> {
+        let (column_type, start) = *self
+            .fields
+            .get(idx)
+            .ok_or_else(|| anyhow!(msg!("value-out-of-range", index = idx, count = self.len())))?;
+        decode_value(column_type, &self.bytes[start..start + column_type.size()], self.encoding, self.decoding)
+    }
+
+    /// Decodes every value.
+    pub fn values(&self) -> Result<Vec<Record>> {
+        (0..self.len()).map(|idx| self.get(idx)).collect()
+    }
+}
+
+/// The value of `column_type` whose bytes in a record are `bytes`.
+fn decode_value(column_type: ColumnType, bytes: &[u8], encoding: TextEncoding, decoding: TextDecoding) -> Result<Record> {
+    let integer = |bytes: &[u8]| {
+        // Shifting back down sign-extends the bytes read into the top.
+        let mut value = [0; 8];
+        value[..bytes.len()].copy_from_slice(bytes);
+        i64::from_be_bytes(value) >> (64 - 8 * bytes.len())
+    };
+    Ok(match column_type {
+        ColumnType::Null => Record::Null,
+        ColumnType::Int8 => Record::Int8(bytes[0] as i8),
+        ColumnType::Int16 => Record::Int16(integer(bytes) as i16),
+        ColumnType::Int24 => Record::Int24(integer(bytes) as i32),
+        ColumnType::Int32 => Record::Int32(integer(bytes) as i32),
+        ColumnType::Int48 => Record::Int48(integer(bytes)),
+        ColumnType::Int64 => Record::Int64(integer(bytes)),
+        ColumnType::Float => Record::Float(f64::from_be_bytes(bytes.try_into()?)),
+        ColumnType::Zero => Record::Zero,
+        ColumnType::One => Record::One,
+        ColumnType::Blob(_) => Record::Blob(bytes.to_vec()),
+        ColumnType::Text(_) => decoding.decode(encoding, bytes)?,
+    })
+}
+
+/// A value of a record that borrows its text or blob from the bytes it was
+/// decoded from instead of copying them. Text that has to be converted, from
+/// UTF-16 or by [`TextDecoding::Lossy`], cannot borrow and is owned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueRef<'a> {
+    Null,
+    Integer(i64),
+    Float(f64),
+    Text(Cow<'a, str>),
+    Blob(Cow<'a, [u8]>),
+}
+
+impl ValueRef<'_> {
+    /// The value as an owned [`Record`].
+    pub fn to_record(&self) -> Record {
+        match self {
+            ValueRef::Null => Record::Null,
+            ValueRef::Integer(v) => Record::Int64(*v),
+            ValueRef::Float(v) => Record::Float(*v),
+            ValueRef::Text(text) => Record::Text(text.to_string()),
+            ValueRef::Blob(bytes) => Record::Blob(bytes.to_vec()),
+        }
+    }
+}
+
+impl From<Record> for ValueRef<'_> {
+    fn from(record: Record) -> Self {
+        match record {
+            Record::Null => ValueRef::Null,
+            Record::Float(v) => ValueRef::Float(v),
+            Record::Text(text) => ValueRef::Text(Cow::Owned(text)),
+            Record::Blob(bytes) => ValueRef::Blob(Cow::Owned(bytes)),
+            other => ValueRef::Integer(other.integer().unwrap_or_default()),
+        }
+    }
+}
+
+/// Decodes the record that starts at `start` in `bytes` and ends with them,
+/// like [`parse_record`] and [`RawRecord::values`] together, but with text
+/// and blobs borrowed from `bytes`.
+pub fn decode_borrowed(
+    bytes: &[u8],
+    start: usize,
+    schema_format: u32,
+    encoding: TextEncoding,
+    decoding: TextDecoding,
+) -> Result<Vec<ValueRef<'_>>> {
+    let fields = parse_record(bytes, start, schema_format)?;
+    fields
+        .into_iter()
+        .map(|(column_type, start)| {
+            let value = &bytes[start..start + column_type.size()];
+            Ok(match column_type {
+                ColumnType::Blob(_) => ValueRef::Blob(Cow::Borrowed(value)),
+                ColumnType::Text(_) if encoding == TextEncoding::Utf8 => match std::str::from_utf8(value) {
+                    Ok(text) => ValueRef::Text(Cow::Borrowed(text)),
+                    Err(_) => match decoding.decode_utf8(value)? {
+                        // The bytes are the blob.
+                        Record::Blob(_) => ValueRef::Blob(Cow::Borrowed(value)),
+                        other => other.into(),
+                    },
+                },
+                _ => decode_value(column_type, value, encoding, decoding)?.into(),
+            })
+        })
+        .collect()
+}
+
+/// What to do with a text value whose bytes are not valid UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextDecoding {
+    /// Fail the query.
+    Strict,
+    /// Substitute U+FFFD for the invalid sequences.
+    Lossy,
+    /// Hand the raw bytes back as a blob.
+    Blob,
+}
+
+/// How text values are stored, from the text encoding field of the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16le,
+    Utf16be,
+}
+
+impl TextEncoding {
+    /// Reads the header field at offset 56. Databases without any schema yet
+    /// may leave it at 0, which means UTF-8.
+    pub fn from_header(value: u32) -> Result<Self> {
+        match value {
+            0 | 1 => Ok(TextEncoding::Utf8),
+            2 => Ok(TextEncoding::Utf16le),
+            3 => Ok(TextEncoding::Utf16be),
+            _ => Err(Error::UnsupportedFeature(msg!("unsupported-text-encoding", encoding = value)))?,
+        }
+    }
+}
+
+impl Display for TextEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextEncoding::Utf8 => write!(f, "UTF-8"),
+            TextEncoding::Utf16le => write!(f, "UTF-16le"),
+            TextEncoding::Utf16be => write!(f, "UTF-16be"),
+        }
+    }
+}
+
+impl TextDecoding {
+    pub fn decode(&self, encoding: TextEncoding, bytes: &[u8]) -> Result<Record> {
+        let units = |from: fn([u8; 2]) -> u16| {
+            // A trailing odd byte is not part of any character; sqlite drops it too.
+            bytes
+                .chunks_exact(2)
+                .map(|pair| from([pair[0], pair[1]]))
+                .collect::<Vec<_>>()
+        };
+        let units = match encoding {
+            TextEncoding::Utf8 => return self.decode_utf8(bytes),
+            TextEncoding::Utf16le => units(u16::from_le_bytes),
+            TextEncoding::Utf16be => units(u16::from_be_bytes),
+        };
+        match String::from_utf16(&units) {
+            Ok(text) => Ok(Record::Text(text)),
+            Err(e) => match self {
+                TextDecoding::Strict => Err(anyhow!(msg!("invalid-text", encoding = encoding, error = e))),
+                TextDecoding::Lossy => Ok(Record::Text(String::from_utf16_lossy(&units))),
+                TextDecoding::Blob => Ok(Record::Blob(bytes.to_vec())),
+            },
+        }
+    }
+
+    fn decode_utf8(&self, bytes: &[u8]) -> Result<Record> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Ok(Record::Text(text.to_string())),
+            Err(e) => match self {
+                TextDecoding::Strict => Err(anyhow!(msg!("invalid-text", encoding = "UTF-8", error = e))),
+                TextDecoding::Lossy => Ok(Record::Text(String::from_utf8_lossy(bytes).into_owned())),
+                TextDecoding::Blob => Ok(Record::Blob(bytes.to_vec())),
+            },
+        }
+    }
+}
+
+impl FromStr for TextDecoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "strict" => Ok(TextDecoding::Strict),
+            "lossy" => Ok(TextDecoding::Lossy),
+            "blob" => Ok(TextDecoding::Blob),
+            _ => Err(anyhow!(msg!("unknown-text-decoding", decoding = s))),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Record {
     Null,
@@ -30,30 +332,262 @@ pub enum Record {
     Float(f64),
     Zero,
     One,
-    Reserved1,
-    Reserved2,
     Blob(Vec<u8>),
     Text(String),
 }
 
+impl Record {
+    /// Value of any of the integer serial types.
+    pub fn integer(&self) -> Option<i64> {
+        match self {
+            Record::Int8(v) => Some(*v as i64),
+            Record::Int16(v) => Some(*v as i64),
+            Record::Int24(v) | Record::Int32(v) => Some(*v as i64),
+            Record::Int48(v) | Record::Int64(v) => Some(*v),
+            Record::Zero => Some(0),
+            Record::One => Some(1),
+            _ => None,
+        }
+    }
+}
+
+/// Conversion of a value into a Rust type, for [`Row::get`]. Integers of
+/// every serial type convert to any integer type that holds their value, and
+/// to `f64`; NULL converts only to `Option`, which takes whatever its inner
+/// type does.
+pub trait FromRecord: Sized {
+    fn from_record(value: &Record) -> Result<Self>;
+}
+
+fn mismatch(value: &Record, expected: &str) -> anyhow::Error {
+    anyhow!(msg!("cannot-read-as", value_type = type_name(value), expected = expected))
+}
+
+macro_rules! integer_from_record {
+    ($($t:ty),*) => {$(
+        impl FromRecord for $t {
+            fn from_record(value: &Record) -> Result<Self> {
+                let v = value.integer().ok_or_else(|| mismatch(value, stringify!($t)))?;
+                <$t>::try_from(v).map_err(|_| anyhow!(msg!("integer-does-not-fit", value = v, expected = stringify!($t))))
+            }
+        }
+    )*};
+}
+
+integer_from_record!(i8, i16, i32, i64, u8, u16, u32, u64, usize);
+
+impl FromRecord for bool {
+    fn from_record(value: &Record) -> Result<Self> {
+        Ok(value.integer().ok_or_else(|| mismatch(value, "bool"))? != 0)
+    }
+}
+
+impl FromRecord for f64 {
+    fn from_record(value: &Record) -> Result<Self> {
+        match value {
+            Record::Float(v) => Ok(*v),
+            other => Ok(other.integer().ok_or_else(|| mismatch(other, "f64"))? as f64),
+        }
+    }
+}
+
+impl FromRecord for String {
+    fn from_record(value: &Record) -> Result<Self> {
+        match value {
+            Record::Text(s) => Ok(s.clone()),
+            other => Err(mismatch(other, "String")),
+        }
+    }
+}
+
+/// Text converts to its UTF-8 bytes.
+impl FromRecord for Vec<u8> {
+    fn from_record(value: &Record) -> Result<Self> {
+        match value {
+            Record::Blob(b) => Ok(b.clone()),
+            Record::Text(s) => Ok(s.as_bytes().to_vec()),
+            other => Err(mismatch(other, "Vec<u8>")),
+        }
+    }
+}
+
+impl<T: FromRecord> FromRecord for Option<T> {
+    fn from_record(value: &Record) -> Result<Self> {
+        match value {
+            Record::Null => Ok(None),
+            other => T::from_record(other).map(Some),
+        }
+    }
+}
+
+impl FromRecord for Record {
+    fn from_record(value: &Record) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+/// One row of a result set, with the names of its columns.
+#[derive(Debug, Clone)]
+pub struct Row {
+    names: Rc<[String]>,
+    values: Vec<Record>,
+}
+
+impl Row {
+    /// A row of `values`, named by the `names` shared with the rest of its
+    /// result set.
+    pub fn new(names: Rc<[String]>, values: Vec<Record>) -> Self {
+        Row { names, values }
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn values(&self) -> &[Record] {
+        &self.values
+    }
+
+    /// The value of the column `name`, matched ignoring ASCII case, as a `T`.
+    pub fn get<T: FromRecord>(&self, name: &str) -> Result<T> {
+        let idx = self
+            .names
+            .iter()
+            .position(|n| n.eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::no_such_column(name, None, self.names.iter().map(String::as_str)))?;
+        T::from_record(&self.values[idx]).map_err(|e| anyhow!(msg!("in-column", column = self.names[idx], error = e)))
+    }
+
+    /// The value of the column at `idx`, counting from 0, as a `T`.
+    pub fn get_at<T: FromRecord>(&self, idx: usize) -> Result<T> {
+        let value = self
+            .values
+            .get(idx)
+            .ok_or_else(|| anyhow!(msg!("column-out-of-range", index = idx, count = self.values.len())))?;
+        T::from_record(value).map_err(|e| anyhow!(msg!("in-column", column = self.names[idx], error = e)))
+    }
+}
+
+/// Conversion of a whole row into a Rust type, for
+/// [`Database::query_as`](crate::Database::query_as). Tuples take the
+/// columns in order; structs implement it by naming theirs with
+/// [`Row::get`].
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self>;
+}
+
+impl FromRow for Row {
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(row.clone())
+    }
+}
+
+macro_rules! tuple_from_row {
+    ($(($($t:ident $idx:tt),+)),*) => {$(
+        impl<$($t: FromRecord),+> FromRow for ($($t,)+) {
+            fn from_row(row: &Row) -> Result<Self> {
+                Ok(($(row.get_at::<$t>($idx)?,)+))
+            }
+        }
+    )*};
+}
+
+tuple_from_row!(
+    (A 0),
+    (A 0, B 1),
+    (A 0, B 1, C 2),
+    (A 0, B 1, C 2, D 3),
+    (A 0, B 1, C 2, D 3, E 4),
+    (A 0, B 1, C 2, D 3, E 4, F 5)
+);
+
+/// How text values compare with each other. Values of other types compare
+/// the same under every collation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    /// Byte by byte.
+    #[default]
+    Binary,
+    /// Byte by byte once letters are folded to lowercase.
+    Nocase(CaseFolding),
+}
+
+impl Collation {
+    /// The collation `COLLATE name` asks for, folding NOCASE text with
+    /// `folding`; BINARY for no name.
+    pub fn named(name: Option<&str>, folding: CaseFolding) -> Collation {
+        match name {
+            Some(name) if name.eq_ignore_ascii_case("NOCASE") => Collation::Nocase(folding),
+            _ => Collation::Binary,
+        }
+    }
+
+    /// Orders values the way sqlite sorts them: NULL first, then numbers by
+    /// value, then text by the collation, then blobs byte by byte.
+    pub fn compare(self, a: &Record, b: &Record) -> Ordering {
+        fn class(value: &Record) -> u8 {
+            match value {
+                Record::Null => 0,
+                Record::Text(_) => 2,
+                Record::Blob(_) => 3,
+                _ => 1,
+            }
+        }
+
+        match (a, b) {
+            (Record::Text(a), Record::Text(b)) => match self {
+                Collation::Binary => a.as_bytes().cmp(b.as_bytes()),
+                Collation::Nocase(folding) => folding.fold(a).cmp(&folding.fold(b)),
+            },
+            (Record::Blob(a), Record::Blob(b)) => a.cmp(b),
+            _ if class(a) != class(b) => class(a).cmp(&class(b)),
+            (Record::Float(a), Record::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Record::Float(real), int) => compare_integer_real(int.integer().unwrap_or(0), *real).reverse(),
+            (int, Record::Float(real)) => compare_integer_real(int.integer().unwrap_or(0), *real),
+            _ => a.integer().cmp(&b.integer()),
+        }
+    }
+}
+
+/// Orders values the way sqlite sorts them, with the BINARY collation.
+pub fn compare_records(a: &Record, b: &Record) -> Ordering {
+    Collation::Binary.compare(a, b)
+}
+
+/// Compares an integer with a real exactly, without rounding the integer to
+/// the nearest real first as a cast would: 2^53 + 1 is above 2^53 as a real.
+fn compare_integer_real(int: i64, real: f64) -> Ordering {
+    // 2^63 as a real: every i64 is below it and at or above its negation.
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if real.is_nan() {
+        return Ordering::Equal;
+    }
+    if real >= LIMIT {
+        return Ordering::Less;
+    }
+    if real < -LIMIT {
+        return Ordering::Greater;
+    }
+    let whole = real.trunc();
+    match int.cmp(&(whole as i64)) {
+        Ordering::Equal => whole.partial_cmp(&real).unwrap_or(Ordering::Equal),
+        order => order,
+    }
+}
+
+/// Integer serial types compare by value, so the `Zero`/`One` constants of
+/// serial types 8 and 9 equal the same number stored in any integer width.
 impl PartialEq for Record {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Record::Null, Record::Null) => true,
-            (Record::Int8(a), Record::Int8(b)) => a == b,
-            (Record::Int16(a), Record::Int16(b)) => a == b,
-            (Record::Int24(a), Record::Int24(b)) => a == b,
-            (Record::Int32(a), Record::Int32(b)) => a == b,
-            (Record::Int48(a), Record::Int48(b)) => a == b,
-            (Record::Int64(a), Record::Int64(b)) => a == b,
             (Record::Float(a), Record::Float(b)) => a == b,
-            (Record::Zero, Record::Zero) => true,
-            (Record::One, Record::One) => true,
-            (Record::Reserved1, Record::Reserved1) => true,
-            (Record::Reserved2, Record::Reserved2) => true,
             (Record::Blob(a), Record::Blob(b)) => a == b,
             (Record::Text(a), Record::Text(b)) => a == b,
-            _ => false,
+            (a, b) => match (a.integer(), b.integer()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            },
         }
     }
 }
@@ -68,13 +602,230 @@ impl Display for Record {
             Record::Int32(v) => write!(f, "{}", v),
             Record::Int48(v) => write!(f, "{}", v),
             Record::Int64(v) => write!(f, "{}", v),
-            Record::Float(v) => write!(f, "{}", v),
+            Record::Float(v) => write!(f, "{}", format_real(*v)),
             Record::Zero => write!(f, "0"),
             Record::One => write!(f, "1"),
-            Record::Reserved1 => write!(f, "Reserved1"),
-            Record::Reserved2 => write!(f, "Reserved2"),
-            Record::Blob(v) => write!(f, "{:?}", v),
+            Record::Blob(v) => write!(f, "X'{}'", v.iter().map(|b| format!("{:02X}", b)).collect::<String>()),
             Record::Text(v) => write!(f, "{}", v),
         }
     }
 }
+
+/// Reads a varint off the front of `data`: its value, the bytes after it,
+/// and how many bytes it took.
+pub fn parse_varint(data: &[u8]) -> Result<(u64, &[u8], usize)> {
+    let mut result: u64 = 0;
+
+    for (idx, &byte) in data.iter().enumerate() {
+        if idx >= 10 {
+            return Err(anyhow!(msg!("varint-too-long")));
+        }
+
+        // The 9th byte contributes all of its 8 bits.
+        if idx == 8 {
+            return Ok(((result << 8) | byte as u64, &data[idx + 1..], idx + 1));
+        }
+        result = (result << 7) | (byte & 0x7F) as u64;
+
+        if byte & 0x80 == 0 {
+            return Ok((result, &data[idx + 1..], idx + 1));
+        }
+    }
+
+    Err(anyhow!(msg!("varint-incomplete")))
+}
+
+/// Appends `value` as a varint: big-endian groups of 7 bits with the high bit
+/// set on all but the last, whose 9th byte, if reached, carries a full 8 bits.
+pub fn encode_varint(value: u64, out: &mut Vec<u8>) {
+    if value >> 56 != 0 {
+        for shift in (1..=8).rev() {
+            out.push(((value >> (shift * 7 + 1)) & 0x7f) as u8 | 0x80);
+        }
+        out.push(value as u8);
+        return;
+    }
+    let mut groups = vec![(value & 0x7f) as u8];
+    let mut rest = value >> 7;
+    while rest != 0 {
+        groups.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.extend(groups.iter().rev());
+}
+
+/// Encodes `values` as a record the way sqlite writes it into a UTF-8
+/// database of the current schema format; see [`serialize_record`].
+pub fn encode(values: &[Record]) -> Vec<u8> {
+    serialize_record(values, TextEncoding::Utf8, 4)
+}
+
+/// Encodes `values` as a record: a header of serial types, then the values,
+/// each integer in the fewest bytes that hold it. `schema_format` 4 and up
+/// allows the 0 and 1 constants that take no body at all.
+pub fn serialize_record(values: &[Record], encoding: TextEncoding, schema_format: u32) -> Vec<u8> {
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        let serial_type = match value {
+            Record::Null => 0,
+            Record::Float(v) => {
+                body.extend_from_slice(&v.to_be_bytes());
+                7
+            }
+            Record::Text(s) => {
+                let bytes = match encoding {
+                    TextEncoding::Utf8 => s.as_bytes().to_vec(),
+                    TextEncoding::Utf16le => s.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+                    TextEncoding::Utf16be => s.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+                };
+                body.extend_from_slice(&bytes);
+                bytes.len() as u64 * 2 + 13
+            }
+            Record::Blob(b) => {
+                body.extend_from_slice(b);
+                b.len() as u64 * 2 + 12
+            }
+            integer => {
+                let v = integer.integer().unwrap_or_default();
+                let (serial_type, width) = match v {
+                    0 if schema_format >= 4 => (8, 0),
+                    1 if schema_format >= 4 => (9, 0),
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&v.to_be_bytes()[8 - width..]);
+                serial_type
+            }
+        };
+        encode_varint(serial_type, &mut types);
+    }
+
+    // The header's size counts the varint holding it.
+    let mut header_size = types.len() + 1;
+    loop {
+        let mut size = Vec::new();
+        encode_varint(header_size as u64, &mut size);
+        if size.len() + types.len() == header_size {
+            size.extend(types);
+            size.extend(body);
+            return size;
+        }
+        header_size = size.len() + types.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_records, encode, Collation, Record};
+    use crate::functions::CaseFolding;
+    use std::cmp::Ordering;
+
+    /// Checks `values` encode to `sqlite`, the hex of the record sqlite3
+    /// wrote for them into a table with a column for each.
+    fn check(values: &[Record], sqlite: &str) {
+        let hex = encode(values).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        assert_eq!(hex, sqlite, "{:?}", values);
+    }
+
+    fn ints(values: &[i64]) -> Vec<Record> {
+        values.iter().map(|&v| Record::Int64(v)).collect()
+    }
+
+    #[test]
+    fn null_and_the_constants() {
+        check(&[Record::Null, Record::Int64(0), Record::Int64(1), Record::Int64(2), Record::Int64(-1)], "06000809010102ff");
+        // However the integer was read.
+        check(&[Record::Null, Record::Zero, Record::One, Record::Int8(2), Record::Int48(-1)], "06000809010102ff");
+    }
+
+    #[test]
+    fn integers_take_the_fewest_bytes() {
+        check(&ints(&[127, -128]), "0301017f80");
+        check(&ints(&[128, -129, 32767, -32768]), "05020202020080ff7f7fff8000");
+        check(&ints(&[32768, -32769, 8388607, -8388608]), "0503030303008000ff7fff7fffff800000");
+        check(&ints(&[8388608, -8388609, 2147483647, -2147483648]), "050404040400800000ff7fffff7fffffff80000000");
+        check(
+            &ints(&[2147483648, -2147483649, 140737488355327, -140737488355328]),
+            "0505050505000080000000ffff7fffffff7fffffffffff800000000000",
+        );
+        check(
+            &ints(&[140737488355328, -140737488355329, i64::MAX, i64::MIN]),
+            "05060606060000800000000000ffff7fffffffffff7fffffffffffffff8000000000000000",
+        );
+    }
+
+    #[test]
+    fn floats_take_eight_bytes() {
+        let values = [1.5, -0.25, 1e300, 0.0].map(Record::Float);
+        check(&values, "05070707073ff8000000000000bfd00000000000007e37e43c8800759c0000000000000000");
+    }
+
+    #[test]
+    fn blobs_and_text() {
+        check(&[vec![], vec![0x00, 0xff], vec![0; 3]].map(Record::Blob), "040c101200ff000000");
+        let text = ["", "hello", "h\u{e9}llo", &"x".repeat(60)].map(|s| Record::Text(s.to_string()));
+        check(&text, &format!("060d1719810568656c6c6f68c3a96c6c6f{}", "78".repeat(60)));
+    }
+
+    #[test]
+    fn header_size_counts_its_own_varint() {
+        let mut values = vec![Record::Null; 130];
+        values.push(Record::Int64(7));
+        check(&values, &format!("8105{}0107", "00".repeat(130)));
+    }
+
+    fn text(s: &str) -> Record {
+        Record::Text(s.to_string())
+    }
+
+    #[test]
+    fn null_sorts_before_numbers_before_text_before_blobs() {
+        let sorted = [
+            Record::Null,
+            Record::Int64(i64::MIN),
+            Record::Float(-0.5),
+            Record::Zero,
+            Record::Float(0.5),
+            Record::Int8(1),
+            Record::Float(1e300),
+            text(""),
+            text("B"),
+            text("a"),
+            Record::Blob(vec![]),
+            Record::Blob(vec![0]),
+        ];
+        for (i, a) in sorted.iter().enumerate() {
+            for (j, b) in sorted.iter().enumerate() {
+                assert_eq!(compare_records(a, b), i.cmp(&j), "{:?} against {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn integers_equal_reals_of_the_same_value() {
+        assert_eq!(compare_records(&Record::Int64(2), &Record::Float(2.0)), Ordering::Equal);
+        assert_eq!(compare_records(&Record::Float(-3.0), &Record::Int16(-3)), Ordering::Equal);
+        assert_eq!(compare_records(&Record::One, &Record::Int48(1)), Ordering::Equal);
+        // Exactly, not after rounding the integer to a real.
+        let big = (1i64 << 53) + 1;
+        assert_eq!(compare_records(&Record::Int64(big), &Record::Float((1i64 << 53) as f64)), Ordering::Greater);
+        assert_eq!(compare_records(&Record::Float(9.3e18), &Record::Int64(i64::MAX)), Ordering::Greater);
+    }
+
+    #[test]
+    fn nocase_folds_only_text() {
+        let nocase = Collation::Nocase(CaseFolding::Ascii);
+        assert_eq!(nocase.compare(&text("abc"), &text("ABC")), Ordering::Equal);
+        assert_eq!(nocase.compare(&text("a"), &text("B")), Ordering::Less);
+        assert_eq!(Collation::Binary.compare(&text("a"), &text("B")), Ordering::Greater);
+        // ASCII folding leaves other letters alone, as sqlite does.
+        assert_eq!(nocase.compare(&text("\u{e9}"), &text("\u{c9}")), Ordering::Greater);
+        assert_eq!(Collation::Nocase(CaseFolding::Unicode).compare(&text("\u{e9}"), &text("\u{c9}")), Ordering::Equal);
+        assert_eq!(nocase.compare(&Record::Blob(b"a".to_vec()), &Record::Blob(b"A".to_vec())), Ordering::Greater);
+    }
+}
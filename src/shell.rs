@@ -0,0 +1,1156 @@
+use crate::{
+    csv,
+    db::{apply_delta, dedup_pages, Database, PageRole, PageUse, Repair, RowKey},
+    error::Error,
+    interrupt,
+    manifest::{self, Checksummed},
+    messages::{message, msg},
+    page::schema::{Kind, Schema},
+    output::{self, Format},
+    record::Record,
+    sql::{self, parse_sql},
+    trace::read_trace,
+};
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write},
+    ops::ControlFlow,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Instant,
+};
+
+/// Rows written between saves of an export's cursor file.
+const CURSOR_INTERVAL: usize = 1000;
+
+const HELP: &str = "\
+.apply-delta DELTA FILE
+                    Replay the delta file DELTA onto the backup FILE
+.backup FILE        Copy the database to FILE, even while others write to it
+.backup --incremental STATE DELTA
+                    Write the pages changed since the backup that left the
+                    page hashes in STATE to the delta file DELTA, then
+                    update STATE; every page when there is no STATE yet
+.carve TABLE        List rows of TABLE that the free space of the database
+                    still holds, with how likely each is to be one
+.databases          List the open databases; * marks the current one
+.dbinfo             Show status information about the database
+.decode MODE        Handle invalid UTF-8 text: strict, lossy or blob
+.dedup FILE ?FILE...?
+                    Hash every page of the database and of each FILE, and
+                    count the pages they have in common
+.dump ?TABLE?       Write SQL that recreates the database, or only TABLE
+                    and its indexes
+.exit               Exit this program
+.export TABLE ?OPTIONS?
+                    Write every row of TABLE. Options:
+                      --output FILE       write to FILE instead of stdout
+                      --cursor-file FILE  resume after the last row an
+                                          earlier run saved in FILE
+                      --manifest FILE     append the row count, SHA-256 and
+                                          source header counters of the
+                                          output file to FILE
+                      --jobs N            cut the table into N rowid ranges
+                                          at interior page keys and write
+                                          them side by side, to the --output
+                                          FILE with .1 to .N appended
+.freelist           List the free pages, and how many pages each table and
+                    index takes up
+.help               Show this message
+.import FILE TABLE  Append the rows of the CSV file FILE to TABLE, creating
+                    it from the header row when there is no such table
+.integrity-check    Check every b-tree and the freelist for corruption
+.lint               Look for unindexed foreign keys, tables without a PRIMARY
+                    KEY, duplicate and redundant indexes and TEXT keys
+.mode MODE ?TABLE?  Set output mode: list, csv, json, table or insert
+.once FILE          Send the results of the next statement to FILE, or to
+                    the standard input of a command given as '| COMMAND'
+.open FILE          Switch to FILE, opening it unless it already is open
+.output ?FILE?      Send results to FILE or '| COMMAND' until the next
+                    .output; to stdout without FILE
+.profile ?QUERY?    Run the canned QUERY of the --profile the database was
+                    opened with, or list them
+.quit               Exit this program
+.read FILE          Read input from FILE
+.replay-reads FILE  Read the pages of the read trace FILE again, in order,
+                    and time it
+.repair PAGE REPAIR BACKUP
+                    Fix one b-tree page after saving it to the new file
+                    BACKUP. REPAIR is cell-count, cell-pointers (rebuilt
+                    from the cells on a table page) or freeblocks (rebuilt
+                    from the gaps between the cells)
+.schema ?TABLE?     Show the CREATE statements matching TABLE
+.slack ?--hex?      List the unallocated space and freeblocks of every
+                    b-tree page and how much of each is not zero; with
+                    --hex, dump the lines that are not
+.set ?NAME VALUE?   Set variable NAME, which $(NAME) in later input stands
+                    for, to the rest of the line; list them all without NAME
+.tables             List names of tables
+.unset NAME         Forget variable NAME
+
+Input is read a line at a time with no history and no cursor movement beyond
+the terminal's own erase keys; run the shell under rlwrap to edit lines.";
+
+/// Dispatches dot commands and SQL statements against the current one of the
+/// databases it has open.
+pub struct Shell {
+    /// In the order they were opened. Each keeps its own schema and any
+    /// transaction it has open while another one is current.
+    databases: Vec<Database>,
+    current: usize,
+    format: Format,
+    bail: bool,
+    /// Blobs are written as their bytes rather than as `X'..'` literals.
+    raw_blobs: bool,
+    failures: usize,
+    /// Set by `.set`, substituted for `$(name)` in commands and statements.
+    variables: BTreeMap<String, String>,
+    /// Where results go, as set by `.output`.
+    output: Destination,
+    /// Set by `.once`; takes the place of `output` for one statement.
+    once: Option<Destination>,
+}
+
+/// A place results are written to.
+enum Destination {
+    Stdout,
+    File(BufWriter<File>),
+    /// A command reading results on its standard input.
+    Pipe(Child),
+}
+
+/// Where `.export` writes and what it keeps track of.
+#[derive(Default)]
+struct ExportOptions<'a> {
+    output: Option<&'a str>,
+    cursor: Option<&'a str>,
+    manifest: Option<&'a str>,
+    /// Shards to write at the same time.
+    jobs: Option<usize>,
+}
+
+/// Returned once `--bail` has stopped a script; the failing statement has
+/// already been reported.
+#[derive(Debug, thiserror::Error)]
+#[error("stopped after an error")]
+pub struct Bailed;
+
+impl Shell {
+    pub fn new(db: Database, format: Format) -> Self {
+        Self {
+            databases: vec![db],
+            current: 0,
+            format,
+            bail: false,
+            raw_blobs: false,
+            failures: 0,
+            variables: BTreeMap::new(),
+            output: Destination::Stdout,
+            once: None,
+        }
+    }
+
+    /// Whether a script stops at its first failing statement.
+    pub fn set_bail(&mut self, bail: bool) {
+        self.bail = bail;
+    }
+
+    pub fn set_raw_blobs(&mut self, raw: bool) {
+        self.raw_blobs = raw;
+    }
+
+    /// Number of script statements that have failed so far.
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+
+    fn db(&self) -> &Database {
+        &self.databases[self.current]
+    }
+
+    fn db_mut(&mut self) -> &mut Database {
+        &mut self.databases[self.current]
+    }
+
+    /// Makes the database at `path` current, opening it first unless it is
+    /// open already.
+    fn open(&mut self, path: &str) -> Result<()> {
+        let same_file = |db: &Database| match (db.path().map(fs::canonicalize), fs::canonicalize(path)) {
+            (Some(Ok(a)), Ok(b)) => a == b,
+            (Some(_), _) => db.path() == Some(path),
+            (None, _) => false,
+        };
+        if let Some(idx) = self.databases.iter().position(same_file) {
+            self.current = idx;
+            return Ok(());
+        }
+        let mut db = Database::load_db(path.to_string()).map_err(|e| anyhow!(msg!("cannot-open-database", path = path, error = e)))?;
+        db.copy_settings(self.db());
+        self.databases.push(db);
+        self.current = self.databases.len() - 1;
+        Ok(())
+    }
+
+    pub fn execute(&mut self, command: &str) -> Result<()> {
+        let command = self.substitute(command.trim())?;
+        let command = command.as_str();
+        let mut words = command.split_whitespace();
+
+        match words.next().unwrap_or_default() {
+            ".dbinfo" => self.db().info(),
+            ".tables" => self.db().tables(),
+            ".open" => self.open(words.next().ok_or_else(|| anyhow!(msg!("usage", usage = ".open FILE")))?),
+            ".backup" => match (words.next(), words.next(), words.next()) {
+                (Some("--incremental"), Some(state), Some(delta)) => {
+                    let delta = self.db().backup_delta(state, delta)?;
+                    println!("{} of {} pages changed", delta.changed, delta.pages);
+                    Ok(())
+                }
+                (Some(path), None, None) if !path.starts_with("--") => self.db().backup_to(path).map(drop),
+                _ => Err(anyhow!(msg!("usage", usage = ".backup FILE or .backup --incremental STATE DELTA"))),
+            },
+            ".apply-delta" => match (words.next(), words.next()) {
+                (Some(delta), Some(target)) => {
+                    let written = apply_delta(delta, target)?;
+                    println!("{} pages written", written);
+                    Ok(())
+                }
+                _ => Err(anyhow!(msg!("usage", usage = ".apply-delta DELTA FILE"))),
+            },
+            ".databases" => {
+                for (idx, db) in self.databases.iter().enumerate() {
+                    let marker = if idx == self.current { '*' } else { ' ' };
+                    let access = if db.is_read_only() { "r/o" } else { "r/w" };
+                    println!("{} {} {}", marker, db.path().unwrap_or(":memory:"), access);
+                }
+                Ok(())
+            }
+            ".carve" => {
+                let table = words.next().ok_or_else(|| anyhow!(msg!("usage", usage = ".carve TABLE")))?;
+                let piped = self.once.as_ref().unwrap_or(&self.output).is_pipe();
+                let result = self.carve(table);
+                self.end_output(result, piped)
+            }
+            ".dedup" => {
+                let paths = words.collect::<Vec<_>>();
+                if paths.is_empty() {
+                    Err(anyhow!(msg!("usage", usage = ".dedup FILE ?FILE...?")))?
+                }
+                self.dedup(&paths)
+            }
+            ".freelist" => self.freelist(),
+            ".slack" => match words.next() {
+                None => self.slack(false),
+                Some("--hex") => self.slack(true),
+                Some(_) => Err(anyhow!(msg!("usage", usage = ".slack ?--hex?"))),
+            },
+            ".profile" => {
+                let profile = self.db().profile().ok_or_else(|| anyhow!(msg!("no-profile")))?;
+                let Some(name) = words.next() else {
+                    println!("{}: {}", profile.name, profile.description);
+                    for query in profile.queries {
+                        println!("  {:<12} {}", query.name, query.description);
+                    }
+                    return Ok(());
+                };
+                self.execute(profile.query(name)?.sql)
+            }
+            ".integrity-check" => {
+                let problems = self.db().integrity_check()?;
+                if problems.is_empty() {
+                    println!("ok");
+                }
+                for problem in problems {
+                    println!("{}", problem);
+                }
+                Ok(())
+            }
+            ".lint" => {
+                for lint in self.db().lint()? {
+                    println!("{}", lint);
+                }
+                Ok(())
+            }
+            ".replay-reads" => {
+                let pages = read_trace(words.next().ok_or_else(|| anyhow!(msg!("usage", usage = ".replay-reads FILE")))?)?;
+                let start = Instant::now();
+                let bytes = self.db().replay_reads(&pages)?;
+                let elapsed = start.elapsed();
+                println!(
+                    "{} page reads, {} bytes in {:.3} ms ({:.1} MB/s)",
+                    pages.len(),
+                    bytes,
+                    elapsed.as_secs_f64() * 1000.0,
+                    bytes as f64 / 1e6 / elapsed.as_secs_f64().max(1e-9)
+                );
+                Ok(())
+            }
+            ".repair" => {
+                let usage = || anyhow!(msg!("usage", usage = ".repair PAGE REPAIR BACKUP"));
+                let page = words.next().ok_or_else(usage)?;
+                let page = page.parse().map_err(|_| anyhow!(msg!("not-a-page-number", text = page)))?;
+                let repair: Repair = words.next().ok_or_else(usage)?.parse()?;
+                let backup = words.next().ok_or_else(usage)?;
+                let repaired = self.db_mut().repair_page(page, repair, backup)?;
+                match repair {
+                    Repair::CellCount => println!("page {}: cell count {} set to {}", page, repaired.before, repaired.after),
+                    Repair::CellPointers => {
+                        println!("page {}: {} cell pointers written, where {} were counted", page, repaired.after, repaired.before)
+                    }
+                    Repair::Freeblocks => println!(
+                        "page {}: freeblock list from offset {} rebuilt, starting at {}",
+                        page, repaired.before, repaired.after
+                    ),
+                }
+                println!("the page as it was is in {}", backup);
+                Ok(())
+            }
+            ".schema" => self.db().schema_sql(words.next()),
+            ".dump" => {
+                let piped = self.once.as_ref().unwrap_or(&self.output).is_pipe();
+                let result = self.dump(words.next());
+                self.end_output(result, piped)
+            }
+            ".set" => {
+                let Some(name) = words.next() else {
+                    for (name, value) in &self.variables {
+                        println!("{} {}", name, value);
+                    }
+                    return Ok(());
+                };
+                let value = command[".set".len()..].trim_start()[name.len()..].trim();
+                if value.is_empty() {
+                    Err(anyhow!(msg!("usage", usage = ".set ?NAME VALUE?")))?
+                }
+                self.variables.insert(name.to_string(), value.to_string());
+                Ok(())
+            }
+            ".unset" => {
+                let name = words.next().ok_or_else(|| anyhow!(msg!("usage", usage = ".unset NAME")))?;
+                self.variables.remove(name);
+                Ok(())
+            }
+            ".export" => {
+                let usage = || anyhow!(msg!("usage", usage = ".export TABLE ?OPTIONS?"));
+                let table = words.next().ok_or_else(usage)?;
+                let mut options = ExportOptions::default();
+                while let Some(option) = words.next() {
+                    let value = words.next().ok_or_else(usage)?;
+                    match option {
+                        "--cursor-file" => options.cursor = Some(value),
+                        "--output" => options.output = Some(value),
+                        "--manifest" => options.manifest = Some(value),
+                        "--jobs" => match value.parse() {
+                            Ok(jobs) if jobs > 0 => options.jobs = Some(jobs),
+                            _ => Err(anyhow!(msg!("jobs-not-positive", value = value)))?,
+                        },
+                        _ => Err(usage())?,
+                    }
+                }
+                if options.manifest.is_some() && options.output.is_none() {
+                    Err(anyhow!(msg!("manifest-needs-output")))?
+                }
+                if options.jobs.is_some() && options.output.is_none() {
+                    Err(anyhow!(msg!("jobs-need-output")))?
+                }
+                if options.jobs.is_some() && options.cursor.is_some() {
+                    Err(anyhow!(msg!("cursor-file-with-jobs")))?
+                }
+                if options.output.is_some() {
+                    return self.export(table, &options);
+                }
+                let piped = self.once.as_ref().unwrap_or(&self.output).is_pipe();
+                let result = self.export(table, &options);
+                self.end_output(result, piped)
+            }
+            ".import" => {
+                let usage = || anyhow!(msg!("usage", usage = ".import FILE TABLE"));
+                let path = words.next().ok_or_else(usage)?;
+                let table = words.next().ok_or_else(usage)?;
+                self.import(path, table)
+            }
+            ".decode" => {
+                let mode = words.next().ok_or_else(|| anyhow!(msg!("usage", usage = ".decode MODE")))?;
+                self.db_mut().set_text_decoding(mode.parse()?);
+                Ok(())
+            }
+            ".output" | ".once" => {
+                let target = command.split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim());
+                if target.is_empty() && command.starts_with(".once") {
+                    Err(anyhow!(msg!("usage", usage = ".once FILE")))?
+                }
+                let destination = Destination::open(target)?;
+                match command.starts_with(".once") {
+                    true => self.once = Some(destination),
+                    false => std::mem::replace(&mut self.output, destination).close()?,
+                }
+                Ok(())
+            }
+            ".mode" => {
+                let mode = words.next().ok_or_else(|| anyhow!(msg!("usage", usage = ".mode MODE ?TABLE?")))?;
+                self.format = match (mode.parse()?, words.next()) {
+                    (Format::Insert(_), Some(table)) => Format::Insert(table.to_string()),
+                    (format, _) => format,
+                };
+                Ok(())
+            }
+            ".read" => {
+                let path = words.next().ok_or_else(|| anyhow!(msg!("usage", usage = ".read FILE")))?;
+                let file =
+                    File::open(path).map_err(|e| anyhow!(msg!("cannot-open", path = path, error = e)))?;
+                self.run_script(BufReader::new(file), false)
+            }
+            ".help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            dot if dot.starts_with('.') => Err(anyhow!(msg!("unknown-command", command = &dot[1..]))),
+            _ => {
+                interrupt::clear();
+                let statement = parse_sql(command).map_err(|e| match e.downcast_ref::<Error>() {
+                    Some(&Error::SqlParse { offset, .. }) => anyhow!("{}", pointed(command, offset, &e)),
+                    _ => e,
+                })?;
+                let destination = self.once.as_mut().unwrap_or(&mut self.output);
+                let piped = destination.is_pipe();
+                let mut out = output::writer(&self.format, destination.writer());
+                out.set_raw_blobs(self.raw_blobs);
+                let result = self.databases[self.current]
+                    .execute_statement(&statement, out.as_mut())
+                    .and_then(|()| out.finish())
+                    .and_then(|()| out.flush());
+                drop(out);
+                self.end_output(result, piped).map_err(|e| match e.downcast_ref::<Error>() {
+                    Some(lookup) if lookup.lookup_name().is_some() => anyhow!("{}", snippet(command, lookup)),
+                    _ => e,
+                })
+            }
+        }
+    }
+
+    /// `command` with each `$(name)` replaced by the value of variable `name`.
+    fn substitute(&self, command: &str) -> Result<String> {
+        let mut substituted = String::with_capacity(command.len());
+        let mut rest = command;
+        while let Some(start) = rest.find("$(") {
+            substituted.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let end = after.find(')').ok_or_else(|| anyhow!(msg!("unterminated-substitution", command = command)))?;
+            let name = &after[..end];
+            let value = self.variables.get(name).ok_or_else(|| anyhow!(msg!("no-such-variable", name = name)))?;
+            substituted.push_str(value);
+            rest = &after[end + 1..];
+        }
+        substituted.push_str(rest);
+        Ok(substituted)
+    }
+
+    /// Ends the output of a statement that returned `result`, closing the
+    /// destination `.once` gave it. A command that stops reading early, like
+    /// `head`, ends the output and nothing else.
+    fn end_output(&mut self, result: Result<()>, piped: bool) -> Result<()> {
+        let result = match result {
+            Err(e) if piped && is_broken_pipe(&e) => Ok(()),
+            result => result,
+        };
+        let closed = self.once.take().map_or(Ok(()), Destination::close);
+        result.and(closed)
+    }
+
+    /// Writes every row of `table` in key order. With a cursor file, the key of
+    /// the last row written is saved there every [`CURSOR_INTERVAL`] rows and
+    /// when the export stops for any reason; a later run starts after it.
+    /// With a manifest, a line describing the output file is appended to it.
+    fn export(&mut self, table: &str, options: &ExportOptions) -> Result<()> {
+        if let (Some(jobs), Some(output)) = (options.jobs, options.output) {
+            return self.export_shards(table, output, options.manifest, jobs);
+        }
+        if options.cursor.is_some() && self.format == Format::Table {
+            Err(anyhow!(msg!("cursor-file-table-mode")))?
+        }
+        let after = match options.cursor {
+            Some(path) => match fs::read_to_string(path) {
+                Ok(text) => Some(text.parse::<RowKey>()?),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => Err(anyhow!(msg!("cannot-read", path = path, error = e)))?,
+            },
+            None => None,
+        };
+        let names = self.db().columns(table)?.into_iter().map(|c| c.name).collect::<Vec<_>>();
+        // Taken before reading any row, so a change during the export shows.
+        let (schema_cookie, change_counter) = (self.db().schema_cookie()?, self.db().change_counter()?);
+
+        let sink: Box<dyn Write> = match options.output {
+            Some(path) => {
+                let file = File::create(path).map_err(|e| anyhow!(msg!("cannot-open", path = path, error = e)))?;
+                Box::new(BufWriter::new(file))
+            }
+            None => self.once.as_mut().unwrap_or(&mut self.output).writer(),
+        };
+        let mut sink = Checksummed::new(sink);
+
+        interrupt::clear();
+        let mut out = output::writer(&self.format, &mut sink);
+        out.set_raw_blobs(self.raw_blobs);
+        out.write_header(&names)?;
+        let mut last = None;
+        let mut rows = 0;
+        let mut unsaved = 0;
+        let result = self.databases[self.current].scan_table(table, after.as_ref(), &mut |key, row| {
+            interrupt::check()?;
+            out.write_row(&row)?;
+            rows += 1;
+            unsaved += 1;
+            if let Some(path) = options.cursor.filter(|_| unsaved == CURSOR_INTERVAL) {
+                out.flush()?;
+                save_cursor(path, &key)?;
+                unsaved = 0;
+            }
+            last = Some(key);
+            Ok(ControlFlow::Continue(()))
+        });
+        let complete = result.is_ok();
+        let result = result.and_then(|()| out.finish());
+        // Only rows that reached the output count as written.
+        let flushed = out.flush().is_ok();
+        drop(out);
+        if !flushed {
+            return result;
+        }
+
+        if let (Some(path), Some(key)) = (options.cursor, &last) {
+            if unsaved > 0 {
+                save_cursor(path, key)?;
+            }
+        }
+        if let (Some(manifest), Some(file)) = (options.manifest, options.output) {
+            let entry = manifest::Entry {
+                table: table.to_string(),
+                file: file.to_string(),
+                format: self.format.to_string(),
+                rows,
+                bytes: sink.bytes(),
+                sha256: sink.sha256(),
+                schema_cookie,
+                change_counter,
+                after: after.map(|key| key.to_string()),
+                last: last.map(|key| key.to_string()),
+                complete,
+            };
+            entry.append_to(manifest)?;
+        }
+        result
+    }
+
+    /// Writes the statements that rebuild the database in a new file, as
+    /// sqlite3's `.dump` does: every table with its rows, then the views,
+    /// triggers and indexes. With `filter`, only the objects of that table.
+    fn dump(&mut self, filter: Option<&str>) -> Result<()> {
+        let db = &self.databases[self.current];
+        let mut sink = self.once.as_mut().unwrap_or(&mut self.output).writer();
+        let matches = |schema: &&Schema| filter.is_none_or(|name| schema.tbl_name.eq_ignore_ascii_case(name));
+        // Like sqlite3, sqlite_sequence goes last so its rows outlive the
+        // tables that add to it.
+        let tables = db
+            .schema()
+            .iter()
+            .filter(|schema| schema.kind == Kind::Table)
+            .filter(matches)
+            .sorted_by_key(|schema| schema.name == "sqlite_sequence");
+
+        interrupt::clear();
+        writeln!(sink, "PRAGMA foreign_keys=OFF;")?;
+        writeln!(sink, "BEGIN TRANSACTION;")?;
+        let mut analyzed = false;
+        for table in tables {
+            if table.name == "sqlite_sequence" {
+                writeln!(sink, "DELETE FROM sqlite_sequence;")?;
+            } else if table.name.starts_with("sqlite_stat") {
+                if !analyzed {
+                    writeln!(sink, "ANALYZE sqlite_schema;")?;
+                    analyzed = true;
+                }
+            } else if table.name.starts_with("sqlite_") {
+                continue;
+            } else if table.sql.starts_with("CREATE TABLE \"") || table.sql.starts_with("CREATE TABLE '") {
+                writeln!(sink, "CREATE TABLE IF NOT EXISTS {};", &table.sql["CREATE TABLE ".len()..])?;
+            } else {
+                writeln!(sink, "{};", table.sql)?;
+            }
+            // Virtual tables keep no rows of their own.
+            if table.rootpage == 0 {
+                continue;
+            }
+            let mut out = output::writer(&Format::Insert(table.name.clone()), &mut sink);
+            db.scan_table(&table.name, None, &mut |_, row| {
+                interrupt::check()?;
+                out.write_row(&row)?;
+                Ok(ControlFlow::Continue(()))
+            })?;
+            out.finish()?;
+        }
+        for kind in [Kind::View, Kind::Trigger, Kind::Index] {
+            for schema in db.schema().iter().filter(|schema| schema.kind == kind).filter(matches) {
+                // Indexes for UNIQUE and PRIMARY KEY constraints come with their tables.
+                if !schema.sql.is_empty() {
+                    writeln!(sink, "{};", schema.sql)?;
+                }
+            }
+        }
+        writeln!(sink, "COMMIT;")?;
+        sink.flush()?;
+        Ok(())
+    }
+
+    /// Writes the rows of `table` as up to `jobs` shards at the same time, one
+    /// for each of the rowid ranges [`Database::rowid_ranges`] cuts it into,
+    /// to `output` with `.1`, `.2` and so on appended. Every job reads through
+    /// a connection of its own. With a manifest, each shard gets a line.
+    fn export_shards(&self, table: &str, output: &str, manifest: Option<&str>, jobs: usize) -> Result<()> {
+        let path = self.db().path().ok_or_else(|| anyhow!(msg!("jobs-need-file")))?;
+        let names = self.db().columns(table)?.into_iter().map(|c| c.name).collect::<Vec<_>>();
+        let ranges = self.db().rowid_ranges(table, jobs)?;
+        let (decoding, folding) = (self.db().text_decoding(), self.db().case_folding());
+        let (format, raw_blobs) = (self.format.clone(), self.raw_blobs);
+
+        interrupt::clear();
+        let results = thread::scope(|scope| {
+            let jobs = ranges
+                .iter()
+                .enumerate()
+                .map(|(idx, &range)| {
+                    let (names, format) = (&names, &format);
+                    scope.spawn(move || {
+                        let mut db = Database::load_db(path.to_string())?;
+                        db.set_text_decoding(decoding);
+                        db.set_case_folding(folding);
+                        export_range(&db, table, range, names, (format, raw_blobs), &format!("{}.{}", output, idx + 1))
+                    })
+                })
+                .collect::<Vec<_>>();
+            jobs.into_iter()
+                .map(|job| job.join().unwrap_or_else(|_| Err(anyhow!(msg!("export-job-panicked")))))
+                .collect::<Vec<_>>()
+        });
+
+        let mut failed = None;
+        for result in results {
+            match (result, manifest) {
+                (Ok(entry), Some(manifest)) => entry.append_to(manifest)?,
+                (Ok(_), None) => {}
+                (Err(e), _) => failed = failed.or(Some(e)),
+            }
+        }
+        failed.map_or(Ok(()), Err)
+    }
+
+    /// Prints the free pages, how many pages each b-tree takes up, and a map
+    /// of which runs of pages each one has.
+    /// Writes the rows `.carve` finds, after where each was found and how
+    /// likely it is to be a row of `table`.
+    fn carve(&mut self, table: &str) -> Result<()> {
+        interrupt::clear();
+        let carved = self.db().carve(table)?;
+        let mut names = ["page", "offset", "region", "confidence", "rowid"].map(String::from).to_vec();
+        names.extend(self.db().columns(table)?.into_iter().map(|c| c.name));
+        let mut out = output::writer(&self.format, self.once.as_mut().unwrap_or(&mut self.output).writer());
+        out.set_raw_blobs(self.raw_blobs);
+        out.write_header(&names)?;
+        for row in carved {
+            let mut values = vec![
+                Record::Int64(row.page as i64),
+                Record::Int64(row.offset as i64),
+                Record::Text(row.region.to_string()),
+                Record::Float((row.confidence * 100.0).round() / 100.0),
+                row.rowid.map_or(Record::Null, Record::Int64),
+            ];
+            values.extend(row.values);
+            out.write_row(&values)?;
+        }
+        out.finish()?;
+        out.flush()
+    }
+
+    fn freelist(&self) -> Result<()> {
+        let freelist = self.db().freelist()?;
+        let usage = self.db().page_usage()?;
+        let free = freelist.pages();
+        println!(
+            "free pages: {} ({} trunk, {} leaf)",
+            free.len(),
+            freelist.trunks.len(),
+            freelist.leaves.len()
+        );
+        if !free.is_empty() {
+            println!("free page numbers: {}", page_ranges(&free));
+        }
+
+        let label = |page: &PageUse| match page {
+            PageUse::Tree { name, .. } => name.clone(),
+            PageUse::FreeTrunk | PageUse::FreeLeaf => "(free)".to_string(),
+            PageUse::PointerMap => "(pointer map)".to_string(),
+            PageUse::PendingByte => "(pending byte)".to_string(),
+            PageUse::Unused => "(unused)".to_string(),
+        };
+        // In order of first page, with interior, leaf and overflow counts.
+        let mut owners: Vec<(String, [usize; 3])> = Vec::new();
+        for page in &usage {
+            let name = label(page);
+            let idx = match owners.iter().position(|(owner, _)| *owner == name) {
+                Some(idx) => idx,
+                None => {
+                    owners.push((name, [0; 3]));
+                    owners.len() - 1
+                }
+            };
+            let role = match page {
+                PageUse::Tree { role: PageRole::Interior, .. } => 0,
+                PageUse::Tree { role: PageRole::Overflow, .. } => 2,
+                _ => 1,
+            };
+            owners[idx].1[role] += 1;
+        }
+        let width = owners.iter().map(|(name, _)| name.len()).max().unwrap_or_default();
+        println!("page usage:");
+        for (name, [interior, leaf, overflow]) in &owners {
+            let total = interior + leaf + overflow;
+            match name.starts_with('(') {
+                true => println!("  {:width$}  {}", name, total),
+                false => println!(
+                    "  {:width$}  {} ({} interior, {} leaf, {} overflow)",
+                    name, total, interior, leaf, overflow
+                ),
+            }
+        }
+        println!("page map:");
+        let mut start = 0;
+        for idx in 1..=usage.len() {
+            if idx == usage.len() || label(&usage[idx]) != label(&usage[start]) {
+                let pages = match idx - start {
+                    1 => (start + 1).to_string(),
+                    _ => format!("{}-{}", start + 1, idx),
+                };
+                println!("  {:12}  {}", pages, label(&usage[start]));
+                start = idx;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints a line for each region of slack, with the lines of a hex dump
+    /// of it that are not all zero when `hex` is set, then the totals.
+    fn dedup(&self, paths: &[&str]) -> Result<()> {
+        let others = paths
+            .iter()
+            .map(|path| Database::load_db(path.to_string()).map_err(|e| anyhow!(msg!("cannot-open-database", path = path, error = e))))
+            .collect::<Result<Vec<_>>>()?;
+        interrupt::clear();
+        let databases = std::iter::once(self.db()).chain(&others).collect::<Vec<_>>();
+        let dedup = dedup_pages(&databases)?;
+        for file in &dedup.files {
+            println!(
+                "{}: {} pages, {} shared, {} unique",
+                file.path.as_deref().unwrap_or(":memory:"),
+                file.pages,
+                file.shared,
+                file.unique()
+            );
+        }
+        let percent = match dedup.total_bytes() {
+            0 => 0.0,
+            total => 100.0 * dedup.shared_bytes as f64 / total as f64,
+        };
+        println!(
+            "{} pages, {} distinct; {} of {} bytes shared ({:.1}%)",
+            dedup.total_pages(),
+            dedup.distinct,
+            dedup.shared_bytes,
+            dedup.total_bytes(),
+            percent
+        );
+        Ok(())
+    }
+
+    fn slack(&self, hex: bool) -> Result<()> {
+        interrupt::clear();
+        let slack = self.db().slack()?;
+        for region in &slack {
+            println!(
+                "page {} ({}): {} at {}, {} bytes, {} not zero",
+                region.page,
+                region.tree,
+                region.region,
+                region.offset,
+                region.bytes.len(),
+                region.nonzero()
+            );
+            if !hex {
+                continue;
+            }
+            let start = region.offset + region.bytes.len() - region.contents().len();
+            for (idx, line) in region.contents().chunks(16).enumerate() {
+                if line.iter().all(|&b| b == 0) {
+                    continue;
+                }
+                let bytes = line.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ");
+                let text = line.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' });
+                println!("  {:05x}  {:<47}  {}", start + 16 * idx, bytes, text.collect::<String>());
+            }
+        }
+        let total: usize = slack.iter().map(|region| region.bytes.len()).sum();
+        let nonzero: usize = slack.iter().map(|region| region.nonzero()).sum();
+        println!("{} regions, {} bytes, {} not zero", slack.len(), total, nonzero);
+        Ok(())
+    }
+
+    /// Appends the records of the CSV file at `path` to `table` as rows of
+    /// text values. A table that does not exist yet is created first, with
+    /// a TEXT column for each field of the first record. Records with the
+    /// wrong number of fields are warned about, as sqlite does, and padded
+    /// with NULLs or cut short.
+    fn import(&mut self, path: &str, table: &str) -> Result<()> {
+        let text = fs::read_to_string(path).map_err(|e| anyhow!(msg!("cannot-open", path = path, error = e)))?;
+        let mut records = csv::parse(&text).map_err(|e| anyhow!("{}:{}", path, e))?.into_iter();
+        let columns = match self.db().columns(table) {
+            Ok(columns) => columns.len(),
+            Err(e) if matches!(e.downcast_ref::<Error>(), Some(Error::TableNotFound { .. })) => {
+                let header = records.next().ok_or_else(|| anyhow!(msg!("empty-file", path = path)))?;
+                let definitions = header.fields.iter().map(|name| format!("{} TEXT", sql::quote_identifier(name, true)));
+                let sql = format!("CREATE TABLE {}({})", sql::quote_identifier(table, true), definitions.collect::<Vec<_>>().join(", "));
+                let mut out = output::writer(&self.format, io::sink());
+                self.db_mut().execute_statement(&parse_sql(&sql)?, out.as_mut())?;
+                header.fields.len()
+            }
+            Err(e) => Err(e)?,
+        };
+
+        let rows = records
+            .map(|record| {
+                let found = record.fields.len();
+                let args: [(&str, &dyn Display); 4] =
+                    [("path", &path), ("line", &record.line), ("expected", &columns), ("found", &found)];
+                if found < columns {
+                    eprintln!("{}", message("import-too-few", &args));
+                } else if found > columns {
+                    eprintln!("{}", message("import-too-many", &args));
+                }
+                let mut row = record.fields.into_iter().take(columns).map(Record::Text).collect::<Vec<_>>();
+                row.resize(columns, Record::Null);
+                row
+            })
+            .collect();
+        interrupt::clear();
+        self.db().import_rows(table, rows)?;
+        Ok(())
+    }
+
+    /// Reads statements from stdin until EOF or `.quit`.
+    pub fn repl(&mut self) -> Result<()> {
+        let stdin = io::stdin();
+        let interactive = stdin.is_terminal();
+        self.run_script(stdin.lock(), interactive)?;
+        if interactive {
+            println!();
+        }
+        Ok(())
+    }
+
+    /// Runs every statement in `input`. Statements may span several lines and
+    /// end at the first `;` outside of a quoted string. Outside interactive
+    /// use, failures are counted and reported with their position in the script.
+    fn run_script(&mut self, input: impl BufRead, interactive: bool) -> Result<()> {
+        let mut lines = input.lines();
+        let mut buffer = String::new();
+        let mut line_number = 0;
+        let mut start_line = 0;
+        let mut index = 0;
+
+        loop {
+            if interactive {
+                print!("{}", if buffer.is_empty() { "sqlite> " } else { "   ...> " });
+                io::stdout().flush()?;
+            }
+
+            let Some(line) = lines.next() else {
+                break;
+            };
+            let line = line?;
+            line_number += 1;
+
+            if buffer.is_empty() && line.trim_start().starts_with('.') {
+                match line.trim() {
+                    ".quit" | ".exit" => break,
+                    command => {
+                        index += 1;
+                        let result = self.execute(command);
+                        self.report(result, interactive, line_number, index)?;
+                    }
+                }
+                continue;
+            }
+
+            if buffer.trim().is_empty() {
+                start_line = line_number;
+            }
+            buffer.push_str(&line);
+            buffer.push('\n');
+            while let Some(end) = statement_end(&buffer) {
+                let statement: String = buffer.drain(..=end).collect();
+                if !statement.trim().trim_end_matches(';').is_empty() {
+                    index += 1;
+                    let result = self.execute(&statement);
+                    self.report(result, interactive, start_line, index)?;
+                }
+                start_line = line_number;
+            }
+            if buffer.trim().is_empty() {
+                buffer.clear();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Prints a failed statement's error and decides whether the script goes
+    /// on. A closed stdout or `--bail` ends it.
+    fn report(
+        &mut self,
+        result: Result<()>,
+        interactive: bool,
+        line: usize,
+        index: usize,
+    ) -> Result<()> {
+        let Err(e) = result else {
+            return Ok(());
+        };
+        if is_broken_pipe(&e) || e.is::<Bailed>() {
+            return Err(e);
+        }
+        if interactive {
+            eprintln!("{}", message("error", &[("message", &e)]));
+            return Ok(());
+        }
+
+        eprintln!(
+            "{}",
+            message("error-near-line", &[("line", &line), ("statement", &index), ("message", &e)])
+        );
+        self.failures += 1;
+        match self.bail {
+            true => Err(Bailed.into()),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Destination {
+    /// The destination `.output` and `.once` name with `target`: a file, a
+    /// command after `|`, or stdout when empty.
+    fn open(target: &str) -> Result<Self> {
+        if target.is_empty() {
+            return Ok(Destination::Stdout);
+        }
+        let Some(command) = target.strip_prefix('|') else {
+            let file = File::create(target).map_err(|e| anyhow!(msg!("cannot-open", path = target, error = e)))?;
+            return Ok(Destination::File(BufWriter::new(file)));
+        };
+        let command = command.trim();
+        #[cfg(unix)]
+        let mut shell = Command::new("sh");
+        #[cfg(unix)]
+        shell.arg("-c");
+        #[cfg(not(unix))]
+        let mut shell = Command::new("cmd");
+        #[cfg(not(unix))]
+        shell.arg("/C");
+        let child = shell
+            .arg(command)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!(msg!("cannot-run", command = command, error = e)))?;
+        Ok(Destination::Pipe(child))
+    }
+
+    fn is_pipe(&self) -> bool {
+        matches!(self, Destination::Pipe(_))
+    }
+
+    fn writer(&mut self) -> Box<dyn Write + '_> {
+        match self {
+            Destination::Stdout => Box::new(io::stdout().lock()),
+            Destination::File(file) => Box::new(file),
+            Destination::Pipe(child) => Box::new(child.stdin.as_mut().unwrap()),
+        }
+    }
+
+    /// Flushes what was written; for a command, closes its input and waits
+    /// for it to finish.
+    fn close(self) -> Result<()> {
+        match self {
+            Destination::Stdout => Ok(()),
+            Destination::File(mut file) => Ok(file.flush()?),
+            Destination::Pipe(mut child) => {
+                drop(child.stdin.take());
+                child.wait()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Drop for Shell {
+    /// A command still reading `.output` gets to finish before the shell exits.
+    fn drop(&mut self) {
+        let output = std::mem::replace(&mut self.output, Destination::Stdout);
+        let _ = output.close();
+    }
+}
+
+/// Replaces the cursor file in one step, so that a crash leaves the old key
+/// or the new one but never a partial write.
+/// Sorted page numbers written as comma-separated runs, such as `3, 7-9`.
+fn page_ranges(pages: &[usize]) -> String {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for &page in pages {
+        match runs.last_mut() {
+            Some(run) if run.1 + 1 == page => run.1 = page,
+            _ => runs.push((page, page)),
+        }
+    }
+    let runs = runs.iter().map(|&(first, last)| match first == last {
+        true => first.to_string(),
+        false => format!("{}-{}", first, last),
+    });
+    runs.collect::<Vec<_>>().join(", ")
+}
+
+/// Writes the rows of `table` with rowids in `low..=high` to a new file at
+/// `path` and describes it for the manifest.
+fn export_range(
+    db: &Database,
+    table: &str,
+    (low, high): (i64, i64),
+    names: &[String],
+    (format, raw_blobs): (&Format, bool),
+    path: &str,
+) -> Result<manifest::Entry> {
+    // Taken before reading any row, so a change during the export shows.
+    let (schema_cookie, change_counter) = (db.schema_cookie()?, db.change_counter()?);
+    let file = File::create(path).map_err(|e| anyhow!(msg!("cannot-open", path = path, error = e)))?;
+    let mut sink = Checksummed::new(BufWriter::new(file));
+
+    let mut out = output::writer(format, &mut sink);
+    out.set_raw_blobs(raw_blobs);
+    out.write_header(names)?;
+    let after = low.checked_sub(1).map(RowKey::Rowid);
+    let mut last = None;
+    let mut rows = 0;
+    db.scan_table(table, after.as_ref(), &mut |key, row| {
+        interrupt::check()?;
+        if matches!(key, RowKey::Rowid(rowid) if rowid > high) {
+            return Ok(ControlFlow::Break(()));
+        }
+        out.write_row(&row)?;
+        rows += 1;
+        last = Some(key);
+        Ok(ControlFlow::Continue(()))
+    })?;
+    out.finish()?;
+    out.flush()?;
+    drop(out);
+
+    Ok(manifest::Entry {
+        table: table.to_string(),
+        file: path.to_string(),
+        format: format.to_string(),
+        rows,
+        bytes: sink.bytes(),
+        sha256: sink.sha256(),
+        schema_cookie,
+        change_counter,
+        after: after.map(|key| key.to_string()),
+        last: last.map(|key| key.to_string()),
+        complete: true,
+    })
+}
+
+fn save_cursor(path: &str, key: &RowKey) -> Result<()> {
+    let temp = format!("{}.tmp", path);
+    fs::write(&temp, format!("{}\n", key))?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+pub fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    let io_error = match error.downcast_ref::<Error>() {
+        Some(Error::Io(e)) => Some(e),
+        _ => error.downcast_ref::<io::Error>(),
+    };
+    io_error.is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+}
+
+/// Renders a lookup failure with the statement line that names the missing
+/// table or column, the name underlined, and the suggested fix if there is one.
+fn snippet(statement: &str, error: &Error) -> String {
+    let name = error.lookup_name().unwrap_or_default();
+    let hint = error
+        .suggestion()
+        .map(|s| message("did-you-mean", &[("name", &s)]))
+        .unwrap_or_default();
+
+    match sql::name_span(statement, name) {
+        Some(span) => {
+            let start = statement[..span.start].rfind('\n').map_or(0, |at| at + 1);
+            let end = statement[span.start..].find('\n').map_or(statement.len(), |at| span.start + at);
+            // A qualified name can go on over the next line; underline this one.
+            let width = statement[span.start..span.end.min(end)].chars().count();
+            format!(
+                "{}\n  {}\n  {}{} {}",
+                error,
+                &statement[start..end],
+                " ".repeat(statement[start..span.start].chars().count()),
+                "^".repeat(width),
+                hint
+            )
+            .trim_end()
+            .to_string()
+        }
+        None if hint.is_empty() => error.to_string(),
+        None => format!("{}\n  {}", error, hint),
+    }
+}
+
+/// Renders `error` with the line of `statement` holding byte `offset` and a
+/// caret under it.
+fn pointed(statement: &str, offset: usize, error: &anyhow::Error) -> String {
+    let start = statement[..offset].rfind('\n').map_or(0, |at| at + 1);
+    let end = statement[offset..].find('\n').map_or(statement.len(), |at| offset + at);
+    let indent = " ".repeat(statement[start..offset].chars().count());
+    format!("{}\n  {}\n  {}^", error, &statement[start..end], indent)
+}
+
+/// Byte offset of the `;` terminating the first complete statement.
+fn statement_end(input: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut comment = false;
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((idx, c)) = chars.next() {
+        match (quote, c) {
+            _ if comment => comment = c != '\n',
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '-') if matches!(chars.peek(), Some((_, '-'))) => comment = true,
+            (None, ';') => return Some(idx),
+            _ => {}
+        }
+    }
+
+    None
+}
@@ -0,0 +1,197 @@
+//! Where database pages are read from: a file on disk or a buffer in memory.
+
+use crate::{error::Error, messages::msg};
+use anyhow::{anyhow, Result};
+use std::{fs::File, sync::RwLock};
+
+/// Positioned reads and writes over the raw bytes of a database. Reads may
+/// come from several threads at once.
+pub trait PageSource: Send + Sync {
+    /// Fills `buf` with the bytes starting at `offset`.
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<()>;
+
+    /// Stores `buf` at `offset`, growing the database if it ends there.
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> Result<()>;
+
+    /// Total number of bytes available.
+    fn size(&self) -> Result<u64>;
+
+    /// Cuts the database, or extends it with zeros, to `size` bytes.
+    fn truncate(&self, size: u64) -> Result<()>;
+
+    /// Returns once everything written so far would survive a crash.
+    fn sync(&self) -> Result<()>;
+
+    /// Whether every write fails.
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Fixes what reads see until the next call, for a statement about to
+    /// run, where other processes' commits could otherwise show up halfway
+    /// through it.
+    fn pin_snapshot(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Calls `f` with the `len` bytes at `offset`, from where the source
+    /// keeps them when it can lend them out and from a copy otherwise.
+    fn with_bytes(&self, offset: u64, len: usize, f: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        let mut buf = vec![0; len];
+        self.read_exact_at(&mut buf, offset)?;
+        f(&buf)
+    }
+}
+
+#[cfg(unix)]
+impl PageSource for File {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, buf, offset).map_err(Error::Io)?;
+        Ok(())
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> Result<()> {
+        std::os::unix::fs::FileExt::write_all_at(self, buf, offset).map_err(Error::Io)?;
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.metadata().map_err(Error::Io)?.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        self.set_len(size).map_err(Error::Io)?;
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.sync_all().map_err(Error::Io)?;
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl PageSource for File {
+    // `seek_read` and `seek_write` take the offset with each call, so threads
+    // sharing the file do not race on its cursor; they may do less than all
+    // of it, though.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> Result<()> {
+        use std::os::windows::fs::FileExt;
+
+        while !buf.is_empty() {
+            match self.seek_read(buf, offset).map_err(Error::Io)? {
+                0 => Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()))?,
+                read => {
+                    buf = &mut buf[read..];
+                    offset += read as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_all_at(&self, mut buf: &[u8], mut offset: u64) -> Result<()> {
+        use std::os::windows::fs::FileExt;
+
+        while !buf.is_empty() {
+            match self.seek_write(buf, offset).map_err(Error::Io)? {
+                0 => Err(Error::Io(std::io::ErrorKind::WriteZero.into()))?,
+                written => {
+                    buf = &buf[written..];
+                    offset += written as u64;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.metadata().map_err(Error::Io)?.len())
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        self.set_len(size).map_err(Error::Io)?;
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.sync_all().map_err(Error::Io)?;
+        Ok(())
+    }
+}
+
+/// The whole database already in memory.
+impl PageSource for RwLock<Vec<u8>> {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let bytes = self.read().unwrap();
+        let start = usize::try_from(offset)?;
+        let found = start
+            .checked_add(buf.len())
+            .and_then(|end| bytes.get(start..end))
+            .ok_or_else(|| anyhow!(msg!("read-past-end", offset = offset)))?;
+        buf.copy_from_slice(found);
+        Ok(())
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> Result<()> {
+        let mut bytes = self.write().unwrap();
+        let start = usize::try_from(offset)?;
+        let end = start + buf.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0);
+        }
+        bytes[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64> {
+        Ok(self.read().unwrap().len() as u64)
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        self.write().unwrap().resize(usize::try_from(size)?, 0);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A source that could only be opened for reading. Writes fail the way
+/// sqlite reports them.
+pub struct ReadOnly<S>(pub S);
+
+impl<S: PageSource> PageSource for ReadOnly<S> {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        self.0.read_exact_at(buf, offset)
+    }
+
+    fn write_all_at(&self, _buf: &[u8], _offset: u64) -> Result<()> {
+        Err(anyhow!(msg!("readonly-database")))
+    }
+
+    fn size(&self) -> Result<u64> {
+        self.0.size()
+    }
+
+    fn truncate(&self, _size: u64) -> Result<()> {
+        Err(anyhow!(msg!("readonly-database")))
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    fn pin_snapshot(&self) -> Result<()> {
+        self.0.pin_snapshot()
+    }
+
+    fn with_bytes(&self, offset: u64, len: usize, f: &mut dyn FnMut(&[u8]) -> Result<()>) -> Result<()> {
+        self.0.with_bytes(offset, len, f)
+    }
+}
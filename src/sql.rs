@@ -1,70 +1,447 @@
 #![allow(unused)]
-use anyhow::Result;
+use crate::{aggregate, error::Error, functions, messages::msg, record::Record};
+use anyhow::{anyhow, Result};
+use itertools::Itertools;
+use std::{collections::HashMap, fmt::Display};
 
 peg::parser! {
     grammar sql_parser() for str {
-        rule _() = quiet!{[' ' | '\t' | '\r' | '\n']*}
+        rule _() = quiet!{([' ' | '\t' | '\r' | '\n'] / "--" [^ '\n']* / "/*" (!"*/" [_])* "*/")*}
 
         rule identifier() -> &'input str
-            = quiet!{ident:$(['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '*']*) { ident }}
+            = quiet!{ident:$(['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*) { ident }}
             / expected!("identifier")
 
-        rule table_name() -> &'input str = identifier()
+        // Quoted the way `ALTER TABLE ... RENAME TO` writes names, or not.
+        rule table_name() -> &'input str
+            = "\"" n:$((!"\"" [_])*) "\"" { n }
+            / "[" n:$((!"]" [_])*) "]" { n }
+            / "`" n:$((!"`" [_])*) "`" { n }
+            / identifier()
 
-        rule column_name() -> &'input str = i("count(*)") / identifier()
+        rule column_name() -> &'input str = identifier()
 
-        rule data_type() -> &'input str
-            = quiet!{
-                t:$("integer" / "text" / "INT" / "TEXT" / "REAL" / "BLOB") { t }
-            } / expected!("data type")
+        rule name() -> &'input str
+            = "\"" n:$((!"\"" [_])*) "\"" { n }
+            / "[" n:$((!"]" [_])*) "]" { n }
+            / "`" n:$((!"`" [_])*) "`" { n }
+            / "'" n:$((!"'" [_])*) "'" { n }
+            / identifier()
+
+        rule word() -> &'input str
+            = quiet!{w:$(['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*) { w }}
+
+        rule signed_number() -> &'input str
+            = $(['+' | '-']? ['0'..='9']+ ("." ['0'..='9']*)?)
+
+        rule balanced() = "(" ("'" ("''" / [^ '\''])* "'" / balanced() / [^ '(' | ')'])* ")"
+
+        rule reserved()
+            = keyword("CONSTRAINT") / keyword("PRIMARY") / keyword("NOT") / keyword("NULL")
+            / keyword("UNIQUE") / keyword("CHECK") / keyword("DEFAULT") / keyword("COLLATE")
+            / keyword("REFERENCES") / keyword("GENERATED") / keyword("AS") / keyword("FOREIGN")
+
+        rule type_name() -> TypeName
+            = text:$(type_words() (_ type_args())?) {
+                let (name, args) = text.split_once('(').unwrap_or((text, ""));
+                TypeName {
+                    name: name.split_whitespace().collect::<Vec<_>>().join(" "),
+                    args: args.trim_end_matches(')').split(',').map(str::trim).filter(|a| !a.is_empty()).map(String::from).collect(),
+                    text: text.to_string(),
+                }
+            }
+
+        rule type_words() = (!reserved() word()) ++ _
+
+        rule type_args() = "(" _ (signed_number() ** (_ "," _)) _ ")"
+
+        rule conflict_clause() = keyword("ON") _ keyword("CONFLICT") _ word()
+
+        rule default_value() -> &'input str
+            = $(balanced())
+            / signed_number()
+            / $("'" ("''" / [^ '\''])* "'")
+            / $(['x' | 'X'] "'" ['0'..='9' | 'a'..='f' | 'A'..='F']* "'")
+            / word()
+
+        rule fk_action()
+            = keyword("ON") _ (keyword("DELETE") / keyword("UPDATE")) _ (
+                keyword("SET") _ (keyword("NULL") / keyword("DEFAULT"))
+                / keyword("CASCADE") / keyword("RESTRICT") / keyword("NO") _ keyword("ACTION")
+            )
+            / keyword("MATCH") _ word()
+            / (keyword("NOT") _)? keyword("DEFERRABLE") (_ keyword("INITIALLY") _ (keyword("DEFERRED") / keyword("IMMEDIATE")))?
+
+        rule foreign_key_clause() -> ForeignKey
+            = keyword("REFERENCES") _ table:name() _ columns:("(" _ c:(name() ** (_ "," _)) _ ")" { c })? (_ fk_action())* {
+                ForeignKey {
+                    table: table.to_string(),
+                    columns: columns.unwrap_or_default().into_iter().map(|c| c.to_string()).collect(),
+                }
+            }
+
+        rule column_constraint() -> ColumnConstraint
+            = (keyword("CONSTRAINT") _ name() _)? c:(
+                keyword("PRIMARY") _ keyword("KEY") _ order:(keyword("ASC") / keyword("DESC"))? _ conflict_clause()? _ auto:keyword("AUTOINCREMENT")? {
+                    ColumnConstraint::PrimaryKey {
+                        descending: order == Some("DESC"),
+                        autoincrement: auto.is_some(),
+                    }
+                }
+                / keyword("NOT") _ keyword("NULL") _ conflict_clause()? { ColumnConstraint::NotNull }
+                / keyword("NULL") { ColumnConstraint::Null }
+                / keyword("UNIQUE") _ conflict_clause()? { ColumnConstraint::Unique }
+                / keyword("CHECK") _ e:$(balanced()) { ColumnConstraint::Check(e.to_string()) }
+                / keyword("DEFAULT") _ d:default_value() { ColumnConstraint::Default(d.to_string()) }
+                / keyword("COLLATE") _ c:name() { ColumnConstraint::Collate(c.to_string()) }
+                / fk:foreign_key_clause() { ColumnConstraint::References(fk) }
+                / (keyword("GENERATED") _ keyword("ALWAYS") _)? keyword("AS") _ e:$(balanced()) _ (keyword("STORED") / keyword("VIRTUAL"))? {
+                    ColumnConstraint::Generated(e.to_string())
+                }
+            ) { c }
+
+        rule indexed_column() -> &'input str
+            = n:name() (_ keyword("COLLATE") _ name())? (_ (keyword("ASC") / keyword("DESC")))? { n }
+
+        rule indexed_columns() -> Vec<String>
+            = "(" _ cols:(indexed_column() ** (_ "," _)) _ ")" {
+                cols.into_iter().map(|c| c.to_string()).collect()
+            }
+
+        rule table_constraint_start()
+            = keyword("CONSTRAINT") / keyword("PRIMARY") / keyword("UNIQUE") / keyword("CHECK") / keyword("FOREIGN")
+
+        rule table_constraint() -> TableConstraint
+            = (keyword("CONSTRAINT") _ name() _)? c:(
+                keyword("PRIMARY") _ keyword("KEY") _ cols:indexed_columns() _ conflict_clause()? {
+                    TableConstraint::PrimaryKey(cols)
+                }
+                / keyword("UNIQUE") _ cols:indexed_columns() _ conflict_clause()? { TableConstraint::Unique(cols) }
+                / keyword("CHECK") _ e:$(balanced()) { TableConstraint::Check(e.to_string()) }
+                / keyword("FOREIGN") _ keyword("KEY") _ cols:indexed_columns() _ fk:foreign_key_clause() {
+                    TableConstraint::ForeignKey {
+                        columns: cols,
+                        references: fk,
+                    }
+                }
+            ) { c }
 
         rule value() -> &'input str
             = quiet!{val:$(['a'..='z' | 'A'..='Z' | '0'..='9' | '_']+) { val }}
 
-        rule string_literal() -> &'input str
-            = "\"" val:$((!"\"" [_])*) "\"" { val }
-            / "'" val:$((!"'" [_])*) "'" { val }
+        rule string_literal() -> String
+            = "\"" val:$((!"\"" [_])*) "\"" { val.to_string() }
+            / "'" val:$(("''" / [^ '\''])*) "'" { val.replace("''", "'") }
 
-        rule condition() -> Condition
-        = col:column_name() _ "=" _ val:string_literal() {
+        rule integer_literal() -> Record
+            = n:$("-"? ['0'..='9']+) {? n.parse().map(Record::Int64).or(Err("integer")) }
+
+        rule float_literal() -> Record
+            = n:$("-"? ['0'..='9']* "." ['0'..='9']+) {? n.parse().map(Record::Float).or(Err("float")) }
+
+        rule literal() -> Record
+            = float_literal()
+            / integer_literal()
+            / s:string_literal() { Record::Text(s) }
+            / b:$(['x' | 'X'] "'" ['0'..='9' | 'a'..='f' | 'A'..='F']* "'") { literal_value(b) }
+            / keyword("NULL") { Record::Null }
+
+        rule function_call() -> Expr
+            = name:identifier() _ "(" _ "*" _ ")" {
+                Expr::Function {
+                    name: name.to_string(),
+                    args: Vec::new(),
+                    distinct: false,
+                }
+            }
+            / name:identifier() _ "(" _ distinct:(keyword("DISTINCT") _)? args:(expr() ** (_ "," _)) _ ")" {
+                Expr::Function {
+                    name: name.to_string(),
+                    args,
+                    distinct: distinct.is_some(),
+                }
+            }
+
+        rule primary() -> Expr
+            = "(" _ e:expr() _ ")" { e }
+            / function_call()
+            / l:float_literal() { Expr::Literal(l) }
+            / l:integer_literal() { Expr::Literal(l) }
+            / t:table_name() _ "." _ c:name() { Expr::Column(ColumnRef::qualified(t, c)) }
+            / "\"" c:$((!"\"" [_])*) "\"" { Expr::Column(ColumnRef::quoted(c)) }
+            / s:string_literal() { Expr::Literal(Record::Text(s)) }
+            / keyword("NULL") { Expr::Literal(Record::Null) }
+            / b:$(['x' | 'X'] "'" ['0'..='9' | 'a'..='f' | 'A'..='F']* "'") { Expr::Literal(literal_value(b)) }
+            / parameter()
+            / c:column_name() { Expr::Column(ColumnRef::bare(c)) }
+
+        // Numbered from 1 by `number_parameters` once the statement is parsed.
+        rule parameter() -> Expr
+            = n:$("?" ['0'..='9']+) {?
+                match n[1..].parse() {
+                    Ok(index) if (1..=MAX_PARAMETER).contains(&index) => {
+                        Ok(Expr::Parameter { index, name: Some(n.to_string()) })
+                    }
+                    _ => Err("parameter number from 1 to 32766"),
+                }
+            }
+            / "?" { Expr::Parameter { index: 0, name: None } }
+            / n:$([':' | '@' | '$'] ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']+) {
+                Expr::Parameter { index: 0, name: Some(n.to_string()) }
+            }
+
+        rule unary() -> Expr
+            = "-" _ e:unary() { Expr::Negate(Box::new(e)) }
+            / "+" _ e:unary() { e }
+            / primary()
+
+        rule concat() -> Expr
+            = first:unary() rest:(_ "||" _ e:unary() { (BinaryOp::Concat, e) })* { fold_binary(first, rest) }
+
+        rule product() -> Expr
+            = first:concat() rest:(_ op:product_op() _ e:concat() { (op, e) })* { fold_binary(first, rest) }
+
+        rule expr() -> Expr
+            = first:product() rest:(_ op:sum_op() _ e:product() { (op, e) })* { fold_binary(first, rest) }
+
+        rule product_op() -> BinaryOp
+            = "*" { BinaryOp::Mul }
+            / "/" { BinaryOp::Div }
+            / "%" { BinaryOp::Rem }
+
+        rule sum_op() -> BinaryOp
+            = "+" { BinaryOp::Add }
+            / "-" { BinaryOp::Sub }
+
+        rule binary_op() = product_op() / sum_op() / "||"
+
+        rule clause_keyword()
+            = keyword("WHERE") / keyword("GROUP") / keyword("HAVING") / keyword("ORDER") / keyword("LIMIT")
+            / keyword("JOIN") / keyword("INNER") / keyword("CROSS") / keyword("LEFT") / keyword("NATURAL")
+            / keyword("ON") / keyword("USING") / keyword("UNION") / keyword("INTERSECT") / keyword("EXCEPT")
+
+        rule table_ref() -> TableRef
+            = schema:(s:identifier() _ "." _ { s.to_string() })? name:table_name()
+            alias:(_ (keyword("AS") _)? !clause_keyword() a:identifier() { a.to_string() })? {
+                TableRef {
+                    schema,
+                    name: name.to_string(),
+                    alias,
+                }
+            }
+
+        rule join() -> Join
+            = _ ("," / ((keyword("INNER") / keyword("CROSS")) _)? keyword("JOIN")) _ table:table_ref()
+            on:(_ keyword("ON") _ c:condition() { c })? {
+                Join { table, on }
+            }
+
+        rule result_column() -> ResultColumn
+            = text:$(expr()) alias:(_ keyword("AS") _ a:name() { a.to_string() })? {?
+                // sqlite names unaliased columns by their text as written.
+                let expr = expr_text(text).or(Err("expression"))?;
+                Ok(ResultColumn { expr, alias, text: text.to_string() })
+            }
+
+        rule comparison() -> Condition
+            = col:column_name() _ "=" _ val:string_literal() !(_ binary_op()) {
                 Condition::Equals {
                     column: col.to_string(),
-                    value: val.to_string(),
+                    value: val,
+                }
+            }
+            / col:column_name() _ keyword("LIKE") _ pattern:string_literal() !(_ binary_op()) {
+                Condition::Like {
+                    column: col.to_string(),
+                    pattern,
+                }
+            }
+            / e:expr() _ not:(keyword("NOT") _)? keyword("BETWEEN") _ low:expr() _ keyword("AND") _ high:expr() {
+                let between = Condition::Between {
+                    expr: Box::new(e),
+                    low: Box::new(low),
+                    high: Box::new(high),
+                };
+                match not {
+                    Some(_) => Condition::Not(Box::new(between)),
+                    None => between,
+                }
+            }
+            / e:expr() _ not:(keyword("NOT") _)? keyword("IN") _ "(" _ list:(expr() ** (_ "," _)) _ ")" {
+                let within = Condition::In {
+                    expr: Box::new(e),
+                    list,
+                };
+                match not {
+                    Some(_) => Condition::Not(Box::new(within)),
+                    None => within,
+                }
+            }
+            / left:expr() _ op:compare_op() _ right:expr() {
+                Condition::Compare {
+                    left: Box::new(left),
+                    op,
+                    right: Box::new(right),
                 }
             }
 
+        rule compare_op() -> CompareOp
+            = ("==" / "=") { CompareOp::Eq }
+            / ("!=" / "<>") { CompareOp::Ne }
+            / "<=" { CompareOp::Le }
+            / ">=" { CompareOp::Ge }
+            / "<" { CompareOp::Lt }
+            / ">" { CompareOp::Gt }
+
+        rule condition() -> Condition
+            = first:and_condition() rest:(_ keyword("OR") _ c:and_condition() { c })* {
+                rest.into_iter()
+                    .fold(first, |a, b| Condition::Or(Box::new(a), Box::new(b)))
+            }
+
+        rule and_condition() -> Condition
+            = first:not_condition() rest:(_ keyword("AND") _ c:not_condition() { c })* {
+                rest.into_iter()
+                    .fold(first, |a, b| Condition::And(Box::new(a), Box::new(b)))
+            }
+
+        rule not_condition() -> Condition
+            = keyword("NOT") _ c:not_condition() { Condition::Not(Box::new(c)) }
+            / "(" _ c:condition() _ ")" { c }
+            / comparison()
+
         rule column_def() -> ColumnDef
-            = name:(column_name() / string_literal()) _ data_type:data_type() {
-                ColumnDef {
-                    name: name.to_string(),
-                    data_type: data_type.to_string(),
-                }
+            = !table_constraint_start() name:name() _ type_name:type_name()? _ constraints:(column_constraint() ** _) {
+                ColumnDef::new(name, type_name, constraints)
             }
 
         rule select_statement() -> Statement
-            = i("SELECT") _ cols:(column_name() ** (_ "," _)) _ i("FROM") _ table:table_name() _ cond:(i("WHERE") _ c:condition() { c })? {
+            = i("SELECT") _ cols:(result_column() ** (_ "," _)) _ i("FROM") _ from:table_ref() joins:join()* _ cond:(i("WHERE") _ c:condition() { c })?
+            _ group_by:(keyword("GROUP") _ keyword("BY") _ g:(expr() ++ (_ "," _)) { g })?
+            _ having:(keyword("HAVING") _ c:condition() { c })? {
                 Statement::Select {
-                    table: table.to_string(),
-                    columns: cols.into_iter().map(|s| s.to_string()).collect(),
+                    from,
+                    joins,
+                    columns: cols,
                     condition: cond,
+                    group_by: group_by.unwrap_or_default(),
+                    having,
+                }
+            }
+
+        rule compound_operator() -> CompoundOp
+            = keyword("UNION") _ keyword("ALL") { CompoundOp::UnionAll }
+            / keyword("UNION") { CompoundOp::Union }
+            / keyword("INTERSECT") { CompoundOp::Intersect }
+            / keyword("EXCEPT") { CompoundOp::Except }
+
+        rule ordering_term() -> OrderingTerm
+            = expr:expr() descending:(_ d:(keyword("ASC") { false } / keyword("DESC") { true }) { d })?
+            nulls_first:(_ keyword("NULLS") _ f:(keyword("FIRST") { true } / keyword("LAST") { false }) { f })? {
+                let descending = descending.unwrap_or(false);
+                OrderingTerm {
+                    expr,
+                    descending,
+                    nulls_first: nulls_first.unwrap_or(!descending),
+                }
+            }
+
+        // `LIMIT a, b` skips a rows and keeps b.
+        rule limit_clause() -> (Expr, Option<Expr>)
+            = limit:expr() _ keyword("OFFSET") _ offset:expr() { (limit, Some(offset)) }
+            / offset:expr() _ "," _ limit:expr() { (limit, Some(offset)) }
+            / limit:expr() { (limit, None) }
+
+        rule query_statement() -> Statement
+            = first:select_statement() rest:(_ op:compound_operator() _ s:select_statement() { (op, s) })*
+            order_by:(_ keyword("ORDER") _ keyword("BY") _ t:(ordering_term() ++ (_ "," _)) { t })?
+            limit:(_ keyword("LIMIT") _ l:limit_clause() { l })? {
+                if rest.is_empty() && order_by.is_none() && limit.is_none() {
+                    first
+                } else {
+                    let (operators, rest): (Vec<_>, Vec<_>) = rest.into_iter().unzip();
+                    let (limit, offset) = limit.unzip();
+                    Statement::Compound {
+                        selects: std::iter::once(first).chain(rest).collect(),
+                        operators,
+                        order_by: order_by.unwrap_or_default(),
+                        limit,
+                        offset: offset.flatten(),
+                    }
                 }
             }
 
         rule create_table_statement() -> Statement
-        = i("CREATE") _ i("TABLE") _ "\""? table:table_name() "\""? _ "(" _ cols:column_def() ** ([^ ',']* "," _) _ ")" {
-                Statement::CreateTable {
+        = i("CREATE") _ temporary:((keyword("TEMPORARY") / keyword("TEMP")) _)? i("TABLE") _
+            if_not_exists:(keyword("IF") _ keyword("NOT") _ keyword("EXISTS") _)? text:$(table_definition()) {?
+                // sqlite stores the definition as written, from the name on.
+                let (table, mut cols, constraints, options) = table_definition(text).or(Err("table definition"))?;
+                let without_rowid = options.contains(&TableOption::WithoutRowid);
+                if !without_rowid {
+                    mark_rowid_alias(&mut cols, &constraints);
+                }
+                Ok(Statement::CreateTable {
                     table: table.to_string(),
                     columns: cols,
-                }
+                    constraints,
+                    without_rowid,
+                    strict: options.contains(&TableOption::Strict),
+                    temporary: temporary.is_some(),
+                    if_not_exists: if_not_exists.is_some(),
+                    sql: format!("CREATE TABLE {}", text.trim_end()),
+                })
+            }
+
+        pub rule table_definition() -> (&'input str, Vec<ColumnDef>, Vec<TableConstraint>, Vec<TableOption>)
+            = table:name() _ "(" _ cols:(column_def() ** (_ "," _)) constraints:(_ "," _ c:table_constraint() { c })* _ ")"
+            _ options:(table_option() ** (_ "," _)) {
+                (table, cols, constraints, options)
             }
 
+        rule table_option() -> TableOption
+            = keyword("WITHOUT") _ keyword("ROWID") { TableOption::WithoutRowid }
+            / keyword("STRICT") { TableOption::Strict }
+
         rule create_index_statement() -> Statement
-            = i("CREATE") _ i("INDEX") _ if_not_exists:("IF NOT EXISTS" _ { true })? _ index:identifier() _ i("ON") _ table:table_name() _ "(" _ columns:(column_name() ** (_ "," _)) _ ")" {
-                Statement::CreateIndex {
+            = i("CREATE") _ unique:(keyword("UNIQUE") _)? i("INDEX") _ if_not_exists:(keyword("IF") _ keyword("NOT") _ keyword("EXISTS") _)?
+            text:$(index_definition()) {?
+                // Kept like a table's definition, from the name on.
+                let (index, table, columns, partial) = index_definition(text).or(Err("index definition"))?;
+                let unique = unique.is_some();
+                Ok(Statement::CreateIndex {
                     index_name: index.to_string(),
                     table: table.to_string(),
-                    columns: columns.into_iter().map(|s| s.to_string()).collect(),
-                    if_not_exists: if_not_exists.unwrap_or(false),
+                    columns,
+                    unique,
+                    if_not_exists: if_not_exists.is_some(),
+                    partial,
+                    sql: format!("CREATE {}INDEX {}", if unique { "UNIQUE " } else { "" }, text.trim_end()),
+                })
+            }
+
+        pub rule index_definition() -> (&'input str, &'input str, Vec<IndexColumn>, bool)
+            = index:name() _ i("ON") _ table:name() _ "(" _ columns:(index_column() ** (_ "," _)) _ ")" _
+            partial:(keyword("WHERE") [_]*)? {
+                (index, table, columns, partial.is_some())
+            }
+
+        rule create_view_statement() -> Statement
+            = i("CREATE") _ ((keyword("TEMPORARY") / keyword("TEMP")) _)? i("VIEW") _
+            (keyword("IF") _ keyword("NOT") _ keyword("EXISTS") _)? view:name() _
+            columns:("(" _ c:(n:name() { n.to_string() }) ++ (_ "," _) _ ")" _ { c })? i("AS") _ select:query_statement() {
+                Statement::CreateView {
+                    view: view.to_string(),
+                    columns: columns.unwrap_or_default(),
+                    select: Box::new(select),
+                }
+            }
+
+        rule index_column() -> IndexColumn
+            = n:name() collation:(_ keyword("COLLATE") _ c:name() { c.to_string() })? order:(_ o:(keyword("ASC") / keyword("DESC")) { o })? {
+                IndexColumn {
+                    name: n.to_string(),
+                    collation,
+                    descending: order == Some("DESC"),
                 }
             }
 
@@ -73,47 +450,1256 @@ peg::parser! {
                 if input.eq_ignore_ascii_case(expected) && input.len() == expected.len() {
                     Ok(expected)
                 } else {
-                    Err("case-insensitive match failed")
+                    Err(expected)
                 }
             }
 
+        rule keyword(expected: &'static str) -> &'static str
+            = input:$(quiet!{['a'..='z' | 'A'..='Z' | '0'..='9' | '_']+}) {?
+                if input.eq_ignore_ascii_case(expected) {
+                    Ok(expected)
+                } else {
+                    Err(expected)
+                }
+            }
+
+        rule pragma_statement() -> Statement
+            = keyword("PRAGMA") _ name:name() _ arg:("(" _ a:name() _ ")" { a } / "=" _ a:name() { a })? {
+                Statement::Pragma {
+                    name: name.to_lowercase(),
+                    arg: arg.map(str::to_string),
+                }
+            }
+
+        rule update_statement() -> Statement
+            = keyword("UPDATE") _ table:name() _ keyword("SET") _
+            assignments:((c:name() _ "=" _ e:expr() { (c.to_string(), e) }) ++ (_ "," _))
+            condition:(_ keyword("WHERE") _ c:condition() { c })? {
+                Statement::Update {
+                    table: table.to_string(),
+                    assignments,
+                    condition,
+                }
+            }
+
+        rule transaction_statement() -> Statement
+            = keyword("BEGIN") (_ (keyword("DEFERRED") / keyword("IMMEDIATE") / keyword("EXCLUSIVE")))?
+                (_ keyword("TRANSACTION"))? { Statement::Begin }
+            / (keyword("COMMIT") / keyword("END")) (_ keyword("TRANSACTION"))? { Statement::Commit }
+            / keyword("ROLLBACK") (_ keyword("TRANSACTION"))? { Statement::Rollback }
+
+        rule alter_table_statement() -> Statement
+            = keyword("ALTER") _ keyword("TABLE") _ table:name() _ keyword("RENAME") _ keyword("TO") _ new_name:name() {
+                Statement::RenameTable {
+                    table: table.to_string(),
+                    new_name: new_name.to_string(),
+                }
+            }
+
+        rule vacuum_statement() -> Statement
+            = keyword("VACUUM") { Statement::Vacuum }
+
+        rule attach_statement() -> Statement
+            = keyword("ATTACH") _ (keyword("DATABASE") _)? path:string_literal() _ keyword("AS") _ name:name() {
+                Statement::Attach {
+                    path,
+                    name: name.to_string(),
+                }
+            }
+            / keyword("DETACH") _ (keyword("DATABASE") _)? name:name() {
+                Statement::Detach { name: name.to_string() }
+            }
+
+        rule explain_statement() -> Statement
+            = keyword("EXPLAIN") _ keyword("QUERY") _ keyword("PLAN") _ s:select_statement() {
+                Statement::Explain(Box::new(s))
+            }
+
+        pub rule expr_text() -> Expr = expr()
+
+        /// Comma-separated constants, as `functions::quote` writes them.
+        pub rule literal_list() -> Vec<Record> = _ l:(literal() ** (_ "," _)) _ { l }
+
         pub rule sql() -> Statement
-            = stmt:(select_statement() / create_table_statement() / create_index_statement()) {
+            = _ stmt:(query_statement() / create_table_statement() / create_index_statement() / create_view_statement()
+                / pragma_statement()
+                / update_statement() / alter_table_statement() / transaction_statement() / vacuum_statement() / attach_statement()
+                / explain_statement())
+                _ (";" _)? {
                 stmt
             }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Statement {
     Select {
-        table: String,
-        columns: Vec<String>,
+        from: TableRef,
+        /// Inner joins, in the order they are written.
+        joins: Vec<Join>,
+        columns: Vec<ResultColumn>,
         condition: Option<Condition>,
+        group_by: Vec<Expr>,
+        having: Option<Condition>,
+    },
+    /// Selects joined by UNION, INTERSECT or EXCEPT, or a single one with
+    /// ORDER BY or LIMIT; either way those apply to the rows of the whole.
+    Compound {
+        /// Each a [`Statement::Select`].
+        selects: Vec<Statement>,
+        /// The operator before each select after the first.
+        operators: Vec<CompoundOp>,
+        order_by: Vec<OrderingTerm>,
+        limit: Option<Expr>,
+        offset: Option<Expr>,
     },
     CreateTable {
         table: String,
         columns: Vec<ColumnDef>,
+        constraints: Vec<TableConstraint>,
+        /// Rows live in an index b-tree keyed by the primary key.
+        without_rowid: bool,
+        strict: bool,
+        temporary: bool,
+        if_not_exists: bool,
+        /// The statement as sqlite keeps it in `sqlite_schema`.
+        sql: String,
     },
     CreateIndex {
         index_name: String,
         table: String,
-        columns: Vec<String>,
+        columns: Vec<IndexColumn>,
+        unique: bool,
         if_not_exists: bool,
+        /// Has a WHERE clause, so it only covers some of the table's rows.
+        partial: bool,
+        /// The statement as sqlite keeps it in `sqlite_schema`.
+        sql: String,
+    },
+    /// Only read from `sqlite_schema`, for the SELECT a view stands for.
+    CreateView {
+        view: String,
+        /// The names given to the view's columns, if any.
+        columns: Vec<String>,
+        select: Box<Statement>,
+    },
+    Pragma {
+        /// Lower-cased.
+        name: String,
+        arg: Option<String>,
+    },
+    Update {
+        table: String,
+        /// Columns and the values they get, in the order written.
+        assignments: Vec<(String, Expr)>,
+        condition: Option<Condition>,
+    },
+    /// `ALTER TABLE table RENAME TO new_name`.
+    RenameTable {
+        table: String,
+        new_name: String,
+    },
+    Begin,
+    Commit,
+    Rollback,
+    /// Writes the whole database again without free pages.
+    Vacuum,
+    /// `ATTACH DATABASE 'path' AS name`: opens another file whose tables
+    /// queries name as `name.table`.
+    Attach {
+        path: String,
+        name: String,
+    },
+    Detach {
+        name: String,
     },
+    /// `EXPLAIN QUERY PLAN`: describes how the statement would run instead
+    /// of running it.
+    Explain(Box<Statement>),
 }
 
-#[derive(Debug)]
+impl Statement {
+    /// Calls `f` on each expression of the statement, in the order they are
+    /// written.
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        match self {
+            Statement::Select {
+                joins,
+                columns,
+                condition,
+                group_by,
+                having,
+                ..
+            } => {
+                columns.iter_mut().for_each(|c| c.expr.visit_mut(f));
+                joins.iter_mut().filter_map(|j| j.on.as_mut()).for_each(|c| c.visit_mut(f));
+                condition.iter_mut().for_each(|c| c.visit_mut(f));
+                group_by.iter_mut().for_each(|e| e.visit_mut(f));
+                having.iter_mut().for_each(|c| c.visit_mut(f));
+            }
+            Statement::Update {
+                assignments,
+                condition,
+                ..
+            } => {
+                assignments.iter_mut().for_each(|(_, e)| e.visit_mut(f));
+                condition.iter_mut().for_each(|c| c.visit_mut(f));
+            }
+            Statement::Compound {
+                selects,
+                order_by,
+                limit,
+                offset,
+                ..
+            } => {
+                selects.iter_mut().for_each(|s| s.visit_mut(f));
+                order_by.iter_mut().for_each(|t| t.expr.visit_mut(f));
+                limit.iter_mut().chain(offset).for_each(|e| e.visit_mut(f));
+            }
+            Statement::Explain(statement) => statement.visit_mut(f),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Column(ColumnRef),
+    Literal(Record),
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        distinct: bool,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Negate(Box<Expr>),
+    /// `?`, `?N` or `:name`, numbered from 1. Unbound parameters are NULL.
+    Parameter { index: usize, name: Option<String> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompoundOp {
+    UnionAll,
+    Union,
+    Intersect,
+    Except,
+}
+
+impl Display for CompoundOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            CompoundOp::UnionAll => "UNION ALL",
+            CompoundOp::Union => "UNION",
+            CompoundOp::Intersect => "INTERSECT",
+            CompoundOp::Except => "EXCEPT",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// A term of ORDER BY.
+#[derive(Debug, Clone)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+    /// NULLs come first unless the term says otherwise or is DESC.
+    pub nulls_first: bool,
+}
+
+/// A table in the FROM clause.
+#[derive(Debug, Clone)]
+pub struct TableRef {
+    /// The attached database the table is in, when named: `main` or the
+    /// name it was attached as.
+    pub schema: Option<String>,
+    pub name: String,
+    pub alias: Option<String>,
+}
+
+impl TableRef {
+    /// The name its columns are qualified with.
+    pub fn qualifier(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub table: TableRef,
+    pub on: Option<Condition>,
+}
+
+/// A column named in an expression, optionally as `table.column`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnRef {
+    pub table: Option<String>,
+    pub name: String,
+    /// Written in double quotes, which makes it a string when no column
+    /// has the name, as in sqlite.
+    pub quoted: bool,
+}
+
+impl ColumnRef {
+    pub fn bare(name: &str) -> Self {
+        Self {
+            table: None,
+            name: name.to_string(),
+            quoted: false,
+        }
+    }
+
+    pub fn qualified(table: &str, name: &str) -> Self {
+        Self {
+            table: Some(table.to_string()),
+            name: name.to_string(),
+            quoted: false,
+        }
+    }
+
+    pub fn quoted(name: &str) -> Self {
+        Self {
+            quoted: true,
+            ..Self::bare(name)
+        }
+    }
+}
+
+impl Display for ColumnRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.table {
+            Some(table) => write!(f, "{}.{}", table, self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// An entry of the select list.
+#[derive(Debug, Clone)]
+pub struct ResultColumn {
+    pub expr: Expr,
+    pub alias: Option<String>,
+    /// The expression as written in the statement.
+    pub text: String,
+}
+
+impl ResultColumn {
+    /// The heading of the output column: its alias, or the expression as written.
+    pub fn name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Concat,
+}
+
+impl BinaryOp {
+    /// Binding strength; higher binds tighter.
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Concat => 3,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 2,
+            BinaryOp::Add | BinaryOp::Sub => 1,
+        }
+    }
+}
+
+impl Display for BinaryOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Concat => "||",
+        };
+        write!(f, "{}", op)
+    }
+}
+
+/// Left-associates a chain of operators of equal precedence.
+fn fold_binary(first: Expr, rest: Vec<(BinaryOp, Expr)>) -> Expr {
+    rest.into_iter().fold(first, |left, (op, right)| Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    })
+}
+
+impl Expr {
+    /// Whether the expression is, or contains, an aggregate function call.
+    pub fn is_aggregate(&self) -> bool {
+        match self {
+            Expr::Function { name, args, .. } => {
+                aggregate::is_aggregate(name, args.len()) || args.iter().any(Expr::is_aggregate)
+            }
+            Expr::Binary { left, right, .. } => left.is_aggregate() || right.is_aggregate(),
+            Expr::Negate(e) => e.is_aggregate(),
+            _ => false,
+        }
+    }
+
+    /// Collects the distinct aggregate calls in the expression, outermost first.
+    pub fn aggregate_calls<'a>(&'a self, calls: &mut Vec<&'a Expr>) {
+        match self {
+            Expr::Function { name, args, .. } if aggregate::is_aggregate(name, args.len()) => {
+                if !calls.contains(&self) {
+                    calls.push(self);
+                }
+            }
+            Expr::Function { args, .. } => args.iter().for_each(|arg| arg.aggregate_calls(calls)),
+            Expr::Binary { left, right, .. } => {
+                left.aggregate_calls(calls);
+                right.aggregate_calls(calls);
+            }
+            Expr::Negate(e) => e.aggregate_calls(calls),
+            Expr::Column(_) | Expr::Literal(_) | Expr::Parameter { .. } => {}
+        }
+    }
+
+    /// Collects the columns the expression reads.
+    pub fn column_refs(&self, refs: &mut Vec<ColumnRef>) {
+        match self {
+            Expr::Column(column) => refs.push(column.clone()),
+            Expr::Literal(_) | Expr::Parameter { .. } => {}
+            Expr::Function { args, .. } => args.iter().for_each(|arg| arg.column_refs(refs)),
+            Expr::Binary { left, right, .. } => {
+                left.column_refs(refs);
+                right.column_refs(refs);
+            }
+            Expr::Negate(e) => e.column_refs(refs),
+        }
+    }
+
+    /// A copy of the expression with every column reference replaced by `f`'s.
+    pub fn map_columns(&self, f: &mut impl FnMut(&ColumnRef) -> Result<ColumnRef>) -> Result<Expr> {
+        Ok(match self {
+            Expr::Column(column) if column.quoted => match f(column) {
+                Err(e) if matches!(e.downcast_ref(), Some(Error::ColumnNotFound { .. })) => {
+                    Expr::Literal(Record::Text(column.name.clone()))
+                }
+                resolved => Expr::Column(resolved?),
+            },
+            Expr::Column(column) => Expr::Column(f(column)?),
+            Expr::Literal(_) | Expr::Parameter { .. } => self.clone(),
+            Expr::Function {
+                name,
+                args,
+                distinct,
+            } => Expr::Function {
+                name: name.clone(),
+                args: args.iter().map(|arg| arg.map_columns(f)).collect::<Result<_>>()?,
+                distinct: *distinct,
+            },
+            Expr::Binary { op, left, right } => Expr::Binary {
+                op: *op,
+                left: Box::new(left.map_columns(f)?),
+                right: Box::new(right.map_columns(f)?),
+            },
+            Expr::Negate(e) => Expr::Negate(Box::new(e.map_columns(f)?)),
+        })
+    }
+
+    /// Calls `f` on the expression and then on each one inside it, in the
+    /// order they are written.
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        f(self);
+        match self {
+            Expr::Function { args, .. } => args.iter_mut().for_each(|arg| arg.visit_mut(f)),
+            Expr::Binary { left, right, .. } => {
+                left.visit_mut(f);
+                right.visit_mut(f);
+            }
+            Expr::Negate(e) => e.visit_mut(f),
+            Expr::Column(_) | Expr::Literal(_) | Expr::Parameter { .. } => {}
+        }
+    }
+
+    /// `count(*)`, which can be answered without decoding any row.
+    pub fn is_count_rows(&self) -> bool {
+        matches!(self, Expr::Function { name, args, distinct: false }
+            if args.is_empty() && name.eq_ignore_ascii_case("count"))
+    }
+}
+
+impl Condition {
+    /// Collects the columns the condition reads.
+    pub fn column_refs(&self, refs: &mut Vec<ColumnRef>) {
+        match self {
+            Condition::Equals { column, .. } | Condition::Like { column, .. } => {
+                refs.push(ColumnRef::bare(column))
+            }
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                a.column_refs(refs);
+                b.column_refs(refs);
+            }
+            Condition::Not(c) => c.column_refs(refs),
+            Condition::Compare { left, right, .. } => {
+                left.column_refs(refs);
+                right.column_refs(refs);
+            }
+            Condition::Between { expr, low, high } => {
+                expr.column_refs(refs);
+                low.column_refs(refs);
+                high.column_refs(refs);
+            }
+            Condition::In { expr, list } => {
+                expr.column_refs(refs);
+                list.iter().for_each(|e| e.column_refs(refs));
+            }
+        }
+    }
+
+    /// A copy of the condition with every column reference replaced by `f`'s.
+    pub fn map_columns(&self, f: &mut impl FnMut(&ColumnRef) -> Result<ColumnRef>) -> Result<Condition> {
+        let mut bare = |column: &str| Ok::<_, anyhow::Error>(f(&ColumnRef::bare(column))?.name);
+        Ok(match self {
+            Condition::Equals { column, value } => Condition::Equals {
+                column: bare(column)?,
+                value: value.clone(),
+            },
+            Condition::Like { column, pattern } => Condition::Like {
+                column: bare(column)?,
+                pattern: pattern.clone(),
+            },
+            Condition::And(a, b) => Condition::And(Box::new(a.map_columns(f)?), Box::new(b.map_columns(f)?)),
+            Condition::Or(a, b) => Condition::Or(Box::new(a.map_columns(f)?), Box::new(b.map_columns(f)?)),
+            Condition::Not(c) => Condition::Not(Box::new(c.map_columns(f)?)),
+            Condition::Compare { left, op, right } => Condition::Compare {
+                left: Box::new(left.map_columns(f)?),
+                op: *op,
+                right: Box::new(right.map_columns(f)?),
+            },
+            Condition::Between { expr, low, high } => Condition::Between {
+                expr: Box::new(expr.map_columns(f)?),
+                low: Box::new(low.map_columns(f)?),
+                high: Box::new(high.map_columns(f)?),
+            },
+            Condition::In { expr, list } => Condition::In {
+                expr: Box::new(expr.map_columns(f)?),
+                list: list.iter().map(|e| e.map_columns(f)).collect::<Result<_>>()?,
+            },
+        })
+    }
+
+    /// Calls `f` on each expression of the condition, as [`Expr::visit_mut`] does.
+    pub fn visit_mut(&mut self, f: &mut impl FnMut(&mut Expr)) {
+        match self {
+            Condition::Equals { .. } | Condition::Like { .. } => {}
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                a.visit_mut(f);
+                b.visit_mut(f);
+            }
+            Condition::Not(c) => c.visit_mut(f),
+            Condition::Compare { left, right, .. } => {
+                left.visit_mut(f);
+                right.visit_mut(f);
+            }
+            Condition::Between { expr, low, high } => {
+                expr.visit_mut(f);
+                low.visit_mut(f);
+                high.visit_mut(f);
+            }
+            Condition::In { expr, list } => {
+                expr.visit_mut(f);
+                list.iter_mut().for_each(|e| e.visit_mut(f));
+            }
+        }
+    }
+
+    /// The conjuncts of the condition: `a AND (b AND c)` gives a, b and c.
+    pub fn conjuncts(&self) -> Vec<&Condition> {
+        match self {
+            Condition::And(a, b) => a.conjuncts().into_iter().chain(b.conjuncts()).collect(),
+            other => vec![other],
+        }
+    }
+
+    /// Collects the distinct aggregate calls in the condition's expressions.
+    pub fn aggregate_calls<'a>(&'a self, calls: &mut Vec<&'a Expr>) {
+        match self {
+            Condition::Equals { .. } | Condition::Like { .. } => {}
+            Condition::And(a, b) | Condition::Or(a, b) => {
+                a.aggregate_calls(calls);
+                b.aggregate_calls(calls);
+            }
+            Condition::Not(c) => c.aggregate_calls(calls),
+            Condition::Compare { left, right, .. } => {
+                left.aggregate_calls(calls);
+                right.aggregate_calls(calls);
+            }
+            Condition::Between { expr, low, high } => {
+                expr.aggregate_calls(calls);
+                low.aggregate_calls(calls);
+                high.aggregate_calls(calls);
+            }
+            Condition::In { expr, list } => {
+                expr.aggregate_calls(calls);
+                list.iter().for_each(|e| e.aggregate_calls(calls));
+            }
+        }
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Column(column) => write!(f, "{}", column),
+            Expr::Literal(value) => write!(f, "{}", functions::quote(value)),
+            Expr::Binary { op, left, right } => {
+                // Operands that bind more loosely than `op` were parenthesized.
+                let operand = |e: &Expr, f: &mut std::fmt::Formatter<'_>, tighter: bool| match e {
+                    Expr::Binary { op: inner, .. }
+                        if inner.precedence() < op.precedence()
+                            || (tighter && inner.precedence() == op.precedence()) =>
+                    {
+                        write!(f, "({})", e)
+                    }
+                    e => write!(f, "{}", e),
+                };
+                operand(left, f, false)?;
+                write!(f, " {} ", op)?;
+                operand(right, f, true)
+            }
+            Expr::Negate(e) => match e.as_ref() {
+                Expr::Binary { .. } => write!(f, "-({})", e),
+                e => write!(f, "-{}", e),
+            },
+            Expr::Parameter { name: Some(name), .. } => write!(f, "{}", name),
+            Expr::Parameter { index, name: None } => write!(f, "?{}", index),
+            Expr::Function { name, args, .. } if args.is_empty() && aggregate::is_aggregate(name, 0) => {
+                write!(f, "{}(*)", name)
+            }
+            Expr::Function {
+                name,
+                args,
+                distinct,
+            } => {
+                let distinct = if *distinct { "DISTINCT " } else { "" };
+                write!(f, "{}({}{})", name, distinct, args.iter().join(", "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ColumnDef {
     pub name: String,
-    data_type: String,
+    pub type_name: Option<TypeName>,
+    pub not_null: bool,
+    /// Default value as written in the schema, e.g. `'x'`, `0` or `(1 + 2)`.
+    pub default: Option<String>,
+    pub primary_key: bool,
+    pub descending: bool,
+    pub autoincrement: bool,
+    pub unique: bool,
+    pub collation: Option<String>,
+    pub checks: Vec<String>,
+    pub references: Option<ForeignKey>,
+    pub generated: Option<String>,
+    /// `INTEGER PRIMARY KEY` column whose value is the rowid itself.
+    pub rowid_alias: bool,
+}
+
+impl ColumnDef {
+    /// The column a rowid table without an `INTEGER PRIMARY KEY` exposes its
+    /// rowid through.
+    pub fn rowid() -> Self {
+        let integer = TypeName {
+            name: "INTEGER".to_string(),
+            args: Vec::new(),
+            text: "INTEGER".to_string(),
+        };
+        ColumnDef {
+            rowid_alias: true,
+            ..ColumnDef::new("rowid", Some(integer), Vec::new())
+        }
+    }
+
+    /// A column with nothing declared about it but `type_name`.
+    pub fn plain(name: &str, type_name: Option<TypeName>) -> Self {
+        ColumnDef::new(name, type_name, Vec::new())
+    }
+
+    fn new(name: &str, type_name: Option<TypeName>, constraints: Vec<ColumnConstraint>) -> Self {
+        let mut column = ColumnDef {
+            name: name.to_string(),
+            type_name,
+            not_null: false,
+            default: None,
+            primary_key: false,
+            descending: false,
+            autoincrement: false,
+            unique: false,
+            collation: None,
+            checks: Vec::new(),
+            references: None,
+            generated: None,
+            rowid_alias: false,
+        };
+
+        for constraint in constraints {
+            match constraint {
+                ColumnConstraint::PrimaryKey {
+                    descending,
+                    autoincrement,
+                } => {
+                    column.primary_key = true;
+                    column.descending = descending;
+                    column.autoincrement = autoincrement;
+                }
+                ColumnConstraint::NotNull => column.not_null = true,
+                ColumnConstraint::Null => {}
+                ColumnConstraint::Unique => column.unique = true,
+                ColumnConstraint::Check(expr) => column.checks.push(expr),
+                ColumnConstraint::Default(value) => column.default = Some(value),
+                ColumnConstraint::Collate(collation) => column.collation = Some(collation),
+                ColumnConstraint::References(fk) => column.references = Some(fk),
+                ColumnConstraint::Generated(expr) => column.generated = Some(expr),
+            }
+        }
+
+        column
+    }
+
+    /// Declared type as written, e.g. `VARCHAR(255)`, or an empty string.
+    /// The DEFAULT clause as a value; NULL when there is none.
+    pub fn default_value(&self) -> Record {
+        match &self.default {
+            Some(text) => literal_value(text),
+            None => Record::Null,
+        }
+    }
+
+    pub fn declared_type(&self) -> String {
+        self.type_name
+            .as_ref()
+            .map(|t| t.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Affinity of the declared type, by the first of sqlite's rules that
+    /// matches its text.
+    pub fn affinity(&self) -> Affinity {
+        let declared = self.declared_type().to_uppercase();
+        let has = |part: &str| declared.contains(part);
+        if has("INT") {
+            Affinity::Integer
+        } else if has("CHAR") || has("CLOB") || has("TEXT") {
+            Affinity::Text
+        } else if has("BLOB") || declared.is_empty() {
+            Affinity::Blob
+        } else if has("REAL") || has("FLOA") || has("DOUB") {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+}
+
+/// The storage class a column prefers for its values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob,
+}
+
+impl Display for Affinity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Affinity::Text => write!(f, "TEXT"),
+            Affinity::Numeric => write!(f, "NUMERIC"),
+            Affinity::Integer => write!(f, "INTEGER"),
+            Affinity::Real => write!(f, "REAL"),
+            Affinity::Blob => write!(f, "BLOB"),
+        }
+    }
+}
+
+/// A lone `INTEGER PRIMARY KEY` column aliases the rowid, except for the
+/// historical `PRIMARY KEY DESC` column-constraint quirk.
+#[derive(PartialEq)]
+pub(crate) enum TableOption {
+    WithoutRowid,
+    Strict,
+}
+
+fn mark_rowid_alias(columns: &mut [ColumnDef], constraints: &[TableConstraint]) {
+    let table_pk = constraints.iter().find_map(|c| match c {
+        TableConstraint::PrimaryKey(cols) => Some(cols),
+        _ => None,
+    });
+
+    let alias = match table_pk {
+        Some(pk) if pk.len() == 1 => columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(&pk[0])),
+        Some(_) => None,
+        None => {
+            let mut pk_columns = columns.iter().positions(|c| c.primary_key);
+            match (pk_columns.next(), pk_columns.next()) {
+                (Some(idx), None) if !columns[idx].descending => Some(idx),
+                _ => None,
+            }
+        }
+    };
+
+    if let Some(idx) = alias {
+        let is_integer = columns[idx]
+            .type_name
+            .as_ref()
+            .is_some_and(|t| t.name.eq_ignore_ascii_case("INTEGER") && t.args.is_empty());
+        columns[idx].rowid_alias = is_integer;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TypeName {
+    pub name: String,
+    pub args: Vec<String>,
+    /// The type as written, spacing and all, which is how sqlite keeps it.
+    pub text: String,
+}
+
+impl Display for TypeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
 }
 
 #[derive(Debug)]
+enum ColumnConstraint {
+    PrimaryKey { descending: bool, autoincrement: bool },
+    NotNull,
+    Null,
+    Unique,
+    Check(String),
+    Default(String),
+    Collate(String),
+    References(ForeignKey),
+    Generated(String),
+}
+
+/// One key column of an index, in index order.
+#[derive(Debug, Clone)]
+pub struct IndexColumn {
+    pub name: String,
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForeignKey {
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TableConstraint {
+    PrimaryKey(Vec<String>),
+    Unique(Vec<String>),
+    Check(String),
+    ForeignKey {
+        columns: Vec<String>,
+        references: ForeignKey,
+    },
+}
+
+#[derive(Debug, Clone)]
 pub enum Condition {
     Equals { column: String, value: String },
+    Like { column: String, pattern: String },
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    Compare {
+        left: Box<Expr>,
+        op: CompareOp,
+        right: Box<Expr>,
+    },
+    /// `expr BETWEEN low AND high`, both ends included.
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+    },
+    /// `expr IN (list)`.
+    In { expr: Box<Expr>, list: Vec<Expr> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    pub fn holds(&self, ordering: std::cmp::Ordering) -> bool {
+        use std::cmp::Ordering::*;
+        match self {
+            CompareOp::Eq => ordering == Equal,
+            CompareOp::Ne => ordering != Equal,
+            CompareOp::Lt => ordering == Less,
+            CompareOp::Le => ordering != Greater,
+            CompareOp::Gt => ordering == Greater,
+            CompareOp::Ge => ordering != Less,
+        }
+    }
+}
+
+/// Value of the constant text of a DEFAULT clause.
+fn literal_value(text: &str) -> Record {
+    let text = text.trim();
+    if let Some(inner) = text.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        return literal_value(inner);
+    }
+    if let Some(quoted) = text.strip_prefix('\'').and_then(|t| t.strip_suffix('\'')) {
+        return Record::Text(quoted.replace("''", "'"));
+    }
+    if let Some(hex) = text
+        .strip_prefix(['x', 'X'])
+        .and_then(|t| t.strip_prefix('\''))
+        .and_then(|t| t.strip_suffix('\''))
+    {
+        let bytes = (0..hex.len() / 2).map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16));
+        if let Ok(bytes) = bytes.collect() {
+            return Record::Blob(bytes);
+        }
+    }
+    if let Ok(v) = text.trim_start_matches('+').parse::<i64>() {
+        return Record::Int64(v);
+    }
+    if text.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c)) {
+        if let Ok(v) = text.parse::<f64>() {
+            return Record::Float(v);
+        }
+    }
+    match text.to_ascii_uppercase().as_str() {
+        "NULL" => Record::Null,
+        "TRUE" => Record::Int64(1),
+        "FALSE" => Record::Int64(0),
+        _ => Record::Text(text.to_string()),
+    }
+}
+
+/// A token of SQL text, as far as [`rename_table`] and [`name_span`] need to
+/// tell them apart.
+enum Token {
+    /// An unquoted name or keyword.
+    Word,
+    /// A quoted name, unquoted.
+    Quoted(String),
+    Punct(u8),
+    /// A string, number or operator.
+    Other,
+}
+
+/// The tokens of `sql` with their byte ranges, comments and whitespace left out.
+fn tokens(sql: &str) -> Vec<(Token, std::ops::Range<usize>)> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        let start = at;
+        let rest = &bytes[at..];
+        let word = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || b >= 0x80;
+        let token = match rest[0] {
+            b if b.is_ascii_whitespace() => {
+                at += 1;
+                continue;
+            }
+            b'-' if rest.get(1) == Some(&b'-') => {
+                at += rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+                continue;
+            }
+            b'/' if rest.get(1) == Some(&b'*') => {
+                at += rest.windows(2).skip(2).position(|w| w == b"*/").map_or(rest.len(), |end| end + 4);
+                continue;
+            }
+            quote @ (b'\'' | b'"' | b'`' | b'[') => {
+                let close = if quote == b'[' { b']' } else { quote };
+                let mut text = Vec::new();
+                at += 1;
+                while at < bytes.len() {
+                    if bytes[at] == close {
+                        // A quote written twice stands for one, except in brackets.
+                        if close != b']' && bytes.get(at + 1) == Some(&close) {
+                            at += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    text.push(bytes[at]);
+                    at += 1;
+                }
+                at = (at + 1).min(bytes.len());
+                match quote {
+                    b'\'' => Token::Other,
+                    _ => Token::Quoted(String::from_utf8_lossy(&text).into_owned()),
+                }
+            }
+            b if b.is_ascii_digit() => {
+                at += rest.iter().position(|&b| !word(b) && b != b'.').unwrap_or(rest.len());
+                Token::Other
+            }
+            b if word(b) => {
+                at += rest.iter().position(|&b| !word(b)).unwrap_or(rest.len());
+                Token::Word
+            }
+            b @ (b'.' | b',' | b'(' | b')' | b';') => {
+                at += 1;
+                Token::Punct(b)
+            }
+            _ => {
+                at += 1;
+                Token::Other
+            }
+        };
+        tokens.push((token, start..at));
+    }
+    tokens
+}
+
+/// `sql`, a statement of `sqlite_schema`, with every reference to the table
+/// `from` renamed to `to` as `ALTER TABLE ... RENAME TO` renames them: the
+/// name after TABLE, FROM, JOIN, INTO, UPDATE, REFERENCES and an ON that is
+/// not a join's, a trigger's `UPDATE OF ... ON` included, each further table of a FROM list, and qualifiers such as
+/// `from.column` unless the statement gives another table `from` as an
+/// alias. The new name is written quoted, as sqlite writes it; the rest of
+/// the text is kept as it was.
+pub fn rename_table(sql: &str, from: &str, to: &str) -> String {
+    let tokens = tokens(sql);
+    // Whether the next name is a table's or, after one in a FROM list, its
+    // alias; and for each open parenthesis whether the text in it is a FROM
+    // list.
+    let mut table_next = false;
+    let mut alias_next = false;
+    let mut from_lists = vec![false];
+    // Where the table is named, and whether as a qualifier.
+    let mut renames = Vec::new();
+    let mut aliased = false;
+    for (idx, (token, range)) in tokens.iter().enumerate() {
+        let dot_next = matches!(tokens.get(idx + 1), Some((Token::Punct(b'.'), _)));
+        let name = match token {
+            Token::Word => Some(&sql[range.clone()]),
+            Token::Quoted(name) => Some(name.as_str()),
+            _ => None,
+        };
+        let is_table = name.is_some_and(|name| name.eq_ignore_ascii_case(from));
+        let is = |keywords: &[&str]| {
+            matches!(token, Token::Word) && keywords.iter().any(|k| sql[range.clone()].eq_ignore_ascii_case(k))
+        };
+        let in_from_list = from_lists.last_mut().unwrap();
+        let alias = std::mem::take(&mut alias_next);
+        match token {
+            _ if table_next && is(&["IF", "NOT", "EXISTS", "OR", "ROLLBACK", "ABORT", "REPLACE", "FAIL", "IGNORE"]) => (),
+            // A trigger's `UPDATE [OF columns] ON table`, whose ON names the
+            // table in turn.
+            _ if table_next && is(&["ON"]) => (),
+            _ if table_next && is(&["OF"]) => table_next = false,
+            // Before a dot, a name there is a schema's.
+            Token::Word | Token::Quoted(_) if table_next => {
+                if !dot_next {
+                    if is_table {
+                        renames.push((range.clone(), false));
+                    }
+                    table_next = false;
+                    alias_next = *in_from_list;
+                }
+            }
+            _ if alias && is(&["AS"]) => alias_next = true,
+            _ if is(&["FROM", "JOIN"]) => {
+                *in_from_list = true;
+                table_next = true;
+            }
+            _ if is(&["TABLE", "INTO", "UPDATE", "REFERENCES"]) => table_next = true,
+            _ if is(&["ON"]) => {
+                table_next = !*in_from_list;
+                *in_from_list = false;
+            }
+            _ if is(&["WHERE", "GROUP", "HAVING", "ORDER", "LIMIT", "UNION", "INTERSECT", "EXCEPT", "USING", "WINDOW", "SET", "VALUES"]) => {
+                *in_from_list = false;
+            }
+            Token::Word | Token::Quoted(_) if is_table && dot_next => renames.push((range.clone(), true)),
+            Token::Word | Token::Quoted(_) => aliased |= is_table && alias,
+            Token::Punct(b'.') => (),
+            Token::Punct(b',') => table_next = *in_from_list,
+            Token::Punct(b'(') => {
+                from_lists.push(false);
+                table_next = false;
+            }
+            Token::Punct(b')') => {
+                if from_lists.len() > 1 {
+                    from_lists.pop();
+                }
+                table_next = false;
+            }
+            Token::Punct(_) => {
+                *in_from_list = false;
+                table_next = false;
+            }
+            Token::Other => table_next = false,
+        }
+    }
+
+    let quoted = quote_identifier(to, true);
+    let mut renamed = String::new();
+    let mut copied = 0;
+    for (range, qualifier) in renames {
+        if qualifier && aliased {
+            continue;
+        }
+        renamed.push_str(&sql[copied..range.start]);
+        renamed.push_str(&quoted);
+        copied = range.end;
+    }
+    renamed.push_str(&sql[copied..]);
+    renamed
+}
+
+/// `name` as an identifier in SQL: double-quoted, its own double quotes
+/// doubled, unless `always` is false and it is a plain identifier that is
+/// not also a keyword. sqlite3 quotes only where it must in `.dump` and
+/// INSERT output, and always in the tables `.import` creates.
+pub fn quote_identifier(name: &str, always: bool) -> String {
+    const KEYWORDS: &[&str] = &[
+        "all", "and", "as", "asc", "between", "by", "case", "check", "collate", "create",
+        "default", "delete", "desc", "distinct", "drop", "else", "end", "exists", "from",
+        "group", "having", "in", "index", "insert", "into", "is", "join", "key", "like", "limit",
+        "not", "null", "on", "or", "order", "primary", "references", "select", "set", "table",
+        "then", "union", "unique", "update", "values", "when", "where",
+    ];
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !KEYWORDS.contains(&name.to_ascii_lowercase().as_str());
+    match plain && !always {
+        true => name.to_string(),
+        false => format!("\"{}\"", name.replace('"', "\"\"")),
+    }
+}
+
+/// Byte range of the tokens of `sql` that first name `name`, a table or a
+/// column, qualified as `table.column` or not, in any case and quoted or
+/// not. Names inside strings and comments are not names.
+pub fn name_span(sql: &str, name: &str) -> Option<std::ops::Range<usize>> {
+    let parts = name.split('.').collect::<Vec<_>>();
+    let tokens = tokens(sql);
+    let names = |(token, range): &(Token, std::ops::Range<usize>), part: &str| match token {
+        Token::Word => sql[range.clone()].eq_ignore_ascii_case(part),
+        Token::Quoted(text) => text.eq_ignore_ascii_case(part),
+        _ => false,
+    };
+    (0..tokens.len()).find_map(|start| {
+        let end = start + 2 * parts.len() - 1;
+        let candidate = tokens.get(start..end)?;
+        let matched = candidate.iter().enumerate().all(|(idx, token)| match idx % 2 {
+            0 => names(token, parts[idx / 2]),
+            _ => matches!(token.0, Token::Punct(b'.')),
+        });
+        let before_dot = start > 0 && matches!(tokens[start - 1].0, Token::Punct(b'.'));
+        let after_dot = matches!(tokens.get(end), Some((Token::Punct(b'.'), _)));
+        (matched && !before_dot && !after_dot).then(|| tokens[start].1.start..tokens[end - 1].1.end)
+    })
 }
 
 pub fn parse_sql(input: &str) -> Result<Statement> {
-    sql_parser::sql(input).map_err(|e| anyhow::anyhow!("{}", e))
+    let mut statement = sql_parser::sql(input).map_err(parse_error)?;
+    number_parameters(&mut statement)?;
+    Ok(statement)
+}
+
+/// The highest parameter number sqlite accepts.
+const MAX_PARAMETER: usize = 32766;
+
+/// Numbers the parameters of `statement` as sqlite does: `?` takes one more
+/// than the highest number so far, and `:name` does the first time it is
+/// used and keeps that number after.
+fn number_parameters(statement: &mut Statement) -> Result<()> {
+    let mut named = HashMap::new();
+    let mut highest = 0;
+    statement.visit_mut(&mut |e| {
+        if let Expr::Parameter { index, name } = e {
+            if *index == 0 {
+                *index = match name {
+                    Some(name) => *named.entry(name.clone()).or_insert(highest + 1),
+                    None => highest + 1,
+                };
+            }
+            highest = highest.max(*index);
+        }
+    });
+    if highest > MAX_PARAMETER {
+        Err(anyhow!(msg!("too-many-variables")))?
+    }
+    Ok(())
+}
+
+pub fn parse_literals(input: &str) -> Result<Vec<Record>> {
+    sql_parser::literal_list(input).map_err(parse_error)
+}
+
+fn parse_error(e: peg::error::ParseError<peg::str::LineCol>) -> anyhow::Error {
+    Error::SqlParse {
+        offset: e.location.offset,
+        line: e.location.line,
+        column: e.location.column,
+        expected: expected(&e.expected),
+    }
+    .into()
+}
+
+/// What the parser would have taken: the token, or a list of them.
+fn expected(set: &peg::error::ExpectedSet) -> String {
+    let tokens = set.tokens().sorted().collect::<Vec<_>>();
+    match &tokens[..] {
+        [token] => token.to_string(),
+        _ => msg!("expected-one-of", tokens = tokens.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_sql, rename_table};
+
+    #[test]
+    fn rename_reaches_the_table_of_a_trigger_on_update() {
+        let sql = "CREATE TRIGGER t AFTER UPDATE ON users BEGIN UPDATE log SET n = n + 1 WHERE users_id = new.id; END";
+        let renamed = "CREATE TRIGGER t AFTER UPDATE ON \"people\" BEGIN UPDATE log SET n = n + 1 WHERE users_id = new.id; END";
+        assert_eq!(rename_table(sql, "users", "people"), renamed);
+        let sql = "CREATE TRIGGER t AFTER UPDATE OF name, users ON Users BEGIN INSERT INTO users VALUES (1); END";
+        let renamed = "CREATE TRIGGER t AFTER UPDATE OF name, users ON \"people\" BEGIN INSERT INTO \"people\" VALUES (1); END";
+        assert_eq!(rename_table(sql, "users", "people"), renamed);
+        let sql = "CREATE TRIGGER t BEFORE DELETE ON users BEGIN DELETE FROM users; END";
+        assert_eq!(rename_table(sql, "users", "people"), "CREATE TRIGGER t BEFORE DELETE ON \"people\" BEGIN DELETE FROM \"people\"; END");
+    }
+
+    #[test]
+    fn renamed_views_still_parse() {
+        let sql = "CREATE VIEW v AS SELECT users.name, l.x FROM users JOIN log l ON l.id = users.id WHERE users.id > 1";
+        let renamed = rename_table(sql, "users", "people");
+        assert_eq!(
+            renamed,
+            "CREATE VIEW v AS SELECT \"people\".name, l.x FROM \"people\" JOIN log l ON l.id = \"people\".id WHERE \"people\".id > 1"
+        );
+        parse_sql(&renamed).unwrap();
+        parse_sql("SELECT name FROM \"Userz\"").unwrap();
+    }
 }
@@ -0,0 +1,63 @@
+//! Traces of the pages each statement reads, in the order it reads them, for
+//! comparing the I/O of two plans and for replaying it against a cold cache.
+//! A trace is text: for every statement a `# statement N` line, then the
+//! number of each page read, one to a line, repeats and all.
+
+use crate::messages::msg;
+use anyhow::{anyhow, Result};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    sync::Mutex,
+};
+
+/// A trace file open for writing, and the reads of the statement running.
+pub struct ReadTrace {
+    file: Mutex<BufWriter<File>>,
+    pages: Mutex<Vec<usize>>,
+    statements: Mutex<usize>,
+}
+
+impl ReadTrace {
+    /// Creates the trace at `path`, replacing any file there.
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).map_err(|e| anyhow!(msg!("cannot-create", path = path, error = e)))?;
+        Ok(ReadTrace {
+            file: Mutex::new(BufWriter::new(file)),
+            pages: Mutex::new(Vec::new()),
+            statements: Mutex::new(0),
+        })
+    }
+
+    pub(crate) fn read(&self, page_num: usize) {
+        self.pages.lock().unwrap().push(page_num);
+    }
+
+    /// Writes the reads since the last statement ended as the next
+    /// statement's.
+    pub(crate) fn end_statement(&self) -> Result<()> {
+        let pages = std::mem::take(&mut *self.pages.lock().unwrap());
+        let mut statements = self.statements.lock().unwrap();
+        *statements += 1;
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "# statement {}", statements)?;
+        for page_num in pages {
+            writeln!(file, "{}", page_num)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Every page read in the trace at `path`, over all its statements.
+pub fn read_trace(path: &str) -> Result<Vec<usize>> {
+    let text = fs::read_to_string(path).map_err(|e| anyhow!(msg!("cannot-open", path = path, error = e)))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| match line.trim().parse() {
+            Ok(page_num) if page_num > 0 => Ok(page_num),
+            _ => Err(anyhow!(msg!("trace-not-a-page", path = path, line = n + 1, text = line))),
+        })
+        .collect()
+}
@@ -0,0 +1,395 @@
+//! Reading databases in WAL mode. A commit appends its pages to the `-wal`
+//! file beside the database as frames, a page each behind a small header,
+//! and they reach the database file only when a checkpoint copies them
+//! back; until then a page is read from its latest committed frame.
+//!
+//! Which frames count is pinned when a statement starts: those committed
+//! by then, up to what sqlite calls `mxFrame`, and no later ones, so no
+//! statement sees pages of two different commits. A read slot in the
+//! `-shm` wal-index, taken the way sqlite readers take one, keeps
+//! checkpoints from copying later frames into the database file and
+//! writers from starting the WAL over while the snapshot is in use. Like an
+//! open read transaction it is held until the next statement pins a new
+//! snapshot. When no other connection has the wal-index open, it cannot be
+//! trusted, and the committed frames are found by checking the WAL's
+//! checksums instead.
+
+use crate::{
+    lock::{self, SharedLock, BUSY_TIMEOUT},
+    messages::msg,
+    source::PageSource,
+};
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io, mem,
+    sync::RwLock,
+    thread,
+    time::Duration,
+};
+
+const WAL_HEADER: u64 = 32;
+const FRAME_HEADER: u64 = 24;
+/// With the low bit set, checksums read the WAL as big-endian words.
+const WAL_MAGIC: u32 = 0x377f_0682;
+
+/// The wal-index header: two copies of sqlite's `WalIndexHdr`, then the
+/// checkpoint's progress and the read marks, all in native byte order.
+const INDEX_HEADER: usize = 136;
+const INDEX_COPY: usize = 48;
+const BACKFILLED: usize = 96;
+const READ_MARKS: usize = 100;
+const READERS: usize = 5;
+/// A read mark no reader uses.
+const UNUSED_MARK: u32 = 0xffff_ffff;
+/// Byte locks of the wal-index: the writer's, the checkpointer's and the
+/// recovery's, one for each read slot, and past them the one every
+/// connection holds shared for as long as it has the wal-index open.
+const LOCKS: i64 = 120;
+const IN_USE: i64 = LOCKS + 8;
+
+fn read_slot(slot: usize) -> i64 {
+    LOCKS + 3 + slot as i64
+}
+
+/// What a statement reads from the WAL.
+#[derive(Default)]
+struct Snapshot {
+    /// The WAL, while the snapshot has frames in it.
+    wal: Option<File>,
+    /// The salt every frame of this WAL carries, as stored.
+    salt: [u8; 8],
+    /// Frames that count, counting from 1.
+    frames: u32,
+    /// Database size in pages after the last commit that counts.
+    pages: u32,
+    /// Whether the frames were found by their checksums; then the checksum
+    /// of the last that counts, to go on from.
+    recovered: bool,
+    checksum: [u32; 2],
+    /// The latest frame of each page among those that count.
+    latest: HashMap<u32, u32>,
+}
+
+struct State {
+    /// Kept open once opened: closing it would drop every lock on it.
+    shm: Option<File>,
+    /// Whether the wal-index lets read marks be set.
+    shm_writable: bool,
+    /// Whether this process holds the lock that marks the wal-index in use.
+    attached: bool,
+    /// The read slot held for the snapshot.
+    slot: Option<usize>,
+    snapshot: Snapshot,
+}
+
+/// A database file read through its WAL.
+pub struct WalSource {
+    db: Box<dyn PageSource>,
+    page_size: u64,
+    wal_path: String,
+    shm_path: String,
+    /// Held while the source is open, as every sqlite connection to a WAL
+    /// database holds it: it keeps the last of them to close from
+    /// checkpointing and deleting a WAL that is still being read.
+    _db_lock: SharedLock<File>,
+    state: RwLock<State>,
+}
+
+impl WalSource {
+    /// Reads `db`, the database file at `path`, through its WAL, pinning a
+    /// first snapshot of what is committed.
+    pub fn open(db: Box<dyn PageSource>, path: &str, page_size: usize) -> Result<Self> {
+        let source = WalSource {
+            db,
+            page_size: page_size as u64,
+            wal_path: format!("{}-wal", path),
+            shm_path: format!("{}-shm", path),
+            _db_lock: SharedLock::acquire(File::open(path)?)?,
+            state: RwLock::new(State {
+                shm: None,
+                shm_writable: false,
+                attached: false,
+                slot: None,
+                snapshot: Snapshot::default(),
+            }),
+        };
+        source.pin_snapshot()?;
+        Ok(source)
+    }
+
+    fn frame_offset(&self, frame: u32) -> u64 {
+        WAL_HEADER + (frame as u64 - 1) * (FRAME_HEADER + self.page_size)
+    }
+
+    /// Pins a snapshot of what is committed now; false when a writer got in
+    /// the way and it is worth trying again.
+    fn try_pin(&self, state: &mut State) -> Result<bool> {
+        if let (Some(shm), Some(slot)) = (&state.shm, state.slot.take()) {
+            lock::unlock(shm, read_slot(slot), 1)?;
+        }
+        if state.shm.is_none() {
+            let shm = OpenOptions::new().read(true).write(true).open(&self.shm_path);
+            state.shm_writable = shm.is_ok();
+            state.shm = shm.or_else(|_| File::open(&self.shm_path)).ok();
+        }
+        let wal = match File::open(&self.wal_path) {
+            Ok(wal) => wal,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                state.snapshot = Snapshot::default();
+                return Ok(true);
+            }
+            Err(e) => Err(e)?,
+        };
+        if !self.attach(state)? {
+            return self.recover(&mut state.snapshot, wal).map(|()| true);
+        }
+
+        let shm = state.shm.as_ref().unwrap();
+        let Some(index) = read_index(shm)? else {
+            return Ok(false);
+        };
+        let header = &index[..INDEX_COPY];
+        let field = |at: usize| u32::from_ne_bytes(index[at..at + 4].try_into().unwrap());
+        let (frames, backfilled) = (field(16), field(BACKFILLED));
+        let mark = |slot: usize| field(READ_MARKS + 4 * slot);
+
+        // Everything is in the database file already: read nothing from the
+        // WAL, holding the slot that keeps checkpoints from writing to it.
+        if backfilled == frames && lock::try_lock(shm, false, read_slot(0), 1)? {
+            if read_index(shm)?.is_some_and(|again| again[..INDEX_COPY] == *header) {
+                state.slot = Some(0);
+                state.snapshot = Snapshot::default();
+                return Ok(true);
+            }
+            lock::unlock(shm, read_slot(0), 1)?;
+            return Ok(false);
+        }
+
+        // A slot whose mark is at most `frames` keeps checkpoints from
+        // copying any frame this snapshot does not count.
+        let mut chosen = (1..READERS)
+            .map(|slot| (slot, mark(slot)))
+            .filter(|&(_, mark)| mark != UNUSED_MARK && mark <= frames)
+            .max_by_key(|&(_, mark)| mark);
+        if state.shm_writable && chosen.is_none_or(|(_, mark)| mark < frames) {
+            for slot in 1..READERS {
+                if lock::try_lock(shm, true, read_slot(slot), 1)? {
+                    let written = write_mark(shm, slot, frames);
+                    lock::unlock(shm, read_slot(slot), 1)?;
+                    written?;
+                    chosen = Some((slot, frames));
+                    break;
+                }
+            }
+        }
+        let Some((slot, expected)) = chosen else {
+            return Ok(false);
+        };
+        if !lock::try_lock(shm, false, read_slot(slot), 1)? {
+            return Ok(false);
+        }
+        let unchanged = read_index(shm)?.is_some_and(|again| {
+            again[..INDEX_COPY] == *header && again[READ_MARKS + 4 * slot..][..4] == expected.to_ne_bytes()
+        });
+        let salt = index[32..40].try_into().unwrap();
+        if !unchanged || !self.load(&mut state.snapshot, wal, salt, frames, field(20))? {
+            lock::unlock(shm, read_slot(slot), 1)?;
+            return Ok(false);
+        }
+        state.slot = Some(slot);
+        Ok(true)
+    }
+
+    /// Whether other connections have the wal-index open, which makes it
+    /// worth trusting; this one then holds it open too.
+    fn attach(&self, state: &mut State) -> Result<bool> {
+        let Some(shm) = &state.shm else {
+            return Ok(false);
+        };
+        if !state.attached {
+            // Held exclusively while a connection sets the wal-index up.
+            if !lock::try_lock(shm, false, IN_USE, 1)? {
+                return Ok(false);
+            }
+            state.attached = true;
+        }
+        // Only other processes' locks show up here.
+        if !lock::is_locked(shm, IN_USE, 1)? {
+            lock::unlock(shm, IN_USE, 1)?;
+            state.attached = false;
+        }
+        Ok(state.attached)
+    }
+
+    /// Takes the frames of `wal` that it has `salt` on, up to `frames`,
+    /// going on from what `snapshot` already took of the same WAL. False
+    /// when a frame is not what the wal-index says it is.
+    fn load(&self, snapshot: &mut Snapshot, wal: File, salt: [u8; 8], frames: u32, pages: u32) -> Result<bool> {
+        let mut next = Snapshot {
+            salt,
+            frames,
+            pages,
+            ..Default::default()
+        };
+        let mut start = 1;
+        if !snapshot.recovered && snapshot.wal.is_some() && snapshot.salt == salt && snapshot.frames <= frames {
+            next.latest = mem::take(&mut snapshot.latest);
+            start = snapshot.frames + 1;
+        }
+        let mut header = [0; FRAME_HEADER as usize];
+        for frame in start..=frames {
+            if PageSource::read_exact_at(&wal, &mut header, self.frame_offset(frame)).is_err() || header[8..16] != salt {
+                *snapshot = Snapshot::default();
+                return Ok(false);
+            }
+            next.latest.insert(u32::from_be_bytes(header[..4].try_into().unwrap()), frame);
+        }
+        next.wal = Some(wal);
+        *snapshot = next;
+        Ok(true)
+    }
+
+    /// Finds the committed frames of `wal` by its checksums, as sqlite's
+    /// recovery does: frames count up to the last commit before the first
+    /// one that does not check out.
+    fn recover(&self, snapshot: &mut Snapshot, wal: File) -> Result<()> {
+        let mut header = [0; WAL_HEADER as usize];
+        let word = |bytes: &[u8], at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+        let valid = PageSource::read_exact_at(&wal, &mut header, 0).is_ok()
+            && word(&header, 0) | 1 == WAL_MAGIC | 1
+            && word(&header, 8) as u64 == self.page_size;
+        let big_endian = word(&header, 0) & 1 == 1;
+        if !valid || checksum(&header[..24], big_endian, [0, 0]) != [word(&header, 24), word(&header, 28)] {
+            *snapshot = Snapshot::default();
+            return Ok(());
+        }
+        let salt: [u8; 8] = header[16..24].try_into().unwrap();
+        if !snapshot.recovered || snapshot.salt != salt || snapshot.wal.is_none() {
+            *snapshot = Snapshot {
+                salt,
+                recovered: true,
+                checksum: [word(&header, 24), word(&header, 28)],
+                ..Default::default()
+            };
+        }
+
+        let mut frame = vec![0; (FRAME_HEADER + self.page_size) as usize];
+        let mut pending = Vec::new();
+        let mut sum = snapshot.checksum;
+        let mut at = snapshot.frames + 1;
+        while PageSource::read_exact_at(&wal, &mut frame, self.frame_offset(at)).is_ok() {
+            sum = checksum(&frame[..8], big_endian, sum);
+            sum = checksum(&frame[FRAME_HEADER as usize..], big_endian, sum);
+            if frame[8..16] != salt || sum != [word(&frame, 16), word(&frame, 20)] {
+                break;
+            }
+            pending.push((word(&frame, 0), at));
+            let committed = word(&frame, 4);
+            if committed != 0 {
+                snapshot.latest.extend(pending.drain(..));
+                snapshot.frames = at;
+                snapshot.pages = committed;
+                snapshot.checksum = sum;
+            }
+            at += 1;
+        }
+        snapshot.wal = Some(wal);
+        Ok(())
+    }
+}
+
+/// The wal-index header, when both copies agree and check out; a writer is
+/// in the middle of changing it otherwise.
+fn read_index(shm: &File) -> Result<Option<[u8; INDEX_HEADER]>> {
+    let mut index = [0; INDEX_HEADER];
+    if PageSource::read_exact_at(shm, &mut index, 0).is_err() {
+        return Ok(None);
+    }
+    let (first, second) = (&index[..INDEX_COPY], &index[INDEX_COPY..2 * INDEX_COPY]);
+    let stored = |at: usize| u32::from_ne_bytes(first[at..at + 4].try_into().unwrap());
+    let sum = checksum(&first[..40], cfg!(target_endian = "big"), [0, 0]);
+    let valid = first == second && first[12] == 1 && sum == [stored(40), stored(44)];
+    Ok(valid.then_some(index))
+}
+
+fn write_mark(shm: &File, slot: usize, mark: u32) -> Result<()> {
+    PageSource::write_all_at(shm, &mark.to_ne_bytes(), (READ_MARKS + 4 * slot) as u64)
+}
+
+/// sqlite's WAL checksum of `bytes`, read as pairs of 32-bit words, going
+/// on from `sum`.
+fn checksum(bytes: &[u8], big_endian: bool, sum: [u32; 2]) -> [u32; 2] {
+    let [mut s0, mut s1] = sum;
+    for pair in bytes.chunks_exact(8) {
+        let word = |b: &[u8]| match big_endian {
+            true => u32::from_be_bytes(b.try_into().unwrap()),
+            false => u32::from_le_bytes(b.try_into().unwrap()),
+        };
+        s0 = s0.wrapping_add(word(&pair[..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..])).wrapping_add(s0);
+    }
+    [s0, s1]
+}
+
+impl PageSource for WalSource {
+    fn read_exact_at(&self, buf: &mut [u8], offset: u64) -> Result<()> {
+        let state = self.state.read().unwrap();
+        let snapshot = &state.snapshot;
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done as u64;
+            let within = at % self.page_size;
+            let len = ((self.page_size - within) as usize).min(buf.len() - done);
+            let part = &mut buf[done..done + len];
+            let page = (at / self.page_size + 1) as u32;
+            match (snapshot.latest.get(&page), &snapshot.wal) {
+                (Some(&frame), Some(wal)) => {
+                    PageSource::read_exact_at(wal, part, self.frame_offset(frame) + FRAME_HEADER + within)?
+                }
+                _ => self.db.read_exact_at(part, at)?,
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> Result<()> {
+        self.db.write_all_at(buf, offset)
+    }
+
+    fn size(&self) -> Result<u64> {
+        match self.state.read().unwrap().snapshot.pages {
+            0 => self.db.size(),
+            pages => Ok(pages as u64 * self.page_size),
+        }
+    }
+
+    fn truncate(&self, size: u64) -> Result<()> {
+        self.db.truncate(size)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.db.sync()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.db.is_read_only()
+    }
+
+    fn pin_snapshot(&self) -> Result<()> {
+        self.db.pin_snapshot()?;
+        let mut state = self.state.write().unwrap();
+        let mut waited = Duration::ZERO;
+        while !self.try_pin(&mut state)? {
+            if waited >= BUSY_TIMEOUT {
+                Err(anyhow!(msg!("database-locked")))?
+            }
+            let pause = Duration::from_millis(10);
+            thread::sleep(pause);
+            waited += pause;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+//! Builds the fixture corpus with gen_fixtures.sh, which needs the sqlite3
+//! shell, runs every query it recorded through this program and compares the
+//! output with sqlite3's, blobs written as raw bytes like sqlite3 writes them.
+//! Skipped when sqlite3 is not installed.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// A directory removed, with everything in it, when this goes out of scope,
+/// failed asserts included.
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn queries_answer_as_sqlite3_does() {
+    if Command::new("sqlite3").arg("-version").output().is_err() {
+        eprintln!("sqlite3 is not installed; skipping the fixtures");
+        return;
+    }
+    let root = env!("CARGO_MANIFEST_DIR");
+    let dir = TempDir(std::env::temp_dir().join(format!("sqlite-lite-fixtures-{}", std::process::id())));
+    let dir = &dir.0;
+    let generated = Command::new("sh")
+        .arg(Path::new(root).join("gen_fixtures.sh"))
+        .arg(dir)
+        .current_dir(root)
+        .output()
+        .unwrap();
+    assert!(generated.status.success(), "gen_fixtures.sh failed: {}", String::from_utf8_lossy(&generated.stderr));
+
+    let mut queries = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sql"))
+        .collect::<Vec<_>>();
+    queries.sort();
+    let mut failed = Vec::new();
+    for sql in &queries {
+        // `deep.5.sql` is the fifth query, against `deep.db`.
+        let name = sql.file_stem().unwrap().to_str().unwrap();
+        let db = dir.join(format!("{}.db", name.rsplit_once('.').map_or(name, |(db, _)| db)));
+        let query = fs::read_to_string(sql).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust"))
+            .arg("--raw-blob")
+            .arg(&db)
+            .arg(query.trim_end_matches('\n'))
+            .output()
+            .unwrap();
+        let expected = fs::read(sql.with_extension("out")).unwrap();
+        // Compared as the shell compares them, trailing newlines aside.
+        let mut actual = [output.stdout, output.stderr].concat();
+        while actual.last() == Some(&b'\n') {
+            actual.pop();
+        }
+        if actual.is_empty() && expected.is_empty() {
+            continue;
+        }
+        actual.push(b'\n');
+        if actual != expected {
+            failed.push(format!(
+                "{}: {}\n    expected:\n{}\n    got:\n{}",
+                name,
+                query.trim_end(),
+                String::from_utf8_lossy(&expected),
+                String::from_utf8_lossy(&actual)
+            ));
+        }
+    }
+    assert!(failed.is_empty(), "{} of {} queries differ:\n{}", failed.len(), queries.len(), failed.join("\n"));
+    assert!(!queries.is_empty(), "gen_fixtures.sh recorded no queries");
+}